@@ -0,0 +1,199 @@
+// 原子地替换文件内容：先写到同一个目录下的临时文件，fsync 之后再 rename 到目标路径
+// - rename 在同一个文件系统里是原子的，别的进程读到的要么是完整的旧内容，要么是完整的新内容，不会读到写了一半的文件
+// - rename 之前先 fsync 临时文件，否则断电后可能出现"改名已经生效、数据还没落盘"的空文件
+// - rename 本身是对目录的修改，要让它在断电后也还在，还得 fsync 所在的目录（unix 上可以打开目录再 sync_all）
+// - 写的过程中出错或者 panic 时删掉临时文件，目标文件保持原样；进程直接崩溃留下的临时文件不影响目标文件，下次写照常进行
+// 目标文件已经存在时，新文件沿用它的权限
+#[cfg(test)]
+pub(crate) mod atomic_write {
+
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // 临时文件以点开头，ls 默认看不到；名字里带进程 id 和计数器，同时写同一个文件的线程、进程各用各的
+    fn temp_path(path: &Path) -> io::Result<PathBuf> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a file path", path.display()),
+            )
+        })?;
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        Ok(path.with_file_name(format!(
+            ".{}.tmp-{}-{}",
+            name.to_string_lossy(),
+            process::id(),
+            n
+        )))
+    }
+
+    // 没有改名成功之前一直负责删除临时文件，包括 write 里 panic 的情况
+    struct TempGuard {
+        path: PathBuf,
+        armed: bool,
+    }
+
+    impl Drop for TempGuard {
+        fn drop(&mut self) {
+            if self.armed {
+                let _ = fs::remove_file(&self.path);
+            }
+        }
+    }
+
+    // 目录的 fsync：让刚才的 rename 落盘。只有 unix 能这样打开目录
+    #[cfg(unix)]
+    fn sync_dir(dir: &Path) -> io::Result<()> {
+        File::open(dir)?.sync_all()
+    }
+
+    #[cfg(not(unix))]
+    fn sync_dir(_dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+        write_atomic_with(path, |file| file.write_all(contents))
+    }
+
+    // 内容由 write 写进临时文件，可以直接交给 serde_json::to_writer 之类的函数，不用先拼出整个字节数组
+    pub(crate) fn write_atomic_with(
+        path: &Path,
+        write: impl FnOnce(&mut File) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let temp = temp_path(path)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        let mut guard = TempGuard {
+            path: temp,
+            armed: true,
+        };
+        write(&mut file)?;
+        match fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&guard.path, path)?;
+        guard.armed = false;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        sync_dir(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::atomic_write::*;
+    use crate::tempfs_example::tempfs::TempDir;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn replaces_contents_and_keeps_permissions() {
+        let dir = TempDir::new("atomic-write").unwrap();
+        let path = dir.join("state.json");
+        write_atomic(&path, b"{\"version\":1}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"version\":1}");
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomic_with(&path, |file| {
+            serde_json::to_writer(file, &serde_json::json!({"version": 2})).map_err(io::Error::from)
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"version\":2}");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // 临时文件都改名掉了
+        assert_eq!(entries(dir.path()), ["state.json"]);
+
+        // 不存在的目录、不是文件的路径都报错
+        assert!(write_atomic(&dir.join("missing/state.json"), b"x").is_err());
+        let error = write_atomic(Path::new("/"), b"x").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn interrupted_writes_leave_the_old_contents() {
+        let dir = TempDir::new("atomic-interrupt").unwrap();
+        let path = dir.join("config.toml");
+        write_atomic(&path, b"workers = 4\n").unwrap();
+
+        // 写到一半出错：目标文件不变，临时文件被删掉
+        let error = write_atomic_with(&path, |file| {
+            file.write_all(b"workers = ")?;
+            Err(io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "disk full");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "workers = 4\n");
+        assert_eq!(entries(dir.path()), ["config.toml"]);
+
+        // 写到一半 panic 也一样
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            write_atomic_with(&path, |file| {
+                file.write_all(b"wor")?;
+                panic!("simulated crash while serializing");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "workers = 4\n");
+        assert_eq!(entries(dir.path()), ["config.toml"]);
+
+        // 进程被杀掉时来不及清理，留下写了一半的临时文件：目标文件不受影响，之后照常写
+        fs::write(dir.join(".config.toml.tmp-1-0"), "work").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "workers = 4\n");
+        write_atomic(&path, b"workers = 8\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "workers = 8\n");
+    }
+
+    #[test]
+    fn readers_never_see_partial_files() {
+        // 一个线程反复写两种内容，另一个线程反复读，读到的只能是完整的某一种
+        let dir = TempDir::new("atomic-readers").unwrap();
+        let path = Arc::new(dir.join("state"));
+        let (a, b) = (vec![b'a'; 64 * 1024], vec![b'b'; 64 * 1024]);
+        write_atomic(&path, &a).unwrap();
+        let writer = {
+            let path = Arc::clone(&path);
+            let (a, b) = (a.clone(), b.clone());
+            thread::spawn(move || {
+                for i in 0..100 {
+                    write_atomic(&path, if i % 2 == 0 { &b } else { &a }).unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let contents = fs::read(&*path).unwrap();
+            assert!(contents == a || contents == b, "{} bytes", contents.len());
+        }
+        writer.join().unwrap();
+        assert_eq!(fs::read(&*path).unwrap(), a);
+    }
+}
@@ -0,0 +1,270 @@
+// 批处理：有些操作的代价主要在"做一次"上，而不是"做多少"——fsync 一次、发一个上游请求、开一个事务。
+// Batcher 把一段时间里陆续到来的单个请求攒成一批，交给 execute 一次处理，再把每个请求自己的结果通过 oneshot 送回去
+// 攒够 max_size 个或者第一个请求已经等了 max_wait 就执行，两个条件哪个先到算哪个：
+// 负载高时一批很快攒满，吞吐量高；负载低时一个请求最多多等 max_wait
+#[cfg(test)]
+pub(crate) mod batcher {
+
+    use std::fmt;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::runtime::Handle;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio::time::{self, Instant};
+
+    // 执行批处理的任务已经退出（execute panic 了），请求没有结果
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct Closed;
+
+    impl fmt::Display for Closed {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "batcher is closed")
+        }
+    }
+
+    type Request<Req, Resp> = (Req, oneshot::Sender<Resp>);
+
+    pub(crate) struct Batcher<Req, Resp> {
+        sender: mpsc::UnboundedSender<Request<Req, Resp>>,
+        batches: Arc<AtomicUsize>,
+    }
+
+    impl<Req: Send + 'static, Resp: Send + 'static> Batcher<Req, Resp> {
+        // execute 拿到一批请求，按同样的顺序返回同样个数的结果。一批里某个请求失败时，把错误放在它自己的结果里
+        // 批处理在 handle 所属的运行时上的一个任务里逐批执行，Batcher 被丢弃后处理完剩下的请求就退出
+        pub(crate) fn new<F, Fut>(
+            handle: &Handle,
+            max_size: usize,
+            max_wait: Duration,
+            execute: F,
+        ) -> Batcher<Req, Resp>
+        where
+            F: FnMut(Vec<Req>) -> Fut + Send + 'static,
+            Fut: Future<Output = Vec<Resp>> + Send + 'static,
+        {
+            assert!(max_size > 0);
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let batches = Arc::new(AtomicUsize::new(0));
+            handle.spawn(Batcher::run(
+                receiver,
+                max_size,
+                max_wait,
+                execute,
+                Arc::clone(&batches),
+            ));
+            Batcher { sender, batches }
+        }
+
+        pub(crate) async fn call(&self, request: Req) -> Result<Resp, Closed> {
+            let (sender, receiver) = oneshot::channel();
+            self.sender.send((request, sender)).map_err(|_| Closed)?;
+            receiver.await.map_err(|_| Closed)
+        }
+
+        // 已经执行了多少批
+        pub(crate) fn batches(&self) -> usize {
+            self.batches.load(Ordering::SeqCst)
+        }
+
+        async fn run<F, Fut>(
+            mut receiver: mpsc::UnboundedReceiver<Request<Req, Resp>>,
+            max_size: usize,
+            max_wait: Duration,
+            mut execute: F,
+            batches: Arc<AtomicUsize>,
+        ) where
+            F: FnMut(Vec<Req>) -> Fut,
+            Fut: Future<Output = Vec<Resp>>,
+        {
+            // 没有请求时在这里等，不会空转；第一个请求到了才开始计时
+            while let Some(first) = receiver.recv().await {
+                let deadline = Instant::now() + max_wait;
+                let mut batch = vec![first];
+                while batch.len() < max_size {
+                    match time::timeout_at(deadline, receiver.recv()).await {
+                        Ok(Some(request)) => batch.push(request),
+                        // 时间到了，或者 Batcher 已经被丢弃、不会再有新请求
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+                let (requests, senders): (Vec<Req>, Vec<oneshot::Sender<Resp>>) =
+                    batch.into_iter().unzip();
+                let results = execute(requests).await;
+                assert_eq!(results.len(), senders.len(), "one result per request");
+                batches.fetch_add(1, Ordering::SeqCst);
+                // 调用方可能已经不等了（例如超时取消），接收端不在时结果直接丢弃
+                for (sender, result) in senders.into_iter().zip(results) {
+                    let _ = sender.send(result);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::batcher::*;
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
+    use crate::tempfs_example::tempfs::TempFile;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::runtime::Handle;
+
+    #[test]
+    fn batches_by_size_and_by_time() {
+        let rt = runtime(Preset::IoHeavy);
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let batcher = {
+            let sizes = Arc::clone(&sizes);
+            Batcher::new(
+                rt.handle(),
+                10,
+                Duration::from_millis(200),
+                move |numbers: Vec<u32>| {
+                    sizes.lock().unwrap().push(numbers.len());
+                    async move { numbers.into_iter().map(|n| n * n).collect::<Vec<_>>() }
+                },
+            )
+        };
+        let batcher = Arc::new(batcher);
+        // 100 个请求几乎同时到达：每批攒满 10 个就执行，不用等到 max_wait；每个请求拿到的是自己的结果
+        rt.block_on(async {
+            let calls: Vec<_> = (0..100)
+                .map(|n| {
+                    let batcher = Arc::clone(&batcher);
+                    tokio::spawn(async move { (n, batcher.call(n).await.unwrap()) })
+                })
+                .collect();
+            for call in calls {
+                let (n, square) = call.await.unwrap();
+                assert_eq!(square, n * n);
+            }
+        });
+        assert_eq!(batcher.batches(), 10);
+        assert!(sizes.lock().unwrap().iter().all(|&size| size == 10));
+
+        // 只有一个请求时攒不满，等 max_wait 之后单独执行
+        let start = Instant::now();
+        assert_eq!(rt.block_on(batcher.call(7)), Ok(49));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(*sizes.lock().unwrap().last().unwrap(), 1);
+    }
+
+    // 组提交：每条记录单独 write 再 fsync 的话，fsync 的次数就是记录数；攒成一批之后一批只 fsync 一次，
+    // 数据库的预写日志就是这样提高写入吞吐量的。结果是这条记录在日志里的字节偏移，或者这一批的错误
+    fn group_commit(handle: &Handle, path: &Path) -> Batcher<String, Result<u64, String>> {
+        let file = Arc::new(Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap(),
+        ));
+        Batcher::new(
+            handle,
+            64,
+            Duration::from_millis(2),
+            move |lines: Vec<String>| {
+                let file = Arc::clone(&file);
+                let count = lines.len();
+                async move {
+                    // write 和 fsync 都是阻塞调用，放到阻塞线程上，不占用工作线程
+                    tokio::task::spawn_blocking(move || {
+                        let mut file = file.lock().unwrap();
+                        let mut offset = file.metadata().map_err(|e| e.to_string())?.len();
+                        let mut buffer = String::new();
+                        let mut offsets = Vec::new();
+                        for line in &lines {
+                            offsets.push(offset);
+                            offset += line.len() as u64 + 1;
+                            buffer.push_str(line);
+                            buffer.push('\n');
+                        }
+                        file.write_all(buffer.as_bytes())
+                            .and_then(|_| file.sync_data())
+                            .map_err(|e| e.to_string())?;
+                        Ok::<_, String>(offsets)
+                    })
+                    .await
+                    .unwrap()
+                    // 一批共用一次 fsync，失败时这一批的每条记录都拿到同一个错误
+                    .map_or_else(
+                        |e| vec![Err(e); count],
+                        |offsets| offsets.into_iter().map(Ok).collect(),
+                    )
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn group_commit_fsyncs_once_per_batch() {
+        let wal = TempFile::new("batcher-wal").unwrap();
+        let path = wal.path();
+        let rt = runtime(Preset::IoHeavy);
+        let offsets = rt.block_on(async {
+            let log = Arc::new(group_commit(rt.handle(), path));
+            let appends: Vec<_> = (0..200)
+                .map(|i| {
+                    let log = Arc::clone(&log);
+                    tokio::spawn(async move { log.call(format!("record {:03}", i)).await })
+                })
+                .collect();
+            let mut offsets = Vec::new();
+            for append in appends {
+                offsets.push(append.await.unwrap().unwrap().unwrap());
+            }
+            // 200 条记录远远用不了 200 次 fsync
+            assert!(log.batches() < 50, "{} batches", log.batches());
+            offsets
+        });
+        // 每条记录都在它拿到的偏移上
+        let contents = fs::read_to_string(path).unwrap();
+        assert_eq!(contents.lines().count(), 200);
+        for (i, offset) in offsets.into_iter().enumerate() {
+            let at = &contents[offset as usize..];
+            assert!(at.starts_with(&format!("record {:03}\n", i)));
+        }
+    }
+
+    #[test]
+    fn per_item_errors_and_closed_batcher() {
+        let rt = runtime(Preset::SingleThreadTest);
+        // 一批里各个请求的结果互不影响
+        let parse = Batcher::new(
+            rt.handle(),
+            8,
+            Duration::from_millis(5),
+            |texts: Vec<&str>| async move {
+                texts.into_iter().map(str::parse::<u8>).collect::<Vec<_>>()
+            },
+        );
+        let results = rt.block_on(async {
+            let (a, b, c) = tokio::join!(parse.call("7"), parse.call("x"), parse.call("300"));
+            (a.unwrap(), b.unwrap(), c.unwrap())
+        });
+        assert_eq!(results.0, Ok(7));
+        assert!(results.1.is_err() && results.2.is_err());
+
+        // execute panic 之后批处理任务退出，这一批和之后的请求都得到 Closed
+        let broken = Batcher::new(
+            rt.handle(),
+            8,
+            Duration::from_millis(5),
+            |numbers: Vec<u8>| async move {
+                assert!(!numbers.contains(&0), "upstream exploded");
+                numbers
+            },
+        );
+        assert_eq!(rt.block_on(broken.call(1)), Ok(1));
+        assert_eq!(rt.block_on(broken.call(0)), Err(Closed));
+        assert_eq!(rt.block_on(broken.call(2)), Err(Closed));
+        assert_eq!(Closed.to_string(), "batcher is closed");
+    }
+}
@@ -0,0 +1,44 @@
+// 共享内存环形缓冲区的读者进程：创建缓冲区后打印一行 ready，然后把读到的消息逐行打印出来
+// 启动它的一方看到 ready 之后再启动 ipc_writer，写者打开文件时头部已经初始化好了
+// cargo run --bin ipc_reader -- <文件> <消息数> [容量]
+#[path = "../ipc_example.rs"]
+mod ipc_example;
+
+#[cfg(unix)]
+fn main() -> std::io::Result<()> {
+    use ipc_example::shared_ring::SharedRing;
+    use std::io::{self, Write};
+    use std::{env, path::Path, process, time::Duration};
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, count, capacity) = match args.as_slice() {
+        [path, count] => (path, count.parse::<usize>(), Ok(8)),
+        [path, count, capacity] => (path, count.parse::<usize>(), capacity.parse::<usize>()),
+        _ => {
+            eprintln!("usage: ipc_reader <path> <count> [capacity]");
+            process::exit(2);
+        }
+    };
+    let (Ok(count), Ok(capacity)) = (count, capacity) else {
+        eprintln!("message count and capacity must be non-negative integers");
+        process::exit(2);
+    };
+
+    let ring = SharedRing::create(Path::new(path), capacity)?;
+    // 标准输出按行缓冲，ready 这一行马上就能被对方读到
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "ready")?;
+    for _ in 0..count {
+        // 写者迟迟没有写入时超时退出，而不是永远等下去
+        let message = ring.pop(Duration::from_secs(10))?;
+        stdout.write_all(&message)?;
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("ipc_reader needs mmap and only runs on unix");
+    std::process::exit(1);
+}
@@ -0,0 +1,38 @@
+// 共享内存环形缓冲区的写者进程：打开 ipc_reader 创建好的缓冲区，依次写入 message 0 到 message <消息数 - 1>
+// 缓冲区满时 push 会等待读者腾出位置，所以写者可以比读者先启动写入，但必须在读者创建好文件之后
+// cargo run --bin ipc_writer -- <文件> <消息数>
+#[path = "../ipc_example.rs"]
+mod ipc_example;
+
+#[cfg(unix)]
+fn main() -> std::io::Result<()> {
+    use ipc_example::shared_ring::SharedRing;
+    use std::{env, path::Path, process};
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, count) = match args.as_slice() {
+        [path, count] => match count.parse::<usize>() {
+            Ok(count) => (path, count),
+            Err(_) => {
+                eprintln!("invalid message count: {}", count);
+                process::exit(2);
+            }
+        },
+        _ => {
+            eprintln!("usage: ipc_writer <path> <count>");
+            process::exit(2);
+        }
+    };
+
+    let ring = SharedRing::open(Path::new(path))?;
+    for i in 0..count {
+        ring.push(format!("message {}", i).as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("ipc_writer needs mmap and only runs on unix");
+    std::process::exit(1);
+}
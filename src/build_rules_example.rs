@@ -0,0 +1,527 @@
+// 类似 Makefile 的构建规则：每条规则声明输入文件、输出文件和生成输出的命令（recipe）
+// 一条规则的输入是另一条规则的输出时，两者之间就有了依赖，所有规则组成一张 DAG，交给 dag_runner 按依赖顺序在线程池上并行执行
+// 每条规则执行前判断输出是否过期，没过期就跳过命令。判断方式有两种：
+// - Mtime：和 make 一样比较修改时间，输出不存在或者有输入比最旧的输出还新就重新构建
+// - Hash：比较输入内容和命令的哈希与上次构建成功时记下的是否一致，只是 touch 了一下不会触发重新构建，改了命令会
+// 输入可以写成 glob（例如 src/*.txt），每次构建开始时展开成构建目录里已有的文件和其他规则会生成的文件
+#[cfg(all(test, unix))]
+pub(crate) mod build_rules {
+
+    use crate::atomic_write_example::atomic_write;
+    use crate::dag_runner_example::dag_runner::{Dag, Report, Task};
+    use crate::glob_example::glob;
+    use crate::thread_pool_example::thread_pool::ThreadPool;
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    // 哈希模式下记录上次构建结果的文件，放在构建目录里
+    const STATE_FILE: &str = ".build-state";
+
+    pub(crate) struct Rule {
+        name: String,
+        inputs: Vec<PathBuf>,
+        outputs: Vec<PathBuf>,
+        recipe: String,
+    }
+
+    impl Rule {
+        // recipe 交给 sh -c 执行，工作目录是构建目录
+        pub(crate) fn new(name: &str, recipe: &str) -> Rule {
+            Rule {
+                name: name.to_string(),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                recipe: recipe.to_string(),
+            }
+        }
+
+        // 路径都相对于构建目录，输入可以是 glob
+        pub(crate) fn inputs(mut self, inputs: &[&str]) -> Rule {
+            self.inputs.extend(inputs.iter().map(PathBuf::from));
+            self
+        }
+
+        pub(crate) fn outputs(mut self, outputs: &[&str]) -> Rule {
+            self.outputs.extend(outputs.iter().map(PathBuf::from));
+            self
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Staleness {
+        Mtime,
+        Hash,
+    }
+
+    pub(crate) struct Engine {
+        dir: PathBuf,
+        staleness: Staleness,
+        rules: Vec<Arc<Rule>>,
+    }
+
+    // build 的结果：report 是 dag_runner 的执行报告，rebuilt 是真正执行了命令的规则，按完成的顺序
+    pub(crate) struct Outcome {
+        pub(crate) report: Report,
+        pub(crate) rebuilt: Vec<String>,
+    }
+
+    impl Engine {
+        pub(crate) fn new(dir: &Path, staleness: Staleness) -> Engine {
+            Engine {
+                dir: dir.to_path_buf(),
+                staleness,
+                rules: Vec::new(),
+            }
+        }
+
+        pub(crate) fn add(&mut self, rule: Rule) -> &mut Engine {
+            self.rules.push(Arc::new(rule));
+            self
+        }
+
+        // 构建所有规则，同时执行的命令数等于线程池的大小（相当于 make -j）
+        // 规则之间的依赖有问题（例如有环）时返回错误，一条命令都不执行；命令失败记在报告里，依赖它的规则被跳过
+        pub(crate) fn build(&self, pool: &ThreadPool) -> Result<Outcome, Box<dyn Error>> {
+            // 每个输出文件由哪条规则生成
+            let mut producers = HashMap::new();
+            for rule in &self.rules {
+                for output in &rule.outputs {
+                    if let Some(other) = producers.insert(output, &rule.name) {
+                        return Err(format!(
+                            "{} is an output of both {} and {}",
+                            output.display(),
+                            other,
+                            rule.name
+                        )
+                        .into());
+                    }
+                }
+            }
+
+            let mut rules = Vec::new();
+            for rule in &self.rules {
+                let inputs = expand_inputs(&self.dir, rule, &producers)
+                    .map_err(|e| format!("rule {}: {}", rule.name, e))?;
+                rules.push(Arc::new(Rule {
+                    name: rule.name.clone(),
+                    inputs,
+                    outputs: rule.outputs.clone(),
+                    recipe: rule.recipe.clone(),
+                }));
+            }
+
+            let state = Arc::new(Mutex::new(load_state(&self.dir)?));
+            let rebuilt = Arc::new(Mutex::new(Vec::new()));
+            let mut dag = Dag::new();
+            for rule in &rules {
+                // 不是任何规则的输出的输入就是源文件，没有依赖
+                let mut deps: Vec<&str> = rule
+                    .inputs
+                    .iter()
+                    .filter_map(|input| producers.get(input).map(|name| name.as_str()))
+                    .collect();
+                deps.sort_unstable();
+                deps.dedup();
+                let (rule, dir, staleness) = (Arc::clone(rule), self.dir.clone(), self.staleness);
+                let (state, rebuilt) = (Arc::clone(&state), Arc::clone(&rebuilt));
+                dag.add(
+                    Task::new(&rule.name.clone(), move || {
+                        let fresh = match staleness {
+                            Staleness::Mtime => up_to_date_by_mtime(&dir, &rule),
+                            Staleness::Hash => {
+                                let hash = hash_rule(&dir, &rule).map_err(|e| e.to_string())?;
+                                Ok(hash.is_some()
+                                    && state.lock().unwrap().get(&rule.name) == hash.as_ref())
+                            }
+                        }?;
+                        if fresh {
+                            return Ok(());
+                        }
+                        run_recipe(&dir, &rule)?;
+                        if staleness == Staleness::Hash {
+                            // 命令成功之后才记下哈希，失败的规则下次还会重新构建
+                            if let Some(hash) = hash_rule(&dir, &rule).map_err(|e| e.to_string())? {
+                                state.lock().unwrap().insert(rule.name.clone(), hash);
+                            }
+                        }
+                        rebuilt.lock().unwrap().push(rule.name.clone());
+                        Ok(())
+                    })
+                    .after(&deps),
+                );
+            }
+            let report = dag.run(pool)?;
+            if self.staleness == Staleness::Hash {
+                save_state(&self.dir, &state.lock().unwrap())?;
+            }
+            let rebuilt = rebuilt.lock().unwrap().clone();
+            Ok(Outcome { report, rebuilt })
+        }
+    }
+
+    // 把 glob 输入展开成匹配的文件：构建目录里已有的普通文件，加上其他规则的输出（可能还没生成），按路径排序
+    // 规则自己的输出和状态文件不算，免得 *.txt 这样的输入让规则依赖自己
+    fn expand_inputs(
+        dir: &Path,
+        rule: &Rule,
+        producers: &HashMap<&PathBuf, &String>,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut inputs = Vec::new();
+        for input in &rule.inputs {
+            let text = input.to_string_lossy();
+            if !glob::is_pattern(&text) {
+                inputs.push(input.clone());
+                continue;
+            }
+            let mut matched = Vec::new();
+            for path in glob::glob(dir, &text)? {
+                let path = path?;
+                if dir.join(&path).is_file() {
+                    matched.push(path);
+                }
+            }
+            let pattern = glob::Pattern::new(&text)?;
+            matched.extend(
+                producers
+                    .keys()
+                    .filter(|output| pattern.matches(&output.to_string_lossy()))
+                    .map(|output| output.to_path_buf()),
+            );
+            matched.retain(|path| !rule.outputs.contains(path) && path.as_os_str() != STATE_FILE);
+            matched.sort();
+            matched.dedup();
+            inputs.extend(matched);
+        }
+        Ok(inputs)
+    }
+
+    fn modified(path: &Path) -> Result<SystemTime, String> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // 输出都存在、而且没有输入比最旧的输出更新
+    fn up_to_date_by_mtime(dir: &Path, rule: &Rule) -> Result<bool, String> {
+        let mut oldest_output = None;
+        for output in &rule.outputs {
+            match fs::metadata(dir.join(output)) {
+                Ok(metadata) => {
+                    let time = metadata.modified().map_err(|e| e.to_string())?;
+                    oldest_output = Some(oldest_output.map_or(time, |t: SystemTime| t.min(time)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(format!("{}: {}", output.display(), e)),
+            }
+        }
+        // 没有输出的规则（类似 make 的伪目标）每次都执行
+        let Some(oldest_output) = oldest_output else {
+            return Ok(false);
+        };
+        for input in &rule.inputs {
+            // 输入不存在是错误：源文件缺失，或者生成它的规则没有真的生成它
+            if modified(&dir.join(input))? > oldest_output {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // FNV-1a，哈希值要写进状态文件，下次构建时再比较，所以不能用每个进程随机密钥的 RandomState
+    fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+        data.iter().fold(hash, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    // 命令、每个输入的路径和内容，输出文件不存在时返回 None 让规则重新构建
+    fn hash_rule(dir: &Path, rule: &Rule) -> io::Result<Option<u64>> {
+        if rule.outputs.iter().any(|output| !dir.join(output).exists()) {
+            return Ok(None);
+        }
+        let mut hash = fnv1a(0xcbf2_9ce4_8422_2325, rule.recipe.as_bytes());
+        for input in &rule.inputs {
+            // 加一个分隔字节，避免 "ab" + "c" 和 "a" + "bc" 得到一样的结果
+            hash = fnv1a(hash, input.to_string_lossy().as_bytes());
+            hash = fnv1a(hash, &[0]);
+            hash = fnv1a(hash, &fs::read(dir.join(input))?);
+            hash = fnv1a(hash, &[0]);
+        }
+        Ok(Some(hash))
+    }
+
+    fn run_recipe(dir: &Path, rule: &Rule) -> Result<(), String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&rule.recipe)
+            .current_dir(dir)
+            .output()
+            .map_err(|e| format!("failed to start sh: {}", e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("recipe {} ({})", output.status, stderr.trim()));
+        }
+        for path in &rule.outputs {
+            if !dir.join(path).exists() {
+                return Err(format!("recipe did not create {}", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    // 状态文件每行一条规则：名字、制表符、十六进制的哈希。规则名字里不应该有制表符和换行
+    fn load_state(dir: &Path) -> io::Result<HashMap<String, u64>> {
+        let text = match fs::read_to_string(dir.join(STATE_FILE)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(text
+            .lines()
+            .filter_map(|line| {
+                let (name, hash) = line.split_once('\t')?;
+                let hash = u64::from_str_radix(hash, 16).ok()?;
+                Some((name.to_string(), hash))
+            })
+            .collect())
+    }
+
+    fn save_state(dir: &Path, state: &HashMap<String, u64>) -> io::Result<()> {
+        let mut lines: Vec<String> = state
+            .iter()
+            .map(|(name, hash)| format!("{}\t{:016x}\n", name, hash))
+            .collect();
+        lines.sort();
+        // 写到一半被打断时留下的是旧的状态文件，最多多重建几条规则，不会读到半行
+        atomic_write::write_atomic(&dir.join(STATE_FILE), lines.concat().as_bytes())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use super::build_rules::*;
+    use crate::dag_runner_example::dag_runner::Status;
+    use crate::thread_pool_example::thread_pool::ThreadPool;
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::time::{Duration, SystemTime};
+
+    fn build_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("build-rules-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 三条串起来的规则：upper 依赖源文件 a.txt，concat 依赖 upper 的输出和源文件 b.txt，count 依赖 concat
+    fn engine(dir: &Path, staleness: Staleness, upper: &str) -> Engine {
+        let mut engine = Engine::new(dir, staleness);
+        engine
+            .add(
+                Rule::new("count", "wc -l < out.txt | tr -d ' ' > count.txt")
+                    .inputs(&["out.txt"])
+                    .outputs(&["count.txt"]),
+            )
+            .add(
+                Rule::new("concat", "cat A.txt b.txt > out.txt")
+                    .inputs(&["A.txt", "b.txt"])
+                    .outputs(&["out.txt"]),
+            )
+            .add(
+                Rule::new("upper", upper)
+                    .inputs(&["a.txt"])
+                    .outputs(&["A.txt"]),
+            );
+        engine
+    }
+
+    const UPPER: &str = "tr a-z A-Z < a.txt > A.txt";
+
+    // 把目录里所有文件的修改时间改到过去，之后再改动的文件一定更新
+    fn age_files(dir: &Path) {
+        let past = SystemTime::now() - Duration::from_secs(100);
+        for entry in fs::read_dir(dir).unwrap() {
+            File::options()
+                .write(true)
+                .open(entry.unwrap().path())
+                .unwrap()
+                .set_modified(past)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn rebuilds_only_out_of_date_targets_by_mtime() {
+        let dir = build_dir("mtime");
+        fs::write(dir.join("a.txt"), "alpha\n").unwrap();
+        fs::write(dir.join("b.txt"), "beta\n").unwrap();
+        let pool = ThreadPool::new(2);
+        let engine = engine(&dir, Staleness::Mtime, UPPER);
+
+        let outcome = engine.build(&pool).unwrap();
+        assert!(outcome.report.succeeded());
+        // 依赖的规则先执行
+        assert_eq!(outcome.rebuilt, ["upper", "concat", "count"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("out.txt")).unwrap(),
+            "ALPHA\nbeta\n"
+        );
+        assert_eq!(fs::read_to_string(dir.join("count.txt")).unwrap(), "2\n");
+
+        // 什么都没变，什么都不做
+        assert!(engine.build(&pool).unwrap().rebuilt.is_empty());
+
+        // b.txt 变了：concat 和依赖它的 count 重新构建，upper 不受影响
+        age_files(&dir);
+        fs::write(dir.join("b.txt"), "beta\ngamma\n").unwrap();
+        assert_eq!(engine.build(&pool).unwrap().rebuilt, ["concat", "count"]);
+        assert_eq!(fs::read_to_string(dir.join("count.txt")).unwrap(), "3\n");
+
+        // 输出被删掉了也要重新构建
+        fs::remove_file(dir.join("A.txt")).unwrap();
+        assert_eq!(
+            engine.build(&pool).unwrap().rebuilt,
+            ["upper", "concat", "count"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hashes_ignore_touches_but_see_recipe_changes() {
+        let dir = build_dir("hash");
+        fs::write(dir.join("a.txt"), "alpha\n").unwrap();
+        fs::write(dir.join("b.txt"), "beta\n").unwrap();
+        let pool = ThreadPool::new(2);
+        assert_eq!(
+            engine(&dir, Staleness::Hash, UPPER)
+                .build(&pool)
+                .unwrap()
+                .rebuilt,
+            ["upper", "concat", "count"]
+        );
+
+        // 内容没变，只是修改时间变了：按修改时间会重新构建，按哈希不会
+        fs::write(dir.join("b.txt"), "beta\n").unwrap();
+        assert!(engine(&dir, Staleness::Hash, UPPER)
+            .build(&pool)
+            .unwrap()
+            .rebuilt
+            .is_empty());
+
+        // 换了命令，这条规则和下游都重新构建；下游的输入内容其实没变（A.txt 还是一样），所以到 concat 就停了
+        let upper = "tr a-z A-Z < a.txt > A.txt.tmp && mv A.txt.tmp A.txt";
+        assert_eq!(
+            engine(&dir, Staleness::Hash, upper)
+                .build(&pool)
+                .unwrap()
+                .rebuilt,
+            ["upper"]
+        );
+        fs::write(dir.join("a.txt"), "alpha\nomega\n").unwrap();
+        assert_eq!(
+            engine(&dir, Staleness::Hash, upper)
+                .build(&pool)
+                .unwrap()
+                .rebuilt,
+            ["upper", "concat", "count"]
+        );
+        assert_eq!(fs::read_to_string(dir.join("count.txt")).unwrap(), "3\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn glob_inputs_cover_sources_and_generated_files() {
+        let dir = build_dir("glob");
+        fs::write(dir.join("part1.txt"), "one\n").unwrap();
+        fs::write(dir.join("part2.txt"), "two\n").unwrap();
+        let mut engine = Engine::new(&dir, Staleness::Mtime);
+        engine
+            .add(
+                Rule::new("all", "cat part*.txt > all.txt")
+                    .inputs(&["part*.txt"])
+                    .outputs(&["all.txt"]),
+            )
+            // part3.txt 还不存在，但它是另一条规则的输出，也算 all 的输入，all 要等它生成
+            .add(Rule::new("third", "echo three > part3.txt").outputs(&["part3.txt"]));
+        let pool = ThreadPool::new(2);
+        assert_eq!(engine.build(&pool).unwrap().rebuilt, ["third", "all"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("all.txt")).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+        assert!(engine.build(&pool).unwrap().rebuilt.is_empty());
+
+        // 新加一个匹配的源文件，下次构建时就是 all 的输入
+        age_files(&dir);
+        fs::write(dir.join("part4.txt"), "four\n").unwrap();
+        assert_eq!(engine.build(&pool).unwrap().rebuilt, ["all"]);
+
+        engine.add(Rule::new("bad", "true").inputs(&["[part"]));
+        let err = engine.build(&pool).err().unwrap();
+        assert_eq!(err.to_string(), "rule bad: unclosed character class at 0");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failures_and_parallel_jobs() {
+        let dir = build_dir("jobs");
+        // left 和 right 互相等对方开始，只有同时执行才能在限定的次数内等到
+        let wait_for = |me: &str, other: &str| {
+            format!(
+                "touch {me}.started; i=0; while [ ! -e {other}.started ]; do i=$((i+1)); \
+                 [ $i -gt 500 ] && exit 1; sleep 0.01; done; echo {me} > {me}.out"
+            )
+        };
+        let mut engine = Engine::new(&dir, Staleness::Mtime);
+        engine
+            .add(Rule::new("left", &wait_for("left", "right")).outputs(&["left.out"]))
+            .add(Rule::new("right", &wait_for("right", "left")).outputs(&["right.out"]))
+            .add(
+                Rule::new("broken", "echo no space left >&2; exit 3")
+                    .inputs(&["left.out"])
+                    .outputs(&["broken.out"]),
+            )
+            .add(
+                Rule::new("lazy", "true")
+                    .inputs(&["right.out"])
+                    .outputs(&["lazy.out"]),
+            )
+            .add(
+                Rule::new("packaged", "cat broken.out > packaged.out")
+                    .inputs(&["broken.out"])
+                    .outputs(&["packaged.out"]),
+            );
+        let outcome = engine.build(&ThreadPool::new(2)).unwrap();
+        let status = |name: &str| &outcome.report.task(name).unwrap().status;
+        assert_eq!(status("left"), &Status::Succeeded);
+        assert_eq!(status("right"), &Status::Succeeded);
+        assert_eq!(
+            status("broken"),
+            &Status::Failed(String::from("recipe exit status: 3 (no space left)"))
+        );
+        assert_eq!(
+            status("lazy"),
+            &Status::Failed(String::from("recipe did not create lazy.out"))
+        );
+        assert_eq!(status("packaged"), &Status::Skipped);
+
+        // 两条规则生成同一个文件是规则写错了
+        engine.add(Rule::new("again", "true").outputs(&["left.out"]));
+        // Outcome 没有实现 Debug，不能用 unwrap_err
+        let err = engine.build(&ThreadPool::new(1)).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "left.out is an output of both left and again"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
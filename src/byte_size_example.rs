@@ -0,0 +1,313 @@
+// 字节数和 "10MiB" 这样的写法互相转换，配置文件里的大小限制用它来写
+// - 二进制单位按 1024 进位：KiB、MiB、GiB、TiB、PiB、EiB
+// - 十进制（SI）单位按 1000 进位：kB、MB、GB、TB、PB、EB
+// - 只写一个字母（10K、5M）和 du -h、sort -h 一样按二进制单位算
+// 单位不区分大小写，数字和单位之间可以有空格，可以带小数（1.5GiB），换算后不足 1 字节的部分舍去
+#[cfg(test)]
+pub(crate) mod byte_size {
+
+    use serde::Deserialize;
+    use std::error::Error;
+    use std::fmt;
+
+    const BINARY_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    const SI_UNITS: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum SizeError {
+        Empty,
+        InvalidNumber(String),
+        UnknownUnit(String),
+        // 超过了 u64 能表示的字节数（16 EiB）
+        Overflow(String),
+    }
+
+    impl fmt::Display for SizeError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                SizeError::Empty => write!(f, "size is empty"),
+                SizeError::InvalidNumber(text) => write!(f, "invalid number in size {:?}", text),
+                SizeError::UnknownUnit(unit) => write!(f, "unknown size unit {:?}", unit),
+                SizeError::Overflow(text) => write!(f, "size {:?} is too large", text),
+            }
+        }
+    }
+
+    impl Error for SizeError {}
+
+    // 单位对应多少字节
+    fn multiplier(unit: &str) -> Option<u128> {
+        let unit = unit.to_ascii_lowercase();
+        if unit.is_empty() || unit == "b" {
+            return Some(1);
+        }
+        let (prefix, suffix) = unit.split_at(unit.chars().next()?.len_utf8());
+        let exp = "kmgtpe".find(prefix)? as u32 + 1;
+        match suffix {
+            "" | "ib" => Some(1024u128.pow(exp)),
+            "b" => Some(1000u128.pow(exp)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn parse_size(text: &str) -> Result<u64, SizeError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(SizeError::Empty);
+        }
+        let split = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let (number, unit) = (&text[..split], text[split..].trim_start());
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        // "1."、".5"、"1.2.3" 和没有数字的都不接受
+        if whole.is_empty()
+            || (number.contains('.') && fraction.is_empty())
+            || fraction.contains('.')
+        {
+            return Err(SizeError::InvalidNumber(text.to_string()));
+        }
+        let multiplier =
+            multiplier(unit).ok_or_else(|| SizeError::UnknownUnit(unit.to_string()))?;
+        let overflow = || SizeError::Overflow(text.to_string());
+
+        // 全部用整数算，1.1MB 正好是 1100000 字节，用 f64 算会差一点
+        let whole: u128 = whole.parse().map_err(|_| overflow())?;
+        let mut bytes = whole.checked_mul(multiplier).ok_or_else(overflow)?;
+        // 小数部分最多看 18 位，再往后对结果的影响不到 1 字节
+        let fraction = &fraction[..fraction.len().min(18)];
+        if !fraction.is_empty() {
+            let digits: u128 = fraction.parse().unwrap();
+            // 整数部分刚好没溢出时，加上小数部分还可能溢出
+            bytes = bytes
+                .checked_add(digits * multiplier / 10u128.pow(fraction.len() as u32))
+                .ok_or_else(overflow)?;
+        }
+        u64::try_from(bytes).map_err(|_| overflow())
+    }
+
+    // 选一个让数值小于进位基数的单位，保留一位小数（四舍五入）
+    fn format_with(bytes: u64, base: u128, units: &[&str; 7]) -> String {
+        let bytes = bytes as u128;
+        if bytes < base {
+            return format!("{} B", bytes);
+        }
+        let mut exp = 1;
+        while exp + 1 < units.len() && bytes >= base.pow(exp as u32 + 1) {
+            exp += 1;
+        }
+        let tenths = |exp: usize| {
+            let unit = base.pow(exp as u32);
+            (bytes * 10 + unit / 2) / unit
+        };
+        let mut rounded = tenths(exp);
+        // 1048575 字节是 1023.999 KiB，四舍五入成 1024.0 KiB 不如写成 1.0 MiB
+        if rounded >= base * 10 && exp + 1 < units.len() {
+            exp += 1;
+            rounded = tenths(exp);
+        }
+        format!("{}.{} {}", rounded / 10, rounded % 10, units[exp])
+    }
+
+    pub(crate) fn format_size(bytes: u64) -> String {
+        format_with(bytes, 1024, &BINARY_UNITS)
+    }
+
+    pub(crate) fn format_size_si(bytes: u64) -> String {
+        format_with(bytes, 1000, &SI_UNITS)
+    }
+
+    // 配置文件里的大小：可以写成字节数（1048576），也可以写成带单位的字符串（"1MiB"）
+    #[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(try_from = "RawSize")]
+    pub(crate) struct ByteSize(pub(crate) u64);
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawSize {
+        Bytes(u64),
+        Text(String),
+    }
+
+    impl TryFrom<RawSize> for ByteSize {
+        type Error = SizeError;
+
+        fn try_from(raw: RawSize) -> Result<ByteSize, SizeError> {
+            match raw {
+                RawSize::Bytes(bytes) => Ok(ByteSize(bytes)),
+                RawSize::Text(text) => parse_size(&text).map(ByteSize),
+            }
+        }
+    }
+
+    impl fmt::Display for ByteSize {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&format_size(self.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::byte_size::*;
+
+    #[test]
+    fn parses_si_and_binary_units() {
+        let cases: &[(&str, u64)] = &[
+            ("0", 0),
+            ("42", 42),
+            ("42B", 42),
+            ("42 b", 42),
+            ("  7 B  ", 7),
+            ("1KiB", 1024),
+            ("1kb", 1000),
+            ("1kB", 1000),
+            ("1K", 1024),
+            ("10MiB", 10 << 20),
+            ("10MB", 10_000_000),
+            ("10M", 10 << 20),
+            ("10 mib", 10 << 20),
+            ("3GiB", 3 << 30),
+            ("3GB", 3_000_000_000),
+            ("2TiB", 2 << 40),
+            ("2TB", 2_000_000_000_000),
+            ("1PiB", 1 << 50),
+            ("1PB", 1_000_000_000_000_000),
+            ("1EiB", 1 << 60),
+            ("1EB", 1_000_000_000_000_000_000),
+            ("15EiB", 15 << 60),
+            ("18446744073709551615", u64::MAX),
+            // 小数按整数精确换算，不足 1 字节的部分舍去
+            ("1.5KiB", 1536),
+            ("1.1MB", 1_100_000),
+            ("0.5GiB", 1 << 29),
+            ("0.1KiB", 102),
+            ("2.999B", 2),
+            ("1.000000000000000000000001kB", 1000),
+            ("007MiB", 7 << 20),
+        ];
+        for &(text, bytes) in cases {
+            assert_eq!(parse_size(text), Ok(bytes), "{:?}", text);
+        }
+        // 每个单位的大写、小写和混合写法都一样
+        for (exp, prefix) in "KMGTPE".chars().enumerate() {
+            let binary = 1u64 << (10 * (exp + 1));
+            let si = 1000u64.pow(exp as u32 + 1);
+            for unit in [format!("{}iB", prefix), format!("{}", prefix)] {
+                assert_eq!(parse_size(&format!("1{}", unit)), Ok(binary));
+                assert_eq!(parse_size(&format!("1{}", unit.to_lowercase())), Ok(binary));
+                assert_eq!(parse_size(&format!("1{}", unit.to_uppercase())), Ok(binary));
+            }
+            assert_eq!(parse_size(&format!("1{}B", prefix)), Ok(si));
+            assert_eq!(
+                parse_size(&format!("1{}b", prefix.to_ascii_lowercase())),
+                Ok(si)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_sizes() {
+        assert_eq!(parse_size(""), Err(SizeError::Empty));
+        assert_eq!(parse_size("   "), Err(SizeError::Empty));
+        for text in ["MiB", "-1", "+1", "1.", ".5", "1.2.3", "..", "one", "1,024"] {
+            assert!(
+                matches!(
+                    parse_size(text),
+                    Err(SizeError::InvalidNumber(_)) | Err(SizeError::UnknownUnit(_))
+                ),
+                "{:?} -> {:?}",
+                text,
+                parse_size(text)
+            );
+        }
+        assert_eq!(
+            parse_size("MiB"),
+            Err(SizeError::InvalidNumber(String::from("MiB")))
+        );
+        assert_eq!(
+            parse_size("1.KiB"),
+            Err(SizeError::InvalidNumber(String::from("1.KiB")))
+        );
+        for (text, unit) in [
+            ("1X", "X"),
+            ("1Kb/s", "Kb/s"),
+            ("1KiBB", "KiBB"),
+            ("1ZiB", "ZiB"),
+            ("1 iB", "iB"),
+            ("1 MiB extra", "MiB extra"),
+            ("1 字节", "字节"),
+        ] {
+            assert_eq!(
+                parse_size(text),
+                Err(SizeError::UnknownUnit(unit.to_string()))
+            );
+        }
+        for text in [
+            "16EiB",
+            "18.5EB",
+            "18446744073709551616",
+            "99999999999999999999999999999999999999999",
+            "340282366920938463463374607431768211.999kB",
+        ] {
+            assert_eq!(parse_size(text), Err(SizeError::Overflow(text.to_string())));
+        }
+        assert_eq!(
+            parse_size("1ZiB").unwrap_err().to_string(),
+            "unknown size unit \"ZiB\""
+        );
+    }
+
+    #[test]
+    fn formats_sizes_and_round_trips() {
+        let cases: &[(u64, &str, &str)] = &[
+            (0, "0 B", "0 B"),
+            (1, "1 B", "1 B"),
+            (999, "999 B", "999 B"),
+            (1000, "1000 B", "1.0 kB"),
+            (1023, "1023 B", "1.0 kB"),
+            (1024, "1.0 KiB", "1.0 kB"),
+            (1536, "1.5 KiB", "1.5 kB"),
+            // 四舍五入：1075 是 1.0498 KiB，1076 是 1.0508 KiB
+            (1075, "1.0 KiB", "1.1 kB"),
+            (1076, "1.1 KiB", "1.1 kB"),
+            (10 << 20, "10.0 MiB", "10.5 MB"),
+            (999_949, "976.5 KiB", "999.9 kB"),
+            // 进位到下一个单位
+            (999_950, "976.5 KiB", "1.0 MB"),
+            ((1 << 20) - 1, "1.0 MiB", "1.0 MB"),
+            (3 << 30, "3.0 GiB", "3.2 GB"),
+            (1 << 40, "1.0 TiB", "1.1 TB"),
+            (1 << 50, "1.0 PiB", "1.1 PB"),
+            (1 << 60, "1.0 EiB", "1.2 EB"),
+            (u64::MAX, "16.0 EiB", "18.4 EB"),
+        ];
+        for &(bytes, binary, si) in cases {
+            assert_eq!(format_size(bytes), binary, "{}", bytes);
+            assert_eq!(format_size_si(bytes), si, "{}", bytes);
+        }
+        assert_eq!(ByteSize(1 << 20).to_string(), "1.0 MiB");
+
+        // 格式化只保留一位小数，再解析回来和原来的差距不超过半个最小刻度（0.05 个单位，相对误差不到 5%）
+        let mut seed: u64 = 7;
+        for _ in 0..10_000 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            // 16 EiB 以上格式化成 "16.0 EiB" 之后解析会溢出，只取 8 EiB 以内的数
+            let bytes = seed >> (seed % 64).max(1);
+            for text in [format_size(bytes), format_size_si(bytes)] {
+                let parsed = parse_size(&text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+                let error = (parsed as f64 - bytes as f64).abs();
+                assert!(
+                    error <= bytes as f64 * 0.05,
+                    "{} -> {} -> {}",
+                    bytes,
+                    text,
+                    parsed
+                );
+            }
+        }
+    }
+}
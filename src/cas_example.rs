@@ -0,0 +1,595 @@
+// 内容寻址存储（content-addressable store），对象格式仿照 git：
+// - 对象的 id 是 "类型 长度\0内容" 的 SHA-256，内容相同的对象只存一份（去重），读出来时重新计算哈希就能发现损坏
+// - blob 是文件内容；tree 是一个目录，每一项是 "权限 名字\0" 加上 32 字节的对象 id，按名字排序，相同的目录得到相同的 id
+// - 对象存放在 objects/前两位十六进制/剩下的位 里，避免一个目录下文件太多
+// snapshot 把一个目录递归存进去，返回根 tree 的 id；restore 按 id 把目录还原出来
+#[cfg(all(test, unix))]
+pub(crate) mod cas {
+
+    use crate::atomic_write_example::atomic_write;
+    use crate::path_util_example::path_util;
+    use sha2::{Digest, Sha256};
+    use std::error::Error;
+    use std::fmt;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub(crate) struct Oid([u8; 32]);
+
+    impl Oid {
+        pub(crate) fn from_hex(hex: &str) -> Option<Oid> {
+            if hex.len() != 64 {
+                return None;
+            }
+            let mut bytes = [0; 32];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+            }
+            Some(Oid(bytes))
+        }
+    }
+
+    impl fmt::Display for Oid {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            for byte in self.0 {
+                write!(f, "{:02x}", byte)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Kind {
+        Blob,
+        Tree,
+    }
+
+    impl Kind {
+        fn name(self) -> &'static str {
+            match self {
+                Kind::Blob => "blob",
+                Kind::Tree => "tree",
+            }
+        }
+    }
+
+    fn invalid(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    fn header(kind: Kind, len: usize) -> Vec<u8> {
+        format!("{} {}\0", kind.name(), len).into_bytes()
+    }
+
+    pub(crate) fn hash(kind: Kind, data: &[u8]) -> Oid {
+        let mut hasher = Sha256::new();
+        hasher.update(header(kind, data.len()));
+        hasher.update(data);
+        Oid(hasher.finalize().into())
+    }
+
+    // 写入的统计：duplicates 是已经存在、不用再写的对象，deduplicated_bytes 是因此省下的字节数
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub(crate) struct Stats {
+        pub(crate) written: u64,
+        pub(crate) written_bytes: u64,
+        pub(crate) duplicates: u64,
+        pub(crate) deduplicated_bytes: u64,
+    }
+
+    impl fmt::Display for Stats {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "{} objects written ({} bytes), {} duplicates ({} bytes saved)",
+                self.written, self.written_bytes, self.duplicates, self.deduplicated_bytes
+            )
+        }
+    }
+
+    pub(crate) struct Store {
+        root: PathBuf,
+        stats: Stats,
+    }
+
+    impl Store {
+        pub(crate) fn open(root: &Path) -> io::Result<Store> {
+            fs::create_dir_all(root.join("objects"))?;
+            Ok(Store {
+                root: root.to_path_buf(),
+                stats: Stats::default(),
+            })
+        }
+
+        pub(crate) fn stats(&self) -> Stats {
+            self.stats
+        }
+
+        fn path(&self, oid: &Oid) -> PathBuf {
+            let hex = oid.to_string();
+            self.root.join("objects").join(&hex[..2]).join(&hex[2..])
+        }
+
+        pub(crate) fn contains(&self, oid: &Oid) -> bool {
+            self.path(oid).exists()
+        }
+
+        pub(crate) fn put(&mut self, kind: Kind, data: &[u8]) -> io::Result<Oid> {
+            let oid = hash(kind, data);
+            if self.contains(&oid) {
+                self.stats.duplicates += 1;
+                self.stats.deduplicated_bytes += data.len() as u64;
+                return Ok(oid);
+            }
+            let path = self.path(&oid);
+            fs::create_dir_all(path.parent().unwrap())?;
+            // 先写临时文件再改名：改名是原子的，中途崩溃不会留下只写了一半、id 却对得上的对象
+            atomic_write::write_atomic_with(&path, |file| {
+                file.write_all(&header(kind, data.len()))?;
+                file.write_all(data)
+            })?;
+            self.stats.written += 1;
+            self.stats.written_bytes += data.len() as u64;
+            Ok(oid)
+        }
+
+        // 读出对象并校验：头部的类型和长度要对，内容的哈希要等于 id
+        pub(crate) fn get(&self, oid: &Oid) -> io::Result<(Kind, Vec<u8>)> {
+            let raw = fs::read(self.path(oid))?;
+            let nul = raw
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| invalid(format!("object {} has no header", oid)))?;
+            let header = std::str::from_utf8(&raw[..nul]).unwrap_or("");
+            let (kind, len) = match header.split_once(' ') {
+                Some(("blob", len)) => (Kind::Blob, len),
+                Some(("tree", len)) => (Kind::Tree, len),
+                _ => return Err(invalid(format!("object {} has a bad header", oid))),
+            };
+            let data = raw[nul + 1..].to_vec();
+            if len.parse() != Ok(data.len()) || hash(kind, &data) != *oid {
+                return Err(invalid(format!("object {} is corrupted", oid)));
+            }
+            Ok((kind, data))
+        }
+
+        fn get_kind(&self, oid: &Oid, expected: Kind) -> io::Result<Vec<u8>> {
+            let (kind, data) = self.get(oid)?;
+            if kind != expected {
+                return Err(invalid(format!(
+                    "object {} is a {}, not a {}",
+                    oid,
+                    kind.name(),
+                    expected.name()
+                )));
+            }
+            Ok(data)
+        }
+    }
+
+    // 权限用 git 的写法：普通文件 100644，可执行文件 100755，目录 40000
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Mode {
+        File,
+        Executable,
+        Dir,
+    }
+
+    impl Mode {
+        fn as_str(self) -> &'static str {
+            match self {
+                Mode::File => "100644",
+                Mode::Executable => "100755",
+                Mode::Dir => "40000",
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct TreeEntry {
+        pub(crate) mode: Mode,
+        pub(crate) name: String,
+        pub(crate) oid: Oid,
+    }
+
+    // 按名字排序后编码，同样的目录内容不管读目录的顺序如何都得到同样的字节
+    pub(crate) fn encode_tree(entries: &mut [TreeEntry]) -> Vec<u8> {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut out = Vec::new();
+        for entry in entries.iter() {
+            out.extend_from_slice(entry.mode.as_str().as_bytes());
+            out.push(b' ');
+            out.extend_from_slice(entry.name.as_bytes());
+            out.push(0);
+            out.extend_from_slice(&entry.oid.0);
+        }
+        out
+    }
+
+    pub(crate) fn decode_tree(mut data: &[u8]) -> io::Result<Vec<TreeEntry>> {
+        let bad = || invalid(String::from("malformed tree entry"));
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            let nul = data.iter().position(|&b| b == 0).ok_or_else(bad)?;
+            let text = std::str::from_utf8(&data[..nul]).map_err(|_| bad())?;
+            let (mode, name) = text.split_once(' ').ok_or_else(bad)?;
+            let mode = match mode {
+                "100644" => Mode::File,
+                "100755" => Mode::Executable,
+                "40000" => Mode::Dir,
+                _ => return Err(bad()),
+            };
+            // 名字里不能有路径分隔符，也不能是 . 和 ..，否则还原时可能写到目标目录外面去
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                return Err(bad());
+            }
+            let oid = data.get(nul + 1..nul + 33).ok_or_else(bad)?;
+            entries.push(TreeEntry {
+                mode,
+                name: name.to_string(),
+                oid: Oid(oid.try_into().unwrap()),
+            });
+            data = &data[nul + 33..];
+        }
+        Ok(entries)
+    }
+
+    // 递归存入目录，子目录先存，得到 id 之后才能写父目录的 tree；名字相同的目录项按名字排序，结果和遍历顺序无关
+    // 只处理普通文件和目录，符号链接之类的跳过
+    pub(crate) fn snapshot(store: &mut Store, dir: &Path) -> io::Result<Oid> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|name| invalid(format!("file name {:?} is not UTF-8", name)))?;
+            let file_type = entry.file_type()?;
+            let (mode, oid) = if file_type.is_dir() {
+                (Mode::Dir, snapshot(store, &entry.path())?)
+            } else if file_type.is_file() {
+                let executable = entry.metadata()?.permissions().mode() & 0o111 != 0;
+                let mode = if executable {
+                    Mode::Executable
+                } else {
+                    Mode::File
+                };
+                (mode, store.put(Kind::Blob, &fs::read(entry.path())?)?)
+            } else {
+                continue;
+            };
+            entries.push(TreeEntry { mode, name, oid });
+        }
+        store.put(Kind::Tree, &encode_tree(&mut entries))
+    }
+
+    // 还原到 dest，dest 不存在时创建；已有的同名文件被覆盖，多出来的文件保留
+    // dest 里已有的符号链接如果指向外面，顺着它写就会改到 dest 以外的文件，这种情况直接报错
+    pub(crate) fn restore(store: &Store, tree: &Oid, dest: &Path) -> io::Result<()> {
+        fs::create_dir_all(dest)?;
+        for entry in decode_tree(&store.get_kind(tree, Kind::Tree)?)? {
+            let path = path_util::safe_join_resolved(dest, Path::new(&entry.name))
+                .map_err(|e| invalid(e.to_string()))?;
+            match entry.mode {
+                Mode::Dir => restore(store, &entry.oid, &path)?,
+                Mode::File | Mode::Executable => {
+                    fs::write(&path, store.get_kind(&entry.oid, Kind::Blob)?)?;
+                    let mode = if entry.mode == Mode::Executable {
+                        0o755
+                    } else {
+                        0o644
+                    };
+                    fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 从根 tree 开始检查能到达的所有对象，返回损坏或者缺失的对象的路径（相对于快照的根，根自己是 "."）
+    // tree 里记着子对象的 id，tree 的 id 又是对这些内容的哈希，所以一个快照就是一棵 Merkle 树：
+    // 只要相信根的 id，下面任何一个对象被改过都能发现。损坏的 tree 读不出子对象，只报告它自己
+    pub(crate) fn fsck(store: &Store, root: &Oid) -> Vec<String> {
+        let mut damaged = Vec::new();
+        check_tree(store, root, Path::new("."), &mut damaged);
+        damaged
+    }
+
+    fn check_tree(store: &Store, oid: &Oid, path: &Path, damaged: &mut Vec<String>) {
+        let entries = match store
+            .get_kind(oid, Kind::Tree)
+            .and_then(|data| decode_tree(&data))
+        {
+            Ok(entries) => entries,
+            Err(_) => {
+                damaged.push(path.display().to_string());
+                return;
+            }
+        };
+        for entry in entries {
+            let path = path.join(&entry.name);
+            match entry.mode {
+                Mode::Dir => check_tree(store, &entry.oid, &path, damaged),
+                Mode::File | Mode::Executable => {
+                    if store.get_kind(&entry.oid, Kind::Blob).is_err() {
+                        damaged.push(path.display().to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    // 命令行：cas snapshot <store> <dir> 输出根 tree 的 id 和去重统计；cas restore <store> <id> <dest>；
+    // cas fsck <store> <id> 列出损坏的对象，有损坏时返回错误
+    pub(crate) fn run_cli(args: &[String], out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args[1..] {
+            ["snapshot", store, dir] => {
+                let mut store = Store::open(Path::new(store))?;
+                let oid = snapshot(&mut store, Path::new(dir))?;
+                writeln!(out, "{}", oid)?;
+                writeln!(out, "{}", store.stats())?;
+            }
+            ["restore", store, oid, dest] => {
+                let oid = Oid::from_hex(oid).ok_or("object id must be 64 hex digits")?;
+                restore(&Store::open(Path::new(store))?, &oid, Path::new(dest))?;
+                writeln!(out, "restored {} to {}", oid, dest)?;
+            }
+            ["fsck", store, oid] => {
+                let oid = Oid::from_hex(oid).ok_or("object id must be 64 hex digits")?;
+                let damaged = fsck(&Store::open(Path::new(store))?, &oid);
+                for path in &damaged {
+                    writeln!(out, "damaged: {}", path)?;
+                }
+                if !damaged.is_empty() {
+                    return Err(format!("{} damaged objects", damaged.len()).into());
+                }
+            }
+            _ => {
+                return Err(
+                    "usage: cas snapshot <store> <dir> | cas restore <store> <id> <dest> | cas fsck <store> <id>"
+                        .into(),
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use super::cas::*;
+    use crate::tempfs_example::tempfs::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    // 测试结束（包括失败）时自动删除
+    fn temp_dir(name: &str) -> TempDir {
+        TempDir::new(&format!("cas-{}", name)).unwrap()
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn blobs_are_deduplicated_and_verified() {
+        let root = temp_dir("blobs");
+        let mut store = Store::open(root.path()).unwrap();
+        let oid = store.put(Kind::Blob, b"hello world\n").unwrap();
+        // 和 printf 'blob 12\0hello world\n' | sha256sum 的结果一样
+        assert_eq!(
+            oid.to_string(),
+            "0bd69098bd9b9cc5934a610ab65da429b525361147faa7b5b922919e9a23143d"
+        );
+        assert_eq!(Oid::from_hex(&oid.to_string()), Some(oid));
+        assert_eq!(Oid::from_hex("0bd6"), None);
+        assert!(root
+            .join("objects/0b/d69098bd9b9cc5934a610ab65da429b525361147faa7b5b922919e9a23143d")
+            .exists());
+
+        assert_eq!(store.put(Kind::Blob, b"hello world\n").unwrap(), oid);
+        // 内容相同、类型不同是两个对象
+        assert_ne!(store.put(Kind::Tree, b"").unwrap(), hash(Kind::Blob, b""));
+        assert_eq!(
+            store.stats(),
+            Stats {
+                written: 2,
+                written_bytes: 12,
+                duplicates: 1,
+                deduplicated_bytes: 12
+            }
+        );
+        assert_eq!(
+            store.get(&oid).unwrap(),
+            (Kind::Blob, b"hello world\n".to_vec())
+        );
+
+        // 对象文件被改过
+        let path =
+            root.join("objects/0b/d69098bd9b9cc5934a610ab65da429b525361147faa7b5b922919e9a23143d");
+        fs::write(&path, b"blob 12\0hello World\n").unwrap();
+        let err = store.get(&oid).unwrap_err();
+        assert_eq!(err.to_string(), format!("object {} is corrupted", oid));
+    }
+
+    #[test]
+    fn tree_format() {
+        let oid = hash(Kind::Blob, b"x");
+        let mut entries = vec![
+            TreeEntry {
+                mode: Mode::File,
+                name: String::from("b.txt"),
+                oid,
+            },
+            TreeEntry {
+                mode: Mode::Dir,
+                name: String::from("a"),
+                oid,
+            },
+        ];
+        let encoded = encode_tree(&mut entries);
+        assert_eq!(&encoded[..8], b"40000 a\0");
+        assert_eq!(encoded.len(), 8 + 32 + 13 + 32);
+        assert_eq!(decode_tree(&encoded).unwrap(), entries);
+
+        // 截断的、带路径分隔符的都不接受
+        assert!(decode_tree(&encoded[..50]).is_err());
+        let mut escape = vec![TreeEntry {
+            mode: Mode::File,
+            name: String::from("../x"),
+            oid,
+        }];
+        assert!(decode_tree(&encode_tree(&mut escape)).is_err());
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let base = temp_dir("snapshot");
+        let src = base.join("src");
+        write(&src.join("README"), "read me\n");
+        write(&src.join("docs/a.md"), "same\n");
+        write(&src.join("copy/a.md"), "same\n");
+        write(&src.join("bin/run.sh"), "#!/bin/sh\necho hi\n");
+        fs::set_permissions(src.join("bin/run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut store = Store::open(&base.join("store")).unwrap();
+        let first = snapshot(&mut store, &src).unwrap();
+        // docs 和 copy 内容相同，是同一个 tree，里面的 a.md 是同一个 blob
+        let root = decode_tree(&store.get(&first).unwrap().1).unwrap();
+        let names: Vec<&str> = root.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["README", "bin", "copy", "docs"]);
+        assert_eq!(root[2].oid, root[3].oid);
+        assert_eq!(
+            decode_tree(&store.get(&root[1].oid).unwrap().1).unwrap()[0].mode,
+            Mode::Executable
+        );
+        // 3 个 blob、3 个 tree（bin、docs、根）写入，copy 的 tree 和其中的 a.md 重复
+        assert_eq!((store.stats().written, store.stats().duplicates), (6, 2));
+
+        // 什么都没改，再存一次全是重复的，根 id 也不变
+        let mut again = Store::open(&base.join("store")).unwrap();
+        assert_eq!(snapshot(&mut again, &src).unwrap(), first);
+        assert_eq!(again.stats().written, 0);
+        // 改一个文件：只有这个 blob 和它到根路径上的 tree 是新的
+        write(&src.join("docs/a.md"), "changed\n");
+        let second = snapshot(&mut again, &src).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(again.stats().written, 3);
+
+        // 还原第一个快照
+        let dest = base.join("restored");
+        restore(&again, &first, &dest).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("docs/a.md")).unwrap(),
+            "same\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dest.join("README")).unwrap(),
+            "read me\n"
+        );
+        let mode = fs::metadata(dest.join("bin/run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(snapshot(&mut again, &dest).unwrap(), first);
+
+        // blob 的 id 不能当目录还原
+        assert!(restore(&again, &root[0].oid, &base.join("bad")).is_err());
+
+        // 目标目录里的 docs 是指向外面的符号链接，不能顺着它把文件写出去
+        let outside = base.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let trap = base.join("trap");
+        fs::create_dir_all(&trap).unwrap();
+        std::os::unix::fs::symlink(&outside, trap.join("docs")).unwrap();
+        let error = restore(&again, &first, &trap).unwrap_err();
+        assert!(error.to_string().contains("escapes the root"), "{}", error);
+        assert!(!outside.join("a.md").exists());
+    }
+
+    #[test]
+    fn cli() {
+        let base = temp_dir("cli");
+        write(&base.join("src/a.txt"), "a\n");
+        let path = |name: &str| base.join(name).to_string_lossy().into_owned();
+        let args =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|arg| arg.to_string()).collect() };
+
+        let mut out = Vec::new();
+        run_cli(
+            &args(&["cas", "snapshot", &path("store"), &path("src")]),
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[1],
+            "2 objects written (47 bytes), 0 duplicates (0 bytes saved)"
+        );
+
+        let mut out = Vec::new();
+        run_cli(
+            &args(&["cas", "restore", &path("store"), lines[0], &path("dest")]),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(base.join("dest/a.txt")).unwrap(), "a\n");
+
+        let err = run_cli(
+            &args(&["cas", "restore", &path("store"), "xyz", &path("dest")]),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "object id must be 64 hex digits");
+        assert!(run_cli(&args(&["cas", "snapshot"]), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn fsck_detects_tampering() {
+        let base = temp_dir("fsck");
+        let src = base.join("src");
+        write(&src.join("a.txt"), "a\n");
+        write(&src.join("docs/b.txt"), "b\n");
+        write(&src.join("docs/c.txt"), "c\n");
+        let mut store = Store::open(&base.join("store")).unwrap();
+        let root = snapshot(&mut store, &src).unwrap();
+        assert!(fsck(&store, &root).is_empty());
+
+        // 改掉 b.txt 的对象、删掉 a.txt 的对象
+        let object = |content: &str| {
+            let hex = hash(Kind::Blob, content.as_bytes()).to_string();
+            base.join("store/objects").join(&hex[..2]).join(&hex[2..])
+        };
+        fs::write(object("b\n"), "blob 2\0B\n").unwrap();
+        fs::remove_file(object("a\n")).unwrap();
+        assert_eq!(fsck(&store, &root), ["./a.txt", "./docs/b.txt"]);
+
+        let mut out = Vec::new();
+        let args: Vec<String> = [
+            "cas",
+            "fsck",
+            &base.join("store").to_string_lossy(),
+            &root.to_string(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let err = run_cli(&args, &mut out).unwrap_err();
+        assert_eq!(err.to_string(), "2 damaged objects");
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "damaged: ./a.txt\ndamaged: ./docs/b.txt\n"
+        );
+        // 根本身不是 tree（或者损坏了）时只能报告根
+        assert_eq!(fsck(&store, &hash(Kind::Blob, b"c\n")), ["."]);
+    }
+}
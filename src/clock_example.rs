@@ -0,0 +1,163 @@
+// 时钟抽象：和时间有关的代码不直接调用 Instant::now、thread::sleep，而是通过 Clock 取时间、睡眠、等待定时器
+// 平时用 SystemClock，测试里换成 SimClock：时间只在测试拨动时才前进，"等一个小时"瞬间完成，
+// 每次运行的结果都一样，不会因为机器忙、调度慢而时好时坏
+#[cfg(test)]
+pub(crate) mod clock {
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    pub(crate) trait Clock: Send + Sync {
+        // 单调时间，用来计算间隔和到期时刻
+        fn now(&self) -> Instant;
+        // 墙上时间，需要跨进程比较时用（例如租约文件里的过期时间）
+        fn system_time(&self) -> SystemTime;
+        fn sleep(&self, duration: Duration);
+        // 定时器：按这个时钟离 deadline 还要真实地等多久。模拟时钟的时间不会自己走，返回 None，
+        // 等待的一方一直等下去，由 advance 调用 on_advance 注册的回调叫醒，醒来后重新看 now()
+        fn timer(&self, deadline: Instant) -> Option<Duration>;
+        fn on_advance(&self, wake: Box<dyn Fn() + Send + Sync>);
+    }
+
+    pub(crate) struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            SystemTime::now()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            thread::sleep(duration);
+        }
+
+        fn timer(&self, deadline: Instant) -> Option<Duration> {
+            Some(deadline.saturating_duration_since(Instant::now()))
+        }
+
+        // 真实时间自己会走，等待的一方按 timer 返回的时长超时醒来就行
+        fn on_advance(&self, _wake: Box<dyn Fn() + Send + Sync>) {}
+    }
+
+    pub(crate) fn system() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+
+    // 模拟时钟的墙上时间从 2024-01-01 00:00:00 UTC 开始
+    const SIM_EPOCH: Duration = Duration::from_secs(1_704_067_200);
+
+    // 模拟时钟：时间只在 advance 时前进。sleep 也不真的等待，而是直接把时钟拨过去，
+    // 所以单线程的代码（例如一个带 sleep 的轮询循环）可以原样跑在模拟时间上
+    pub(crate) struct SimClock {
+        base: Instant,
+        state: Mutex<SimState>,
+    }
+
+    struct SimState {
+        elapsed: Duration,
+        wakers: Vec<Arc<dyn Fn() + Send + Sync>>,
+    }
+
+    impl SimClock {
+        pub(crate) fn new() -> Arc<SimClock> {
+            Arc::new(SimClock {
+                base: Instant::now(),
+                state: Mutex::new(SimState {
+                    elapsed: Duration::ZERO,
+                    wakers: Vec::new(),
+                }),
+            })
+        }
+
+        // 拨动时钟之后叫醒所有等待定时器的线程。回调在锁外调用，回调里可以再读时钟
+        pub(crate) fn advance(&self, duration: Duration) {
+            let wakers = {
+                let mut state = self.state.lock().unwrap();
+                state.elapsed += duration;
+                state.wakers.clone()
+            };
+            for wake in wakers {
+                wake();
+            }
+        }
+
+        pub(crate) fn elapsed(&self) -> Duration {
+            self.state.lock().unwrap().elapsed
+        }
+    }
+
+    impl Clock for SimClock {
+        fn now(&self) -> Instant {
+            self.base + self.elapsed()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            UNIX_EPOCH + SIM_EPOCH + self.elapsed()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+
+        fn timer(&self, _deadline: Instant) -> Option<Duration> {
+            None
+        }
+
+        fn on_advance(&self, wake: Box<dyn Fn() + Send + Sync>) {
+            self.state.lock().unwrap().wakers.push(Arc::from(wake));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::clock::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    #[test]
+    fn simulated_time_only_moves_when_told() {
+        let sim = SimClock::new();
+        let clock: Arc<dyn Clock> = sim.clone();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        // 模拟的一小时瞬间过完
+        let real = Instant::now();
+        clock.sleep(Duration::from_secs(3600));
+        assert!(real.elapsed() < Duration::from_secs(1));
+        assert_eq!(clock.now() - start, Duration::from_secs(3600));
+        sim.advance(Duration::from_millis(5));
+        assert_eq!(sim.elapsed(), Duration::from_millis(3_600_005));
+        // 墙上时间是确定的
+        let wall = clock.system_time().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(wall, Duration::from_millis(1_704_067_200_000 + 3_600_005));
+        assert_eq!(clock.timer(clock.now() + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn advance_wakes_timer_waiters() {
+        let sim = SimClock::new();
+        let woken = Arc::new(AtomicUsize::new(0));
+        {
+            let woken = Arc::clone(&woken);
+            sim.on_advance(Box::new(move || {
+                woken.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        sim.advance(Duration::from_secs(1));
+        sim.sleep(Duration::from_secs(1));
+        assert_eq!(woken.load(Ordering::SeqCst), 2);
+
+        let system = SystemClock;
+        let deadline = system.now() + Duration::from_secs(60);
+        let wait = system.timer(deadline).unwrap();
+        assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(60));
+        assert_eq!(system.timer(system.now()), Some(Duration::ZERO));
+    }
+}
@@ -2,6 +2,8 @@
 #[cfg(test)]
 mod tests {
 
+    use crate::collections_example::lru::LruCache;
+    use std::cell::Cell;
     use std::thread;
     use std::time::Duration;
 
@@ -12,9 +14,12 @@ mod tests {
         T: Fn(u32) -> u32,
     {
         calculation: T,
-        value: Option<u32>,
+        // 只存一个 Option 的话，第一次算出来的值会被当成所有参数的结果；按参数分别缓存，最多记住 CACHER_CAPACITY 个
+        values: LruCache<u32, u32>,
     }
 
+    const CACHER_CAPACITY: usize = 16;
+
     // 带缓存的闭包调用
     impl<T> Cacher<T>
     where
@@ -23,19 +28,17 @@ mod tests {
         fn new(calculation: T) -> Cacher<T> {
             Cacher {
                 calculation,
-                value: None,
+                values: LruCache::new(CACHER_CAPACITY),
             }
         }
 
         fn value(&mut self, arg: u32) -> u32 {
-            match self.value {
-                Some(v) => v,
-                None => {
-                    let v = (self.calculation)(arg);
-                    self.value = Some(v);
-                    v
-                }
+            if let Some(&v) = self.values.get(&arg) {
+                return v;
             }
+            let v = (self.calculation)(arg);
+            self.values.put(arg, v);
+            v
         }
     }
 
@@ -102,4 +105,25 @@ mod tests {
         let y = vec![1, 2, 3];
         assert!(equal_to_x(y));
     }
+
+    #[test]
+    fn cacher_caches_per_argument() {
+        let calls = Cell::new(0);
+        let mut square = Cacher::new(|n| {
+            calls.set(calls.get() + 1);
+            n * n
+        });
+        assert_eq!(square.value(2), 4);
+        // 不同的参数得到各自的结果，相同的参数不再计算
+        assert_eq!(square.value(3), 9);
+        assert_eq!(square.value(2), 4);
+        assert_eq!(calls.get(), 2);
+        // 超过容量以后最久没用的结果被淘汰，再用到时重新计算
+        for n in 10..10 + CACHER_CAPACITY as u32 {
+            square.value(n);
+        }
+        assert_eq!(calls.get(), 2 + CACHER_CAPACITY);
+        assert_eq!(square.value(2), 4);
+        assert_eq!(calls.get(), 3 + CACHER_CAPACITY);
+    }
 }
@@ -0,0 +1,404 @@
+// 行编解码器：TCP 是字节流，没有"消息"的边界，一次 read 可能读到半行，也可能读到好几行
+// FramedLines 手写 Stream 和 Sink（不用 tokio-util 的 LinesCodec），看看基于 poll 的状态机是怎么写的：
+// - poll_next 先在缓冲里找换行符，找到就切出一行返回；找不到就 poll_read 读更多。底层返回 Pending 时原样返回 Pending，
+//   底层已经登记了唤醒，数据到了运行时会再来 poll 我们，缓冲里的半行留着下次接着拼
+// - 对方一直不发换行符的话缓冲会无限增长，所以一行超过 max_length 字节就报错，并丢弃到下一个换行符，连接还能接着用
+// - Sink 这边 start_send 只是把编码好的行放进写缓冲，poll_flush 才真正写出去；写缓冲太大时 poll_ready 先写出去，形成背压
+#[cfg(test)]
+pub(crate) mod codec {
+
+    use futures::{Sink, Stream};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    // 写缓冲超过这么多字节时，poll_ready 要先把它写出去才接受下一行
+    const BACKPRESSURE: usize = 8 * 1024;
+
+    pub(crate) struct FramedLines<T> {
+        io: T,
+        max_length: usize,
+        // 已经读进来、还没切成行的字节
+        read_buf: Vec<u8>,
+        // read_buf 里已经找过、确定没有换行符的前缀长度，下次从这里接着找，不用每次从头扫描
+        scanned: usize,
+        // 正在丢弃一个超长的行，直到下一个换行符
+        discarding: bool,
+        eof: bool,
+        // 已经编码、还没写出去的字节
+        write_buf: Vec<u8>,
+    }
+
+    fn invalid_data(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    impl<T> FramedLines<T> {
+        // max_length 是一行不算换行符的最大字节数
+        pub(crate) fn new(io: T, max_length: usize) -> FramedLines<T> {
+            FramedLines {
+                io,
+                max_length,
+                read_buf: Vec::new(),
+                scanned: 0,
+                discarding: false,
+                eof: false,
+                write_buf: Vec::new(),
+            }
+        }
+
+        pub(crate) fn into_inner(self) -> T {
+            self.io
+        }
+
+        // 去掉行尾的 \r（兼容 \r\n），再检查是不是合法的 UTF-8
+        fn decode(mut line: Vec<u8>) -> io::Result<String> {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            String::from_utf8(line).map_err(|e| invalid_data(e.to_string()))
+        }
+
+        fn too_long(&self) -> io::Error {
+            invalid_data(format!("line longer than {} bytes", self.max_length))
+        }
+    }
+
+    impl<T: AsyncRead + Unpin> Stream for FramedLines<T> {
+        type Item = io::Result<String>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+            // T: Unpin，FramedLines 自己也是 Unpin，可以直接拿到 &mut
+            let this = self.get_mut();
+            loop {
+                // 缓冲里已经有完整的一行就直接返回，不用去读
+                let newline = this.read_buf[this.scanned..]
+                    .iter()
+                    .position(|&b| b == b'\n');
+                if let Some(i) = newline {
+                    let end = this.scanned + i;
+                    let mut line: Vec<u8> = this.read_buf.drain(..=end).collect();
+                    line.pop();
+                    this.scanned = 0;
+                    if this.discarding {
+                        // 超长行的剩余部分到此为止，之后的行照常解析
+                        this.discarding = false;
+                        continue;
+                    }
+                    if line.len() > this.max_length {
+                        return Poll::Ready(Some(Err(this.too_long())));
+                    }
+                    return Poll::Ready(Some(FramedLines::<T>::decode(line)));
+                }
+                this.scanned = this.read_buf.len();
+
+                // 还没看到换行符就已经超长了：报错一次，之后读到的字节都丢掉，直到换行符
+                if this.read_buf.len() > this.max_length {
+                    this.read_buf.clear();
+                    this.scanned = 0;
+                    if !this.discarding {
+                        this.discarding = true;
+                        return Poll::Ready(Some(Err(this.too_long())));
+                    }
+                }
+
+                if this.eof {
+                    // 最后一行没有换行符也算一行；丢弃中的超长行到这里就结束了
+                    if this.read_buf.is_empty() || this.discarding {
+                        return Poll::Ready(None);
+                    }
+                    let line = std::mem::take(&mut this.read_buf);
+                    this.scanned = 0;
+                    return Poll::Ready(Some(FramedLines::<T>::decode(line)));
+                }
+
+                let mut chunk = [0; 4096];
+                let mut buf = ReadBuf::new(&mut chunk);
+                // 底层还没有数据时 ready! 返回 Pending，缓冲和状态都保存在 self 里，下次被 poll 时从这里继续
+                ready!(Pin::new(&mut this.io).poll_read(cx, &mut buf))?;
+                if buf.filled().is_empty() {
+                    this.eof = true;
+                } else {
+                    this.read_buf.extend_from_slice(buf.filled());
+                }
+            }
+        }
+    }
+
+    impl<T: AsyncWrite + Unpin> FramedLines<T> {
+        // 把写缓冲全部写出去。poll_write 可能只写了一部分，写了多少就从缓冲里去掉多少
+        fn poll_write_buf(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+            while !self.write_buf.is_empty() {
+                let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.write_buf))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.write_buf.drain(..n);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<T: AsyncWrite + Unpin> Sink<String> for FramedLines<T> {
+        type Error = io::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            if this.write_buf.len() >= BACKPRESSURE {
+                ready!(this.poll_write_buf(cx))?;
+            }
+            Poll::Ready(Ok(()))
+        }
+
+        // 行里不能有换行符，否则对方会把它当成两行；超长的行对方会拒绝，在发送这边就拦下来
+        fn start_send(self: Pin<&mut Self>, line: String) -> io::Result<()> {
+            let this = self.get_mut();
+            if line.contains('\n') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "line contains a newline",
+                ));
+            }
+            if line.len() > this.max_length {
+                return Err(this.too_long());
+            }
+            this.write_buf.extend_from_slice(line.as_bytes());
+            this.write_buf.push(b'\n');
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_write_buf(cx))?;
+            Pin::new(&mut this.io).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::codec::*;
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
+    use futures::{SinkExt, StreamExt};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::broadcast;
+
+    // 一次最多给 3 个字节，而且每隔一次先返回 Pending，模拟网络上零零碎碎到达的数据
+    struct Trickle {
+        data: Vec<u8>,
+        stalled: bool,
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            self.stalled = !self.stalled;
+            if self.stalled {
+                // 返回 Pending 之前要安排唤醒，否则这个任务再也不会被 poll
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = self.data.len().min(3).min(buf.remaining());
+            buf.put_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn lines_across_chunk_boundaries() {
+        let rt = runtime(Preset::SingleThreadTest);
+        rt.block_on(async {
+            let trickle = Trickle {
+                data: b"hello\r\nworld\n\nna\xffme\nlast".to_vec(),
+                stalled: false,
+            };
+            let lines: Vec<Result<String, io::ErrorKind>> = FramedLines::new(trickle, 64)
+                .map(|line| line.map_err(|e| e.kind()))
+                .collect()
+                .await;
+            assert_eq!(
+                lines,
+                [
+                    Ok(String::from("hello")),
+                    Ok(String::from("world")),
+                    Ok(String::new()),
+                    Err(io::ErrorKind::InvalidData),
+                    Ok(String::from("last")),
+                ]
+            );
+
+            // Sink 这边写出去的字节，Stream 那边原样解析回来
+            let (client, server) = tokio::io::duplex(16);
+            let writer = tokio::spawn(async move {
+                let mut lines = FramedLines::new(client, 64);
+                for i in 0..100 {
+                    lines.send(format!("line {}", i)).await.unwrap();
+                }
+                let error = lines.send(String::from("two\nlines")).await.unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+                lines.close().await.unwrap();
+            });
+            let received: Vec<String> = FramedLines::new(server, 64)
+                .map(Result::unwrap)
+                .collect()
+                .await;
+            writer.await.unwrap();
+            assert_eq!(received.len(), 100);
+            assert_eq!(received[99], "line 99");
+        });
+    }
+
+    #[test]
+    fn max_length_protection() {
+        let rt = runtime(Preset::SingleThreadTest);
+        rt.block_on(async {
+            // 一次就读到整行（带换行符）的超长行，和分很多次读到、迟迟没有换行符的超长行，都只报一次错，之后的行照常解析
+            let mut data = b"0123456789\nshort\n".to_vec();
+            data.extend(b"x".repeat(11));
+            data.extend(b"\nafter\n");
+            data.extend(b"y".repeat(100));
+            data.extend(b"\nend");
+            let readers: [Box<dyn AsyncRead + Unpin>; 2] = [
+                Box::new(&data[..]),
+                Box::new(Trickle {
+                    data: data.clone(),
+                    stalled: false,
+                }),
+            ];
+            for reader in readers {
+                let lines: Vec<Result<String, String>> = FramedLines::new(reader, 10)
+                    .map(|line| line.map_err(|e| e.to_string()))
+                    .collect()
+                    .await;
+                let too_long = Err(String::from("line longer than 10 bytes"));
+                assert_eq!(
+                    lines,
+                    [
+                        Ok(String::from("0123456789")),
+                        Ok(String::from("short")),
+                        too_long.clone(),
+                        Ok(String::from("after")),
+                        too_long,
+                        Ok(String::from("end")),
+                    ]
+                );
+            }
+            // 对方一直不发换行符：只报一次错，后面的数据读一块丢一块，不会全部攒在缓冲里
+            let endless = b"z".repeat(1 << 20);
+            let endless = FramedLines::new(&endless[..], 10);
+            let lines: Vec<_> = endless.collect().await;
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].is_err());
+        });
+    }
+
+    const MAX_LINE: usize = 1024;
+
+    // 聊天室：连上以后先发一行昵称，之后每发一行就广播给所有人（包括自己）
+    async fn chat_client(stream: TcpStream, room: broadcast::Sender<String>) -> io::Result<()> {
+        let (reader, writer) = stream.into_split();
+        let mut lines = FramedLines::new(reader, MAX_LINE);
+        let mut out = FramedLines::new(writer, MAX_LINE);
+        let name = match lines.next().await {
+            Some(Ok(name)) => name,
+            _ => return Ok(()),
+        };
+        let mut messages = room.subscribe();
+        let _ = room.send(format!("* {} joined", name));
+        loop {
+            tokio::select! {
+                line = lines.next() => match line {
+                    Some(Ok(text)) => {
+                        let _ = room.send(format!("{}: {}", name, text));
+                    }
+                    // 超长的行或者不是 UTF-8 的行只告诉发送者，连接保持
+                    Some(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                        out.send(format!("! {}", e)).await?
+                    }
+                    _ => break,
+                },
+                message = messages.recv() => match message {
+                    Ok(message) => out.send(message).await?,
+                    // 这个客户端太慢，广播通道里的消息被覆盖了
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        out.send(format!("! missed {} messages", n)).await?
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+        let _ = room.send(format!("* {} left", name));
+        Ok(())
+    }
+
+    async fn chat_server(listener: TcpListener) {
+        let (room, _) = broadcast::channel(100);
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            tokio::spawn(chat_client(stream, room.clone()));
+        }
+    }
+
+    #[test]
+    fn chat_server_over_framed_lines() {
+        let rt = runtime(Preset::IoHeavy);
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(chat_server(listener));
+            let connect = |name: &'static str| async move {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                let mut client = FramedLines::new(stream, MAX_LINE);
+                client.send(String::from(name)).await.unwrap();
+                // 看到自己加入的消息，说明已经订阅了，之后的消息都能收到
+                assert_eq!(
+                    client.next().await.unwrap().unwrap(),
+                    format!("* {} joined", name)
+                );
+                client
+            };
+            let mut alice = connect("alice").await;
+            let mut bob = connect("bob").await;
+            assert_eq!(alice.next().await.unwrap().unwrap(), "* bob joined");
+
+            alice.send(String::from("hi bob")).await.unwrap();
+            assert_eq!(alice.next().await.unwrap().unwrap(), "alice: hi bob");
+            assert_eq!(bob.next().await.unwrap().unwrap(), "alice: hi bob");
+
+            // 超长的行被服务器拒绝，连接还能继续用
+            // FramedLines 自己发不出超长的行，绕过它直接往连接里写
+            let mut raw = bob.into_inner();
+            let mut long = b"x".repeat(5000);
+            long.push(b'\n');
+            raw.write_all(&long).await.unwrap();
+            let mut bob = FramedLines::new(raw, MAX_LINE);
+            bob.send(String::from("hi alice")).await.unwrap();
+            assert_eq!(
+                bob.next().await.unwrap().unwrap(),
+                "! line longer than 1024 bytes"
+            );
+            assert_eq!(bob.next().await.unwrap().unwrap(), "bob: hi alice");
+            assert_eq!(alice.next().await.unwrap().unwrap(), "bob: hi alice");
+
+            drop(alice);
+            assert_eq!(bob.next().await.unwrap().unwrap(), "* alice left");
+        });
+    }
+}
@@ -1,7 +1,231 @@
 // 集合
+// LRU 缓存：容量满了以后再放入新的键，就淘汰最久没有用过的那个。HashMap 负责按键查找，
+// 再用一条双向链表按最近使用的顺序把所有条目串起来：用到的条目移到表头，淘汰时取表尾，都是 O(1)
+// 链表的节点放在 Vec 里，prev/next 存的是下标而不是指针，不需要 unsafe 也不需要 Rc<RefCell<...>>
+#[cfg(test)]
+pub(crate) mod lru {
+
+    use std::collections::HashMap;
+    use std::hash::Hash;
+    use std::mem;
+
+    struct Node<K, V> {
+        key: K,
+        value: V,
+        prev: Option<usize>,
+        next: Option<usize>,
+    }
+
+    pub(crate) struct LruCache<K, V> {
+        capacity: usize,
+        // 键 -> 节点在 nodes 里的下标
+        map: HashMap<K, usize>,
+        nodes: Vec<Node<K, V>>,
+        // 表头是最近用过的，表尾是最久没用的
+        head: Option<usize>,
+        tail: Option<usize>,
+    }
+
+    // 键在 map 和节点里各存一份，淘汰时要用节点里的键去删 map，所以要求 Clone
+    impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+        pub(crate) fn new(capacity: usize) -> LruCache<K, V> {
+            assert!(capacity > 0);
+            LruCache {
+                capacity,
+                map: HashMap::with_capacity(capacity),
+                nodes: Vec::with_capacity(capacity),
+                head: None,
+                tail: None,
+            }
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.nodes.len()
+        }
+
+        // 查到的条目变成最近用过的，所以要 &mut self
+        pub(crate) fn get(&mut self, key: &K) -> Option<&V> {
+            let i = *self.map.get(key)?;
+            self.detach(i);
+            self.push_front(i);
+            Some(&self.nodes[i].value)
+        }
+
+        // 放入或者更新一个条目，它变成最近用过的。容量满了时淘汰最久没用的条目并返回它
+        pub(crate) fn put(&mut self, key: K, value: V) -> Option<(K, V)> {
+            if let Some(&i) = self.map.get(&key) {
+                self.nodes[i].value = value;
+                self.detach(i);
+                self.push_front(i);
+                return None;
+            }
+            let node = Node {
+                key: key.clone(),
+                value,
+                prev: None,
+                next: None,
+            };
+            if self.nodes.len() < self.capacity {
+                self.nodes.push(node);
+                let i = self.nodes.len() - 1;
+                self.map.insert(key, i);
+                self.push_front(i);
+                return None;
+            }
+            // 满了：表尾的节点原地换成新条目，下标不变，不用挪动别的节点
+            let i = self.tail.expect("a full cache has a tail");
+            self.detach(i);
+            let evicted = mem::replace(&mut self.nodes[i], node);
+            self.map.remove(&evicted.key);
+            self.map.insert(key, i);
+            self.push_front(i);
+            Some((evicted.key, evicted.value))
+        }
+
+        // 从最近用过的到最久没用的，遍历不改变顺序
+        pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+            std::iter::successors(self.head, |&i| self.nodes[i].next)
+                .map(|i| (&self.nodes[i].key, &self.nodes[i].value))
+        }
+
+        // 把节点从链表里摘下来，前后两个节点直接相连
+        fn detach(&mut self, i: usize) {
+            let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+            match prev {
+                Some(prev) => self.nodes[prev].next = next,
+                None => self.head = next,
+            }
+            match next {
+                Some(next) => self.nodes[next].prev = prev,
+                None => self.tail = prev,
+            }
+        }
+
+        fn push_front(&mut self, i: usize) {
+            self.nodes[i].prev = None;
+            self.nodes[i].next = self.head;
+            match self.head {
+                Some(head) => self.nodes[head].prev = Some(i),
+                None => self.tail = Some(i),
+            }
+            self.head = Some(i);
+        }
+    }
+}
+
+// 区间映射：把一段一段的键区间映射到值，例如 IP 段到地区、一天里的时段到电价
+// 区间是左闭右开的 [start, end)，互不重叠，存在 BTreeMap<start, (end, value)> 里。BTreeMap 的键是有序的，
+// 查某个点落在哪个区间只要用 range(..=point) 找到起点不大于它的最后一个区间，再看它的终点是不是在这个点之后，是 O(log n)
+// 插入和已有区间重叠时，新的覆盖旧的：旧区间被截短或者一分为二；值相同的相邻区间合并成一个
+#[cfg(test)]
+pub(crate) mod interval_map {
+
+    use std::collections::BTreeMap;
+    use std::ops::Range;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct IntervalMap<K, V> {
+        map: BTreeMap<K, (K, V)>,
+    }
+
+    impl<K: Ord + Clone, V: Clone + PartialEq> IntervalMap<K, V> {
+        pub(crate) fn new() -> IntervalMap<K, V> {
+            IntervalMap {
+                map: BTreeMap::new(),
+            }
+        }
+
+        // 区间的个数
+        pub(crate) fn len(&self) -> usize {
+            self.map.len()
+        }
+
+        pub(crate) fn query(&self, point: &K) -> Option<&V> {
+            let (_, (end, value)) = self.map.range(..=point).next_back()?;
+            (point < end).then_some(value)
+        }
+
+        pub(crate) fn insert(&mut self, range: Range<K>, value: V) {
+            if range.is_empty() {
+                return;
+            }
+            self.remove(range.clone());
+            let Range { mut start, mut end } = range;
+            // 紧挨着的左邻居值相同，接到它后面
+            if let Some((left_start, (left_end, left_value))) = self.map.range(..&start).next_back()
+            {
+                if *left_end == start && *left_value == value {
+                    start = left_start.clone();
+                }
+            }
+            // 右邻居同理
+            if let Some((right_end, right_value)) = self.map.get(&end) {
+                if *right_value == value {
+                    let right_end = right_end.clone();
+                    self.map.remove(&end);
+                    end = right_end;
+                }
+            }
+            self.map.insert(start, (end, value));
+        }
+
+        // 清空 range 覆盖的部分，跨过边界的区间只保留边界外的那一段
+        pub(crate) fn remove(&mut self, range: Range<K>) {
+            if range.is_empty() {
+                return;
+            }
+            // 起点在 range 之前、终点伸进 range 的区间：截短，如果它一直伸到 range 之后，后面那段另外放回去
+            if let Some((_, (end, value))) = self.map.range_mut(..&range.start).next_back() {
+                if *end > range.start {
+                    let old_end = std::mem::replace(end, range.start.clone());
+                    if old_end > range.end {
+                        let value = value.clone();
+                        self.map.insert(range.end.clone(), (old_end, value));
+                        return;
+                    }
+                }
+            }
+            // 起点在 range 里的区间都拿掉，最后一个可能伸到 range 之后，把后面那段放回去
+            let inside: Vec<K> = self
+                .map
+                .range(range.clone())
+                .map(|(start, _)| start.clone())
+                .collect();
+            for start in inside {
+                let (end, value) = self.map.remove(&start).unwrap();
+                if end > range.end {
+                    self.map.insert(range.end.clone(), (end, value));
+                }
+            }
+        }
+
+        // 和 range 有重叠的区间，按起点排序
+        pub(crate) fn overlapping(&self, range: Range<K>) -> impl Iterator<Item = (Range<K>, &V)> {
+            // 起点在 range 之前的区间最多只有一个可能重叠，就是紧挨着 range.start 的那个
+            let first = self
+                .map
+                .range(..&range.start)
+                .next_back()
+                .filter(|(_, (end, _))| *end > range.start);
+            first
+                .into_iter()
+                .chain(self.map.range(range.start.clone()..range.end.clone()))
+                .map(|(start, (end, value))| (start.clone()..end.clone(), value))
+        }
+
+        pub(crate) fn iter(&self) -> impl Iterator<Item = (Range<K>, &V)> {
+            self.map
+                .iter()
+                .map(|(start, (end, value))| (start.clone()..end.clone(), value))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    use super::interval_map::IntervalMap;
+    use super::lru::LruCache;
     use std::collections::HashMap;
 
     #[derive(Debug)]
@@ -214,4 +438,161 @@ mod tests {
         }
         println!("{:?}", map);
     }
+
+    // 按最近使用的顺序列出键
+    fn keys(cache: &LruCache<&'static str, i32>) -> Vec<&'static str> {
+        cache.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        assert_eq!(cache.put("a", 1), None);
+        assert_eq!(cache.put("b", 2), None);
+        assert_eq!(cache.put("c", 3), None);
+        assert_eq!(keys(&cache), ["c", "b", "a"]);
+        // 读一次 a，它变成最近用过的，满了之后先淘汰的是 b
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.put("d", 4), Some(("b", 2)));
+        assert_eq!(keys(&cache), ["d", "a", "c"]);
+        assert_eq!(cache.get(&"b"), None);
+        // 更新已有的键不淘汰任何条目，但同样算一次使用
+        assert_eq!(cache.put("c", 30), None);
+        assert_eq!(cache.put("e", 5), Some(("a", 1)));
+        assert_eq!(
+            cache.iter().collect::<Vec<_>>(),
+            [(&"e", &5), (&"c", &30), (&"d", &4)]
+        );
+        assert_eq!(cache.len(), 3);
+
+        let mut single = LruCache::new(1);
+        single.put("x", 1);
+        assert_eq!(single.put("y", 2), Some(("x", 1)));
+        assert_eq!(keys(&single), ["y"]);
+    }
+
+    #[test]
+    fn lru_matches_a_naive_model() {
+        // 和一个用 Vec 按使用顺序排列的朴素实现对照，随机操作一万次
+        let mut cache = LruCache::new(8);
+        let mut model: Vec<(u32, u32)> = Vec::new();
+        let mut seed: u64 = 42;
+        for step in 0..10_000 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let key = (seed >> 33) as u32 % 20;
+            let position = model.iter().position(|&(k, _)| k == key);
+            if seed >> 63 == 0 {
+                let expected = position.map(|i| {
+                    let entry = model.remove(i);
+                    model.insert(0, entry);
+                    entry.1
+                });
+                assert_eq!(cache.get(&key).copied(), expected, "step {}", step);
+            } else {
+                let evicted = match position {
+                    Some(i) => {
+                        model.remove(i);
+                        None
+                    }
+                    None if model.len() == 8 => model.pop(),
+                    None => None,
+                };
+                model.insert(0, (key, step));
+                assert_eq!(cache.put(key, step), evicted, "step {}", step);
+            }
+            let order: Vec<(u32, u32)> = cache.iter().map(|(&k, &v)| (k, v)).collect();
+            assert_eq!(order, model, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn interval_map_lookups() {
+        // 一天里的分时电价，键是分钟数
+        let hour = |h: u32| h * 60;
+        let mut prices = IntervalMap::new();
+        prices.insert(hour(0)..hour(24), "平");
+        prices.insert(hour(8)..hour(11), "峰");
+        prices.insert(hour(18)..hour(21), "峰");
+        prices.insert(hour(23)..hour(24), "谷");
+        prices.insert(hour(0)..hour(7), "谷");
+        assert_eq!(prices.query(&hour(9)), Some(&"峰"));
+        assert_eq!(prices.query(&(hour(11) - 1)), Some(&"峰"));
+        // 左闭右开，11:00 已经不是高峰了
+        assert_eq!(prices.query(&hour(11)), Some(&"平"));
+        assert_eq!(prices.query(&hour(24)), None);
+        let segments: Vec<_> = prices
+            .iter()
+            .map(|(range, price)| (range.start / 60, range.end / 60, *price))
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                (0, 7, "谷"),
+                (7, 8, "平"),
+                (8, 11, "峰"),
+                (11, 18, "平"),
+                (18, 21, "峰"),
+                (21, 23, "平"),
+                (23, 24, "谷")
+            ]
+        );
+        // 中午也算高峰：值相同的相邻区间合并
+        prices.insert(hour(11)..hour(18), "峰");
+        assert_eq!(prices.len(), 5);
+        assert_eq!(
+            prices.overlapping(hour(10)..hour(22)).collect::<Vec<_>>(),
+            [(hour(8)..hour(21), &"峰"), (hour(21)..hour(23), &"平")]
+        );
+        // 挖掉中间一段，原来的区间一分为二
+        prices.remove(hour(12)..hour(13));
+        assert_eq!(prices.query(&hour(12)), None);
+        assert_eq!(prices.query(&hour(13)), Some(&"峰"));
+        assert_eq!(prices.len(), 6);
+    }
+
+    #[test]
+    fn interval_map_matches_a_naive_model() {
+        // 和一个每个点单独存值的数组对照
+        let mut map = IntervalMap::new();
+        let mut model = [None; 64];
+        let mut seed: u64 = 93;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for step in 0..5_000 {
+            let (a, b) = (next(65) as usize, next(65) as usize);
+            let range = a.min(b)..a.max(b);
+            if next(4) == 0 {
+                map.remove(range.clone());
+                model[range].fill(None);
+            } else {
+                // 值只有三种，经常会和邻居相同，合并的路径也能测到
+                let value = next(3);
+                map.insert(range.clone(), value);
+                model[range].fill(Some(value));
+            }
+            for (point, expected) in model.iter().enumerate() {
+                assert_eq!(map.query(&point), expected.as_ref(), "step {}", step);
+            }
+            // 区间互不重叠、按顺序排列，相邻的值相同就应该已经合并了
+            let intervals: Vec<_> = map.iter().collect();
+            for pair in intervals.windows(2) {
+                assert!(pair[0].0.end <= pair[1].0.start);
+                assert!(pair[0].0.end < pair[1].0.start || pair[0].1 != pair[1].1);
+            }
+            let (a, b) = (next(65) as usize, next(65) as usize);
+            let window = a.min(b)..a.max(b);
+            let covered = map.overlapping(window.clone()).count();
+            let expected = intervals
+                .iter()
+                .filter(|(range, _)| range.start < window.end && window.start < range.end)
+                .count();
+            assert_eq!(covered, expected, "step {}", step);
+        }
+    }
 }
@@ -0,0 +1,177 @@
+// 共现统计：推荐系统里“看了这个的人也看了”的最简单做法。把事件日志按用户分组，同一个用户接触过的物品两两配对计数，
+// 再对每个物品取计数最高的 k 个相关物品。计数阶段分块交给线程池并行，每个任务先在自己的 HashMap 里累加，最后再合并，避免争抢同一把锁
+#[cfg(test)]
+mod tests {
+
+    use crate::thread_pool_example::thread_pool::ThreadPool;
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashMap};
+    use std::hash::Hash;
+
+    // 物品对总是按 (较小, 较大) 存放，(a, b) 和 (b, a) 是同一对
+    type Counts<A> = HashMap<(A, A), usize>;
+
+    // 计数大的排在前面、计数相同时物品小的排在前面，外面再套一层 Reverse 变成小顶堆
+    type TopK<A> = BinaryHeap<Reverse<(usize, Reverse<A>)>>;
+
+    // 按用户分组，同一个用户多次接触同一个物品只算一次；物品排好序，配对时天然满足 a < b
+    fn baskets<U, A>(events: &[(U, A)]) -> Vec<Vec<A>>
+    where
+        U: Hash + Eq + Clone,
+        A: Ord + Clone,
+    {
+        let mut by_user: HashMap<U, Vec<A>> = HashMap::new();
+        for (user, item) in events {
+            by_user.entry(user.clone()).or_default().push(item.clone());
+        }
+        by_user
+            .into_values()
+            .map(|mut items| {
+                items.sort();
+                items.dedup();
+                items
+            })
+            .collect()
+    }
+
+    fn count_pairs<A: Hash + Ord + Clone>(baskets: &[Vec<A>]) -> Counts<A> {
+        let mut counts = HashMap::new();
+        for items in baskets {
+            for (i, a) in items.iter().enumerate() {
+                for b in &items[i + 1..] {
+                    *counts.entry((a.clone(), b.clone())).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    // 每个篮子的配对数是 n(n-1)/2，篮子大小差别很大时按篮子个数平均分块并不均衡，这里保持简单
+    // 提交给线程池的任务必须是 'static 的，不能借用调用方的数据，所以先把篮子按块拿走，每个任务拥有自己那一块
+    fn count_pairs_parallel<A>(pool: &ThreadPool, baskets: Vec<Vec<A>>, parts: usize) -> Counts<A>
+    where
+        A: Hash + Ord + Clone + Send + 'static,
+    {
+        assert!(parts > 0);
+        let chunk = baskets.len().div_ceil(parts).max(1);
+        let mut baskets = baskets.into_iter().peekable();
+        let mut handles = Vec::new();
+        while baskets.peek().is_some() {
+            let part: Vec<Vec<A>> = baskets.by_ref().take(chunk).collect();
+            handles.push(pool.submit(move || count_pairs(&part)));
+        }
+        let partials = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        merge(partials)
+    }
+
+    // 把小的表合并进最大的那张，少做一些插入
+    fn merge<A: Hash + Eq>(mut partials: Vec<Counts<A>>) -> Counts<A> {
+        partials.sort_by_key(|counts| Reverse(counts.len()));
+        let mut partials = partials.into_iter();
+        let mut total = partials.next().unwrap_or_default();
+        for counts in partials {
+            for (pair, n) in counts {
+                *total.entry(pair).or_insert(0) += n;
+            }
+        }
+        total
+    }
+
+    // 每个物品计数最高的 k 个相关物品，计数相同时按物品排序，结果是确定的
+    // 用大小为 k 的小顶堆：堆顶是目前入选的里面最差的一个，新来的更好就挤掉它，整体是 O(n log k)
+    fn top_related<A: Hash + Ord + Clone>(
+        counts: &Counts<A>,
+        k: usize,
+    ) -> HashMap<A, Vec<(A, usize)>> {
+        let mut heaps: HashMap<A, TopK<A>> = HashMap::new();
+        let mut offer = |item: &A, other: &A, n: usize| {
+            let heap = heaps.entry(item.clone()).or_default();
+            heap.push(Reverse((n, Reverse(other.clone()))));
+            if heap.len() > k {
+                heap.pop();
+            }
+        };
+        for ((a, b), &n) in counts {
+            offer(a, b, n);
+            offer(b, a, n);
+        }
+        heaps
+            .into_iter()
+            .map(|(item, heap)| {
+                // Reverse 的升序就是原来的降序
+                let related = heap
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|Reverse((n, Reverse(other)))| (other, n))
+                    .collect();
+                (item, related)
+            })
+            .collect()
+    }
+
+    fn event_log() -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("alice", "rust-book"),
+            ("alice", "tokio-guide"),
+            ("alice", "rust-book"),
+            ("alice", "serde-cookbook"),
+            ("bob", "rust-book"),
+            ("bob", "tokio-guide"),
+            ("carol", "rust-book"),
+            ("carol", "serde-cookbook"),
+            ("dave", "go-book"),
+            ("dave", "tokio-guide"),
+            ("erin", "rust-book"),
+        ]
+    }
+
+    #[test]
+    fn counts_co_occurring_items() {
+        let counts = count_pairs(&baskets(&event_log()));
+        assert_eq!(counts[&("rust-book", "tokio-guide")], 2);
+        assert_eq!(counts[&("rust-book", "serde-cookbook")], 2);
+        assert_eq!(counts[&("serde-cookbook", "tokio-guide")], 1);
+        assert_eq!(counts[&("go-book", "tokio-guide")], 1);
+        // alice 看了两次 rust-book，不会和自己配对；erin 只看了一本，没有配对
+        assert_eq!(counts.len(), 4);
+    }
+
+    #[test]
+    fn top_k_related_items() {
+        let counts = count_pairs(&baskets(&event_log()));
+        let related = top_related(&counts, 2);
+        // 计数相同的按名字排序
+        assert_eq!(
+            related["rust-book"],
+            [("serde-cookbook", 2), ("tokio-guide", 2)]
+        );
+        assert_eq!(related["tokio-guide"], [("rust-book", 2), ("go-book", 1)]);
+        assert_eq!(related["go-book"], [("tokio-guide", 1)]);
+        assert!(!related.contains_key("erin"));
+        assert!(top_related(&counts, 0).values().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn parallel_counting_matches_sequential() {
+        // 用一个简单的线性同余生成器造数据，结果可以重现
+        let mut seed = 42u64;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 33) % bound
+        };
+        let events: Vec<(u64, u64)> = (0..20_000).map(|_| (next(500), next(200))).collect();
+        let baskets = baskets(&events);
+        let sequential = count_pairs(&baskets);
+        let pool = ThreadPool::new(4);
+        for parts in [1, 3, 8, 1000] {
+            assert_eq!(
+                count_pairs_parallel(&pool, baskets.clone(), parts),
+                sequential
+            );
+        }
+        assert!(count_pairs_parallel::<u64>(&pool, Vec::new(), 4).is_empty());
+    }
+}
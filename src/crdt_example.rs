@@ -0,0 +1,288 @@
+// CRDT（无冲突复制数据类型）：每个副本各自修改，随时两两交换状态合并，最终所有副本收敛到同一个值
+// 合并函数只要满足交换律、结合律、幂等律，消息乱序、重复、延迟都不影响结果，不需要协调
+// G-Counter 是只增的计数器；LWW-Register 是"最后写入者胜"的寄存器，用向量时钟判断写入的先后
+#[cfg(test)]
+mod tests {
+
+    use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+
+    // 向量时钟：每个节点一个计数，节点每发生一个事件就把自己的计数加一
+    // 两个时钟逐项比较，全部 <= 说明前者发生在后者之前；互有大小说明两个事件是并发的，谁也不知道谁
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    struct VectorClock(BTreeMap<String, u64>);
+
+    impl VectorClock {
+        fn tick(&mut self, node: &str) {
+            *self.0.entry(node.to_string()).or_insert(0) += 1;
+        }
+
+        fn get(&self, node: &str) -> u64 {
+            self.0.get(node).copied().unwrap_or(0)
+        }
+
+        // 逐项取最大值
+        fn merge(&mut self, other: &VectorClock) {
+            for (node, &count) in &other.0 {
+                let entry = self.0.entry(node.clone()).or_insert(0);
+                *entry = (*entry).max(count);
+            }
+        }
+
+        // 偏序：并发时返回 None
+        fn compare(&self, other: &VectorClock) -> Option<Ordering> {
+            let nodes = self.0.keys().chain(other.0.keys());
+            let (mut less, mut greater) = (false, false);
+            for node in nodes {
+                match self.get(node).cmp(&other.get(node)) {
+                    Ordering::Less => less = true,
+                    Ordering::Greater => greater = true,
+                    Ordering::Equal => {}
+                }
+            }
+            match (less, greater) {
+                (false, false) => Some(Ordering::Equal),
+                (true, false) => Some(Ordering::Less),
+                (false, true) => Some(Ordering::Greater),
+                (true, true) => None,
+            }
+        }
+
+        fn concurrent(&self, other: &VectorClock) -> bool {
+            self.compare(other).is_none()
+        }
+
+        // 所有计数之和。a 发生在 b 之前时 a 的和一定更小，所以按和排序不会违反因果顺序
+        fn total(&self) -> u64 {
+            self.0.values().sum()
+        }
+    }
+
+    // G-Counter：每个节点只增加自己那一项，值是所有项之和；合并逐项取最大值
+    // 同一个节点的项只由它自己增加，取最大值就是取最新的那次，不会重复计算
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct GCounter(BTreeMap<String, u64>);
+
+    impl GCounter {
+        fn increment(&mut self, node: &str, by: u64) {
+            *self.0.entry(node.to_string()).or_insert(0) += by;
+        }
+
+        fn value(&self) -> u64 {
+            self.0.values().sum()
+        }
+
+        fn merge(&mut self, other: &GCounter) {
+            for (node, &count) in &other.0 {
+                let entry = self.0.entry(node.clone()).or_insert(0);
+                *entry = (*entry).max(count);
+            }
+        }
+    }
+
+    // LWW-Register：每次写入带上写入者和一个向量时钟，新的时钟在当前值的时钟之后再加一，所以覆盖的写入一定"更晚"
+    // 合并保留更晚的那次写入。因果上有先后的按先后；并发的写入按 (时钟之和, 写入者) 决出胜负，
+    // 这是一个和因果顺序一致的全序，所以合并就是取最大值，三条定律自然成立
+    #[derive(Clone, Debug, PartialEq)]
+    struct LwwRegister<T> {
+        value: Option<T>,
+        writer: String,
+        clock: VectorClock,
+    }
+
+    impl<T: Clone> LwwRegister<T> {
+        fn new() -> LwwRegister<T> {
+            LwwRegister {
+                value: None,
+                writer: String::new(),
+                clock: VectorClock::default(),
+            }
+        }
+
+        fn set(&mut self, node: &str, value: T) {
+            self.clock.tick(node);
+            self.writer = node.to_string();
+            self.value = Some(value);
+        }
+
+        fn get(&self) -> Option<&T> {
+            self.value.as_ref()
+        }
+
+        fn key(&self) -> (u64, &str) {
+            (self.clock.total(), &self.writer)
+        }
+
+        // 返回两次写入是否并发，也就是是否有一方的写入被另一方在不知情的情况下覆盖了
+        fn merge(&mut self, other: &LwwRegister<T>) -> bool {
+            let conflict = self.clock.concurrent(&other.clock);
+            if other.key() > self.key() {
+                *self = other.clone();
+            }
+            conflict
+        }
+    }
+
+    // 测试用的线性同余发生器，结果可以复现
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+    }
+
+    const NODES: [&str; 3] = ["a", "b", "c"];
+
+    // 三个副本各自做一些随机修改，中间随机地互相合并，得到的状态用来检查合并的性质
+    fn random_counters(rng: &mut Lcg) -> Vec<GCounter> {
+        let mut replicas = vec![GCounter::default(); 3];
+        for _ in 0..rng.next() % 20 {
+            let i = (rng.next() % 3) as usize;
+            if rng.next().is_multiple_of(4) {
+                let from = replicas[(rng.next() % 3) as usize].clone();
+                replicas[i].merge(&from);
+            } else {
+                replicas[i].increment(NODES[i], rng.next() % 10);
+            }
+        }
+        replicas
+    }
+
+    fn random_registers(rng: &mut Lcg) -> Vec<LwwRegister<u64>> {
+        let mut replicas = vec![LwwRegister::new(); 3];
+        for _ in 0..rng.next() % 20 {
+            let i = (rng.next() % 3) as usize;
+            if rng.next().is_multiple_of(3) {
+                let from = replicas[(rng.next() % 3) as usize].clone();
+                replicas[i].merge(&from);
+            } else {
+                replicas[i].set(NODES[i], rng.next() % 100);
+            }
+        }
+        replicas
+    }
+
+    // 检查 merge 的交换律、结合律和幂等律
+    fn check_laws<T: Clone + PartialEq + std::fmt::Debug>(
+        x: &T,
+        y: &T,
+        z: &T,
+        merge: fn(&mut T, &T),
+    ) {
+        let merged = |a: &T, b: &T| {
+            let mut a = a.clone();
+            merge(&mut a, b);
+            a
+        };
+        assert_eq!(merged(x, y), merged(y, x));
+        assert_eq!(merged(&merged(x, y), z), merged(x, &merged(y, z)));
+        assert_eq!(merged(x, x), *x);
+    }
+
+    #[test]
+    fn vector_clock_ordering() {
+        let mut a = VectorClock::default();
+        a.tick("a");
+        let mut b = a.clone();
+        b.tick("b");
+        assert_eq!(a.compare(&b), Some(Ordering::Less));
+        assert_eq!(b.compare(&a), Some(Ordering::Greater));
+        assert_eq!(a.compare(&a.clone()), Some(Ordering::Equal));
+        // a 和 b 各自往前走，互相不知道对方的事件
+        a.tick("a");
+        assert!(a.concurrent(&b));
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert_eq!((merged.get("a"), merged.get("b")), (2, 1));
+        assert_eq!(a.compare(&merged), Some(Ordering::Less));
+        assert_eq!(b.compare(&merged), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn merge_laws_hold_for_random_states() {
+        let mut rng = Lcg(2024);
+        for _ in 0..500 {
+            let counters = random_counters(&mut rng);
+            check_laws(&counters[0], &counters[1], &counters[2], GCounter::merge);
+            let registers = random_registers(&mut rng);
+            check_laws(&registers[0], &registers[1], &registers[2], |a, b| {
+                a.merge(b);
+            });
+            let clocks: Vec<VectorClock> = registers.iter().map(|r| r.clock.clone()).collect();
+            check_laws(&clocks[0], &clocks[1], &clocks[2], VectorClock::merge);
+        }
+    }
+
+    #[test]
+    fn replicas_converge_in_any_merge_order() {
+        let mut rng = Lcg(7);
+        for _ in 0..200 {
+            let counters = random_counters(&mut rng);
+            let registers = random_registers(&mut rng);
+            // 每个副本按不同的顺序收到其他副本的状态，最后都一样
+            let orders = [[0, 1, 2], [2, 0, 1], [1, 2, 0], [2, 1, 0]];
+            let counted: Vec<GCounter> = orders
+                .iter()
+                .map(|order| {
+                    let mut replica = GCounter::default();
+                    for &i in order {
+                        replica.merge(&counters[i]);
+                    }
+                    replica
+                })
+                .collect();
+            assert!(counted.iter().all(|c| c == &counted[0]));
+            let expected: u64 = NODES
+                .iter()
+                .map(|node| {
+                    counters
+                        .iter()
+                        .map(|c| c.0.get(*node).copied().unwrap_or(0))
+                        .max()
+                        .unwrap()
+                })
+                .sum();
+            assert_eq!(counted[0].value(), expected);
+
+            let written: Vec<LwwRegister<u64>> = orders
+                .iter()
+                .map(|order| {
+                    let mut replica = LwwRegister::new();
+                    for &i in order {
+                        replica.merge(&registers[i]);
+                    }
+                    replica
+                })
+                .collect();
+            assert!(written.iter().all(|r| r == &written[0]));
+        }
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let mut a = LwwRegister::new();
+        a.set("a", "draft");
+        let mut b = a.clone();
+        // b 看到了 a 的写入之后再写，因果上更晚，不管时钟之和、节点名怎样都是 b 胜出
+        b.set("b", "final");
+        assert!(!a.clone().merge(&b));
+        a.merge(&b);
+        assert_eq!(a.get(), Some(&"final"));
+
+        // 两边在同样的状态上并发写入：报告冲突，两边合并后选同一个胜者
+        let mut left = a.clone();
+        let mut right = a.clone();
+        left.set("a", "left");
+        right.set("b", "right");
+        let snapshot = left.clone();
+        assert!(left.merge(&right));
+        assert!(right.merge(&snapshot));
+        assert_eq!(left, right);
+        assert_eq!(left.get(), Some(&"right"));
+    }
+}
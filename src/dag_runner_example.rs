@@ -0,0 +1,532 @@
+// 工作流（DAG）执行器：每个任务声明自己依赖哪些任务，执行器先做拓扑排序检查依赖是否存在、有没有环，
+// 然后把所有依赖都已完成的任务交给线程池并行执行。任务失败时按设置的次数重试，仍然失败的话，
+// 所有直接或间接依赖它的任务都跳过，互不相关的分支照常执行。最后输出一份执行报告
+#[cfg(test)]
+pub(crate) mod dag_runner {
+
+    use crate::thread_pool_example::thread_pool::{panic_message, ThreadPool};
+    use std::collections::{HashMap, VecDeque};
+    use std::error::Error;
+    use std::fmt;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{mpsc, Arc};
+    use std::time::{Duration, Instant};
+
+    type TaskFn = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+    pub(crate) struct Task {
+        name: String,
+        deps: Vec<String>,
+        retries: u32,
+        run: TaskFn,
+    }
+
+    impl Task {
+        // 任务可能被重试，所以是 Fn 而不是 FnOnce
+        pub(crate) fn new<F>(name: &str, run: F) -> Task
+        where
+            F: Fn() -> Result<(), String> + Send + Sync + 'static,
+        {
+            Task {
+                name: name.to_string(),
+                deps: Vec::new(),
+                retries: 0,
+                run: Arc::new(run),
+            }
+        }
+
+        pub(crate) fn after(mut self, deps: &[&str]) -> Task {
+            self.deps.extend(deps.iter().map(|dep| dep.to_string()));
+            self
+        }
+
+        // 第一次失败之后最多再试 retries 次
+        pub(crate) fn retries(mut self, retries: u32) -> Task {
+            self.retries = retries;
+            self
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum DagError {
+        Duplicate(String),
+        UnknownDependency { task: String, dep: String },
+        // 环上的任务，按依赖的方向排列，首尾相连
+        Cycle(Vec<String>),
+    }
+
+    impl fmt::Display for DagError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                DagError::Duplicate(name) => write!(f, "task {} is defined twice", name),
+                DagError::UnknownDependency { task, dep } => {
+                    write!(f, "task {} depends on unknown task {}", task, dep)
+                }
+                DagError::Cycle(names) => write!(f, "dependency cycle: {}", names.join(" -> ")),
+            }
+        }
+    }
+
+    impl Error for DagError {}
+
+    #[derive(Default)]
+    pub(crate) struct Dag {
+        tasks: Vec<Task>,
+    }
+
+    impl Dag {
+        pub(crate) fn new() -> Dag {
+            Dag::default()
+        }
+
+        pub(crate) fn add(&mut self, task: Task) -> &mut Dag {
+            self.tasks.push(task);
+            self
+        }
+
+        // 每个任务依赖的任务的下标
+        fn resolve(&self) -> Result<Vec<Vec<usize>>, DagError> {
+            let mut index = HashMap::new();
+            for (i, task) in self.tasks.iter().enumerate() {
+                if index.insert(task.name.as_str(), i).is_some() {
+                    return Err(DagError::Duplicate(task.name.clone()));
+                }
+            }
+            self.tasks
+                .iter()
+                .map(|task| {
+                    task.deps
+                        .iter()
+                        .map(|dep| {
+                            index.get(dep.as_str()).copied().ok_or_else(|| {
+                                DagError::UnknownDependency {
+                                    task: task.name.clone(),
+                                    dep: dep.clone(),
+                                }
+                            })
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+
+        // 依赖的下标和拓扑顺序，环上的任务换成名字报告
+        fn sorted(&self) -> Result<(Vec<Vec<usize>>, Vec<usize>), DagError> {
+            let deps = self.resolve()?;
+            match order(&deps) {
+                Ok(order) => Ok((deps, order)),
+                Err(cycle) => Err(DagError::Cycle(
+                    cycle.iter().map(|&i| self.tasks[i].name.clone()).collect(),
+                )),
+            }
+        }
+
+        pub(crate) fn topological_order(&self) -> Result<Vec<&str>, DagError> {
+            let (_, order) = self.sorted()?;
+            Ok(order.iter().map(|&i| self.tasks[i].name.as_str()).collect())
+        }
+
+        // 先检查整张图，有错误时一个任务都不执行
+        pub(crate) fn run(&self, pool: &ThreadPool) -> Result<Report, DagError> {
+            let (deps, order) = self.sorted()?;
+            let dependents = dependents(&deps);
+            // 每个任务还有几个依赖没有完成，减到 0 时进入 ready
+            let mut waiting: Vec<usize> = deps.iter().map(Vec::len).collect();
+
+            let started = Instant::now();
+            let mut results: Vec<Option<TaskReport>> =
+                (0..self.tasks.len()).map(|_| None).collect();
+            let (done, finished) = mpsc::channel();
+            let mut running = 0;
+            let mut ready: VecDeque<usize> =
+                order.iter().copied().filter(|&i| waiting[i] == 0).collect();
+            loop {
+                while let Some(i) = ready.pop_front() {
+                    let task = &self.tasks[i];
+                    let (run, retries, done) = (Arc::clone(&task.run), task.retries, done.clone());
+                    let offset = started.elapsed();
+                    // 线程池的队列是有界的，满了就阻塞在这里，正好限制同时排队的任务数
+                    pool.execute(move || {
+                        let (attempts, result) = attempt(&*run, retries);
+                        let _ =
+                            done.send((i, offset, started.elapsed() - offset, attempts, result));
+                    })
+                    .expect("thread pool rejected a task");
+                    running += 1;
+                }
+                if running == 0 {
+                    break;
+                }
+                let (i, offset, elapsed, attempts, result) = finished.recv().unwrap();
+                running -= 1;
+                let succeeded = result.is_ok();
+                results[i] = Some(TaskReport {
+                    name: self.tasks[i].name.clone(),
+                    status: match result {
+                        Ok(()) => Status::Succeeded,
+                        Err(message) => Status::Failed(message),
+                    },
+                    attempts,
+                    started: offset,
+                    elapsed,
+                });
+                if succeeded {
+                    for &next in &dependents[i] {
+                        waiting[next] -= 1;
+                        if waiting[next] == 0 {
+                            ready.push_back(next);
+                        }
+                    }
+                }
+            }
+
+            // 依赖失败（或者依赖被跳过）的任务从来没有进入 ready，按拓扑顺序补上跳过的记录
+            let tasks = order
+                .into_iter()
+                .map(|i| {
+                    results[i].take().unwrap_or_else(|| TaskReport {
+                        name: self.tasks[i].name.clone(),
+                        status: Status::Skipped,
+                        attempts: 0,
+                        started: Duration::ZERO,
+                        elapsed: Duration::ZERO,
+                    })
+                })
+                .collect();
+            Ok(Report {
+                tasks,
+                elapsed: started.elapsed(),
+            })
+        }
+    }
+
+    // 反过来：每个任务被哪些任务依赖
+    fn dependents(deps: &[Vec<usize>]) -> Vec<Vec<usize>> {
+        let mut dependents = vec![Vec::new(); deps.len()];
+        for (i, task_deps) in deps.iter().enumerate() {
+            for &dep in task_deps {
+                dependents[dep].push(i);
+            }
+        }
+        dependents
+    }
+
+    // Kahn 算法：反复取出入度为 0（依赖都已排好）的任务。同时可以取的任务按加入的顺序，结果是确定的
+    // 取不完说明剩下的任务都在环上或者依赖环上的任务，这时找出一个具体的环作为错误返回
+    fn order(deps: &[Vec<usize>]) -> Result<Vec<usize>, Vec<usize>> {
+        let dependents = dependents(deps);
+        let mut waiting: Vec<usize> = deps.iter().map(Vec::len).collect();
+        let mut ready: VecDeque<usize> = (0..deps.len()).filter(|&i| waiting[i] == 0).collect();
+        let mut order = Vec::with_capacity(deps.len());
+        while let Some(i) = ready.pop_front() {
+            order.push(i);
+            for &next in &dependents[i] {
+                waiting[next] -= 1;
+                if waiting[next] == 0 {
+                    ready.push_back(next);
+                }
+            }
+        }
+        if order.len() == deps.len() {
+            return Ok(order);
+        }
+        // 剩下的每个任务都至少有一个依赖也在剩下的任务里，顺着这样的依赖一直走，必然会回到走过的任务
+        let mut path = vec![(0..deps.len()).find(|&i| waiting[i] > 0).unwrap()];
+        loop {
+            let last = *path.last().unwrap();
+            let next = *deps[last].iter().find(|&&dep| waiting[dep] > 0).unwrap();
+            if let Some(at) = path.iter().position(|&i| i == next) {
+                // 依赖的方向是 path 的反方向，反过来变成“先执行的在前”
+                let mut cycle: Vec<usize> = path[at..].to_vec();
+                cycle.reverse();
+                // 从最早加入的任务开始写，同一个环每次报告的都一样
+                let first = cycle.iter().enumerate().min_by_key(|(_, &i)| i).unwrap().0;
+                cycle.rotate_left(first);
+                cycle.push(cycle[0]);
+                return Err(cycle);
+            }
+            path.push(next);
+        }
+    }
+
+    // 执行一个任务，失败（返回 Err 或者 panic）时重试，返回尝试的次数和最后一次的结果
+    // panic 在这里接住，否则线程池虽然能活下来，执行器却永远等不到这个任务的结果
+    fn attempt(
+        run: &(dyn Fn() -> Result<(), String> + Send + Sync),
+        retries: u32,
+    ) -> (u32, Result<(), String>) {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = panic::catch_unwind(AssertUnwindSafe(run))
+                .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_message(&*payload))));
+            if result.is_ok() || attempts > retries {
+                return (attempts, result);
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum Status {
+        Succeeded,
+        Failed(String),
+        // 依赖的任务失败了，没有执行
+        Skipped,
+    }
+
+    pub(crate) struct TaskReport {
+        pub(crate) name: String,
+        pub(crate) status: Status,
+        pub(crate) attempts: u32,
+        // 相对整个工作流开始的时间，用来看哪些任务是同时执行的
+        pub(crate) started: Duration,
+        pub(crate) elapsed: Duration,
+    }
+
+    // 任务按拓扑顺序排列
+    pub(crate) struct Report {
+        pub(crate) tasks: Vec<TaskReport>,
+        pub(crate) elapsed: Duration,
+    }
+
+    impl Report {
+        pub(crate) fn task(&self, name: &str) -> Option<&TaskReport> {
+            self.tasks.iter().find(|task| task.name == name)
+        }
+
+        pub(crate) fn succeeded(&self) -> bool {
+            self.tasks
+                .iter()
+                .all(|task| task.status == Status::Succeeded)
+        }
+    }
+
+    // 每个任务一行，最后一行是汇总
+    impl fmt::Display for Report {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let width = self
+                .tasks
+                .iter()
+                .map(|task| task.name.len())
+                .max()
+                .unwrap_or(0)
+                .max(4);
+            writeln!(
+                f,
+                "{:<width$}  {:<7}  {:>8}  {:>10}  {:>10}",
+                "task", "status", "attempts", "started", "elapsed"
+            )?;
+            let (mut ok, mut failed, mut skipped) = (0, 0, 0);
+            for task in &self.tasks {
+                let status = match &task.status {
+                    Status::Succeeded => {
+                        ok += 1;
+                        "ok"
+                    }
+                    Status::Failed(_) => {
+                        failed += 1;
+                        "failed"
+                    }
+                    Status::Skipped => {
+                        skipped += 1;
+                        "skipped"
+                    }
+                };
+                write!(
+                    f,
+                    "{:<width$}  {:<7}  {:>8}  {:>10}  {:>10}",
+                    task.name,
+                    status,
+                    task.attempts,
+                    format!("{:.1?}", task.started),
+                    format!("{:.1?}", task.elapsed)
+                )?;
+                if let Status::Failed(message) = &task.status {
+                    write!(f, "  {}", message)?;
+                }
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{} succeeded, {} failed, {} skipped in {:.1?}",
+                ok, failed, skipped, self.elapsed
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::dag_runner::*;
+    use crate::thread_pool_example::thread_pool::ThreadPool;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::time::Duration;
+
+    // 记录任务的执行顺序
+    fn recorder(log: &Arc<Mutex<Vec<String>>>, name: &str) -> Task {
+        let log = Arc::clone(log);
+        let entry = name.to_string();
+        Task::new(name, move || {
+            log.lock().unwrap().push(entry.clone());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn topological_order_and_errors() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut dag = Dag::new();
+        dag.add(recorder(&log, "report").after(&["join"]))
+            .add(recorder(&log, "fetch-users"))
+            .add(recorder(&log, "join").after(&["clean-orders", "fetch-users"]))
+            .add(recorder(&log, "fetch-orders"))
+            .add(recorder(&log, "clean-orders").after(&["fetch-orders"]));
+        assert_eq!(
+            dag.topological_order().unwrap(),
+            [
+                "fetch-users",
+                "fetch-orders",
+                "clean-orders",
+                "join",
+                "report"
+            ]
+        );
+
+        dag.add(recorder(&log, "publish").after(&["upload"]));
+        assert_eq!(
+            dag.topological_order().unwrap_err().to_string(),
+            "task publish depends on unknown task upload"
+        );
+
+        let mut dag = Dag::new();
+        dag.add(recorder(&log, "a"))
+            .add(recorder(&log, "b").after(&["a", "d"]))
+            .add(recorder(&log, "c").after(&["b"]))
+            .add(recorder(&log, "d").after(&["c"]))
+            .add(recorder(&log, "e").after(&["d"]));
+        // e 依赖环上的任务，但不在环上
+        assert_eq!(
+            dag.topological_order().unwrap_err(),
+            DagError::Cycle(vec!["b".into(), "c".into(), "d".into(), "b".into()])
+        );
+        // 有环时一个任务都不执行
+        assert!(dag.run(&ThreadPool::new(2)).is_err());
+        assert!(log.lock().unwrap().is_empty());
+
+        let mut dag = Dag::new();
+        dag.add(recorder(&log, "a")).add(recorder(&log, "a"));
+        assert_eq!(
+            dag.topological_order().unwrap_err(),
+            DagError::Duplicate("a".into())
+        );
+    }
+
+    #[test]
+    fn runs_independent_tasks_in_parallel() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        // 两个抓取任务互相等待对方开始，只有同时执行才能都通过屏障
+        let barrier = Arc::new(Barrier::new(2));
+        let fetch = |name: &str| {
+            let (log, barrier, entry) = (Arc::clone(&log), Arc::clone(&barrier), name.to_string());
+            Task::new(name, move || {
+                barrier.wait();
+                log.lock().unwrap().push(entry.clone());
+                Ok(())
+            })
+        };
+        let mut dag = Dag::new();
+        dag.add(recorder(&log, "report").after(&["join"]))
+            .add(recorder(&log, "join").after(&["fetch-orders", "fetch-users"]))
+            .add(fetch("fetch-users"))
+            .add(fetch("fetch-orders"));
+        let report = dag.run(&ThreadPool::new(2)).unwrap();
+        assert!(report.succeeded());
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[2..], ["join", "report"]);
+        assert_eq!(
+            report
+                .tasks
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            ["fetch-users", "fetch-orders", "join", "report"]
+        );
+        // 依赖都完成之后才开始
+        let join = report.task("join").unwrap();
+        for fetch in ["fetch-users", "fetch-orders"] {
+            let fetch = report.task(fetch).unwrap();
+            assert!(fetch.started + fetch.elapsed <= join.started);
+        }
+    }
+
+    #[test]
+    fn retries_and_skips_dependents_of_failures() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        // 前两次失败，第三次成功
+        let calls = Arc::new(AtomicU32::new(0));
+        let flaky = {
+            let calls = Arc::clone(&calls);
+            Task::new("flaky", move || {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(String::from("connection reset"))
+                } else {
+                    Ok(())
+                }
+            })
+        };
+        let mut dag = Dag::new();
+        dag.add(flaky.retries(2))
+            .add(Task::new("broken", || Err(String::from("disk full"))).retries(1))
+            .add(Task::new("panics", || panic!("index out of bounds")))
+            .add(recorder(&log, "after-broken").after(&["broken"]))
+            .add(recorder(&log, "after-skipped").after(&["after-broken"]))
+            .add(recorder(&log, "after-flaky").after(&["flaky"]));
+        let report = dag.run(&ThreadPool::new(2)).unwrap();
+        assert!(!report.succeeded());
+
+        let flaky = report.task("flaky").unwrap();
+        assert_eq!((&flaky.status, flaky.attempts), (&Status::Succeeded, 3));
+        let broken = report.task("broken").unwrap();
+        assert_eq!(
+            (&broken.status, broken.attempts),
+            (&Status::Failed("disk full".into()), 2)
+        );
+        let panics = report.task("panics").unwrap();
+        assert_eq!(
+            panics.status,
+            Status::Failed("panicked: index out of bounds".into())
+        );
+        // 失败的任务下游全部跳过，间接依赖的也一样；不相关的分支照常执行
+        assert_eq!(report.task("after-broken").unwrap().status, Status::Skipped);
+        assert_eq!(
+            report.task("after-skipped").unwrap().status,
+            Status::Skipped
+        );
+        assert_eq!(*log.lock().unwrap(), ["after-flaky"]);
+
+        let rendered = report.to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(
+            lines[0].starts_with("task           status   attempts"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[2].starts_with("broken         failed          2"),
+            "{}",
+            lines[2]
+        );
+        assert!(lines[2].ends_with("  disk full"), "{}", lines[2]);
+        assert!(
+            lines[7].starts_with("2 succeeded, 2 failed, 2 skipped in "),
+            "{}",
+            lines[7]
+        );
+        assert!(report.elapsed < Duration::from_secs(5));
+    }
+}
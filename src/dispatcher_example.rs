@@ -0,0 +1,284 @@
+// 按优先级调度的异步任务队列：提交的 future 带一个优先级，同一时刻最多运行 concurrency 个，
+// 有空位时总是先放行优先级最高的（BinaryHeap），同优先级先提交的先放行
+// 只按优先级排，源源不断的高优先级任务会让低优先级任务永远等下去（饥饿），所以加上老化：
+// 每放行一个任务，还在排队的任务就"老"一点，每老 aging 次相当于升一级优先级
+#[cfg(test)]
+pub(crate) mod dispatcher {
+
+    use std::collections::BinaryHeap;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use tokio::runtime::Handle;
+    use tokio::sync::oneshot;
+
+    type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    // 老化用放行的次数作为时钟，而不是真实时间，测试里的顺序是确定的
+    // 一个任务的分数是 priority * aging - 提交时的放行次数。两个任务比较时，"现在"的放行次数在两边相减抵消了，
+    // 所以分数在排队期间不用更新，可以直接放进堆里
+    struct Pending {
+        score: i128,
+        seq: u64,
+        future: BoxFuture,
+    }
+
+    impl PartialEq for Pending {
+        fn eq(&self, other: &Pending) -> bool {
+            self.cmp(other) == std::cmp::Ordering::Equal
+        }
+    }
+
+    impl Eq for Pending {}
+
+    impl PartialOrd for Pending {
+        fn partial_cmp(&self, other: &Pending) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    // 分数高的在堆顶；分数相同时 seq 小（先提交）的在堆顶
+    impl Ord for Pending {
+        fn cmp(&self, other: &Pending) -> std::cmp::Ordering {
+            self.score.cmp(&other.score).then(other.seq.cmp(&self.seq))
+        }
+    }
+
+    struct State {
+        pending: BinaryHeap<Pending>,
+        running: usize,
+        admitted: u64,
+        next_seq: u64,
+    }
+
+    struct Shared {
+        handle: Handle,
+        concurrency: usize,
+        // 等待多少次放行相当于升一级，None 表示不老化，严格按优先级
+        aging: Option<u64>,
+        state: Mutex<State>,
+    }
+
+    // 克隆出来的 Dispatcher 共享同一个队列，任务里也可以再提交任务
+    #[derive(Clone)]
+    pub(crate) struct Dispatcher {
+        shared: Arc<Shared>,
+    }
+
+    impl Dispatcher {
+        // 任务在 handle 所属的运行时上执行，所以在运行时外面也可以提交
+        pub(crate) fn new(handle: Handle, concurrency: usize, aging: Option<u64>) -> Dispatcher {
+            assert!(concurrency > 0);
+            assert!(aging != Some(0));
+            Dispatcher {
+                shared: Arc::new(Shared {
+                    handle,
+                    concurrency,
+                    aging,
+                    state: Mutex::new(State {
+                        pending: BinaryHeap::new(),
+                        running: 0,
+                        admitted: 0,
+                        next_seq: 0,
+                    }),
+                }),
+            }
+        }
+
+        // 返回的接收端在任务完成时得到结果；任务 panic 时发送端被丢弃，接收端得到 RecvError
+        pub(crate) fn submit<F, T>(&self, priority: u32, future: F) -> oneshot::Receiver<T>
+        where
+            F: Future<Output = T> + Send + 'static,
+            T: Send + 'static,
+        {
+            let (sender, receiver) = oneshot::channel();
+            let future = Box::pin(async move {
+                let _ = sender.send(future.await);
+            });
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                // 不老化时一级优先级的分量比任何放行次数都大，老化项只在同优先级之间起作用，相当于先来先服务
+                let level = self.shared.aging.unwrap_or(u64::MAX) as i128;
+                let score = priority as i128 * level - state.admitted as i128;
+                let seq = state.next_seq;
+                state.next_seq += 1;
+                state.pending.push(Pending { score, seq, future });
+            }
+            Dispatcher::pump(&self.shared);
+            receiver
+        }
+
+        // 排队的任务数和正在运行的任务数
+        pub(crate) fn load(&self) -> (usize, usize) {
+            let state = self.shared.state.lock().unwrap();
+            (state.pending.len(), state.running)
+        }
+
+        // 有空位就从堆顶取任务放行。在锁里只做出队和计数，spawn 放在锁外
+        fn pump(shared: &Arc<Shared>) {
+            let mut ready = Vec::new();
+            {
+                let mut state = shared.state.lock().unwrap();
+                while state.running < shared.concurrency {
+                    let Some(pending) = state.pending.pop() else {
+                        break;
+                    };
+                    state.running += 1;
+                    state.admitted += 1;
+                    ready.push(pending.future);
+                }
+            }
+            for future in ready {
+                let slot = Slot {
+                    shared: Arc::clone(shared),
+                };
+                shared.handle.spawn(async move {
+                    future.await;
+                    drop(slot);
+                });
+            }
+        }
+    }
+
+    // 占着的一个运行名额。任务结束或者 panic（栈展开时丢弃）都会归还名额并放行下一个
+    struct Slot {
+        shared: Arc<Shared>,
+    }
+
+    impl Drop for Slot {
+        fn drop(&mut self) {
+            self.shared.state.lock().unwrap().running -= 1;
+            Dispatcher::pump(&self.shared);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::dispatcher::*;
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    // 先提交一个等待 gate 的任务占住唯一的名额，后面提交的任务都只能排队，放开 gate 之后按调度的顺序逐个运行
+    fn gated(dispatcher: &Dispatcher) -> oneshot::Sender<()> {
+        let (gate, wait) = oneshot::channel::<()>();
+        dispatcher.submit(0, async move {
+            let _ = wait.await;
+        });
+        gate
+    }
+
+    #[test]
+    fn higher_priorities_are_admitted_first() {
+        let rt = runtime(Preset::SingleThreadTest);
+        let dispatcher = Dispatcher::new(rt.handle().clone(), 1, None);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let gate = gated(&dispatcher);
+        let receivers: Vec<_> = [(1, "a"), (5, "b"), (3, "c"), (5, "d"), (0, "e")]
+            .into_iter()
+            .map(|(priority, name)| {
+                let order = Arc::clone(&order);
+                dispatcher.submit(priority, async move {
+                    order.lock().unwrap().push(name);
+                    name.len()
+                })
+            })
+            .collect();
+        assert_eq!(dispatcher.load(), (5, 1));
+        gate.send(()).unwrap();
+        rt.block_on(async {
+            for receiver in receivers {
+                assert_eq!(receiver.await, Ok(1));
+            }
+        });
+        // 同优先级的 b、d 按提交的顺序
+        assert_eq!(*order.lock().unwrap(), ["b", "d", "c", "a", "e"]);
+        assert_eq!(dispatcher.load(), (0, 0));
+    }
+
+    #[test]
+    fn concurrency_is_bounded() {
+        let rt = runtime(Preset::IoHeavy);
+        let dispatcher = Dispatcher::new(rt.handle().clone(), 3, Some(4));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let receivers: Vec<_> = (0..20)
+            .map(|i| {
+                let (running, most) = (Arc::clone(&running), Arc::clone(&most));
+                dispatcher.submit(i % 4, async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i * 2
+                })
+            })
+            .collect();
+        // 一个 panic 的任务也会归还名额
+        let panicked = dispatcher.submit(9, async { panic!("task exploded") });
+        let results = rt.block_on(async {
+            let mut results = Vec::new();
+            for receiver in receivers {
+                results.push(receiver.await.unwrap());
+            }
+            results
+        });
+        assert_eq!(results, (0..20).map(|i| i * 2).collect::<Vec<_>>());
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+        assert!(rt.block_on(panicked).is_err());
+    }
+
+    // 低优先级任务排着队，高优先级任务一个接一个地到来（每个运行时再提交下一个），返回运行的顺序
+    fn starve(aging: Option<u64>) -> Vec<String> {
+        let rt = runtime(Preset::SingleThreadTest);
+        let dispatcher = Dispatcher::new(rt.handle().clone(), 1, aging);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let gate = gated(&dispatcher);
+        let low = {
+            let order = Arc::clone(&order);
+            dispatcher.submit(
+                0,
+                async move { order.lock().unwrap().push(String::from("low")) },
+            )
+        };
+
+        fn high(dispatcher: Dispatcher, order: Arc<Mutex<Vec<String>>>, i: usize) {
+            let next = dispatcher.clone();
+            dispatcher.submit(5, async move {
+                order.lock().unwrap().push(format!("high{}", i));
+                if i < 7 {
+                    high(next, order, i + 1);
+                }
+            });
+        }
+        high(dispatcher.clone(), Arc::clone(&order), 0);
+        gate.send(()).unwrap();
+        rt.block_on(async {
+            low.await.unwrap();
+            while dispatcher.load() != (0, 0) {
+                tokio::task::yield_now().await;
+            }
+        });
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    #[test]
+    fn aging_prevents_starvation() {
+        // 不老化：只要还有高优先级任务，低优先级任务就一直等到最后
+        let strict = starve(None);
+        assert_eq!(strict.len(), 9);
+        assert_eq!(strict.last().unwrap(), "low");
+        // 每次放行老一岁（aging = 1）：占位的任务放行之后才提交 low，分数 0 - 1；
+        // high5 在第 6 次放行之后提交，分数 5 - 6 和 low 相同，low 先提交所以先运行
+        let aged = starve(Some(1));
+        assert_eq!(
+            aged,
+            ["high0", "high1", "high2", "high3", "high4", "low", "high5", "high6", "high7"]
+        );
+    }
+}
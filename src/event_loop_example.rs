@@ -0,0 +1,341 @@
+// 事件循环：用 epoll 手写单线程非阻塞服务器，看看异步运行时在底层做了什么
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
+    use std::collections::HashMap;
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    // epoll 是 Linux 的 I/O 多路复用机制：把一批文件描述符注册进去，然后一次 epoll_wait 就能知道其中哪些已经可读/可写
+    // 这里对 libc 的原始接口做一层薄封装，把返回值 -1 转换成 io::Error，并在 Drop 时关闭 epoll 描述符
+    struct Epoll {
+        fd: RawFd,
+    }
+
+    impl Epoll {
+        fn new() -> io::Result<Epoll> {
+            // SAFETY: epoll_create1 没有指针参数，失败时返回 -1
+            let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Epoll { fd })
+        }
+
+        // token 是注册时附带的用户数据，事件就绪时原样返回，用来找到对应的连接
+        fn ctl(
+            &self,
+            op: libc::c_int,
+            fd: RawFd,
+            token: u64,
+            events: libc::c_int,
+        ) -> io::Result<()> {
+            let mut event = libc::epoll_event {
+                events: events as u32,
+                u64: token,
+            };
+            // SAFETY: event 是栈上的有效变量，内核只在调用期间读取它
+            let ret = unsafe { libc::epoll_ctl(self.fd, op, fd, &mut event) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        fn add(&self, fd: RawFd, token: u64, events: libc::c_int) -> io::Result<()> {
+            self.ctl(libc::EPOLL_CTL_ADD, fd, token, events)
+        }
+
+        fn modify(&self, fd: RawFd, token: u64, events: libc::c_int) -> io::Result<()> {
+            self.ctl(libc::EPOLL_CTL_MOD, fd, token, events)
+        }
+
+        fn delete(&self, fd: RawFd) -> io::Result<()> {
+            self.ctl(libc::EPOLL_CTL_DEL, fd, 0, 0)
+        }
+
+        // 阻塞直到有描述符就绪或者超时，返回就绪事件的数量
+        fn wait(&self, events: &mut [libc::epoll_event], timeout_ms: i32) -> io::Result<usize> {
+            // SAFETY: events 的长度作为 maxevents 传入，内核最多写入这么多个元素
+            let n = unsafe {
+                libc::epoll_wait(
+                    self.fd,
+                    events.as_mut_ptr(),
+                    events.len() as libc::c_int,
+                    timeout_ms,
+                )
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                // 被信号打断不算错误，当作没有事件
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(0);
+                }
+                return Err(err);
+            }
+            Ok(n as usize)
+        }
+    }
+
+    impl Drop for Epoll {
+        fn drop(&mut self) {
+            // SAFETY: fd 由 epoll_create1 创建，只在这里关闭一次
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+
+    // 两个版本的服务器共用同一个应答：回显请求行
+    fn response_for(request: &[u8]) -> Vec<u8> {
+        let request = String::from_utf8_lossy(request);
+        let body = format!("you asked for: {}", request.lines().next().unwrap_or(""));
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+
+    fn request_complete(buf: &[u8]) -> bool {
+        buf.windows(4).any(|window| window == b"\r\n\r\n")
+    }
+
+    // 每个连接的状态机。在 tokio 里，这些状态由编译器把 async fn 转换成的 Future 自动保存；
+    // 手写事件循环时则必须自己记住“读到哪了”“写到哪了”
+    enum ConnState {
+        // 正在积累请求数据，直到看到头部结束标记
+        Reading(Vec<u8>),
+        // 响应已经生成，written 之前的部分已经写出
+        Writing { buf: Vec<u8>, written: usize },
+    }
+
+    struct Connection {
+        stream: TcpStream,
+        state: ConnState,
+    }
+
+    impl Connection {
+        // 描述符就绪时被调用，尽可能多地读/写，直到遇到 WouldBlock（非阻塞 socket 暂时没有数据或缓冲区已满）
+        // 返回 true 表示连接已经处理完毕，可以关闭
+        fn on_ready(&mut self, epoll: &Epoll, token: u64) -> io::Result<bool> {
+            loop {
+                match &mut self.state {
+                    ConnState::Reading(buf) => {
+                        let mut chunk = [0; 1024];
+                        match self.stream.read(&mut chunk) {
+                            // 对端在发完请求之前就关闭了连接
+                            Ok(0) => return Ok(true),
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                            // 数据暂时读完了，回到事件循环等待下一次可读
+                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                            Err(e) => return Err(e),
+                        }
+                        if request_complete(buf) {
+                            let response = response_for(buf);
+                            self.state = ConnState::Writing {
+                                buf: response,
+                                written: 0,
+                            };
+                            // 接下来关心的是可写事件
+                            epoll.modify(self.stream.as_raw_fd(), token, libc::EPOLLOUT)?;
+                        }
+                    }
+                    ConnState::Writing { buf, written } => {
+                        match self.stream.write(&buf[*written..]) {
+                            Ok(n) => *written += n,
+                            // 发送缓冲区满了，等内核通知可写后再继续
+                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                            Err(e) => return Err(e),
+                        }
+                        if *written == buf.len() {
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // 监听 socket 使用保留的 token 0，连接从 1 开始编号
+    const LISTENER: u64 = 0;
+
+    // 单线程事件循环：所有连接的读写都在这一个线程里交替推进，没有任何一次 read/write 会阻塞
+    fn run_event_loop(listener: TcpListener, stop: &AtomicBool) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        let epoll = Epoll::new()?;
+        epoll.add(listener.as_raw_fd(), LISTENER, libc::EPOLLIN)?;
+
+        let mut connections: HashMap<u64, Connection> = HashMap::new();
+        let mut next_token = LISTENER + 1;
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 64];
+
+        while !stop.load(Ordering::SeqCst) {
+            // 带超时的等待，这样才有机会检查 stop 标记
+            let n = epoll.wait(&mut events, 50)?;
+            for event in &events[..n] {
+                // epoll_event 在 x86_64 上是 packed 结构体，只能按值读取字段，不能取引用
+                let token = event.u64;
+
+                if token == LISTENER {
+                    // 水平触发模式下，只要还有待接受的连接就会一直通知，这里一次性接受完
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                stream.set_nonblocking(true)?;
+                                epoll.add(stream.as_raw_fd(), next_token, libc::EPOLLIN)?;
+                                connections.insert(
+                                    next_token,
+                                    Connection {
+                                        stream,
+                                        state: ConnState::Reading(Vec::new()),
+                                    },
+                                );
+                                next_token += 1;
+                            }
+                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    continue;
+                }
+
+                let done = match connections.get_mut(&token) {
+                    // 单个连接出错只关闭这个连接，不影响事件循环
+                    Some(connection) => connection.on_ready(&epoll, token).unwrap_or(true),
+                    None => continue,
+                };
+                if done {
+                    let connection = connections.remove(&token).unwrap();
+                    epoll.delete(connection.stream.as_raw_fd())?;
+                    // connection 在这里被丢弃，TcpStream 的 Drop 会关闭 socket
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 异步服务器可以监听 TCP 端口，也可以监听 Unix 域套接字
+    enum AsyncListener {
+        Tcp(tokio::net::TcpListener),
+        Unix(tokio::net::UnixListener),
+    }
+
+    // 同样的服务器用 tokio 写：每个连接一个任务，代码是顺序的，状态机由编译器生成，epoll 由运行时的 reactor 负责
+    async fn run_tokio(listener: AsyncListener) {
+        loop {
+            // 两种连接的类型不同，但都实现了 AsyncRead + AsyncWrite，交给同一个泛型函数处理
+            match &listener {
+                AsyncListener::Tcp(listener) => {
+                    if let Ok((stream, _)) = listener.accept().await {
+                        tokio::spawn(serve_async(stream));
+                    }
+                }
+                AsyncListener::Unix(listener) => {
+                    if let Ok((stream, _)) = listener.accept().await {
+                        tokio::spawn(serve_async(stream));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn serve_async(mut stream: impl AsyncRead + AsyncWrite + Unpin) {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+        while !request_complete(&buf) {
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+        let _ = stream.write_all(&response_for(&buf)).await;
+    }
+
+    // 先让所有客户端都连上，再把请求拆成两半交错发送，单线程的服务器必须同时推进所有连接才能全部应答
+    fn exercise(addr: SocketAddr, clients: usize) {
+        let mut streams: Vec<TcpStream> = (0..clients)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+
+        for (i, stream) in streams.iter_mut().enumerate() {
+            write!(stream, "GET /client/{} HT", i).unwrap();
+        }
+        thread::sleep(Duration::from_millis(20));
+        for stream in streams.iter_mut().rev() {
+            stream.write_all(b"TP/1.1\r\n\r\n").unwrap();
+        }
+
+        for (i, mut stream) in streams.into_iter().enumerate() {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.ends_with(&format!("you asked for: GET /client/{} HTTP/1.1", i)));
+        }
+    }
+
+    #[test]
+    fn epoll_event_loop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let server = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run_event_loop(listener, &stop))
+        };
+
+        exercise(addr, 20);
+
+        stop.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn tokio_equivalent() {
+        let rt = runtime(Preset::IoHeavy);
+        let listener = rt
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        rt.spawn(run_tokio(AsyncListener::Tcp(listener)));
+
+        // 客户端是普通的阻塞代码，在测试线程里运行，服务器在运行时的工作线程里运行
+        exercise(addr, 20);
+    }
+
+    #[test]
+    fn tokio_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("event-loop-{}.sock", std::process::id()));
+        // 和同步服务器一样：清理遗留的套接字文件，bind 之后限制权限
+        crate::webserver_example::unix_socket::remove_stale(&path).unwrap();
+        let rt = runtime(Preset::IoHeavy);
+        let listener = {
+            // UnixListener::bind 需要在运行时上下文中调用，才能注册到 reactor 上
+            let _guard = rt.enter();
+            tokio::net::UnixListener::bind(&path).unwrap()
+        };
+        crate::webserver_example::unix_socket::restrict(&path, 0o600).unwrap();
+        rt.spawn(run_tokio(AsyncListener::Unix(listener)));
+
+        for i in 0..5 {
+            let mut stream = UnixStream::connect(&path).unwrap();
+            write!(stream, "GET /unix/{} HTTP/1.1\r\n\r\n", i).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.ends_with(&format!("you asked for: GET /unix/{} HTTP/1.1", i)));
+        }
+
+        drop(rt);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+// 按地区格式化数字、金额和日期。同一个数在不同地区写法不一样：
+// - 1234567.5 在美国是 1,234,567.5，德国是 1.234.567,5，法国用窄不换行空格分组，印度是 12,34,567.5（后三位一组，再往前两位一组）
+// - 货币符号有的在前（$1.00、¥1.00），有的在后面隔一个空格（1,00 €）；日元没有辅币，不带小数
+// - 日期 2024-03-05 在美国是 03/05/2024，英国是 05/03/2024，德国是 05.03.2024，中国是 2024年3月5日
+// 每个地区的约定放在 CONVENTIONS 表里，地区的匹配方式和 i18n 的 Accept-Language 协商一样：en-AU 没有时用 en 开头的第一个
+#[cfg(test)]
+pub(crate) mod format_locale {
+
+    use crate::i18n_example::i18n;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Grouping {
+        // 每三位一组
+        Thousands,
+        // 最后三位一组，之前每两位一组
+        Indian,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum SymbolPosition {
+        Before,
+        // 数字和符号之间是不换行空格，换行时不会把符号单独甩到下一行
+        After,
+    }
+
+    struct Conventions {
+        locale: &'static str,
+        decimal: char,
+        group: &'static str,
+        grouping: Grouping,
+        symbol: SymbolPosition,
+        // chrono 的格式串，%-d 这样带 - 的不补零
+        date: &'static str,
+        time: &'static str,
+    }
+
+    // 第一项是默认地区
+    const CONVENTIONS: &[Conventions] = &[
+        Conventions {
+            locale: "en-US",
+            decimal: '.',
+            group: ",",
+            grouping: Grouping::Thousands,
+            symbol: SymbolPosition::Before,
+            date: "%m/%d/%Y",
+            time: "%-I:%M %p",
+        },
+        Conventions {
+            locale: "en-GB",
+            decimal: '.',
+            group: ",",
+            grouping: Grouping::Thousands,
+            symbol: SymbolPosition::Before,
+            date: "%d/%m/%Y",
+            time: "%H:%M",
+        },
+        Conventions {
+            locale: "en-IN",
+            decimal: '.',
+            group: ",",
+            grouping: Grouping::Indian,
+            symbol: SymbolPosition::Before,
+            date: "%d/%m/%Y",
+            time: "%-I:%M %p",
+        },
+        Conventions {
+            locale: "de-DE",
+            decimal: ',',
+            group: ".",
+            grouping: Grouping::Thousands,
+            symbol: SymbolPosition::After,
+            date: "%d.%m.%Y",
+            time: "%H:%M",
+        },
+        Conventions {
+            locale: "fr-FR",
+            decimal: ',',
+            group: "\u{202f}",
+            grouping: Grouping::Thousands,
+            symbol: SymbolPosition::After,
+            date: "%d/%m/%Y",
+            time: "%H:%M",
+        },
+        Conventions {
+            locale: "zh-CN",
+            decimal: '.',
+            group: ",",
+            grouping: Grouping::Thousands,
+            symbol: SymbolPosition::Before,
+            date: "%Y年%-m月%-d日",
+            time: "%H:%M",
+        },
+        Conventions {
+            locale: "ja-JP",
+            decimal: '.',
+            group: ",",
+            grouping: Grouping::Thousands,
+            symbol: SymbolPosition::Before,
+            date: "%Y/%m/%d",
+            time: "%H:%M",
+        },
+    ];
+
+    fn conventions(locale: &str) -> &'static Conventions {
+        let available: Vec<&str> = CONVENTIONS.iter().map(|c| c.locale).collect();
+        let chosen = i18n::negotiate(locale, &available, CONVENTIONS[0].locale);
+        CONVENTIONS.iter().find(|c| c.locale == chosen).unwrap()
+    }
+
+    // 给一串十进制数字加上分组分隔符
+    fn group_digits(digits: &str, conventions: &Conventions) -> String {
+        let mut groups = Vec::new();
+        let mut end = digits.len();
+        let mut size = 3;
+        while end > size {
+            groups.push(&digits[end - size..end]);
+            end -= size;
+            if conventions.grouping == Grouping::Indian {
+                size = 2;
+            }
+        }
+        groups.push(&digits[..end]);
+        groups.reverse();
+        groups.join(conventions.group)
+    }
+
+    // 整数部分和小数部分都是十进制数字串，拼成这个地区的写法
+    fn assemble(negative: bool, whole: &str, fraction: &str, conventions: &Conventions) -> String {
+        let mut out = String::new();
+        if negative {
+            out.push('-');
+        }
+        out.push_str(&group_digits(whole, conventions));
+        if !fraction.is_empty() {
+            out.push(conventions.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    // 保留 decimals 位小数。f64 是二进制小数，1.005 实际上比 1.005 小一点，保留两位是 1.00，
+    // 需要精确到分的金额用 Money
+    pub(crate) fn format_number(value: f64, decimals: usize, locale: &str) -> String {
+        let text = format!("{:.*}", decimals, value.abs());
+        let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+        // -0.001 保留两位是 0.00，不显示负号
+        let negative = value < 0.0 && text.bytes().any(|b| (b'1'..=b'9').contains(&b));
+        assemble(negative, whole, fraction, conventions(locale))
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Currency {
+        Usd,
+        Eur,
+        Gbp,
+        Jpy,
+        Cny,
+        Inr,
+    }
+
+    impl Currency {
+        fn symbol(self) -> &'static str {
+            match self {
+                Currency::Usd => "$",
+                Currency::Eur => "€",
+                Currency::Gbp => "£",
+                Currency::Jpy => "¥",
+                Currency::Cny => "¥",
+                Currency::Inr => "₹",
+            }
+        }
+
+        // 辅币的位数：1 美元 = 100 美分，日元没有辅币
+        fn minor_digits(self) -> u32 {
+            match self {
+                Currency::Jpy => 0,
+                _ => 2,
+            }
+        }
+    }
+
+    // 金额用最小货币单位的整数表示（美元就是美分），加减和格式化都是精确的，不会有浮点误差
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) struct Money {
+        pub(crate) minor_units: i64,
+        pub(crate) currency: Currency,
+    }
+
+    impl Money {
+        pub(crate) fn new(minor_units: i64, currency: Currency) -> Money {
+            Money {
+                minor_units,
+                currency,
+            }
+        }
+    }
+
+    pub(crate) fn format_money(money: Money, locale: &str) -> String {
+        let conventions = conventions(locale);
+        let digits = money.currency.minor_digits();
+        let scale = 10u64.pow(digits);
+        let units = money.minor_units.unsigned_abs();
+        let whole = (units / scale).to_string();
+        let fraction = if digits == 0 {
+            String::new()
+        } else {
+            format!("{:0width$}", units % scale, width = digits as usize)
+        };
+        let number = assemble(false, &whole, &fraction, conventions);
+        let sign = if money.minor_units < 0 { "-" } else { "" };
+        let symbol = money.currency.symbol();
+        match conventions.symbol {
+            SymbolPosition::Before => format!("{}{}{}", sign, symbol, number),
+            SymbolPosition::After => format!("{}{}\u{a0}{}", sign, number, symbol),
+        }
+    }
+
+    pub(crate) fn format_date(date: NaiveDate, locale: &str) -> String {
+        date.format(conventions(locale).date).to_string()
+    }
+
+    pub(crate) fn format_datetime(datetime: NaiveDateTime, locale: &str) -> String {
+        let conventions = conventions(locale);
+        format!(
+            "{} {}",
+            datetime.format(conventions.date),
+            datetime.format(conventions.time)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::format_locale::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn numbers_are_grouped_per_locale() {
+        assert_eq!(format_number(1234567.891, 2, "en-US"), "1,234,567.89");
+        assert_eq!(format_number(1234567.891, 2, "de-DE"), "1.234.567,89");
+        assert_eq!(
+            format_number(1234567.891, 2, "fr-FR"),
+            "1\u{202f}234\u{202f}567,89"
+        );
+        assert_eq!(format_number(1234567.891, 2, "en-IN"), "12,34,567.89");
+        assert_eq!(format_number(-9876543210.0, 0, "en-IN"), "-9,87,65,43,210");
+        assert_eq!(format_number(999.0, 0, "en-US"), "999");
+        assert_eq!(format_number(1000.0, 1, "en-US"), "1,000.0");
+        assert_eq!(format_number(-0.001, 2, "en-US"), "0.00");
+        assert_eq!(format_number(0.5, 0, "en-US"), "0");
+        // 只有语言时用这个语言的第一个地区；不认识的地区用 en-US
+        assert_eq!(format_number(1234.5, 1, "de"), "1.234,5");
+        assert_eq!(format_number(1234.5, 1, "de-AT"), "1.234,5");
+        assert_eq!(format_number(1234.5, 1, "pt-BR"), "1,234.5");
+    }
+
+    #[test]
+    fn money_uses_minor_units() {
+        let usd = |cents| format_money(Money::new(cents, Currency::Usd), "en-US");
+        assert_eq!(usd(123450), "$1,234.50");
+        assert_eq!(usd(5), "$0.05");
+        assert_eq!(usd(-123450), "-$1,234.50");
+        assert_eq!(usd(0), "$0.00");
+        assert_eq!(
+            format_money(Money::new(-123450, Currency::Eur), "de-DE"),
+            "-1.234,50\u{a0}€"
+        );
+        // 日元没有小数
+        assert_eq!(
+            format_money(Money::new(1234567, Currency::Jpy), "ja-JP"),
+            "¥1,234,567"
+        );
+        assert_eq!(
+            format_money(Money::new(i64::MIN, Currency::Usd), "en-US"),
+            "-$92,233,720,368,547,758.08"
+        );
+    }
+
+    #[test]
+    fn snapshot_of_several_locales() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        let datetime = date.and_hms_opt(14, 7, 0).unwrap();
+        let money = [
+            Money::new(123456789, Currency::Usd),
+            Money::new(123456789, Currency::Gbp),
+            Money::new(123456789, Currency::Inr),
+            Money::new(123456789, Currency::Eur),
+            Money::new(123456789, Currency::Eur),
+            Money::new(123456789, Currency::Cny),
+            Money::new(123456789, Currency::Jpy),
+        ];
+        let locales = [
+            "en-US", "en-GB", "en-IN", "de-DE", "fr-FR", "zh-CN", "ja-JP",
+        ];
+        let snapshot: Vec<String> = locales
+            .iter()
+            .zip(money)
+            .map(|(&locale, money)| {
+                format!(
+                    "{} | {} | {} | {} | {}",
+                    locale,
+                    format_number(-1234567.5, 1, locale),
+                    format_money(money, locale),
+                    format_date(date, locale),
+                    format_datetime(datetime, locale)
+                )
+                // 不可见的空格换成可见的记号，快照才看得出区别
+                .replace('\u{202f}', "⍽")
+                .replace('\u{a0}', "·")
+            })
+            .collect();
+        assert_eq!(
+            snapshot.join("\n"),
+            "\
+en-US | -1,234,567.5 | $1,234,567.89 | 03/05/2024 | 03/05/2024 2:07 PM
+en-GB | -1,234,567.5 | £1,234,567.89 | 05/03/2024 | 05/03/2024 14:07
+en-IN | -12,34,567.5 | ₹12,34,567.89 | 05/03/2024 | 05/03/2024 2:07 PM
+de-DE | -1.234.567,5 | 1.234.567,89·€ | 05.03.2024 | 05.03.2024 14:07
+fr-FR | -1⍽234⍽567,5 | 1⍽234⍽567,89·€ | 05/03/2024 | 05/03/2024 14:07
+zh-CN | -1,234,567.5 | ¥1,234,567.89 | 2024年3月5日 | 2024年3月5日 14:07
+ja-JP | -1,234,567.5 | ¥123,456,789 | 2024/03/05 | 2024/03/05 14:07"
+        );
+    }
+}
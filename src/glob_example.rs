@@ -0,0 +1,407 @@
+// glob 模式匹配路径，不依赖外部 crate
+// - * 匹配除 / 以外的任意个字符，? 匹配除 / 以外的一个字符
+// - ** 匹配任意个字符，可以跨过 /；**/ 还可以什么都不匹配，所以 **/*.rs 也能匹配 main.rs
+// - [abc]、[a-z] 匹配一个在集合里的字符，[!a-z] 或 [^a-z] 匹配一个不在集合里的字符，都不匹配 /；
+//   紧跟在 [、[! 后面的 ] 是集合里的普通字符
+// - \ 转义下一个字符，\* 匹配星号本身
+// 编译好的 Pattern 用动态规划匹配，时间是模式长度乘文本长度，不会像回溯那样在 a*a*a*b 这种模式上指数爆炸
+#[cfg(test)]
+pub(crate) mod glob {
+
+    use std::error::Error;
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Token {
+        Char(char),
+        Any,
+        Star,
+        Globstar,
+        // **/，匹配空串或者以 / 结尾的任意字符串
+        GlobstarSlash,
+        Class {
+            negated: bool,
+            ranges: Vec<(char, char)>,
+        },
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum GlobError {
+        // 位置是 [ 在模式里的字符下标
+        UnclosedClass(usize),
+        InvalidRange(char, char),
+        TrailingBackslash,
+    }
+
+    impl fmt::Display for GlobError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                GlobError::UnclosedClass(at) => write!(f, "unclosed character class at {}", at),
+                GlobError::InvalidRange(from, to) => write!(f, "invalid range {}-{}", from, to),
+                GlobError::TrailingBackslash => write!(f, "pattern ends with a backslash"),
+            }
+        }
+    }
+
+    impl Error for GlobError {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct Pattern {
+        text: String,
+        tokens: Vec<Token>,
+    }
+
+    // 含有这些字符的才是模式，否则就是普通的路径
+    pub(crate) fn is_pattern(text: &str) -> bool {
+        text.contains(['*', '?', '[', '\\'])
+    }
+
+    impl Pattern {
+        pub(crate) fn new(text: &str) -> Result<Pattern, GlobError> {
+            let chars: Vec<char> = text.chars().collect();
+            let mut tokens = Vec::new();
+            let mut i = 0;
+            while i < chars.len() {
+                let token = match chars[i] {
+                    '*' if chars.get(i + 1) == Some(&'*') => {
+                        i += 1;
+                        if chars.get(i + 1) == Some(&'/') {
+                            i += 1;
+                            Token::GlobstarSlash
+                        } else {
+                            Token::Globstar
+                        }
+                    }
+                    '*' => Token::Star,
+                    '?' => Token::Any,
+                    '[' => {
+                        let (token, end) = parse_class(&chars, i)?;
+                        i = end;
+                        token
+                    }
+                    '\\' => {
+                        i += 1;
+                        Token::Char(*chars.get(i).ok_or(GlobError::TrailingBackslash)?)
+                    }
+                    c => Token::Char(c),
+                };
+                tokens.push(token);
+                i += 1;
+            }
+            Ok(Pattern {
+                text: text.to_string(),
+                tokens,
+            })
+        }
+
+        pub(crate) fn as_str(&self) -> &str {
+            &self.text
+        }
+
+        pub(crate) fn matches(&self, text: &str) -> bool {
+            let text: Vec<char> = text.chars().collect();
+            let n = text.len();
+            // next[j] 表示后面的模式能否匹配 text[j..]，从最后一个 token 往前一列一列算
+            let mut next = vec![false; n + 1];
+            next[n] = true;
+            for token in self.tokens.iter().rev() {
+                let mut current = vec![false; n + 1];
+                // 只有 **/ 用到：text[j..] 里有没有一个 / 的后面能匹配剩下的模式
+                let mut slash_ahead = false;
+                for j in (0..=n).rev() {
+                    let c = text.get(j).copied();
+                    current[j] = match token {
+                        Token::Char(expected) => c == Some(*expected) && next[j + 1],
+                        Token::Any => c.is_some_and(|c| c != '/') && next[j + 1],
+                        Token::Class { negated, ranges } => {
+                            c.is_some_and(|c| {
+                                c != '/'
+                                    && ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&c))
+                                        != *negated
+                            }) && next[j + 1]
+                        }
+                        Token::Star => next[j] || (c.is_some_and(|c| c != '/') && current[j + 1]),
+                        Token::Globstar => next[j] || (c.is_some() && current[j + 1]),
+                        Token::GlobstarSlash => {
+                            slash_ahead = slash_ahead || (c == Some('/') && next[j + 1]);
+                            next[j] || slash_ahead
+                        }
+                    };
+                }
+                next = current;
+            }
+            next[0]
+        }
+    }
+
+    // 解析从 start 处的 [ 开始的字符集合，返回 token 和 ] 的下标
+    fn parse_class(chars: &[char], start: usize) -> Result<(Token, usize), GlobError> {
+        let mut i = start + 1;
+        let negated = matches!(chars.get(i), Some('!' | '^'));
+        if negated {
+            i += 1;
+        }
+        let first = i;
+        let mut ranges = Vec::new();
+        loop {
+            let mut c = *chars.get(i).ok_or(GlobError::UnclosedClass(start))?;
+            if c == ']' && i > first {
+                return Ok((Token::Class { negated, ranges }, i));
+            }
+            if c == '\\' {
+                i += 1;
+                c = *chars.get(i).ok_or(GlobError::UnclosedClass(start))?;
+            }
+            // a-z 是一个范围；- 在最后（[a-]）时是普通字符
+            if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+                let mut end = i + 2;
+                if chars[end] == '\\' {
+                    end += 1;
+                }
+                let to = *chars.get(end).ok_or(GlobError::UnclosedClass(start))?;
+                if to < c {
+                    return Err(GlobError::InvalidRange(c, to));
+                }
+                ranges.push((c, to));
+                i = end + 1;
+            } else {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+
+    // 在 root 下找出所有匹配的路径，返回的路径相对于 root，同一个目录里按名字排序
+    // 遍历是惰性的：每次 next 只在需要时读一个目录。模式开头不含通配符的几段（例如 src/**/*.rs 的 src）直接拼到起点上，
+    // 不含 ** 的模式最多往下走到模式的层数为止；符号链接不跟随
+    pub(crate) fn glob(root: &Path, pattern: &str) -> Result<Paths, GlobError> {
+        let compiled = Pattern::new(pattern)?;
+        let segments: Vec<&str> = pattern.split('/').collect();
+        let literal = segments[..segments.len() - 1]
+            .iter()
+            .take_while(|segment| !is_pattern(segment))
+            .count();
+        let base: PathBuf = segments[..literal].iter().collect();
+        let max_depth = (!pattern.contains("**")).then_some(segments.len() - literal);
+        Ok(Paths {
+            root: root.to_path_buf(),
+            pattern: compiled,
+            max_depth,
+            stack: vec![(base, 0, true)],
+        })
+    }
+
+    pub(crate) struct Paths {
+        root: PathBuf,
+        pattern: Pattern,
+        max_depth: Option<usize>,
+        // 还没访问的路径（相对于 root）、相对于起点的层数、是不是目录。后进先出，深度优先
+        stack: Vec<(PathBuf, usize, bool)>,
+    }
+
+    impl Iterator for Paths {
+        type Item = io::Result<PathBuf>;
+
+        fn next(&mut self) -> Option<io::Result<PathBuf>> {
+            while let Some((path, depth, is_dir)) = self.stack.pop() {
+                if is_dir && self.max_depth.is_none_or(|max| depth < max) {
+                    let entries = match fs::read_dir(self.root.join(&path)) {
+                        Ok(entries) => entries,
+                        // 起点不存在就是什么都没匹配到；目录在遍历的过程中被删掉了也一样跳过
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let mut children = Vec::new();
+                    for entry in entries {
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            Err(e) => return Some(Err(e)),
+                        };
+                        let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+                        children.push((path.join(entry.file_name()), depth + 1, is_dir));
+                    }
+                    children.sort_by(|a, b| b.0.cmp(&a.0));
+                    self.stack.extend(children);
+                }
+                if depth > 0 && self.pattern.matches(&path.to_string_lossy()) {
+                    return Some(Ok(path));
+                }
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::glob::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    #[test]
+    fn wildcards_and_classes() {
+        let cases = [
+            ("*.rs", "main.rs", true),
+            ("*.rs", "main.rs.bak", false),
+            ("*", "src/main.rs", false),
+            ("*", "", true),
+            ("target/*", "target/debug", true),
+            ("src/**/*.rs", "src/a/b/c.rs", true),
+            ("src/**/*.rs", "src/c.rs", true),
+            ("src/**/*.rs", "srcx/c.rs", false),
+            ("**/*.rs", "main.rs", true),
+            ("**", "a/b/c", true),
+            ("a**z", "a/b/z", true),
+            ("a*z", "a/b/z", false),
+            ("file?.txt", "file1.txt", true),
+            ("file?.txt", "file.txt", false),
+            ("a?b", "a/b", false),
+            ("[abc].txt", "b.txt", true),
+            ("[abc].txt", "d.txt", false),
+            ("[a-z][0-9]", "q7", true),
+            ("[a-z][0-9]", "Q7", false),
+            ("[!a-z]*", "Makefile", true),
+            ("[^a-z]*", "makefile", false),
+            ("[]]", "]", true),
+            ("[!]]", "]", false),
+            ("[a-]", "-", true),
+            ("a[/]b", "a/b", false),
+            ("[!x]", "/", false),
+            ("\\*", "*", true),
+            ("\\*", "x", false),
+            ("[\\]]", "]", true),
+            ("日志-*.txt", "日志-一月.txt", true),
+            ("??", "日志", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                Pattern::new(pattern).unwrap().matches(text),
+                expected,
+                "{} vs {}",
+                pattern,
+                text
+            );
+        }
+        assert!(is_pattern("src/*.rs") && !is_pattern("src/main.rs"));
+
+        // 回溯实现在这个例子上要试上亿种切分方式
+        let pattern = Pattern::new(&format!("{}c", "a*".repeat(30))).unwrap();
+        assert!(!pattern.matches(&format!("{}b", "a".repeat(60))));
+
+        assert_eq!(Pattern::new("[ab"), Err(GlobError::UnclosedClass(0)));
+        assert_eq!(Pattern::new("x[!]"), Err(GlobError::UnclosedClass(1)));
+        assert_eq!(
+            Pattern::new("[z-a]"),
+            Err(GlobError::InvalidRange('z', 'a'))
+        );
+        assert_eq!(Pattern::new("a\\"), Err(GlobError::TrailingBackslash));
+        assert_eq!(Pattern::new("*.rs").unwrap().as_str(), "*.rs");
+    }
+
+    // 直接按定义回溯的参考实现，只支持 *、?、** 和普通字符
+    fn reference(p: &[char], t: &[char]) -> bool {
+        match p {
+            [] => t.is_empty(),
+            ['*', '*', '/', rest @ ..] => {
+                reference(rest, t)
+                    || (0..t.len()).any(|i| t[i] == '/' && reference(rest, &t[i + 1..]))
+            }
+            ['*', '*', rest @ ..] => (0..=t.len()).any(|i| reference(rest, &t[i..])),
+            ['*', rest @ ..] => {
+                let limit = t.iter().position(|&c| c == '/').unwrap_or(t.len());
+                (0..=limit).any(|i| reference(rest, &t[i..]))
+            }
+            ['?', rest @ ..] => t.first().is_some_and(|&c| c != '/') && reference(rest, &t[1..]),
+            [c, rest @ ..] => t.first() == Some(c) && reference(rest, &t[1..]),
+        }
+    }
+
+    #[test]
+    fn agrees_with_backtracking_reference() {
+        let mut seed: u64 = 92;
+        let mut pick = move |from: &[&'static str]| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            from[(seed >> 33) as usize % from.len()]
+        };
+        for _ in 0..20_000 {
+            let pattern: String = (0..4)
+                .map(|_| pick(&["a", "b", "/", "*", "?", "**", "**/"]))
+                .collect();
+            let text: String = (0..5).map(|_| pick(&["a", "b", "/"])).collect();
+            let p: Vec<char> = pattern.chars().collect();
+            let t: Vec<char> = text.chars().collect();
+            assert_eq!(
+                Pattern::new(&pattern).unwrap().matches(&text),
+                reference(&p, &t),
+                "{:?} vs {:?}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn walks_the_filesystem() {
+        let root = env::temp_dir().join(format!("glob-walk-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for file in [
+            "Cargo.toml",
+            "README.md",
+            "src/main.rs",
+            "src/notes.txt",
+            "src/nested/lib.rs",
+            "src/nested/deeper/mod.rs",
+            "tests/a1.rs",
+            "tests/b2.rs",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let found = |pattern: &str| -> Vec<String> {
+            glob(&root, pattern)
+                .unwrap()
+                .map(|path| path.unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            found("src/**/*.rs"),
+            [
+                "src/main.rs",
+                "src/nested/deeper/mod.rs",
+                "src/nested/lib.rs"
+            ]
+        );
+        assert_eq!(found("**/*.rs").len(), 5);
+        // 不支持 {a,b} 这种写法，花括号是普通字符
+        assert!(found("*.{toml,md}").is_empty());
+        assert_eq!(found("[A-Z]*"), ["Cargo.toml", "README.md"]);
+        assert_eq!(found("*/*.txt"), ["src/notes.txt"]);
+        assert_eq!(found("tests/[!b]?.rs"), ["tests/a1.rs"]);
+        // 目录也会被匹配到
+        assert_eq!(
+            found("src/*"),
+            ["src/main.rs", "src/nested", "src/notes.txt"]
+        );
+        assert!(found("missing/*").is_empty());
+        // 不含通配符的模式就是检查这个路径存不存在
+        assert_eq!(found("src/main.rs"), ["src/main.rs"]);
+
+        // 惰性：取到第一个结果时还没读后面的目录，这时删掉它们也不影响
+        let mut paths = glob(&root, "**/*.md").unwrap();
+        assert_eq!(paths.next().unwrap().unwrap(), PathBuf::from("README.md"));
+        fs::remove_dir_all(root.join("tests")).unwrap();
+        assert!(paths.all(|path| path.is_ok()));
+
+        assert!(glob(&root, "src/[").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
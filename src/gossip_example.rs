@@ -0,0 +1,486 @@
+// Gossip 成员协议（简化的 SWIM）：每个节点周期性地随机挑一个成员发 ping，对方回 ack，
+// ping 和 ack 都捎带上自己知道的整张成员表，成员信息就这样像流言一样在集群里扩散，不需要中心节点
+// 没有按时回 ack 的成员先标记为可疑（suspect），可疑超过一段时间还没有澄清才宣布死亡（dead）
+// 被怀疑的节点听到关于自己的流言后把自己的 incarnation 加一重新声明存活，更大的 incarnation 覆盖旧的怀疑
+// 完整的 SWIM 在 ping 超时后还会请其他节点代为探测（ping-req），减少网络抖动造成的误判，这里省略了
+#[cfg(test)]
+pub(crate) mod gossip {
+
+    use std::collections::{BTreeMap, HashMap};
+    use std::error::Error;
+    use std::fmt;
+    use std::io::{self, Write};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    // 每个协议周期探测一个成员
+    const PERIOD: Duration = Duration::from_millis(20);
+    // ping 发出后这么久没有 ack 就怀疑对方
+    const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+    // 可疑状态持续这么久没有被澄清就宣布死亡
+    const SUSPICION: Duration = Duration::from_millis(300);
+
+    // 同一个 incarnation 下 Dead 覆盖 Suspect，Suspect 覆盖 Alive，所以按这个顺序派生 Ord
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub(crate) enum State {
+        Alive,
+        Suspect,
+        Dead,
+    }
+
+    impl fmt::Display for State {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match self {
+                State::Alive => "alive",
+                State::Suspect => "suspect",
+                State::Dead => "dead",
+            })
+        }
+    }
+
+    impl State {
+        fn parse(text: &str) -> Option<State> {
+            match text {
+                "alive" => Some(State::Alive),
+                "suspect" => Some(State::Suspect),
+                "dead" => Some(State::Dead),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct Member {
+        pub(crate) addr: SocketAddr,
+        pub(crate) incarnation: u64,
+        pub(crate) state: State,
+    }
+
+    // 一个节点眼中的成员表。自己总是存活的，只记 incarnation；since 是进入当前状态的时刻，用来判断可疑是否超时
+    pub(crate) struct Membership {
+        me: SocketAddr,
+        incarnation: u64,
+        others: BTreeMap<SocketAddr, (u64, State, Instant)>,
+    }
+
+    impl Membership {
+        pub(crate) fn new(me: SocketAddr, seeds: &[SocketAddr]) -> Membership {
+            let now = Instant::now();
+            let others = seeds
+                .iter()
+                .filter(|&&seed| seed != me)
+                .map(|&seed| (seed, (0, State::Alive, now)))
+                .collect();
+            Membership {
+                me,
+                incarnation: 0,
+                others,
+            }
+        }
+
+        // 按地址排序的整张表，包括自己
+        pub(crate) fn members(&self) -> Vec<Member> {
+            let mut members: Vec<Member> = self
+                .others
+                .iter()
+                .map(|(&addr, &(incarnation, state, _))| Member {
+                    addr,
+                    incarnation,
+                    state,
+                })
+                .collect();
+            members.push(Member {
+                addr: self.me,
+                incarnation: self.incarnation,
+                state: State::Alive,
+            });
+            members.sort_by_key(|member| member.addr);
+            members
+        }
+
+        // 合并一条流言：(incarnation, state) 更大的胜出，所以合并的顺序不影响结果
+        // 关于自己的怀疑或者死讯不接受，而是把 incarnation 加到比它大，随后的 ping/ack 会把澄清传出去
+        pub(crate) fn merge(&mut self, member: &Member, now: Instant) {
+            if member.addr == self.me {
+                if member.state != State::Alive && member.incarnation >= self.incarnation {
+                    self.incarnation = member.incarnation + 1;
+                }
+                return;
+            }
+            let heard = (member.incarnation, member.state);
+            match self.others.get(&member.addr) {
+                Some(&(incarnation, state, _)) if (incarnation, state) >= heard => {}
+                _ => {
+                    self.others
+                        .insert(member.addr, (member.incarnation, member.state, now));
+                }
+            }
+        }
+
+        fn suspect(&mut self, addr: SocketAddr, now: Instant) {
+            if let Some(entry) = self.others.get_mut(&addr) {
+                if entry.1 == State::Alive {
+                    *entry = (entry.0, State::Suspect, now);
+                }
+            }
+        }
+
+        fn expire(&mut self, now: Instant) {
+            for entry in self.others.values_mut() {
+                if entry.1 == State::Suspect && now - entry.2 >= SUSPICION {
+                    *entry = (entry.0, State::Dead, now);
+                }
+            }
+        }
+
+        // 死亡的成员留在表里继续传播死讯，但不再探测
+        fn targets(&self) -> Vec<SocketAddr> {
+            self.others
+                .iter()
+                .filter(|(_, entry)| entry.1 != State::Dead)
+                .map(|(&addr, _)| addr)
+                .collect()
+        }
+    }
+
+    // 报文是文本：第一行是 ping 或 ack，后面每行一个成员：地址 incarnation 状态
+    fn encode(kind: &str, members: &[Member]) -> String {
+        let mut text = format!("{}\n", kind);
+        for member in members {
+            text.push_str(&format!(
+                "{} {} {}\n",
+                member.addr, member.incarnation, member.state
+            ));
+        }
+        text
+    }
+
+    fn decode(text: &str) -> Option<(&str, Vec<Member>)> {
+        let mut lines = text.lines();
+        let kind = lines.next().filter(|kind| ["ping", "ack"].contains(kind))?;
+        let members = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                let [addr, incarnation, state] = fields[..] else {
+                    return None;
+                };
+                Some(Member {
+                    addr: addr.parse().ok()?,
+                    incarnation: incarnation.parse().ok()?,
+                    state: State::parse(state)?,
+                })
+            })
+            .collect::<Option<Vec<Member>>>()?;
+        Some((kind, members))
+    }
+
+    // 在后台线程里运行协议的一个节点。stop 之后节点不再收发任何报文，在其他节点看来和崩溃一样
+    pub(crate) struct GossipNode {
+        addr: SocketAddr,
+        view: Arc<Mutex<Membership>>,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl GossipNode {
+        // 在本机的随机端口上启动，seeds 是加入集群时联系的已有成员
+        pub(crate) fn start(seeds: &[SocketAddr]) -> io::Result<GossipNode> {
+            let socket = UdpSocket::bind("127.0.0.1:0")?;
+            // 读超时让循环能按时发起探测、检查 stop
+            socket.set_read_timeout(Some(PERIOD / 4))?;
+            let addr = socket.local_addr()?;
+            let view = Arc::new(Mutex::new(Membership::new(addr, seeds)));
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = {
+                let (view, stop) = (Arc::clone(&view), Arc::clone(&stop));
+                thread::spawn(move || serve(socket, &view, &stop))
+            };
+            Ok(GossipNode {
+                addr,
+                view,
+                stop,
+                thread: Some(thread),
+            })
+        }
+
+        pub(crate) fn addr(&self) -> SocketAddr {
+            self.addr
+        }
+
+        pub(crate) fn view(&self) -> Vec<Member> {
+            self.view.lock().unwrap().members()
+        }
+
+        pub(crate) fn stop(&mut self) {
+            self.stop.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    impl Drop for GossipNode {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    fn serve(socket: UdpSocket, view: &Mutex<Membership>, stop: &AtomicBool) {
+        let mut seed = socket.local_addr().unwrap().port() as u64;
+        // 等待 ack 的成员和 ping 发出的时刻
+        let mut pending: HashMap<SocketAddr, Instant> = HashMap::new();
+        let mut next_probe = Instant::now();
+        let mut buf = [0u8; 65536];
+        while !stop.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if now >= next_probe {
+                next_probe = now + PERIOD;
+                let mut view = view.lock().unwrap();
+                pending.retain(|&addr, &mut sent| {
+                    let late = now - sent >= ACK_TIMEOUT;
+                    if late {
+                        view.suspect(addr, now);
+                    }
+                    !late
+                });
+                view.expire(now);
+                let targets = view.targets();
+                if !targets.is_empty() {
+                    seed = seed
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    let target = targets[(seed >> 33) as usize % targets.len()];
+                    pending.entry(target).or_insert(now);
+                    // UDP 发送失败（例如对方端口已经关闭）和丢包一样处理，靠超时发现
+                    let _ = socket.send_to(encode("ping", &view.members()).as_bytes(), target);
+                }
+            }
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::ConnectionRefused =>
+                {
+                    continue
+                }
+                Err(e) => panic!("gossip socket failed: {}", e),
+            };
+            let Some((kind, members)) = std::str::from_utf8(&buf[..len]).ok().and_then(decode)
+            else {
+                continue;
+            };
+            let mut view = view.lock().unwrap();
+            let now = Instant::now();
+            for member in &members {
+                view.merge(member, now);
+            }
+            if kind == "ping" {
+                let _ = socket.send_to(encode("ack", &view.members()).as_bytes(), from);
+            } else {
+                pending.remove(&from);
+            }
+        }
+    }
+
+    // 成员表画成一张表
+    pub(crate) fn format_view(members: &[Member]) -> String {
+        let mut table = format!("{:<22} {:>11}  {}\n", "member", "incarnation", "state");
+        for member in members {
+            table.push_str(&format!(
+                "{:<22} {:>11}  {}\n",
+                member.addr.to_string(),
+                member.incarnation,
+                member.state
+            ));
+        }
+        table
+    }
+
+    // 所有节点的成员表都一样时返回这张表
+    pub(crate) fn converged(nodes: &[&GossipNode]) -> Option<Vec<Member>> {
+        let first = nodes.first()?.view();
+        nodes[1..]
+            .iter()
+            .all(|node| node.view() == first)
+            .then_some(first)
+    }
+
+    pub(crate) fn wait_until<T>(
+        timeout: Duration,
+        mut ready: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(value) = ready() {
+                return Some(value);
+            }
+            if Instant::now() > deadline {
+                return None;
+            }
+            thread::sleep(PERIOD);
+        }
+    }
+
+    // gossip <节点数> [--kill <序号>]：在本机启动一个集群，等所有成员表收敛后打印出来；
+    // 带 --kill 时再停掉一个节点，等其余节点都认定它死亡后再打印一次
+    pub(crate) fn run_cli(args: &[String], out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (count, kill) = match args[1..] {
+            [count] => (count, None),
+            [count, "--kill", index] => (count, Some(index.parse::<usize>()?)),
+            _ => return Err("usage: gossip <nodes> [--kill <index>]".into()),
+        };
+        let count: usize = count.parse()?;
+        if count < 2 || kill.is_some_and(|index| index >= count) {
+            return Err("need at least 2 nodes and a node index below the node count".into());
+        }
+        // 每个节点只知道第一个节点，靠 gossip 认识其他所有人
+        let first = GossipNode::start(&[])?;
+        let mut nodes = vec![first];
+        for _ in 1..count {
+            let node = GossipNode::start(&[nodes[0].addr()])?;
+            nodes.push(node);
+        }
+        let start = Instant::now();
+        let timeout = Duration::from_secs(20);
+        let view = wait_until(timeout, || {
+            converged(&nodes.iter().collect::<Vec<_>>())
+                .filter(|view| view.len() == count && view.iter().all(|m| m.state == State::Alive))
+        })
+        .ok_or("membership did not converge")?;
+        writeln!(out, "converged after {:?}", start.elapsed())?;
+        write!(out, "{}", format_view(&view))?;
+
+        if let Some(index) = kill {
+            let dead = nodes[index].addr();
+            nodes[index].stop();
+            let start = Instant::now();
+            let rest: Vec<&GossipNode> = nodes
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != index)
+                .map(|(_, node)| node)
+                .collect();
+            let view = wait_until(timeout, || {
+                converged(&rest).filter(|view| {
+                    view.iter()
+                        .all(|m| (m.addr == dead) == (m.state == State::Dead))
+                })
+            })
+            .ok_or("failure was not detected")?;
+            writeln!(out, "{} declared dead after {:?}", dead, start.elapsed())?;
+            write!(out, "{}", format_view(&view))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::gossip::*;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn member(port: u16, incarnation: u64, state: State) -> Member {
+        Member {
+            addr: addr(port),
+            incarnation,
+            state,
+        }
+    }
+
+    #[test]
+    fn merge_prefers_newer_incarnations() {
+        let now = Instant::now();
+        let mut view = Membership::new(addr(1), &[addr(2)]);
+        // 同一个 incarnation 下可疑覆盖存活，存活的旧消息不能撤销怀疑
+        view.merge(&member(2, 0, State::Suspect), now);
+        view.merge(&member(2, 0, State::Alive), now);
+        assert_eq!(view.members()[1], member(2, 0, State::Suspect));
+        // 更大的 incarnation 澄清怀疑
+        view.merge(&member(2, 1, State::Alive), now);
+        assert_eq!(view.members()[1], member(2, 1, State::Alive));
+        view.merge(&member(2, 1, State::Dead), now);
+        view.merge(&member(3, 4, State::Alive), now);
+        assert_eq!(
+            view.members(),
+            [
+                member(1, 0, State::Alive),
+                member(2, 1, State::Dead),
+                member(3, 4, State::Alive)
+            ]
+        );
+        // 听说自己被怀疑：incarnation 变得比流言大，继续声明存活
+        view.merge(&member(1, 0, State::Suspect), now);
+        view.merge(&member(1, 0, State::Dead), now);
+        assert_eq!(view.members()[0], member(1, 1, State::Alive));
+    }
+
+    #[test]
+    fn detects_a_stopped_node() {
+        let first = GossipNode::start(&[]).unwrap();
+        let mut nodes = vec![first];
+        for _ in 0..4 {
+            let node = GossipNode::start(&[nodes[0].addr()]).unwrap();
+            nodes.push(node);
+        }
+        let view = wait_until(Duration::from_secs(20), || {
+            converged(&nodes.iter().collect::<Vec<_>>()).filter(|view| view.len() == 5)
+        })
+        .expect("membership did not converge");
+        assert!(view.iter().all(|m| m.state == State::Alive));
+
+        let mut stopped = nodes.remove(2);
+        let dead = stopped.addr();
+        stopped.stop();
+        let view = wait_until(Duration::from_secs(20), || {
+            let view = converged(&nodes.iter().collect::<Vec<_>>())?;
+            view.iter()
+                .any(|m| m.addr == dead && m.state == State::Dead)
+                .then_some(view)
+        })
+        .expect("failure was not detected");
+        // 其余节点都还活着：误判的怀疑已经被各自的 incarnation 澄清
+        assert!(view
+            .iter()
+            .all(|m| m.addr == dead || m.state == State::Alive));
+    }
+
+    #[test]
+    fn cli_prints_the_converged_view() {
+        let args =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|arg| arg.to_string()).collect() };
+        let mut out = Vec::new();
+        run_cli(&args(&["gossip", "3", "--kill", "1"]), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("converged after"));
+        assert!(lines[1].starts_with("member"));
+        assert_eq!(
+            lines[2..5]
+                .iter()
+                .filter(|line| line.ends_with("alive"))
+                .count(),
+            3
+        );
+        assert!(lines[5].contains("declared dead"));
+        assert_eq!(
+            lines[7..10]
+                .iter()
+                .filter(|line| line.ends_with("dead"))
+                .count(),
+            1
+        );
+        assert!(run_cli(&args(&["gossip"]), &mut Vec::new()).is_err());
+        assert!(run_cli(&args(&["gossip", "2", "--kill", "5"]), &mut Vec::new()).is_err());
+    }
+}
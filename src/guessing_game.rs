@@ -2,44 +2,727 @@
 #[cfg(test)]
 mod tests {
 
-    use rand::Rng;
+    use crate::i18n_example::i18n::builtin;
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+    use ratatui::backend::{Backend, TestBackend};
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::widgets::{Block, Borders, Gauge, List, Paragraph};
+    use ratatui::{Frame, Terminal};
     use std::cmp::Ordering;
-    use std::io;
+    use std::env;
+    use std::fmt;
+    use std::io::{self, BufRead, Write};
+    use std::ops::RangeInclusive;
+    use std::time::Duration;
 
-    #[test]
-    fn guessing_game() {
-        println!("Guess the number!");
+    // 开始时选择难度：范围越大、次数越少越难。Medium 和 Hard 的次数刚好够用二分法猜中，Easy 留了余量
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Difficulty {
+        Easy,
+        Medium,
+        Hard,
+    }
+
+    impl Difficulty {
+        // 输入编号或者名字，不区分大小写；直接回车选 Medium
+        fn parse(input: &str) -> Option<Difficulty> {
+            match input.trim().to_lowercase().as_str() {
+                "1" | "easy" => Some(Difficulty::Easy),
+                "" | "2" | "medium" => Some(Difficulty::Medium),
+                "3" | "hard" => Some(Difficulty::Hard),
+                _ => None,
+            }
+        }
 
-        // 生成一个1～100的随机数
-        let secret_number = rand::thread_rng().gen_range(1..101);
-        println!("The secret number is: {}", secret_number);
+        fn range(self) -> RangeInclusive<u32> {
+            match self {
+                Difficulty::Easy => 1..=50,
+                Difficulty::Medium => 1..=100,
+                Difficulty::Hard => 1..=500,
+            }
+        }
 
+        fn attempts(self) -> u32 {
+            match self {
+                Difficulty::Easy => 10,
+                Difficulty::Medium => 7,
+                Difficulty::Hard => 9,
+            }
+        }
+    }
+
+    // 读入难度，不认识的输入重新问；输入结束时返回 None
+    fn choose_difficulty(
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> io::Result<Option<Difficulty>> {
         loop {
-            println!("Please input your guess.");
+            writeln!(
+                output,
+                "Choose a difficulty: 1) Easy  2) Medium  3) Hard [2]"
+            )?;
+            let mut choice = String::new();
+            if input.read_line(&mut choice)? == 0 {
+                return Ok(None);
+            }
+            if let Some(difficulty) = Difficulty::parse(&choice) {
+                let range = difficulty.range();
+                writeln!(
+                    output,
+                    "{:?}: a number from {} to {}, {} attempts.",
+                    difficulty,
+                    range.start(),
+                    range.end(),
+                    difficulty.attempts()
+                )?;
+                return Ok(Some(difficulty));
+            }
+        }
+    }
+
+    // 秘密数字的来源：默认用 thread_rng；给了种子时用 StdRng，同一个种子每次生成同样的一串秘密数字，
+    // 测试和演示可以原样重放
+    fn secret_rng(seed: Option<u64>) -> Box<dyn RngCore> {
+        match seed {
+            Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+            None => Box::new(rand::thread_rng()),
+        }
+    }
+
+    // 一次猜测的结果。最后一次机会也没猜中时是 OutOfTries 而不是 TooSmall/TooBig
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Outcome {
+        TooSmall,
+        TooBig,
+        Win,
+        OutOfTries,
+    }
+
+    // 提示模式：每次没猜中时比较这次和上一次离答案的距离（更近了/更远了）；
+    // 每错 HINT_EVERY 次再透露一条整除的性质，依次用 DIVISORS 里的数，用完就不再给
+    const HINT_EVERY: usize = 3;
+    const DIVISORS: [u32; 4] = [2, 3, 5, 7];
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Hint {
+        Warmer,
+        Colder,
+        Same,
+        DivisibleBy(u32),
+        NotDivisibleBy(u32),
+    }
+
+    impl fmt::Display for Hint {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Hint::Warmer => write!(f, "Warmer!"),
+                Hint::Colder => write!(f, "Colder!"),
+                Hint::Same => write!(f, "Neither warmer nor colder."),
+                Hint::DivisibleBy(n) => write!(f, "Hint: the number is divisible by {}.", n),
+                Hint::NotDivisibleBy(n) => {
+                    write!(f, "Hint: the number is not divisible by {}.", n)
+                }
+            }
+        }
+    }
+
+    // 游戏规则本身：没有 println!，也不读 stdin，秘密数字由调用方给出，测试里每一步都是确定的
+    struct GameEngine {
+        secret: u32,
+        difficulty: Difficulty,
+        remaining: u32,
+        won: bool,
+        // 算次数的猜测，按先后顺序
+        history: Vec<u32>,
+    }
+
+    impl GameEngine {
+        fn new(secret: u32, difficulty: Difficulty) -> GameEngine {
+            assert!(difficulty.range().contains(&secret));
+            GameEngine {
+                secret,
+                difficulty,
+                remaining: difficulty.attempts(),
+                won: false,
+                history: Vec::new(),
+            }
+        }
+
+        // 在所选难度的范围里生成一个随机数
+        fn random(difficulty: Difficulty, rng: &mut impl Rng) -> GameEngine {
+            GameEngine::new(rng.gen_range(difficulty.range()), difficulty)
+        }
+
+        // 超出范围的猜测不算次数，由调用方先用这个检查
+        fn accepts(&self, guess: u32) -> bool {
+            self.difficulty.range().contains(&guess)
+        }
+
+        // 游戏结束以后再猜，结果保持不变
+        fn guess(&mut self, guess: u32) -> Outcome {
+            if self.won {
+                return Outcome::Win;
+            }
+            if self.remaining == 0 {
+                return Outcome::OutOfTries;
+            }
+            self.remaining -= 1;
+            self.history.push(guess);
+            // 模式匹配/比较大小
+            match guess.cmp(&self.secret) {
+                Ordering::Equal => {
+                    self.won = true;
+                    Outcome::Win
+                }
+                _ if self.remaining == 0 => Outcome::OutOfTries,
+                Ordering::Less => Outcome::TooSmall,
+                Ordering::Greater => Outcome::TooBig,
+            }
+        }
+
+        fn remaining(&self) -> u32 {
+            self.remaining
+        }
+
+        fn secret(&self) -> u32 {
+            self.secret
+        }
+
+        fn history(&self) -> &[u32] {
+            &self.history
+        }
+
+        // 最近一次猜测之后可以给的提示；猜中了就没有提示
+        fn hints(&self) -> Vec<Hint> {
+            let mut hints = Vec::new();
+            if self.won {
+                return hints;
+            }
+            if let [.., previous, latest] = self.history[..] {
+                let before = previous.abs_diff(self.secret);
+                let now = latest.abs_diff(self.secret);
+                hints.push(match now.cmp(&before) {
+                    Ordering::Less => Hint::Warmer,
+                    Ordering::Greater => Hint::Colder,
+                    Ordering::Equal => Hint::Same,
+                });
+            }
+            let misses = self.history.len();
+            if misses > 0 && misses.is_multiple_of(HINT_EVERY) {
+                if let Some(&n) = DIVISORS.get(misses / HINT_EVERY - 1) {
+                    hints.push(if self.secret.is_multiple_of(n) {
+                        Hint::DivisibleBy(n)
+                    } else {
+                        Hint::NotDivisibleBy(n)
+                    });
+                }
+            }
+            hints
+        }
+    }
+
+    // 命令行这一层只管读输入、打印结果，规则都在 GameEngine 里。输入和输出作为参数传进来，测试时可以换成内存里的数据
+    // 不是数字、超出范围的输入不算次数；输入结束（例如 stdin 被关闭）算输。返回是否猜中
+    // hints 打开提示模式，每次没猜中之后打印 GameEngine::hints
+    fn play(
+        engine: &mut GameEngine,
+        hints: bool,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> io::Result<bool> {
+        let range = engine.difficulty.range();
+        loop {
+            writeln!(output, "Please input your guess.")?;
 
             let mut guess = String::new();
 
             // 从标准输入中读取数据
-            io::stdin()
-                .read_line(&mut guess)
-                .expect("Failed to read line");
+            if input.read_line(&mut guess)? == 0 {
+                writeln!(output, "The number was {}.", engine.secret())?;
+                return Ok(false);
+            }
 
             let guess: u32 = match guess.trim().parse() {
                 Ok(num) => num,
                 Err(_) => continue,
             };
+            if !engine.accepts(guess) {
+                writeln!(
+                    output,
+                    "Please guess between {} and {}.",
+                    range.start(),
+                    range.end()
+                )?;
+                continue;
+            }
 
-            println!("You guessed: {}", guess);
+            writeln!(output, "You guessed: {}", guess)?;
+            let left = builtin().plural("en", "attempts_left", engine.remaining() as u64 - 1, &[]);
+            match engine.guess(guess) {
+                Outcome::TooSmall => writeln!(output, "Too small! {}.", left)?,
+                Outcome::TooBig => writeln!(output, "Too big! {}.", left)?,
+                Outcome::Win => {
+                    writeln!(output, "You win!")?;
+                    return Ok(true);
+                }
+                Outcome::OutOfTries => {
+                    writeln!(
+                        output,
+                        "Out of attempts! The number was {}.",
+                        engine.secret()
+                    )?;
+                    return Ok(false);
+                }
+            }
+            if hints {
+                for hint in engine.hints() {
+                    writeln!(output, "{}", hint)?;
+                }
+            }
+        }
+    }
 
-            // 模式匹配/比较大小
-            match guess.cmp(&secret_number) {
-                Ordering::Less => println!("Too small!"),
-                Ordering::Greater => println!("Too big!"),
-                Ordering::Equal => {
-                    println!("You win!");
-                    break;
+    // 自动模式：用二分法和引擎对局，每一步只看 guess 的结果，不偷看秘密数字。打印每一次试探，返回依次猜过的数
+    fn autoplay(engine: &mut GameEngine, output: &mut impl Write) -> io::Result<Vec<u32>> {
+        let (mut low, mut high) = engine.difficulty.range().into_inner();
+        let mut probes = Vec::new();
+        loop {
+            // 不写成 (low + high) / 2，范围再大也不会溢出
+            let probe = low + (high - low) / 2;
+            probes.push(probe);
+            let outcome = engine.guess(probe);
+            writeln!(
+                output,
+                "Probe {}: {} in {}..={} -> {:?}",
+                probes.len(),
+                probe,
+                low,
+                high,
+                outcome
+            )?;
+            match outcome {
+                Outcome::TooSmall => low = probe + 1,
+                Outcome::TooBig => high = probe - 1,
+                Outcome::Win | Outcome::OutOfTries => return Ok(probes),
+            }
+        }
+    }
+
+    // 第二个前端：终端界面。规则还是 GameEngine，只是输入从逐行读 stdin 换成了逐个按键的事件，输出换成了每一帧重画的界面：
+    // 上面是状态和输入框，中间是剩余次数的进度条，下面是猜过的数。界面只由 App 的状态决定，draw 不修改状态
+    struct App {
+        engine: GameEngine,
+        input: String,
+        // 每次算次数的猜测和结果，最新的在最后
+        log: Vec<(u32, Outcome)>,
+        status: String,
+        // 猜中或者次数用完以后只能退出
+        finished: bool,
+        quit: bool,
+    }
+
+    impl App {
+        fn new(engine: GameEngine) -> App {
+            let range = engine.difficulty.range();
+            App {
+                status: format!("Guess a number from {} to {}.", range.start(), range.end()),
+                engine,
+                input: String::new(),
+                log: Vec::new(),
+                finished: false,
+                quit: false,
+            }
+        }
+
+        // 数字追加到输入框，Backspace 删掉最后一位，Enter 提交；Esc、q、Ctrl-C 退出
+        fn on_key(&mut self, key: KeyEvent) {
+            // Windows 上松开按键也会产生事件，只处理按下
+            if key.kind != KeyEventKind::Press {
+                return;
+            }
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.quit = true
+                }
+                KeyCode::Esc | KeyCode::Char('q') => self.quit = true,
+                _ if self.finished => {}
+                // 最多输入 3 位，范围最大到 500
+                KeyCode::Char(digit @ '0'..='9') if self.input.len() < 3 => self.input.push(digit),
+                KeyCode::Backspace => {
+                    self.input.pop();
                 }
+                KeyCode::Enter => self.submit(),
+                _ => {}
+            }
+        }
+
+        fn submit(&mut self) {
+            let guess = match self.input.parse() {
+                Ok(guess) if self.engine.accepts(guess) => guess,
+                _ => {
+                    let range = self.engine.difficulty.range();
+                    self.status = format!(
+                        "Please guess between {} and {}.",
+                        range.start(),
+                        range.end()
+                    );
+                    return;
+                }
+            };
+            self.input.clear();
+            let outcome = self.engine.guess(guess);
+            self.log.push((guess, outcome));
+            self.status = match outcome {
+                Outcome::TooSmall => format!("{} is too small.", guess),
+                Outcome::TooBig => format!("{} is too big.", guess),
+                Outcome::Win => format!("You win! The number was {}.", guess),
+                Outcome::OutOfTries => {
+                    format!("Out of attempts! The number was {}.", self.engine.secret())
+                }
+            };
+            self.finished = matches!(outcome, Outcome::Win | Outcome::OutOfTries);
+            if self.finished {
+                self.status.push_str(" Press q to quit.");
+            }
+        }
+
+        fn draw(&self, frame: &mut Frame) {
+            let [status, input, progress, history] = Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Min(3),
+            ])
+            .areas(frame.area());
+
+            frame.render_widget(Paragraph::new(self.status.as_str()), status);
+            frame.render_widget(
+                Paragraph::new(self.input.as_str())
+                    .block(Block::default().borders(Borders::ALL).title(" Your guess ")),
+                input,
+            );
+            let attempts = self.engine.difficulty.attempts();
+            let remaining = self.engine.remaining();
+            frame.render_widget(
+                Gauge::default()
+                    .block(Block::default().borders(Borders::ALL).title(" Attempts "))
+                    .ratio(remaining as f64 / attempts as f64)
+                    .label(format!("{}/{} left", remaining, attempts)),
+                progress,
+            );
+            // 最新的猜测放在最上面
+            let items: Vec<String> = self
+                .log
+                .iter()
+                .rev()
+                .map(|(guess, outcome)| format!("{:>3}  {:?}", guess, outcome))
+                .collect();
+            frame.render_widget(
+                List::new(items).block(Block::default().borders(Borders::ALL).title(" History ")),
+                history,
+            );
+        }
+    }
+
+    // 事件循环：画一帧，等下一个事件，更新状态，再画一帧，直到退出
+    // next_event 返回 None 表示这一轮没有事件（等待超时），也重画一次；终端大小变化时同样只需要重画
+    fn run_tui<B: Backend>(
+        terminal: &mut Terminal<B>,
+        app: &mut App,
+        mut next_event: impl FnMut() -> io::Result<Option<Event>>,
+    ) -> io::Result<()> {
+        while !app.quit {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Some(Event::Key(key)) = next_event()? {
+                app.on_key(key);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn guessing_game() {
+        println!("Guess the number!");
+
+        let mut input = io::stdin().lock();
+        let mut output = io::stdout();
+        let difficulty =
+            match choose_difficulty(&mut input, &mut output).expect("Failed to read line") {
+                Some(difficulty) => difficulty,
+                None => return,
+            };
+
+        // GUESS_SEED=42 cargo test guessing_game 每次都是同一个秘密数字
+        let seed = env::var("GUESS_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok());
+        let mut engine = GameEngine::random(difficulty, &mut secret_rng(seed));
+        println!("The secret number is: {}", engine.secret());
+
+        // GUESS_AUTO=1 让二分法自己来猜
+        if env::var("GUESS_AUTO").is_ok_and(|auto| auto == "1") {
+            let probes = autoplay(&mut engine, &mut output).expect("Failed to write");
+            println!("Solved in {} guesses.", probes.len());
+            return;
+        }
+
+        // GUESS_HINTS=1 打开提示模式
+        let hints = env::var("GUESS_HINTS").is_ok_and(|hints| hints == "1");
+        play(&mut engine, hints, &mut input, &mut output).expect("Failed to read line");
+    }
+
+    #[test]
+    fn difficulty_levels() {
+        let choose =
+            |typed: &str| choose_difficulty(&mut typed.as_bytes(), &mut Vec::new()).unwrap();
+        assert_eq!(choose("hard\n"), Some(Difficulty::Hard));
+        assert_eq!(choose("\n"), Some(Difficulty::Medium));
+        assert_eq!(choose("impossible\n1\n"), Some(Difficulty::Easy));
+        assert_eq!(choose(""), None);
+        // 二分法在每个难度下都能在次数之内猜中任何数
+        for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            let range = difficulty.range();
+            let worst = (range.end() - range.start() + 1).ilog2() + 1;
+            assert!(worst <= difficulty.attempts(), "{:?}", difficulty);
+        }
+
+        let run = |secret: u32, difficulty: Difficulty, typed: &str| {
+            let mut output = Vec::new();
+            let mut engine = GameEngine::new(secret, difficulty);
+            let won = play(&mut engine, false, &mut typed.as_bytes(), &mut output).unwrap();
+            (won, String::from_utf8(output).unwrap())
+        };
+        let (won, transcript) = run(42, Difficulty::Medium, "50\nabc\n101\n25\n42\n");
+        assert!(won);
+        assert!(transcript.contains("Too big! 6 attempts left."));
+        assert!(transcript.contains("Please guess between 1 and 100."));
+        assert!(transcript.contains("Too small! 5 attempts left."));
+        assert!(transcript.ends_with("You win!\n"));
+
+        let (won, transcript) = run(300, Difficulty::Hard, &"1\n".repeat(9));
+        assert!(!won);
+        assert!(transcript.contains("Too small! 1 attempt left."));
+        assert!(transcript.ends_with("You guessed: 1\nOut of attempts! The number was 300.\n"));
+        assert!(!run(7, Difficulty::Easy, "1\n").0);
+    }
+
+    #[test]
+    fn engine_outcomes() {
+        let mut engine = GameEngine::new(37, Difficulty::Medium);
+        assert_eq!(engine.guess(50), Outcome::TooBig);
+        assert_eq!(engine.guess(25), Outcome::TooSmall);
+        assert_eq!(engine.remaining(), 5);
+        assert_eq!(engine.guess(37), Outcome::Win);
+        // 赢了以后结果不变，也不再扣次数
+        assert_eq!(engine.guess(1), Outcome::Win);
+        assert_eq!(engine.remaining(), 4);
+
+        // 最后一次没猜中就结束
+        let mut engine = GameEngine::new(2, Difficulty::Medium);
+        let outcomes: Vec<Outcome> = (0..8).map(|_| engine.guess(100)).collect();
+        assert_eq!(outcomes[..6], [Outcome::TooBig; 6]);
+        assert_eq!(outcomes[6..], [Outcome::OutOfTries; 2]);
+        assert!(engine.accepts(100) && !engine.accepts(0) && !engine.accepts(101));
+    }
+
+    #[test]
+    fn seeded_games_replay() {
+        // 同一个种子连续开几局，秘密数字的序列完全一样；换一个种子就不一样了
+        let secrets = |seed: Option<u64>| -> Vec<u32> {
+            let mut rng = secret_rng(seed);
+            (0..20)
+                .map(|_| GameEngine::random(Difficulty::Hard, &mut rng).secret())
+                .collect()
+        };
+        assert_eq!(secrets(Some(42)), secrets(Some(42)));
+        assert_ne!(secrets(Some(42)), secrets(Some(43)));
+        assert!(secrets(None)
+            .iter()
+            .all(|secret| Difficulty::Hard.range().contains(secret)));
+
+        // 重放同一局：同样的种子和同样的输入得到一模一样的记录
+        let transcript = || {
+            let mut engine = GameEngine::random(Difficulty::Medium, &mut secret_rng(Some(42)));
+            let mut output = Vec::new();
+            play(
+                &mut engine,
+                true,
+                &mut "50\n25\n75\n".as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(transcript(), transcript());
+    }
+
+    #[test]
+    fn warmer_colder_hints() {
+        let mut engine = GameEngine::new(42, Difficulty::Hard);
+        // 第一次猜测没有可以比较的
+        engine.guess(100);
+        assert_eq!(engine.hints(), []);
+        engine.guess(60);
+        assert_eq!(engine.hints(), [Hint::Warmer]);
+        // 第三次没中：冷暖之外还有第一条整除提示
+        engine.guess(400);
+        assert_eq!(engine.hints(), [Hint::Colder, Hint::DivisibleBy(2)]);
+        engine.guess(38);
+        engine.guess(46);
+        assert_eq!(engine.hints(), [Hint::Same]);
+        engine.guess(41);
+        assert_eq!(engine.hints(), [Hint::Warmer, Hint::DivisibleBy(3)]);
+        assert_eq!(engine.history(), [100, 60, 400, 38, 46, 41]);
+        // 猜中的那次也记在历史里，猜中之后没有提示
+        assert_eq!(engine.guess(42), Outcome::Win);
+        assert_eq!(engine.hints(), []);
+        assert_eq!(engine.history().len(), 7);
+
+        // 整除提示用完就不再给
+        let mut engine = GameEngine::new(35, Difficulty::Hard);
+        let divisibility: Vec<Hint> = (0..8)
+            .flat_map(|_| {
+                engine.guess(1);
+                engine.hints()
+            })
+            .filter(|hint| !matches!(hint, Hint::Same))
+            .collect();
+        assert_eq!(
+            divisibility,
+            [Hint::NotDivisibleBy(2), Hint::NotDivisibleBy(3)]
+        );
+
+        let mut output = Vec::new();
+        let mut engine = GameEngine::new(42, Difficulty::Medium);
+        let typed = "10\n30\n90\n42\n";
+        assert!(play(&mut engine, true, &mut typed.as_bytes(), &mut output).unwrap());
+        let transcript = String::from_utf8(output).unwrap();
+        assert!(transcript.contains("Too small! 5 attempts left.\nWarmer!\n"));
+        assert!(transcript
+            .contains("Too big! 4 attempts left.\nColder!\nHint: the number is divisible by 2.\n"));
+    }
+
+    #[test]
+    fn autoplayer_always_wins() {
+        // 每个难度下把所有可能的秘密数字都试一遍，最多猜 ceil(log2(范围大小)) 次
+        for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            let size = difficulty.range().count() as u32;
+            let bound = (size - 1).ilog2() + 1;
+            for secret in difficulty.range() {
+                let mut engine = GameEngine::new(secret, difficulty);
+                let probes = autoplay(&mut engine, &mut io::sink()).unwrap();
+                assert_eq!(probes.last(), Some(&secret), "{:?}", difficulty);
+                assert!(probes.len() as u32 <= bound, "{:?} {}", difficulty, secret);
+            }
+        }
+
+        let mut output = Vec::new();
+        let mut engine = GameEngine::new(37, Difficulty::Medium);
+        assert_eq!(autoplay(&mut engine, &mut output).unwrap(), [50, 25, 37]);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Probe 1: 50 in 1..=100 -> TooBig\n\
+             Probe 2: 25 in 1..=49 -> TooSmall\n\
+             Probe 3: 37 in 26..=49 -> Win\n"
+        );
+    }
+
+    // 在真正的终端里玩：进入备用屏幕和 raw 模式，按键不用回车就能读到；退出时恢复终端
+    #[test]
+    #[ignore]
+    fn guessing_game_tui() {
+        let mut app = App::new(GameEngine::random(
+            Difficulty::Medium,
+            &mut rand::thread_rng(),
+        ));
+        let mut terminal = ratatui::init();
+        // 250 毫秒没有按键也醒一次
+        let result = run_tui(&mut terminal, &mut app, || {
+            if event::poll(Duration::from_millis(250))? {
+                event::read().map(Some)
+            } else {
+                Ok(None)
+            }
+        });
+        ratatui::restore();
+        result.unwrap();
+        println!("The number was {}.", app.engine.secret());
+    }
+
+    // 界面的每一行，去掉行尾的空格
+    fn screen(terminal: &Terminal<TestBackend>) -> Vec<String> {
+        let buffer = terminal.backend().buffer();
+        let width = buffer.area.width as usize;
+        buffer
+            .content
+            .chunks(width)
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.symbol()).collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tui_front_end() {
+        let key = |code: KeyCode| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        let typed = |text: &str| {
+            text.chars()
+                .map(|c| key(KeyCode::Char(c)))
+                .collect::<Vec<_>>()
+        };
+        // 输入 50 回车；输入 999（只收前 3 位）后删掉重来；输入 25 回车；超出范围的 0；最后猜中 37，再按 q 退出
+        let mut events = typed("50");
+        events.push(key(KeyCode::Enter));
+        events.extend(typed("9999"));
+        events.extend((0..3).map(|_| key(KeyCode::Backspace)));
+        events.extend(typed("25"));
+        events.push(key(KeyCode::Enter));
+        events.extend(typed("0"));
+        events.push(key(KeyCode::Enter));
+        events.push(key(KeyCode::Backspace));
+        events.extend(typed("37"));
+        events.push(key(KeyCode::Enter));
+        // 结束以后输入数字没有反应
+        events.extend(typed("1"));
+        let frames = events.len();
+        events.extend(typed("q"));
+
+        let mut terminal = Terminal::new(TestBackend::new(50, 14)).unwrap();
+        let mut app = App::new(GameEngine::new(37, Difficulty::Medium));
+        let mut events = events.into_iter();
+        let mut drawn = 0;
+        run_tui(&mut terminal, &mut app, || {
+            drawn += 1;
+            Ok(events.next())
+        })
+        .unwrap();
+        assert_eq!(drawn, frames + 1);
+        assert!(app.quit && app.finished);
+        assert_eq!(app.engine.history(), [50, 25, 37]);
+
+        // 退出前的最后一帧
+        let lines = screen(&terminal);
+        assert_eq!(lines[0], "You win! The number was 37. Press q to quit.");
+        assert_eq!(lines[2], format!("│{}│", " ".repeat(48)));
+        assert!(lines[5].contains("4/7 left"));
+        assert!(lines[8].contains(" 37  Win"));
+        assert!(lines[9].contains(" 25  TooSmall"));
+        assert!(lines[10].contains(" 50  TooBig"));
+
+        // 超出范围的输入只更新状态，不算次数
+        let mut app = App::new(GameEngine::new(37, Difficulty::Medium));
+        for event in typed("0") {
+            if let Event::Key(key) = event {
+                app.on_key(key);
             }
         }
+        app.on_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(app.status, "Please guess between 1 and 100.");
+        assert_eq!(app.engine.remaining(), 7);
+        app.on_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        assert!(app.quit);
     }
 }
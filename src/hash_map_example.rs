@@ -0,0 +1,294 @@
+// 自己实现一个哈希表：开放寻址 + 线性探测
+// - 所有条目直接放在一个数组（槽）里。键的哈希值对容量取模得到它的"家"，家被占了就往后一个一个找空槽
+// - 删除时不能简单地把槽清空：后面可能有因为这个槽被占而放到更后面的键，查找它们时会在空槽处提前停下。
+//   这里用"向后移动"的删法：清空之后把后面同一串里能往前挪的条目挪过来补位，不需要墓碑标记
+// - 负载因子（条目数 / 容量）越高，一串连续被占的槽就越长，查找越慢，所以超过 3/4 就把容量翻倍，所有条目重新放一遍
+// - 容量总是 2 的幂，取模可以用按位与
+#[cfg(test)]
+pub(crate) mod my_hash_map {
+
+    use std::borrow::Borrow;
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hash};
+    use std::mem;
+
+    const INITIAL_CAPACITY: usize = 8;
+
+    pub(crate) struct MyHashMap<K, V, S = RandomState> {
+        slots: Vec<Option<(K, V)>>,
+        len: usize,
+        hasher: S,
+    }
+
+    impl<K: Hash + Eq, V> MyHashMap<K, V> {
+        pub(crate) fn new() -> MyHashMap<K, V> {
+            MyHashMap::with_hasher(RandomState::new())
+        }
+    }
+
+    impl<K: Hash + Eq, V, S: BuildHasher> MyHashMap<K, V, S> {
+        // 用指定的哈希函数，测试里可以故意用一个很差的哈希函数制造大量冲突
+        pub(crate) fn with_hasher(hasher: S) -> MyHashMap<K, V, S> {
+            MyHashMap {
+                slots: Vec::new(),
+                len: 0,
+                hasher,
+            }
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.len
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub(crate) fn capacity(&self) -> usize {
+            self.slots.len()
+        }
+
+        fn mask(&self) -> usize {
+            self.slots.len() - 1
+        }
+
+        // 键的家：哈希值的低位
+        fn home<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+            self.hasher.hash_one(key) as usize & self.mask()
+        }
+
+        // 从家开始往后找，遇到这个键返回它的槽，遇到空槽说明不存在。负载因子不超过 3/4，一定会遇到空槽
+        fn find<Q>(&self, key: &Q) -> Option<usize>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            if self.len == 0 {
+                return None;
+            }
+            let mut i = self.home(key);
+            loop {
+                match &self.slots[i] {
+                    Some((k, _)) if k.borrow() == key => return Some(i),
+                    Some(_) => i = (i + 1) & self.mask(),
+                    None => return None,
+                }
+            }
+        }
+
+        pub(crate) fn get<Q>(&self, key: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            let i = self.find(key)?;
+            self.slots[i].as_ref().map(|(_, v)| v)
+        }
+
+        pub(crate) fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            let i = self.find(key)?;
+            self.slots[i].as_mut().map(|(_, v)| v)
+        }
+
+        pub(crate) fn contains_key<Q>(&self, key: &Q) -> bool
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            self.find(key).is_some()
+        }
+
+        // 键已经存在时替换值并返回旧值
+        pub(crate) fn insert(&mut self, key: K, value: V) -> Option<V> {
+            if let Some(i) = self.find(&key) {
+                let (_, old) = self.slots[i].as_mut().unwrap();
+                return Some(mem::replace(old, value));
+            }
+            // 放进去之后负载因子会超过 3/4 就先扩容
+            if (self.len + 1) * 4 > self.capacity() * 3 {
+                self.grow();
+            }
+            self.place(key, value);
+            self.len += 1;
+            None
+        }
+
+        // 放一个已知不存在的键：从家开始找第一个空槽
+        fn place(&mut self, key: K, value: V) {
+            let mut i = self.home(&key);
+            while self.slots[i].is_some() {
+                i = (i + 1) & self.mask();
+            }
+            self.slots[i] = Some((key, value));
+        }
+
+        // 容量翻倍。掩码变了，每个键的家也跟着变，所有条目都要重新放
+        fn grow(&mut self) {
+            let capacity = (self.capacity() * 2).max(INITIAL_CAPACITY);
+            let old = mem::replace(&mut self.slots, (0..capacity).map(|_| None).collect());
+            for (key, value) in old.into_iter().flatten() {
+                self.place(key, value);
+            }
+        }
+
+        pub(crate) fn remove<Q>(&mut self, key: &Q) -> Option<V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+        {
+            let mut hole = self.find(key)?;
+            let (_, value) = self.slots[hole].take().unwrap();
+            self.len -= 1;
+            // 向后移动：看空槽后面同一串里的条目，如果空槽在它的家和它现在的位置之间（绕回开头也算），
+            // 它当初就是因为这个槽被占才放到后面的，挪到空槽里，它原来的位置变成新的空槽。直到遇到空槽为止
+            let mut next = (hole + 1) & self.mask();
+            while let Some((k, _)) = &self.slots[next] {
+                let home = self.home(k);
+                let from_home = next.wrapping_sub(home) & self.mask();
+                let from_hole = next.wrapping_sub(hole) & self.mask();
+                if from_home >= from_hole {
+                    self.slots[hole] = self.slots[next].take();
+                    hole = next;
+                }
+                next = (next + 1) & self.mask();
+            }
+            Some(value)
+        }
+
+        // 顺序就是槽的顺序，和插入顺序无关
+        pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+            self.slots.iter().flatten().map(|(k, v)| (k, v))
+        }
+
+        // 从家到实际位置最多走了几步，用来观察冲突有多严重
+        pub(crate) fn longest_probe(&self) -> usize {
+            (0..self.capacity())
+                .filter_map(|i| {
+                    let (k, _) = self.slots[i].as_ref()?;
+                    Some(i.wrapping_sub(self.home(k)) & self.mask())
+                })
+                .max()
+                .unwrap_or(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::my_hash_map::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
+    use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+    #[test]
+    fn basic_operations_and_resizing() {
+        let mut map = MyHashMap::new();
+        assert!(map.is_empty() && map.get("a").is_none());
+        assert_eq!(map.insert(String::from("a"), 1), None);
+        assert_eq!(map.insert(String::from("b"), 2), None);
+        // 可以用 &str 查 String 的键
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.insert(String::from("a"), 10), Some(1));
+        *map.get_mut("b").unwrap() += 5;
+        assert_eq!(map.get("b"), Some(&7));
+        assert_eq!(map.remove("a"), Some(10));
+        assert_eq!(map.remove("a"), None);
+        assert_eq!(map.len(), 1);
+
+        // 容量从 8 开始，负载因子超过 3/4 时翻倍
+        let mut map = MyHashMap::new();
+        let mut capacities = Vec::new();
+        for i in 0..100 {
+            map.insert(i, i * i);
+            if capacities.last() != Some(&map.capacity()) {
+                capacities.push(map.capacity());
+            }
+            assert!(map.len() * 4 <= map.capacity() * 3);
+        }
+        assert_eq!(capacities, [8, 16, 32, 64, 128, 256]);
+        assert!((0..100).all(|i| map.get(&i) == Some(&(i * i))));
+        let mut entries: Vec<(i32, i32)> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        assert_eq!(entries, (0..100).map(|i| (i, i * i)).collect::<Vec<_>>());
+    }
+
+    // 随机的插入、删除、查找序列，每一步都和标准库的 HashMap 对照
+    fn compare_with_std<S: BuildHasher>(mut mine: MyHashMap<u32, u64, S>, seed: u64, keys: u32) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut std = HashMap::new();
+        for step in 0..20_000u64 {
+            let key = rng.gen_range(0..keys);
+            match rng.gen_range(0..10) {
+                0..=4 => assert_eq!(
+                    mine.insert(key, step),
+                    std.insert(key, step),
+                    "step {}",
+                    step
+                ),
+                5..=7 => assert_eq!(mine.remove(&key), std.remove(&key), "step {}", step),
+                _ => assert_eq!(mine.get(&key), std.get(&key), "step {}", step),
+            }
+            assert_eq!(mine.len(), std.len());
+        }
+        // 最后逐个检查所有可能的键，包括已经删掉的
+        for key in 0..keys {
+            assert_eq!(mine.get(&key), std.get(&key), "key {}", key);
+        }
+        let mut entries: Vec<(u32, u64)> = mine.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        let mut expected: Vec<(u32, u64)> = std.into_iter().collect();
+        expected.sort();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn behaves_like_std_hash_map() {
+        for seed in 0..5 {
+            // 键的范围小，同一个键会被反复插入和删除
+            compare_with_std(MyHashMap::new(), seed, 64);
+            compare_with_std(MyHashMap::new(), seed, 5_000);
+        }
+    }
+
+    // 很差的哈希函数：只看键的最低 3 位，大量的键挤在同几个家上，探测串很长，删除时的向后移动也会经常绕回数组开头
+    #[derive(Default)]
+    struct LowBits(u64);
+
+    impl Hasher for LowBits {
+        fn finish(&self) -> u64 {
+            self.0 & 7
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 << 8) | b as u64;
+            }
+        }
+
+        fn write_u32(&mut self, n: u32) {
+            self.0 = n as u64;
+        }
+    }
+
+    #[test]
+    fn survives_heavy_collisions() {
+        type Colliding = BuildHasherDefault<LowBits>;
+        for seed in 0..5 {
+            compare_with_std(MyHashMap::with_hasher(Colliding::default()), seed, 200);
+        }
+        let mut map = MyHashMap::with_hasher(Colliding::default());
+        for i in 0..40u32 {
+            map.insert(i, ());
+        }
+        // 40 个键只有 8 个不同的家，有的键离家很远，但都还能找到
+        assert!(map.longest_probe() > 20, "{}", map.longest_probe());
+        assert!((0..40).all(|i| map.contains_key(&i)));
+        assert!(!map.contains_key(&40));
+    }
+}
@@ -0,0 +1,222 @@
+// 一致性哈希环：把哈希值空间 [0, 2^64) 首尾相连看成一个环，节点按哈希值放在环上，
+// 一个 key 归顺时针方向遇到的第一个节点。增删节点时只有相邻一段弧换了主人，
+// 而 hash(key) % n 这种做法 n 一变几乎所有的 key 都要搬家
+// 每个节点在环上放很多个虚拟节点，把它负责的弧切成许多小段，负载更均匀，增删节点时的迁移也分摊给所有节点
+#[cfg(test)]
+pub(crate) mod hash_ring {
+
+    use std::collections::BTreeMap;
+
+    // 环的周长 2^64，用 u128 算弧长，不会溢出
+    const SPAN: u128 = 1 << 64;
+
+    // FNV-1a 之后再用 splitmix64 的终结步骤打散：FNV 对只差最后几个字符的字符串（例如 node-1#7、node-1#8）
+    // 给出的高位很接近，直接用会让虚拟节点挤在一起
+    pub(crate) fn hash(key: &str) -> u64 {
+        let mut h: u64 = 0xcbf29ce484222325;
+        for byte in key.bytes() {
+            h ^= byte as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+        h ^= h >> 30;
+        h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+        h ^= h >> 27;
+        h = h.wrapping_mul(0x94d049bb133111eb);
+        h ^ (h >> 31)
+    }
+
+    // 一次拓扑变化搬动了多少：moved 是换了主人的那部分哈希空间占整个环的比例，
+    // 均匀分布的 key 中大约也是这个比例要迁移；transfers 按 (原来的节点, 新的节点) 细分
+    #[derive(Debug, Default)]
+    pub(crate) struct Rebalance {
+        pub(crate) moved: f64,
+        pub(crate) transfers: BTreeMap<(String, String), f64>,
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct HashRing {
+        vnodes: usize,
+        points: BTreeMap<u64, String>,
+    }
+
+    impl HashRing {
+        pub(crate) fn new(vnodes: usize) -> HashRing {
+            assert!(vnodes > 0);
+            HashRing {
+                vnodes,
+                points: BTreeMap::new(),
+            }
+        }
+
+        fn vnode_points(&self, node: &str) -> impl Iterator<Item = u64> + '_ {
+            let node = node.to_string();
+            (0..self.vnodes).map(move |i| hash(&format!("{}#{}", node, i)))
+        }
+
+        pub(crate) fn add(&mut self, node: &str) -> Rebalance {
+            let before = self.clone();
+            for point in before.vnode_points(node) {
+                // 两个虚拟节点撞到同一个位置的概率极小，撞上了就让先来的留着
+                self.points.entry(point).or_insert_with(|| node.to_string());
+            }
+            before.diff(self)
+        }
+
+        pub(crate) fn remove(&mut self, node: &str) -> Rebalance {
+            let before = self.clone();
+            self.points.retain(|_, owner| owner != node);
+            before.diff(self)
+        }
+
+        // 顺时针方向第一个不小于 h 的点，超过最后一个点就绕回开头
+        fn owner(&self, h: u64) -> Option<&str> {
+            self.points
+                .range(h..)
+                .next()
+                .or_else(|| self.points.iter().next())
+                .map(|(_, node)| node.as_str())
+        }
+
+        pub(crate) fn node_for(&self, key: &str) -> Option<&str> {
+            self.owner(hash(key))
+        }
+
+        // 环上的点把环切成若干段弧，(前一个点, 这个点] 归这个点。只有一个点时它拥有整个环
+        fn arcs(points: &[u64]) -> impl Iterator<Item = (u64, u128)> + '_ {
+            (0..points.len()).map(move |i| {
+                let end = points[i];
+                let start = points[(i + points.len() - 1) % points.len()];
+                let len = (end as u128 + SPAN - start as u128) % SPAN;
+                (end, if len == 0 { SPAN } else { len })
+            })
+        }
+
+        // 每个节点负责的哈希空间比例
+        pub(crate) fn ownership(&self) -> BTreeMap<String, f64> {
+            let points: Vec<u64> = self.points.keys().copied().collect();
+            let mut shares = BTreeMap::new();
+            for (end, len) in HashRing::arcs(&points) {
+                *shares.entry(self.points[&end].clone()).or_insert(0.0) += len as f64 / SPAN as f64;
+            }
+            shares
+        }
+
+        // 把两个环的点合在一起切弧，每段弧里两个环的主人都不变，比较一下就知道这段有没有换主人
+        fn diff(&self, after: &HashRing) -> Rebalance {
+            let mut points: Vec<u64> = self
+                .points
+                .keys()
+                .chain(after.points.keys())
+                .copied()
+                .collect();
+            points.sort_unstable();
+            points.dedup();
+            let mut rebalance = Rebalance::default();
+            for (end, len) in HashRing::arcs(&points) {
+                if let (Some(from), Some(to)) = (self.owner(end), after.owner(end)) {
+                    if from != to {
+                        let share = len as f64 / SPAN as f64;
+                        rebalance.moved += share;
+                        *rebalance
+                            .transfers
+                            .entry((from.to_string(), to.to_string()))
+                            .or_insert(0.0) += share;
+                    }
+                }
+            }
+            rebalance
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::hash_ring::*;
+    use std::collections::BTreeMap;
+
+    fn ring(nodes: usize, vnodes: usize) -> HashRing {
+        let mut ring = HashRing::new(vnodes);
+        for i in 0..nodes {
+            ring.add(&format!("node-{}", i));
+        }
+        ring
+    }
+
+    fn keys() -> Vec<String> {
+        (0..100_000).map(|i| format!("user:{}", i)).collect()
+    }
+
+    fn assign<'a>(ring: &'a HashRing, keys: &[String]) -> Vec<&'a str> {
+        keys.iter().map(|key| ring.node_for(key).unwrap()).collect()
+    }
+
+    #[test]
+    fn virtual_nodes_balance_the_load() {
+        let spread = |ring: &HashRing| {
+            let shares: Vec<f64> = ring.ownership().into_values().collect();
+            assert!((shares.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            let max = shares.iter().cloned().fold(0.0, f64::max);
+            let min = shares.iter().cloned().fold(1.0, f64::min);
+            max / min
+        };
+        // 每个节点只有一个点时弧长全凭运气；200 个虚拟节点时各节点的份额很接近
+        let (coarse, fine) = (spread(&ring(4, 1)), spread(&ring(4, 200)));
+        assert!(fine < 1.3, "max/min share {}", fine);
+        assert!(coarse > fine);
+        for share in ring(4, 200).ownership().values() {
+            assert!((share - 0.25).abs() < 0.05, "{}", share);
+        }
+        assert_eq!(HashRing::new(10).node_for("anything"), None);
+        assert_eq!(ring(1, 1).ownership()["node-0"], 1.0);
+    }
+
+    #[test]
+    fn adding_a_node_moves_only_its_share() {
+        let keys = keys();
+        let mut ring = ring(4, 200);
+        let before: Vec<String> = assign(&ring, &keys).into_iter().map(String::from).collect();
+        let rebalance = ring.add("node-4");
+        let after = assign(&ring, &keys);
+        let moved: Vec<usize> = (0..keys.len()).filter(|&i| before[i] != after[i]).collect();
+        let fraction = moved.len() as f64 / keys.len() as f64;
+        // 理想情况是 1/5；实际搬动的 key 的比例和算出来的弧长比例一致
+        assert!((fraction - 0.2).abs() < 0.05, "{}", fraction);
+        assert!((fraction - rebalance.moved).abs() < 0.01);
+        // 搬动的 key 全部去了新节点，来自原来的每一个节点
+        assert!(moved.iter().all(|&i| after[i] == "node-4"));
+        assert_eq!(rebalance.transfers.len(), 4);
+        assert!(rebalance.transfers.keys().all(|(_, to)| to == "node-4"));
+
+        // 对照：按 hash % n 分片，从 4 台加到 5 台时大约 4/5 的 key 要搬家
+        let modulo = keys
+            .iter()
+            .filter(|key| hash(key) % 4 != hash(key) % 5)
+            .count() as f64
+            / keys.len() as f64;
+        assert!(modulo > 0.75, "{}", modulo);
+    }
+
+    #[test]
+    fn removing_a_node_only_moves_its_keys() {
+        let keys = keys();
+        let mut ring = ring(5, 200);
+        let before: Vec<String> = assign(&ring, &keys).into_iter().map(String::from).collect();
+        let share = ring.ownership()["node-2"];
+        let rebalance = ring.remove("node-2");
+        assert!((rebalance.moved - share).abs() < 1e-9);
+        let after = assign(&ring, &keys);
+        let mut received: BTreeMap<&str, usize> = BTreeMap::new();
+        for i in 0..keys.len() {
+            if before[i] == "node-2" {
+                *received.entry(after[i]).or_insert(0) += 1;
+            } else {
+                assert_eq!(before[i], after[i]);
+            }
+        }
+        // 被删节点的 key 分散给了剩下的所有节点，而不是全压给一个邻居
+        assert_eq!(received.len(), 4);
+        assert!(rebalance.transfers.keys().all(|(from, _)| from == "node-2"));
+        assert_eq!(ring.remove("node-9").moved, 0.0);
+    }
+}
@@ -0,0 +1,333 @@
+// 二叉堆：用数组存一棵完全二叉树，下标 i 的孩子是 2i+1 和 2i+2，父节点是 (i-1)/2
+// 大顶堆的性质：每个节点都不小于它的孩子，所以堆顶（下标 0）就是最大值
+// - push 把新元素放在末尾，再和父节点比较、交换，一路上浮（sift up）
+// - pop 把末尾的元素换到堆顶，再和较大的孩子比较、交换，一路下沉（sift down）
+// 两者都只走一条从根到叶子的路径，O(log n)；和标准库的 BinaryHeap 一样是大顶堆，要小顶堆就用 Reverse 包一层
+#[cfg(test)]
+pub(crate) mod binary_heap {
+
+    pub(crate) struct BinaryHeap<T> {
+        data: Vec<T>,
+    }
+
+    impl<T: Ord> BinaryHeap<T> {
+        pub(crate) fn new() -> BinaryHeap<T> {
+            BinaryHeap { data: Vec::new() }
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.data.len()
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.data.is_empty()
+        }
+
+        pub(crate) fn peek(&self) -> Option<&T> {
+            self.data.first()
+        }
+
+        pub(crate) fn push(&mut self, value: T) {
+            self.data.push(value);
+            self.sift_up(self.data.len() - 1);
+        }
+
+        pub(crate) fn pop(&mut self) -> Option<T> {
+            if self.data.is_empty() {
+                return None;
+            }
+            let last = self.data.len() - 1;
+            self.data.swap(0, last);
+            let top = self.data.pop();
+            self.sift_down(0, self.data.len());
+            top
+        }
+
+        // 一直交换到父节点不比自己小为止
+        fn sift_up(&mut self, mut i: usize) {
+            while i > 0 {
+                let parent = (i - 1) / 2;
+                if self.data[i] <= self.data[parent] {
+                    break;
+                }
+                self.data.swap(i, parent);
+                i = parent;
+            }
+        }
+
+        // 和较大的孩子交换，直到两个孩子都不比自己大；只看前 len 个元素，堆排序时后面是已经排好的部分
+        fn sift_down(&mut self, mut i: usize, len: usize) {
+            loop {
+                let (left, right) = (2 * i + 1, 2 * i + 2);
+                let mut largest = i;
+                if left < len && self.data[left] > self.data[largest] {
+                    largest = left;
+                }
+                if right < len && self.data[right] > self.data[largest] {
+                    largest = right;
+                }
+                if largest == i {
+                    break;
+                }
+                self.data.swap(i, largest);
+                i = largest;
+            }
+        }
+
+        // 从后往前对每个非叶子节点做一次下沉就能建堆，总共 O(n)，比逐个 push 的 O(n log n) 快
+        pub(crate) fn from_vec(data: Vec<T>) -> BinaryHeap<T> {
+            let mut heap = BinaryHeap { data };
+            let len = heap.data.len();
+            for i in (0..len / 2).rev() {
+                heap.sift_down(i, len);
+            }
+            heap
+        }
+
+        // 堆排序：不断把堆顶换到末尾，堆缩小一格后再下沉，得到从小到大的序列
+        pub(crate) fn into_sorted_vec(mut self) -> Vec<T> {
+            let mut end = self.data.len();
+            while end > 1 {
+                end -= 1;
+                self.data.swap(0, end);
+                self.sift_down(0, end);
+            }
+            self.data
+        }
+
+        // 检查堆的性质，测试用
+        pub(crate) fn is_valid(&self) -> bool {
+            (1..self.data.len()).all(|i| self.data[(i - 1) / 2] >= self.data[i])
+        }
+    }
+}
+
+// Dijkstra 最短路径：边的权重不能是负数
+// 每次从优先队列里取出当前距离最小的顶点，它的距离就确定了，再用它去松弛相邻的顶点
+// 优先队列用上面的 BinaryHeap 加 Reverse 做成小顶堆；同一个顶点可能因为距离变短被放进去多次，
+// 取出来时发现比已知的距离大就是过期的，直接跳过（懒删除），这样就不需要堆支持"减小某个元素的键"
+#[cfg(test)]
+pub(crate) mod shortest_path {
+
+    use super::binary_heap::BinaryHeap;
+    use std::cmp::Reverse;
+
+    // 邻接表：edges[u] 是从 u 出发的 (v, 权重)
+    pub(crate) struct Graph {
+        edges: Vec<Vec<(usize, u64)>>,
+    }
+
+    impl Graph {
+        pub(crate) fn new(vertices: usize) -> Graph {
+            Graph {
+                edges: vec![Vec::new(); vertices],
+            }
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.edges.len()
+        }
+
+        pub(crate) fn add_edge(&mut self, from: usize, to: usize, weight: u64) {
+            self.edges[from].push((to, weight));
+        }
+
+        pub(crate) fn neighbors(&self, vertex: usize) -> &[(usize, u64)] {
+            &self.edges[vertex]
+        }
+    }
+
+    // 从 source 出发到每个顶点的最短距离，以及最短路径上的前一个顶点；到不了的顶点是 None
+    pub(crate) struct ShortestPaths {
+        pub(crate) dist: Vec<Option<u64>>,
+        prev: Vec<Option<usize>>,
+    }
+
+    impl ShortestPaths {
+        // 顺着 prev 从终点倒推回起点
+        pub(crate) fn path_to(&self, target: usize) -> Option<Vec<usize>> {
+            self.dist[target]?;
+            let mut path = vec![target];
+            while let Some(prev) = self.prev[*path.last().unwrap()] {
+                path.push(prev);
+            }
+            path.reverse();
+            Some(path)
+        }
+    }
+
+    pub(crate) fn dijkstra(graph: &Graph, source: usize) -> ShortestPaths {
+        let mut dist = vec![None; graph.len()];
+        let mut prev = vec![None; graph.len()];
+        let mut queue = BinaryHeap::new();
+        dist[source] = Some(0);
+        queue.push(Reverse((0, source)));
+
+        while let Some(Reverse((d, u))) = queue.pop() {
+            if dist[u].is_some_and(|best| d > best) {
+                continue;
+            }
+            for &(v, weight) in graph.neighbors(u) {
+                let candidate = d + weight;
+                if dist[v].is_none_or(|best| candidate < best) {
+                    dist[v] = Some(candidate);
+                    prev[v] = Some(u);
+                    queue.push(Reverse((candidate, v)));
+                }
+            }
+        }
+        ShortestPaths { dist, prev }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::binary_heap::BinaryHeap;
+    use super::shortest_path::*;
+    use std::cmp::Reverse;
+
+    fn next(seed: &mut u64) -> u64 {
+        *seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *seed >> 33
+    }
+
+    #[test]
+    fn heap_matches_std_binary_heap() {
+        let mut heap = BinaryHeap::new();
+        let mut model = std::collections::BinaryHeap::new();
+        let mut seed: u64 = 96;
+        for _ in 0..5_000 {
+            if next(&mut seed).is_multiple_of(3) {
+                assert_eq!(heap.pop(), model.pop());
+            } else {
+                let value = next(&mut seed) % 100;
+                heap.push(value);
+                model.push(value);
+            }
+            assert_eq!(heap.peek(), model.peek());
+            assert_eq!(heap.len(), model.len());
+            assert!(heap.is_valid());
+        }
+
+        // 建堆再排序，结果和 sort 一样
+        let values: Vec<u64> = (0..1_000).map(|_| next(&mut seed) % 1_000).collect();
+        let heap = BinaryHeap::from_vec(values.clone());
+        assert!(heap.is_valid());
+        let mut sorted = values;
+        sorted.sort();
+        assert_eq!(heap.into_sorted_vec(), sorted);
+
+        // Reverse 变成小顶堆
+        let mut min_heap = BinaryHeap::new();
+        for word in ["pear", "apple", "fig"] {
+            min_heap.push(Reverse(word));
+        }
+        assert_eq!(min_heap.pop(), Some(Reverse("apple")));
+        assert_eq!(min_heap.pop(), Some(Reverse("fig")));
+        assert_eq!(min_heap.pop(), Some(Reverse("pear")));
+        assert!(min_heap.is_empty() && min_heap.pop().is_none());
+    }
+
+    #[test]
+    fn dijkstra_on_a_city_map() {
+        // 几个城市之间的单向道路，权重是公里数
+        //   0 北京 → 1 天津 120, 0 → 2 石家庄 280, 1 → 2 300, 1 → 3 济南 320,
+        //   2 → 3 280, 2 → 4 郑州 410, 3 → 4 450, 3 → 5 南京 620, 4 → 5 700
+        // 6 乌鲁木齐 没有路连进来
+        let mut graph = Graph::new(7);
+        for (from, to, km) in [
+            (0, 1, 120),
+            (0, 2, 280),
+            (1, 2, 300),
+            (1, 3, 320),
+            (2, 3, 280),
+            (2, 4, 410),
+            (3, 4, 450),
+            (3, 5, 620),
+            (4, 5, 700),
+        ] {
+            graph.add_edge(from, to, km);
+        }
+        let paths = dijkstra(&graph, 0);
+        assert_eq!(
+            paths.dist,
+            [
+                Some(0),
+                Some(120),
+                Some(280),
+                Some(440),
+                Some(690),
+                Some(1060),
+                None
+            ]
+        );
+        assert_eq!(paths.path_to(5), Some(vec![0, 1, 3, 5]));
+        // 经过天津到石家庄是 420，比直达的 280 远
+        assert_eq!(paths.path_to(2), Some(vec![0, 2]));
+        assert_eq!(paths.path_to(4), Some(vec![0, 2, 4]));
+        assert_eq!(paths.path_to(0), Some(vec![0]));
+        assert_eq!(paths.path_to(6), None);
+        // 单向道路，从南京哪里也去不了
+        let from_nanjing = dijkstra(&graph, 5);
+        assert_eq!(from_nanjing.dist.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn dijkstra_agrees_with_bellman_ford() {
+        // 随机图上和 Bellman-Ford（把所有边松弛 n-1 轮）的结果对照
+        let mut seed: u64 = 2024;
+        for round in 0..50 {
+            let n = 2 + (next(&mut seed) % 15) as usize;
+            let mut graph = Graph::new(n);
+            let mut edges = Vec::new();
+            for _ in 0..next(&mut seed) % (n as u64 * 3) {
+                let (u, v) = (
+                    (next(&mut seed) % n as u64) as usize,
+                    (next(&mut seed) % n as u64) as usize,
+                );
+                // 包括权重为 0 的边和自环
+                let weight = next(&mut seed) % 20;
+                graph.add_edge(u, v, weight);
+                edges.push((u, v, weight));
+            }
+
+            let mut expected = vec![None; n];
+            expected[0] = Some(0u64);
+            for _ in 1..n {
+                for &(u, v, weight) in &edges {
+                    if let Some(d) = expected[u] {
+                        if expected[v].is_none_or(|best| d + weight < best) {
+                            expected[v] = Some(d + weight);
+                        }
+                    }
+                }
+            }
+
+            let paths = dijkstra(&graph, 0);
+            assert_eq!(paths.dist, expected, "round {}", round);
+            // 每条路径的边权之和等于最短距离
+            for target in 0..n {
+                let Some(path) = paths.path_to(target) else {
+                    continue;
+                };
+                assert_eq!(path[0], 0);
+                let length: u64 = path
+                    .windows(2)
+                    .map(|pair| {
+                        graph
+                            .neighbors(pair[0])
+                            .iter()
+                            .filter(|&&(v, _)| v == pair[1])
+                            .map(|&(_, weight)| weight)
+                            .min()
+                            .unwrap()
+                    })
+                    .sum();
+                assert_eq!(Some(length), paths.dist[target]);
+            }
+        }
+    }
+}
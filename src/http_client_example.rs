@@ -0,0 +1,254 @@
+// HTTP 客户端：和 webserver_example 相对，演示协议的另一端——建立连接、发送请求、解析状态行/头部/响应体
+// client 模块在测试中对其他模块可见，webserver_example 的集成测试用它来访问服务器
+#[cfg(test)]
+pub(crate) mod client {
+
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+    #[cfg(unix)]
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub(crate) struct HttpResponse {
+        pub(crate) status: u16,
+        pub(crate) reason: String,
+        // 保留服务器发送的原始顺序和大小写，查找时不区分大小写
+        pub(crate) headers: Vec<(String, String)>,
+        pub(crate) body: Vec<u8>,
+    }
+
+    impl HttpResponse {
+        pub(crate) fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        pub(crate) fn text(&self) -> String {
+            String::from_utf8_lossy(&self.body).into_owned()
+        }
+    }
+
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+    }
+
+    // 读一行并去掉结尾的 \r\n
+    fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before the response head ended",
+            ));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    // 解析响应：状态行、头部，然后按 Content-Length 读响应体，没有 Content-Length 时一直读到连接关闭
+    // HEAD 请求的响应以及 1xx/204/304 响应按协议规定没有响应体
+    pub(crate) fn read_response(
+        reader: &mut impl BufRead,
+        method: &str,
+    ) -> io::Result<HttpResponse> {
+        // 状态行形如 HTTP/1.1 200 OK，原因短语可以包含空格
+        let status_line = read_line(reader)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or("");
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid("not an HTTP/1.x response"));
+        }
+        let status = parts
+            .next()
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| invalid("invalid status code"))?;
+        let reason = parts.next().unwrap_or("").to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed header line"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut response = HttpResponse {
+            status,
+            reason,
+            headers,
+            body: Vec::new(),
+        };
+        if method == "HEAD" || status / 100 == 1 || status == 204 || status == 304 {
+            return Ok(response);
+        }
+        match response.header("Content-Length") {
+            Some(len) => {
+                let len = len.parse().map_err(|_| invalid("invalid Content-Length"))?;
+                response.body = vec![0; len];
+                reader.read_exact(&mut response.body)?;
+            }
+            None => {
+                reader.read_to_end(&mut response.body)?;
+            }
+        }
+        Ok(response)
+    }
+
+    // 发送一个请求并读取响应，每个请求使用一个新连接
+    pub(crate) fn request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        exchange(stream, &addr.to_string(), method, path, headers, body)
+    }
+
+    // 通过 Unix 域套接字发送请求，HTTP 报文和 TCP 上的完全一样，只是连接方式不同
+    #[cfg(unix)]
+    pub(crate) fn request_unix(
+        socket: &Path,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        let stream = UnixStream::connect(socket)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        // 套接字路径不是主机名，Host 头部填 localhost
+        exchange(stream, "localhost", method, path, headers, body)
+    }
+
+    fn exchange(
+        mut stream: impl Read + Write,
+        host: &str,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, host);
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        // 头部和请求体合在一起一次写出，避免服务器只读到一半
+        let mut message = head.into_bytes();
+        message.extend_from_slice(body);
+        stream.write_all(&message)?;
+
+        read_response(&mut BufReader::new(stream), method)
+    }
+
+    pub(crate) fn get(addr: SocketAddr, path: &str) -> io::Result<HttpResponse> {
+        request(addr, "GET", path, &[], &[])
+    }
+
+    pub(crate) fn post(
+        addr: SocketAddr,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        request(addr, "POST", path, &[("Content-Type", content_type)], body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::client::*;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn parses_status_headers_and_body() {
+        let raw = b"HTTP/1.1 404 NOT FOUND\r\nContent-Type: text/plain\r\ncontent-length: 9\r\n\r\nnot found trailing";
+        let response = read_response(&mut &raw[..], "GET").unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.reason, "NOT FOUND");
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.header("Content-Length"), Some("9"));
+        // 只读 Content-Length 指定的字节数
+        assert_eq!(response.text(), "not found");
+    }
+
+    #[test]
+    fn body_without_length_reads_to_eof() {
+        let raw = b"HTTP/1.0 200 OK\r\n\r\nuntil the end";
+        let response = read_response(&mut &raw[..], "GET").unwrap();
+        assert_eq!(response.text(), "until the end");
+    }
+
+    #[test]
+    fn responses_without_body() {
+        let raw = b"HTTP/1.1 304 NOT MODIFIED\r\nContent-Length: 10\r\n\r\n";
+        assert!(read_response(&mut &raw[..], "GET").unwrap().body.is_empty());
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n";
+        assert!(read_response(&mut &raw[..], "HEAD")
+            .unwrap()
+            .body
+            .is_empty());
+    }
+
+    #[test]
+    fn malformed_responses() {
+        let cases: [&[u8]; 4] = [
+            b"SSH-2.0-OpenSSH\r\n\r\n",
+            b"HTTP/1.1 abc OK\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nno colon here\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nab",
+        ];
+        for raw in cases {
+            assert!(read_response(&mut &raw[..], "GET").is_err());
+        }
+        // 响应头还没结束连接就关闭了
+        let err = read_response(&mut &b"HTTP/1.1 200 OK\r\n"[..], "GET")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn sends_request_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"ping") {
+                let n = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\npong")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let response = post(addr, "/echo", "text/plain", b"ping").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), "pong");
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /echo HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Type: text/plain\r\n"));
+        assert!(request.contains("Content-Length: 4\r\n"));
+    }
+}
@@ -0,0 +1,309 @@
+// 国际化：界面上的文字不直接写在代码里，而是按语言放在消息目录（messages.toml）里，代码只引用消息的名字
+// - 占位符：消息里的 {name} 在运行时替换成参数，不同语言的语序可以不一样
+// - 复数：英语分 1 和其他，法语 0 和 1 都算单数，俄语要看个位和十位，中文不区分，所以要按语言选复数类别
+// - 回退：zh-TW 没有的消息先找 zh，再找默认语言；都没有时返回消息名本身，一眼能看出哪条漏翻了
+// - 协商：浏览器在 Accept-Language 里按偏好列出想要的语言，服务器从自己有的语言里挑最合适的一个
+#[cfg(test)]
+pub(crate) mod i18n {
+
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::OnceLock;
+
+    // 普通消息是一个字符串；区分单复数的消息是 复数类别 -> 字符串 的表
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    enum Message {
+        Text(String),
+        Plural(HashMap<String, String>),
+    }
+
+    pub(crate) struct Catalogs {
+        default: String,
+        locales: HashMap<String, HashMap<String, Message>>,
+    }
+
+    impl Catalogs {
+        // 默认语言必须有目录；复数消息必须有 other，其他类别没写的时候都用它
+        pub(crate) fn parse(text: &str, default: &str) -> Result<Catalogs, Box<dyn Error>> {
+            let locales: HashMap<String, HashMap<String, Message>> = toml::from_str(text)?;
+            if !locales.contains_key(default) {
+                return Err(format!("no catalog for the default locale {}", default).into());
+            }
+            for (locale, messages) in &locales {
+                for (key, message) in messages {
+                    if matches!(message, Message::Plural(forms) if !forms.contains_key("other")) {
+                        return Err(format!("{}.{} has no `other` plural form", locale, key).into());
+                    }
+                }
+            }
+            Ok(Catalogs {
+                default: default.to_string(),
+                locales,
+            })
+        }
+
+        pub(crate) fn load(path: &Path, default: &str) -> Result<Catalogs, Box<dyn Error>> {
+            Catalogs::parse(&fs::read_to_string(path)?, default)
+        }
+
+        // 有目录的语言，按名字排序
+        pub(crate) fn locales(&self) -> Vec<&str> {
+            let mut locales: Vec<&str> = self.locales.keys().map(String::as_str).collect();
+            locales.sort();
+            locales
+        }
+
+        // 依次尝试 zh-Hant-TW、zh-Hant、zh，最后是默认语言，返回找到消息的语言和消息。语言标签不区分大小写
+        fn lookup(&self, locale: &str, key: &str) -> Option<(&str, &Message)> {
+            let mut candidates = Vec::new();
+            let mut tag = locale;
+            loop {
+                candidates.push(tag);
+                match tag.rfind('-') {
+                    Some(i) => tag = &tag[..i],
+                    None => break,
+                }
+            }
+            candidates.push(&self.default);
+            candidates.into_iter().find_map(|tag| {
+                let (locale, messages) = self
+                    .locales
+                    .iter()
+                    .find(|(locale, _)| locale.eq_ignore_ascii_case(tag))?;
+                Some((locale.as_str(), messages.get(key)?))
+            })
+        }
+
+        // 普通消息。找不到时返回消息名
+        pub(crate) fn text(&self, locale: &str, key: &str, args: &[(&str, &str)]) -> String {
+            match self.lookup(locale, key) {
+                Some((_, Message::Text(text))) => interpolate(text, args),
+                Some((_, Message::Plural(forms))) => interpolate(&forms["other"], args),
+                None => key.to_string(),
+            }
+        }
+
+        // 按 count 选复数形式，{count} 自动替换成数量。复数类别按找到消息的那个语言的规则算，
+        // 例如回退到英语的消息要用英语的规则。目录里显式写了 zero 时 0 用它，不管这个语言的规则里有没有 zero
+        pub(crate) fn plural(
+            &self,
+            locale: &str,
+            key: &str,
+            count: u64,
+            args: &[(&str, &str)],
+        ) -> String {
+            let count_text = count.to_string();
+            let mut args = args.to_vec();
+            args.push(("count", &count_text));
+            match self.lookup(locale, key) {
+                Some((_, Message::Text(text))) => interpolate(text, &args),
+                Some((found, Message::Plural(forms))) => {
+                    let category = match forms.get("zero") {
+                        Some(_) if count == 0 => "zero",
+                        _ => plural_category(found, count),
+                    };
+                    let form = forms.get(category).unwrap_or(&forms["other"]);
+                    interpolate(form, &args)
+                }
+                None => key.to_string(),
+            }
+        }
+    }
+
+    // 复数类别，CLDR 规则的简化版，只考虑非负整数
+    pub(crate) fn plural_category(locale: &str, n: u64) -> &'static str {
+        let language = locale.split('-').next().unwrap_or(locale);
+        match language.to_ascii_lowercase().as_str() {
+            // 没有单复数变化
+            "zh" | "ja" | "ko" => "other",
+            "fr" if n <= 1 => "one",
+            "fr" => "other",
+            // 1、21、101 是 one；2-4、22-24 是 few；其余（包括 11-14）是 many
+            "ru" | "uk" => {
+                let (last, last_two) = (n % 10, n % 100);
+                if last == 1 && last_two != 11 {
+                    "one"
+                } else if (2..=4).contains(&last) && !(12..=14).contains(&last_two) {
+                    "few"
+                } else {
+                    "many"
+                }
+            }
+            _ if n == 1 => "one",
+            _ => "other",
+        }
+    }
+
+    // 把 {name} 换成参数的值；没有对应参数的占位符原样保留
+    pub(crate) fn interpolate(template: &str, args: &[(&str, &str)]) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            let value = after.find('}').and_then(|close| {
+                let name = &after[..close];
+                let (_, value) = args.iter().find(|(arg, _)| *arg == name)?;
+                Some((close, value))
+            });
+            match value {
+                Some((close, value)) => {
+                    out.push_str(value);
+                    rest = &after[close + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    // 按 Accept-Language 从 available 里挑一个语言，例如 "fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5"
+    // 按 q 从高到低（相同时按出现的顺序）逐个尝试：先找完全相同的标签，再找主语言相同的（fr-CH 可以用 fr，zh 可以用 zh-TW）
+    // q=0 表示不要这个语言；遇到 * 或者都不匹配时用 default。q 写错的项忽略
+    pub(crate) fn negotiate<'a>(header: &str, available: &[&'a str], default: &'a str) -> &'a str {
+        let primary = |tag: &'a str| tag.split('-').next().unwrap_or(tag);
+        let mut ranges: Vec<(&str, f32)> = header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let tag = parts.next()?.trim();
+                let q = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                    Some(q) => q.trim().parse().ok()?,
+                    None => 1.0,
+                };
+                Some((tag, q))
+            })
+            .filter(|&(tag, q)| !tag.is_empty() && q > 0.0)
+            .collect();
+        // sort_by 是稳定排序，q 相同的保持原来的顺序
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for (tag, _) in ranges {
+            if tag == "*" {
+                return default;
+            }
+            if let Some(exact) = available
+                .iter()
+                .find(|locale| locale.eq_ignore_ascii_case(tag))
+            {
+                return exact;
+            }
+            let language = tag.split('-').next().unwrap_or(tag);
+            if let Some(same) = available
+                .iter()
+                .find(|locale| primary(locale).eq_ignore_ascii_case(language))
+            {
+                return same;
+            }
+        }
+        default
+    }
+
+    // 编译进程序里的 messages.toml，默认语言是英语
+    pub(crate) fn builtin() -> &'static Catalogs {
+        static BUILTIN: OnceLock<Catalogs> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            Catalogs::parse(include_str!("../messages.toml"), "en").expect("invalid messages.toml")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::i18n::*;
+    use std::path::Path;
+
+    #[test]
+    fn catalogs_interpolate_and_fall_back() {
+        let catalogs = builtin();
+        assert_eq!(catalogs.locales(), ["en", "fr", "ru", "zh", "zh-TW"]);
+        let name = [("name", "Ferris")];
+        assert_eq!(catalogs.text("en", "greeting", &name), "Hello, Ferris!");
+        assert_eq!(catalogs.text("fr", "greeting", &name), "Bonjour, Ferris !");
+        // zh-TW 有自己的 farewell，没有 greeting 时回退到 zh；俄语没有 farewell，回退到英语
+        assert_eq!(catalogs.text("zh-TW", "farewell", &name), "再見，Ferris。");
+        assert_eq!(catalogs.text("zh-tw", "greeting", &name), "你好，Ferris！");
+        assert_eq!(catalogs.text("ru", "farewell", &name), "Goodbye, Ferris.");
+        assert_eq!(catalogs.text("de-AT", "greeting", &name), "Hello, Ferris!");
+        assert_eq!(
+            catalogs.text("en", "no_such_message", &name),
+            "no_such_message"
+        );
+        // 磁盘上的 messages.toml 和编译进来的是同一份
+        let loaded = Catalogs::load(Path::new("messages.toml"), "en").unwrap();
+        assert_eq!(loaded.text("zh", "greeting", &name), "你好，Ferris！");
+
+        assert_eq!(
+            interpolate("{a} and {b}, {missing} {a", &[("a", "x"), ("b", "{a}")]),
+            "x and {a}, {missing} {a"
+        );
+        assert!(Catalogs::parse("[fr]\ngreeting = \"Salut\"\n", "en").is_err());
+        let error = Catalogs::parse("[en]\nitems = { one = \"{count} item\" }\n", "en")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "en.items has no `other` plural form");
+    }
+
+    #[test]
+    fn plural_rules() {
+        let catalogs = builtin();
+        let left = |locale: &str, count: u64| catalogs.plural(locale, "attempts_left", count, &[]);
+        assert_eq!(left("en", 1), "1 attempt left");
+        assert_eq!(left("en", 0), "0 attempts left");
+        assert_eq!(left("fr", 0), "0 essai restant");
+        assert_eq!(left("fr", 2), "2 essais restants");
+        assert_eq!(left("zh", 1), "还剩 1 次机会");
+        // 俄语：1、21 单数，2-4、22 few，5、11-14 many
+        let russian: Vec<String> = [1, 2, 5, 11, 12, 21, 22, 111]
+            .into_iter()
+            .map(|n| left("ru", n))
+            .collect();
+        assert_eq!(
+            russian,
+            [
+                "осталась 1 попытка",
+                "осталось 2 попытки",
+                "осталось 5 попыток",
+                "осталось 11 попыток",
+                "осталось 12 попыток",
+                "осталась 21 попытка",
+                "осталось 22 попытки",
+                "осталось 111 попыток",
+            ]
+        );
+        // 显式的 zero 形式；回退到英语的消息按英语的规则
+        let inbox = |locale: &str, count: u64| {
+            catalogs.plural(locale, "new_messages", count, &[("name", "Ann")])
+        };
+        assert_eq!(inbox("en", 0), "No new messages");
+        assert_eq!(inbox("en", 1), "1 new message for Ann");
+        assert_eq!(inbox("fr", 0), "0 nouveau message pour Ann");
+        assert_eq!(inbox("zh", 1), "1 new message for Ann");
+        assert_eq!(plural_category("ja", 1), "other");
+    }
+
+    #[test]
+    fn accept_language_negotiation() {
+        let available = builtin().locales();
+        let pick = |header: &str| negotiate(header, &available, "en");
+        assert_eq!(pick("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5"), "fr");
+        assert_eq!(pick("ZH-tw"), "zh-TW");
+        // zh-HK 没有，主语言相同的 zh 也可以
+        assert_eq!(pick("zh-HK"), "zh");
+        // 按 q 排序，不是按出现的顺序
+        assert_eq!(pick("en;q=0.5, ru;q=0.8"), "ru");
+        assert_eq!(pick("de, ru;q=0.1"), "ru");
+        assert_eq!(pick("fr;q=0, ru;q=0"), "en");
+        assert_eq!(pick("de, *;q=0.5, fr;q=0.1"), "en");
+        assert_eq!(pick("fr;q=abc, ru"), "ru");
+        assert_eq!(pick(""), "en");
+    }
+}
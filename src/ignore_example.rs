@@ -0,0 +1,329 @@
+// .gitignore 规则引擎，和 git 的行为保持一致：
+// - 空行和 # 开头的行跳过；行尾的空格去掉，除非用 \ 转义；\# 和 \! 开头的是普通的 # 和 !
+// - ! 开头的是例外，重新包含前面的规则排除掉的路径；但父目录已经被排除的话，里面的东西救不回来
+// - / 结尾的只匹配目录
+// - 开头或中间有 / 的相对于 .gitignore 所在的目录匹配；否则和任意一层的名字匹配
+// - 同一个文件里最后一条匹配的规则说了算；子目录里的 .gitignore 比外层的优先
+// 通配符的语义（*、?、**、[a-z]）由 glob 模块实现
+#[cfg(test)]
+pub(crate) mod ignore {
+
+    use crate::glob_example::glob::Pattern;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    struct Rule {
+        pattern: Pattern,
+        negate: bool,
+        dir_only: bool,
+        anchored: bool,
+    }
+
+    // 一条规则匹配上以后的结论
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Decision {
+        Ignore,
+        // 被 ! 规则重新包含
+        Include,
+    }
+
+    // 一个 .gitignore 文件：所在的目录和里面的规则
+    pub(crate) struct Gitignore {
+        base: PathBuf,
+        rules: Vec<Rule>,
+    }
+
+    // 去掉行尾没有被 \ 转义的空格
+    fn trim_trailing_spaces(line: &str) -> &str {
+        let mut line = line;
+        while line.ends_with(' ') && !line[..line.len() - 1].ends_with('\\') {
+            line = &line[..line.len() - 1];
+        }
+        line
+    }
+
+    impl Gitignore {
+        // 写错的模式（例如 [ 没有闭合）忽略掉，git 也是这样
+        pub(crate) fn parse(base: &Path, text: &str) -> Gitignore {
+            let rules = text
+                .lines()
+                .map(|line| trim_trailing_spaces(line.trim_end_matches('\r')))
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .filter_map(|line| {
+                    let (negate, line) = match line.strip_prefix('!') {
+                        Some(rest) => (true, rest),
+                        None => (false, line),
+                    };
+                    let (dir_only, line) = match line.strip_suffix('/') {
+                        Some(rest) => (true, rest),
+                        None => (false, line),
+                    };
+                    Some(Rule {
+                        pattern: Pattern::new(line.trim_start_matches('/')).ok()?,
+                        negate,
+                        dir_only,
+                        anchored: line.contains('/'),
+                    })
+                })
+                .collect();
+            Gitignore {
+                base: base.to_path_buf(),
+                rules,
+            }
+        }
+
+        // 读 dir 下的 .gitignore，没有这个文件时返回 None
+        pub(crate) fn load(dir: &Path) -> io::Result<Option<Gitignore>> {
+            match fs::read_to_string(dir.join(".gitignore")) {
+                Ok(text) => Ok(Some(Gitignore::parse(dir, &text))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+
+        // 最后一条匹配 path 的规则的结论，没有规则匹配（或者 path 不在 base 下面）时返回 None
+        pub(crate) fn decide(&self, path: &Path, is_dir: bool) -> Option<Decision> {
+            let relative = path.strip_prefix(&self.base).ok()?.to_string_lossy();
+            let name = relative.rsplit('/').next().unwrap_or(&relative);
+            self.rules
+                .iter()
+                .rev()
+                .filter(|rule| is_dir || !rule.dir_only)
+                .find(|rule| {
+                    let text = if rule.anchored { &relative[..] } else { name };
+                    rule.pattern.matches(text)
+                })
+                .map(|rule| {
+                    if rule.negate {
+                        Decision::Include
+                    } else {
+                        Decision::Ignore
+                    }
+                })
+        }
+    }
+
+    // 遍历目录树时用：进入一个目录时 enter，加载它的 .gitignore（如果有）；离开时 leave
+    // 遍历的一方看到被忽略的目录就不再进去，"父目录被排除就救不回来"自然成立
+    #[derive(Default)]
+    pub(crate) struct IgnoreStack {
+        files: Vec<Option<Gitignore>>,
+    }
+
+    impl IgnoreStack {
+        pub(crate) fn new() -> IgnoreStack {
+            IgnoreStack::default()
+        }
+
+        pub(crate) fn enter(&mut self, dir: &Path) -> io::Result<()> {
+            self.files.push(Gitignore::load(dir)?);
+            Ok(())
+        }
+
+        pub(crate) fn leave(&mut self) {
+            self.files.pop();
+        }
+
+        // 从最里层的 .gitignore 往外找，第一个有结论的说了算
+        pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+            self.files
+                .iter()
+                .rev()
+                .flatten()
+                .find_map(|gitignore| gitignore.decide(path, is_dir))
+                == Some(Decision::Ignore)
+        }
+    }
+
+    // 不遍历，直接判断 root 下的某个路径（相对于 root）是不是被忽略了，相当于 git check-ignore
+    // 从 root 开始一级一级往下，沿途加载 .gitignore；某一级父目录被忽略了，里面的东西都被忽略
+    pub(crate) fn check(root: &Path, relative: &Path, is_dir: bool) -> io::Result<bool> {
+        let mut stack = IgnoreStack::new();
+        stack.enter(root)?;
+        let mut path = root.to_path_buf();
+        let components: Vec<_> = relative.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            path.push(component);
+            let last = i + 1 == components.len();
+            let path_is_dir = if last { is_dir } else { true };
+            if stack.is_ignored(&path, path_is_dir) {
+                return Ok(true);
+            }
+            if !last {
+                stack.enter(&path)?;
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::ignore::*;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    #[test]
+    fn parses_rules_like_git() {
+        let base = Path::new("/repo");
+        let gitignore = Gitignore::parse(
+            base,
+            "# 注释\n\n*.log\n!keep.log\ntarget/\n/TODO\ndocs/*.md\n\\#literal\n\\!bang\n\
+             padded.txt   \nescaped\\ \r\nbroken[\n",
+        );
+        let decide = |path: &str, is_dir: bool| gitignore.decide(&base.join(path), is_dir);
+        assert_eq!(decide("app.log", false), Some(Decision::Ignore));
+        assert_eq!(decide("deep/er/app.log", false), Some(Decision::Ignore));
+        assert_eq!(decide("keep.log", false), Some(Decision::Include));
+        assert_eq!(decide("main.rs", false), None);
+        // target/ 只匹配目录
+        assert_eq!(decide("target", true), Some(Decision::Ignore));
+        assert_eq!(decide("target", false), None);
+        // /TODO 只在 .gitignore 所在的目录生效
+        assert_eq!(decide("TODO", false), Some(Decision::Ignore));
+        assert_eq!(decide("src/TODO", false), None);
+        // 中间有 / 的规则也是相对于所在目录的，* 不跨目录
+        assert_eq!(decide("docs/a.md", false), Some(Decision::Ignore));
+        assert_eq!(decide("docs/api/a.md", false), None);
+        assert_eq!(decide("src/docs/a.md", false), None);
+        assert_eq!(decide("#literal", false), Some(Decision::Ignore));
+        assert_eq!(decide("!bang", false), Some(Decision::Ignore));
+        assert_eq!(decide("padded.txt", false), Some(Decision::Ignore));
+        assert_eq!(decide("escaped ", false), Some(Decision::Ignore));
+        assert_eq!(decide("escaped", false), None);
+        // 写错的规则被跳过，不影响其他规则
+        assert_eq!(decide("broken[", false), None);
+        // 不在 .gitignore 所在目录下的路径和它无关
+        assert_eq!(
+            gitignore.decide(Path::new("/elsewhere/app.log"), false),
+            None
+        );
+    }
+
+    // 夹具：根目录、sub、sub/deep 各有一个 .gitignore
+    const ROOT_RULES: &str = "# comment\n*.log\n!important.log\nbuild/\n/root-only.txt\n\
+        docs/**/*.pdf\n**/cache\nfoo/**\n\\#hash.txt\n\\!bang.txt\ntrailing.txt   \nspace\\ .txt\n\
+        *.tmp\n!/keep.tmp\nvendor\n!vendor/kept.rs\n";
+    const SUB_RULES: &str = "!*.log\nlocal.txt\n/anchored.txt\n";
+    const DEEP_RULES: &str = "*.txt\n!readme.txt\n";
+
+    // 期望的结果是在同样的目录树上用 git check-ignore --no-index 得到的，true 表示被忽略
+    const EXPECTED: &[(&str, bool)] = &[
+        ("!bang.txt", true),
+        ("#hash.txt", true),
+        (".gitignore", false),
+        ("a.log", true),
+        ("a/cache/z", true),
+        ("b/c/cache", true),
+        ("build.rs", false),
+        ("build/x.rs", true),
+        ("cache.rs", false),
+        ("docs/a.pdf", true),
+        ("docs/c.txt", false),
+        ("docs/x/y/b.pdf", true),
+        ("foo.rs", false),
+        ("foo/bar", true),
+        ("foo/baz/qux", true),
+        ("important.log", false),
+        ("keep.tmp", false),
+        ("local.txt", false),
+        ("root-only.txt", true),
+        ("space .txt", true),
+        ("space.txt", false),
+        ("src/build/y.rs", true),
+        ("sub/.gitignore", false),
+        ("sub/a.log", false),
+        ("sub/anchored.txt", true),
+        ("sub/deep/.gitignore", false),
+        ("sub/deep/b.log", false),
+        ("sub/deep/more/readme.txt", false),
+        ("sub/deep/notes.txt", true),
+        ("sub/deep/readme.txt", false),
+        ("sub/keep.tmp", true),
+        ("sub/local.txt", true),
+        ("sub/root-only.txt", false),
+        ("sub/x/anchored.txt", false),
+        ("trailing.txt", true),
+        // vendor 目录整个被排除了，!vendor/kept.rs 救不回来
+        ("vendor/kept.rs", true),
+        ("vendor/lib.rs", true),
+        ("x.tmp", true),
+    ];
+
+    fn fixture(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("ignore-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        for (file, _) in EXPECTED {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(root.join(".gitignore"), ROOT_RULES).unwrap();
+        fs::write(root.join("sub/.gitignore"), SUB_RULES).unwrap();
+        fs::write(root.join("sub/deep/.gitignore"), DEEP_RULES).unwrap();
+        root
+    }
+
+    // 按名字排序递归列出没有被忽略的文件，被忽略的目录不进去
+    fn walk(
+        root: &Path,
+        dir: &Path,
+        stack: &mut IgnoreStack,
+        files: &mut Vec<String>,
+    ) -> io::Result<()> {
+        stack.enter(dir)?;
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let is_dir = entry.file_type()?.is_dir();
+            if stack.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                walk(root, &path, stack, files)?;
+            } else {
+                let relative = path.strip_prefix(root).unwrap();
+                files.push(relative.to_string_lossy().into_owned());
+            }
+        }
+        stack.leave();
+        Ok(())
+    }
+
+    #[test]
+    fn check_agrees_with_git() {
+        let root = fixture("check");
+        for &(file, ignored) in EXPECTED {
+            assert_eq!(
+                check(&root, Path::new(file), false).unwrap(),
+                ignored,
+                "{}",
+                file
+            );
+        }
+        assert!(check(&root, Path::new("build"), true).unwrap());
+        assert!(!check(&root, Path::new("build"), false).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn walk_skips_ignored_paths() {
+        let root = fixture("walk");
+        let mut files = Vec::new();
+        walk(&root, &root, &mut IgnoreStack::new(), &mut files).unwrap();
+        // 和 git ls-files --others --exclude-standard 列出的一样
+        let expected: Vec<&str> = EXPECTED
+            .iter()
+            .filter(|(_, ignored)| !ignored)
+            .map(|(file, _)| *file)
+            .collect();
+        assert_eq!(files, expected);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
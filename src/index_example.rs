@@ -0,0 +1,660 @@
+// 全文检索的倒排索引：正排是“文档 → 里面有哪些词”，倒排反过来记录“词 → 出现在哪些文档的哪些位置”
+// 查询时只需要取出查询词的倒排列表做交集/并集，不用扫描所有文档；记录位置是为了支持短语查询（几个词必须紧挨着出现）
+#[cfg(test)]
+pub(crate) mod index {
+
+    use std::collections::HashMap;
+
+    // 把文本切成词，返回 (位置, 词)，位置是词在文档中的序号
+    // 字母和数字之外的字符都是分隔符，所以 thread_pool 会切成 thread 和 pool 两个相邻的词
+    pub(crate) fn tokenize(text: &str) -> impl Iterator<Item = (usize, String)> + '_ {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(String::from)
+            .enumerate()
+    }
+
+    // 文本分析流水线的各个阶段，每个阶段都是一个迭代器适配器，可以像 map/filter 一样自由组合
+    // 所有阶段都保留词原来的位置：去掉停用词后位置会留下空隙，短语查询据此知道两个词之间隔了几个词
+    pub(crate) trait TokenStream: Iterator<Item = (usize, String)> + Sized {
+        fn lowercase(self) -> impl Iterator<Item = (usize, String)> {
+            self.map(|(position, word)| (position, word.to_lowercase()))
+        }
+
+        // 停用词几乎每篇文档都有，对区分文档没有帮助，还会让 AND 查询漏掉不含这些词的文档
+        fn without_stop_words(self) -> impl Iterator<Item = (usize, String)> {
+            self.filter(|(_, word)| !STOP_WORDS.contains(&word.as_str()))
+        }
+
+        // 把词还原成词干，connection/connected/connecting 都变成 connect，查询其中一个就能找到所有变形
+        fn stemmed(self) -> impl Iterator<Item = (usize, String)> {
+            self.map(|(position, word)| (position, stem(&word)))
+        }
+    }
+
+    impl<I: Iterator<Item = (usize, String)>> TokenStream for I {}
+
+    const STOP_WORDS: [&str; 28] = [
+        "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "in",
+        "is", "it", "its", "of", "on", "or", "that", "the", "this", "to", "was", "were", "will",
+        "with",
+    ];
+
+    // 索引和查询必须用同一个分析器，否则同一个词在两边的形式不一样，永远匹配不上
+    pub(crate) type Analyzer = fn(&str) -> Vec<(usize, String)>;
+
+    // 只切词、转小写
+    pub(crate) fn plain(text: &str) -> Vec<(usize, String)> {
+        tokenize(text).lowercase().collect()
+    }
+
+    // 切词、转小写、去掉停用词、提取词干
+    pub(crate) fn english(text: &str) -> Vec<(usize, String)> {
+        tokenize(text)
+            .lowercase()
+            .without_stop_words()
+            .stemmed()
+            .collect()
+    }
+
+    // Porter 词干提取算法：按固定的几步依次去掉或替换英文后缀，每一步都要求剩下的词干足够长，避免把词砍得面目全非
+    // 这里的“长度”是 measure：把词干看成 [C](VC)^m[V]（C 是连续的辅音，V 是连续的元音），m 就是 VC 重复的次数
+    pub(crate) fn stem(word: &str) -> String {
+        // 太短的词和非 ASCII 的词不处理
+        if word.len() <= 2 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+            return word.to_string();
+        }
+        let mut w = word.as_bytes().to_vec();
+        step1a(&mut w);
+        step1b(&mut w);
+        step1c(&mut w);
+        replace_first(&mut w, STEP2, |stem| measure(stem) > 0);
+        replace_first(&mut w, STEP3, |stem| measure(stem) > 0);
+        step4(&mut w);
+        step5(&mut w);
+        String::from_utf8(w).unwrap()
+    }
+
+    // y 前面是辅音时当作元音，比如 happy 里的 y
+    fn is_consonant(w: &[u8], i: usize) -> bool {
+        match w[i] {
+            b'a' | b'e' | b'i' | b'o' | b'u' => false,
+            b'y' => i == 0 || !is_consonant(w, i - 1),
+            _ => true,
+        }
+    }
+
+    fn measure(w: &[u8]) -> usize {
+        (1..w.len())
+            .filter(|&i| is_consonant(w, i) && !is_consonant(w, i - 1))
+            .count()
+    }
+
+    fn has_vowel(w: &[u8]) -> bool {
+        (0..w.len()).any(|i| !is_consonant(w, i))
+    }
+
+    fn ends_with_double_consonant(w: &[u8]) -> bool {
+        let n = w.len();
+        n >= 2 && w[n - 1] == w[n - 2] && is_consonant(w, n - 1)
+    }
+
+    // 以 辅音-元音-辅音 结尾，且最后一个辅音不是 w/x/y，比如 hop、fil
+    fn ends_cvc(w: &[u8]) -> bool {
+        let n = w.len();
+        n >= 3
+            && is_consonant(w, n - 3)
+            && !is_consonant(w, n - 2)
+            && is_consonant(w, n - 1)
+            && !matches!(w[n - 1], b'w' | b'x' | b'y')
+    }
+
+    // 去掉 suffix 之后的词干长度，不以 suffix 结尾时返回 None
+    fn stem_len(w: &[u8], suffix: &str) -> Option<usize> {
+        w.ends_with(suffix.as_bytes())
+            .then(|| w.len() - suffix.len())
+    }
+
+    fn set_suffix(w: &mut Vec<u8>, stem: usize, replacement: &str) {
+        w.truncate(stem);
+        w.extend_from_slice(replacement.as_bytes());
+    }
+
+    // 找到第一个匹配的后缀（表里长的排在前面），词干满足条件时替换；匹配到了但条件不满足就什么也不做，不再尝试更短的后缀
+    fn replace_first(w: &mut Vec<u8>, rules: &[(&str, &str)], condition: impl Fn(&[u8]) -> bool) {
+        if let Some((stem, replacement)) = rules
+            .iter()
+            .find_map(|(suffix, replacement)| stem_len(w, suffix).map(|stem| (stem, replacement)))
+        {
+            if condition(&w[..stem]) {
+                set_suffix(w, stem, replacement);
+            }
+        }
+    }
+
+    // 复数：caresses → caress，ponies → poni，cats → cat
+    fn step1a(w: &mut Vec<u8>) {
+        replace_first(
+            w,
+            &[("sses", "ss"), ("ies", "i"), ("ss", "ss"), ("s", "")],
+            |_| true,
+        );
+    }
+
+    // 过去式和进行时：agreed → agree，hopping → hop，filing → file
+    fn step1b(w: &mut Vec<u8>) {
+        if let Some(stem) = stem_len(w, "eed") {
+            if measure(&w[..stem]) > 0 {
+                w.pop();
+            }
+            return;
+        }
+        let Some(stem) = stem_len(w, "ed")
+            .or_else(|| stem_len(w, "ing"))
+            .filter(|&stem| has_vowel(&w[..stem]))
+        else {
+            return;
+        };
+        w.truncate(stem);
+        if ["at", "bl", "iz"]
+            .iter()
+            .any(|suffix| w.ends_with(suffix.as_bytes()))
+        {
+            w.push(b'e');
+        } else if ends_with_double_consonant(w) && !matches!(w[w.len() - 1], b'l' | b's' | b'z') {
+            w.pop();
+        } else if measure(w) == 1 && ends_cvc(w) {
+            w.push(b'e');
+        }
+    }
+
+    // happy → happi，这样 happy 和 happiness 的词干一致
+    fn step1c(w: &mut Vec<u8>) {
+        if let Some(stem) = stem_len(w, "y") {
+            if has_vowel(&w[..stem]) {
+                set_suffix(w, stem, "i");
+            }
+        }
+    }
+
+    const STEP2: &[(&str, &str)] = &[
+        ("ational", "ate"),
+        ("tional", "tion"),
+        ("enci", "ence"),
+        ("anci", "ance"),
+        ("izer", "ize"),
+        ("abli", "able"),
+        ("alli", "al"),
+        ("entli", "ent"),
+        ("eli", "e"),
+        ("ousli", "ous"),
+        ("ization", "ize"),
+        ("ation", "ate"),
+        ("ator", "ate"),
+        ("alism", "al"),
+        ("iveness", "ive"),
+        ("fulness", "ful"),
+        ("ousness", "ous"),
+        ("aliti", "al"),
+        ("iviti", "ive"),
+        ("biliti", "ble"),
+    ];
+
+    const STEP3: &[(&str, &str)] = &[
+        ("icate", "ic"),
+        ("ative", ""),
+        ("alize", "al"),
+        ("iciti", "ic"),
+        ("ical", "ic"),
+        ("ful", ""),
+        ("ness", ""),
+    ];
+
+    // 词干足够长（m > 1）时去掉常见的后缀
+    fn step4(w: &mut Vec<u8>) {
+        const SUFFIXES: [&str; 19] = [
+            "al", "ance", "ence", "er", "ic", "able", "ible", "ant", "ement", "ment", "ent", "ion",
+            "ou", "ism", "ate", "iti", "ous", "ive", "ize",
+        ];
+        // 选最长的匹配，例如 adjustment 去掉的是 ment 而不是 ent
+        let Some((stem, suffix)) = SUFFIXES
+            .iter()
+            .filter_map(|suffix| stem_len(w, suffix).map(|stem| (stem, *suffix)))
+            .min_by_key(|(stem, _)| *stem)
+        else {
+            return;
+        };
+        let stem_part = &w[..stem];
+        // ion 只在 s 或 t 之后去掉：adoption → adopt，但 lion 不变
+        let ion_ok = suffix != "ion" || matches!(stem_part.last(), Some(b's' | b't'));
+        if measure(stem_part) > 1 && ion_ok {
+            w.truncate(stem);
+        }
+    }
+
+    // 去掉结尾多余的 e 和双写的 l：probate → probat，controll → control
+    fn step5(w: &mut Vec<u8>) {
+        if let Some(stem) = stem_len(w, "e") {
+            let m = measure(&w[..stem]);
+            if m > 1 || (m == 1 && !ends_cvc(&w[..stem])) {
+                w.truncate(stem);
+            }
+        }
+        if measure(w) > 1 && ends_with_double_consonant(w) && w.ends_with(b"l") {
+            w.pop();
+        }
+    }
+
+    // 一个词在一篇文档中的所有出现位置，位置数就是词频
+    struct Posting {
+        doc: usize,
+        positions: Vec<usize>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum Query {
+        Term(String),
+        // 用双引号括起来的几个词，必须按顺序紧挨着出现；位置是词在短语中的序号，被去掉的停用词也占位置
+        Phrase(Vec<(usize, String)>),
+        And(Vec<Query>),
+        Or(Vec<Query>),
+    }
+
+    impl Query {
+        // 语法：空格分隔的词和 "短语" 之间是 AND，大写的 OR 分隔几组条件，例如 tcp "thread pool" OR mpsc
+        // 查询中的词要经过和索引相同的分析器
+        pub(crate) fn parse(input: &str, analyzer: Analyzer) -> Query {
+            let mut alternatives = Vec::new();
+            let mut clauses = Vec::new();
+            let mut rest = input;
+            loop {
+                rest = rest.trim_start();
+                if rest.is_empty() {
+                    break;
+                }
+                if let Some(quoted) = rest.strip_prefix('"') {
+                    // 没有配对的引号时一直到结尾都算短语
+                    let (phrase, after) = quoted.split_once('"').unwrap_or((quoted, ""));
+                    let words = analyzer(phrase);
+                    if !words.is_empty() {
+                        clauses.push(Query::Phrase(words));
+                    }
+                    rest = after;
+                    continue;
+                }
+                let end = rest.find([' ', '"']).unwrap_or(rest.len());
+                let (word, after) = rest.split_at(end);
+                if word == "OR" {
+                    alternatives.push(Query::and(std::mem::take(&mut clauses)));
+                } else {
+                    clauses.extend(
+                        analyzer(word)
+                            .into_iter()
+                            .map(|(_, word)| Query::Term(word)),
+                    );
+                }
+                rest = after;
+            }
+            alternatives.push(Query::and(clauses));
+            alternatives.retain(|query| *query != Query::And(Vec::new()));
+            match alternatives.len() {
+                1 => alternatives.pop().unwrap(),
+                _ => Query::Or(alternatives),
+            }
+        }
+
+        fn and(mut clauses: Vec<Query>) -> Query {
+            match clauses.len() {
+                1 => clauses.pop().unwrap(),
+                _ => Query::And(clauses),
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct Hit<'a> {
+        pub(crate) name: &'a str,
+        pub(crate) score: usize,
+    }
+
+    pub(crate) struct Index {
+        analyzer: Analyzer,
+        names: Vec<String>,
+        // 文档按加入的顺序编号，所以每个倒排列表天然按文档编号排好了序
+        postings: HashMap<String, Vec<Posting>>,
+    }
+
+    impl Index {
+        pub(crate) fn new() -> Index {
+            Index::with_analyzer(english)
+        }
+
+        pub(crate) fn with_analyzer(analyzer: Analyzer) -> Index {
+            Index {
+                analyzer,
+                names: Vec::new(),
+                postings: HashMap::new(),
+            }
+        }
+
+        // 用这个索引的分析器解析查询
+        pub(crate) fn query(&self, input: &str) -> Query {
+            Query::parse(input, self.analyzer)
+        }
+
+        pub(crate) fn add(&mut self, name: &str, text: &str) {
+            let doc = self.names.len();
+            self.names.push(name.to_string());
+            let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+            for (position, word) in (self.analyzer)(text) {
+                positions.entry(word).or_default().push(position);
+            }
+            for (word, positions) in positions {
+                self.postings
+                    .entry(word)
+                    .or_default()
+                    .push(Posting { doc, positions });
+            }
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.names.len()
+        }
+
+        // 返回匹配的文档，按得分（匹配到的词出现的总次数）从高到低排序，得分相同时按文档名排序
+        pub(crate) fn search(&self, query: &Query) -> Vec<Hit<'_>> {
+            let mut hits: Vec<Hit> = self
+                .evaluate(query)
+                .into_iter()
+                .map(|(doc, score)| Hit {
+                    name: &self.names[doc],
+                    score,
+                })
+                .collect();
+            hits.sort_by(|a, b| b.score.cmp(&a.score).then(a.name.cmp(b.name)));
+            hits
+        }
+
+        // 文档编号 → 得分
+        fn evaluate(&self, query: &Query) -> HashMap<usize, usize> {
+            match query {
+                Query::Term(word) => self
+                    .postings
+                    .get(word)
+                    .into_iter()
+                    .flatten()
+                    .map(|posting| (posting.doc, posting.positions.len()))
+                    .collect(),
+                Query::Phrase(words) => self.phrase(words),
+                Query::And(clauses) => {
+                    let mut clauses = clauses.iter().map(|clause| self.evaluate(clause));
+                    let first = clauses.next().unwrap_or_default();
+                    clauses.fold(first, |acc, next| {
+                        acc.into_iter()
+                            .filter_map(|(doc, score)| next.get(&doc).map(|s| (doc, score + s)))
+                            .collect()
+                    })
+                }
+                Query::Or(clauses) => {
+                    let mut scores = HashMap::new();
+                    for clause in clauses {
+                        for (doc, score) in self.evaluate(clause) {
+                            *scores.entry(doc).or_insert(0) += score;
+                        }
+                    }
+                    scores
+                }
+            }
+        }
+
+        // 短语的得分是它完整出现的次数：第一个词出现在 p 时，短语中和它相隔 d 的词必须出现在 p + d
+        fn phrase(&self, words: &[(usize, String)]) -> HashMap<usize, usize> {
+            let lists: Option<Vec<&Vec<Posting>>> = words
+                .iter()
+                .map(|(_, word)| self.postings.get(word))
+                .collect();
+            let Some((first, rest)) = lists.as_deref().and_then(<[_]>::split_first) else {
+                return HashMap::new();
+            };
+
+            let mut scores = HashMap::new();
+            for posting in first.iter() {
+                // 倒排列表按文档编号排序，可以二分查找同一篇文档的位置
+                let others: Option<Vec<&Vec<usize>>> = rest
+                    .iter()
+                    .map(|list| {
+                        list.binary_search_by_key(&posting.doc, |p| p.doc)
+                            .ok()
+                            .map(|i| &list[i].positions)
+                    })
+                    .collect();
+                let Some(others) = others else {
+                    continue;
+                };
+                let count = posting
+                    .positions
+                    .iter()
+                    .filter(|&&start| {
+                        others.iter().enumerate().all(|(i, at)| {
+                            let offset = words[i + 1].0 - words[0].0;
+                            at.binary_search(&(start + offset)).is_ok()
+                        })
+                    })
+                    .count();
+                if count > 0 {
+                    scores.insert(posting.doc, count);
+                }
+            }
+            scores
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::index::*;
+
+    fn fixture() -> Index {
+        let mut index = Index::new();
+        index.add(
+            "pool.rs",
+            "A thread pool runs jobs. Each worker thread takes jobs from the pool queue.",
+        );
+        index.add(
+            "server.rs",
+            "The server accepts TCP connections and hands them to the thread pool.",
+        );
+        index.add(
+            "client.rs",
+            "The client opens a TCP connection, sends a request and reads the response.",
+        );
+        index
+    }
+
+    fn names<'a>(hits: &[Hit<'a>]) -> Vec<&'a str> {
+        hits.iter().map(|hit| hit.name).collect()
+    }
+
+    #[test]
+    fn tokenizes_words_with_positions() {
+        let tokens: Vec<_> = tokenize("Hello, thread_pool! 2 Workers")
+            .lowercase()
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (0, String::from("hello")),
+                (1, String::from("thread")),
+                (2, String::from("pool")),
+                (3, String::from("2")),
+                (4, String::from("workers")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert_eq!(
+            Query::parse("Pool", plain),
+            Query::Term(String::from("pool"))
+        );
+        assert_eq!(
+            Query::parse(r#"tcp "Thread Pool" OR client"#, plain),
+            Query::Or(vec![
+                Query::And(vec![
+                    Query::Term(String::from("tcp")),
+                    Query::Phrase(vec![(0, String::from("thread")), (1, String::from("pool"))]),
+                ]),
+                Query::Term(String::from("client")),
+            ])
+        );
+        // 空的一组条件被忽略
+        assert_eq!(
+            Query::parse("OR tcp OR", plain),
+            Query::Term(String::from("tcp"))
+        );
+        // 分析器去掉停用词、提取词干，短语里的词保留原来的位置
+        assert_eq!(
+            Query::parse(r#""pool of the Connections""#, english),
+            Query::Phrase(vec![
+                (0, String::from("pool")),
+                (3, String::from("connect"))
+            ])
+        );
+    }
+
+    #[test]
+    fn and_or_queries_ranked_by_term_frequency() {
+        let index = fixture();
+        assert_eq!(index.len(), 3);
+
+        // pool.rs 里 thread 出现 2 次、pool 出现 2 次
+        let hits = index.search(&index.query("thread pool"));
+        assert_eq!(
+            hits,
+            [
+                Hit {
+                    name: "pool.rs",
+                    score: 4
+                },
+                Hit {
+                    name: "server.rs",
+                    score: 2
+                },
+            ]
+        );
+
+        assert_eq!(
+            names(&index.search(&index.query("tcp"))),
+            ["client.rs", "server.rs"]
+        );
+        assert_eq!(
+            names(&index.search(&index.query("jobs OR request"))),
+            ["pool.rs", "client.rs"]
+        );
+        assert!(index.search(&index.query("tcp jobs")).is_empty());
+        assert!(index.search(&index.query("missing")).is_empty());
+    }
+
+    #[test]
+    fn phrase_queries_need_adjacent_words() {
+        let index = fixture();
+        // “thread pool” 在 pool.rs 和 server.rs 中紧挨着出现，“pool thread” 没有
+        assert_eq!(
+            names(&index.search(&index.query(r#""thread pool""#))),
+            ["pool.rs", "server.rs"]
+        );
+        assert!(index.search(&index.query(r#""pool thread""#)).is_empty());
+        assert_eq!(
+            names(&index.search(&index.query(r#""worker thread takes""#))),
+            ["pool.rs"]
+        );
+        assert!(index.search(&index.query(r#""thread missing""#)).is_empty());
+    }
+
+    #[test]
+    fn analysis_pipeline_stages() {
+        let tokens: Vec<_> = tokenize("The Workers are RUNNING jobs")
+            .lowercase()
+            .without_stop_words()
+            .stemmed()
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (1, String::from("worker")),
+                (3, String::from("run")),
+                (4, String::from("job")),
+            ]
+        );
+    }
+
+    #[test]
+    fn porter_stemmer() {
+        for (word, expected) in [
+            ("caresses", "caress"),
+            ("ponies", "poni"),
+            ("cats", "cat"),
+            ("agreed", "agre"),
+            ("hopping", "hop"),
+            ("filing", "file"),
+            ("happy", "happi"),
+            ("relational", "relat"),
+            ("conditional", "condit"),
+            ("generalization", "gener"),
+            ("connections", "connect"),
+            ("connected", "connect"),
+            ("connecting", "connect"),
+            ("adjustment", "adjust"),
+            ("adoption", "adopt"),
+            ("controlling", "control"),
+            ("probate", "probat"),
+            ("is", "is"),
+            ("线程", "线程"),
+        ] {
+            assert_eq!(stem(word), expected, "stem({})", word);
+        }
+    }
+
+    // 一组固定的查询和人工标注的相关文档，比较两种分析器的召回率（相关文档中被找到的比例）
+    #[test]
+    fn stemming_improves_recall() {
+        let documents = [
+            ("connect.rs", "Connecting to the server"),
+            ("accept.rs", "The server accepted two connections"),
+            ("pool.rs", "A connection pool"),
+            ("jobs.rs", "Runs jobs in order"),
+            ("runner.rs", "The job runner is running"),
+            ("queue.rs", "Work waits in queues for a worker"),
+        ];
+        let queries: [(&str, &[&str]); 4] = [
+            ("connection", &["accept.rs", "connect.rs", "pool.rs"]),
+            ("run job", &["jobs.rs", "runner.rs"]),
+            ("the pool", &["pool.rs"]),
+            ("queue workers", &["queue.rs"]),
+        ];
+
+        let recall = |analyzer: Analyzer| {
+            let mut index = Index::with_analyzer(analyzer);
+            for (name, text) in documents {
+                index.add(name, text);
+            }
+            let (mut found, mut relevant) = (0, 0);
+            for (query, expected) in queries {
+                let hits = index.search(&index.query(query));
+                found += expected
+                    .iter()
+                    .filter(|name| names(&hits).contains(name))
+                    .count();
+                relevant += expected.len();
+            }
+            found as f64 / relevant as f64
+        };
+
+        let before = recall(plain);
+        let after = recall(english);
+        assert_eq!(before, 1.0 / 7.0);
+        assert_eq!(after, 1.0);
+    }
+}
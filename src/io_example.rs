@@ -2,41 +2,282 @@
 #[cfg(test)]
 mod tests {
 
+    use crate::atomic_write_example::atomic_write;
+    use crate::glob_example::glob::Pattern;
+    use crate::ignore_example::ignore::IgnoreStack;
+    use crate::thread_pool_example::thread_pool::{panic_message, ThreadPool};
+    use clap::Parser;
+    use regex::bytes::NoExpand;
+    use regex::{Regex, RegexBuilder};
+    use serde::Serialize;
+    use std::collections::{BTreeMap, VecDeque};
     use std::env;
     use std::error::Error;
-    use std::fs;
+    use std::fs::{self, File};
+    use std::io::{self, IsTerminal, Read, Write};
+    use std::ops::Range;
+    use std::path::Path;
     use std::process;
+    use std::time::Instant;
+    use std::{ptr, slice, str};
 
+    // 匹配到的子串用 ANSI 转义序列标成粗体红色，和 grep --color 一样
+    const HIGHLIGHT: &str = "\x1b[1;31m";
+    const RESET: &str = "\x1b[0m";
+
+    // 文件名是 - 或者没有给文件名时从标准输入读，这样可以接在管道后面用，例如 cat poem.txt | minigrep body
+    const STDIN: &str = "-";
+
+    #[derive(Clone)]
     struct Config {
         query: String,
         filename: String,
         case_sensitive: bool,
+        // 带上 --regex 时 query 按正则表达式匹配，默认还是普通的子串查找
+        regex: bool,
+        // 是否高亮匹配的部分：带上 --no-color 或者输出不是终端（例如重定向到文件、接管道）时关闭
+        color: bool,
+        // 每处匹配前后各输出几行上下文，和 grep 的 -B、-A 一样
+        before: usize,
+        after: usize,
+        mode: Mode,
+        // --invert-match 输出不匹配的行；--word 只在单词边界上匹配，单词由字母、数字和下划线组成
+        invert: bool,
+        word: bool,
+        // 默认像 grep 一样把二进制文件的匹配缩成一行提示，带上 --binary 时照常逐行输出
+        binary: bool,
+        // 搜索目录时只搜文件名匹配 include 的文件（为空时搜所有文件），跳过匹配 exclude 的文件和目录
+        include: Vec<Pattern>,
+        exclude: Vec<Pattern>,
+        // --replace 把匹配替换成这段文本并写回文件，开了 --regex 时可以用 $1、${name} 引用捕获组
+        // --dry-run 只输出会怎样修改，不写文件
+        replace: Option<String>,
+        dry_run: bool,
+        // 搜索目录时默认遵守 .gitignore 并跳过 .git 目录，--no-ignore 时全部搜索
+        no_ignore: bool,
+    }
+
+    // 输出方式：默认逐行输出匹配；-c 只输出匹配的行数，-l 只输出有匹配的文件名，-q 什么都不输出，只看退出码
+    // --json 每处匹配输出一行 JSON，给编辑器和脚本读；-Z 和 -l 一样输出文件名，但每个文件名后面跟的是 NUL 而不是换行，
+    // 文件名里有空格、换行也能交给 xargs -0 处理
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    enum Mode {
+        #[default]
+        Lines,
+        Count,
+        FilesWithMatches,
+        NullSeparated,
+        Quiet,
+        Json,
+    }
+
+    // 命令行里的选项，可以出现在任意位置
+    #[derive(Debug, Default, PartialEq)]
+    struct Options {
+        regex: bool,
+        no_color: bool,
+        before: usize,
+        after: usize,
+        mode: Mode,
+        invert: bool,
+        word: bool,
+        binary: bool,
+        include: Vec<Pattern>,
+        exclude: Vec<Pattern>,
+        replace: Option<String>,
+        dry_run: bool,
+        no_ignore: bool,
+    }
+
+    // 参数里的 glob 在解析时就编译，写错了（例如 [a-z 没有闭合）直接报错
+    fn glob_arg(pattern: impl AsRef<str>) -> Result<Pattern, &'static str> {
+        Pattern::new(pattern.as_ref()).map_err(|_| "invalid glob pattern")
+    }
+
+    // 把选项和位置参数分开。-A/-B/-C 带一个行数，可以写成 -A 2 也可以写成 -A2；-C 同时设置前后两边
+    // --include、--exclude 带一个 glob，可以写成 --include '*.rs' 也可以写成 --include='*.rs'，可以给多次
+    fn parse_options(
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<(Options, Vec<String>), &'static str> {
+        let mut options = Options::default();
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--regex" => options.regex = true,
+                "--no-color" => options.no_color = true,
+                "-c" => options.mode = Mode::Count,
+                "-l" => options.mode = Mode::FilesWithMatches,
+                "-q" => options.mode = Mode::Quiet,
+                "--json" => options.mode = Mode::Json,
+                "-Z" | "--null" => options.mode = Mode::NullSeparated,
+                "--invert-match" => options.invert = true,
+                "--word" => options.word = true,
+                "--binary" => options.binary = true,
+                "--include" => options
+                    .include
+                    .push(glob_arg(args.next().ok_or("missing glob pattern")?)?),
+                "--exclude" => options
+                    .exclude
+                    .push(glob_arg(args.next().ok_or("missing glob pattern")?)?),
+                "--replace" => {
+                    options.replace = Some(args.next().ok_or("missing replacement text")?)
+                }
+                "--dry-run" => options.dry_run = true,
+                "--no-ignore" => options.no_ignore = true,
+                _ if arg.starts_with("--replace=") => {
+                    options.replace = Some(arg["--replace=".len()..].to_string())
+                }
+                _ if arg.starts_with("--include=") => {
+                    options.include.push(glob_arg(&arg["--include=".len()..])?)
+                }
+                _ if arg.starts_with("--exclude=") => {
+                    options.exclude.push(glob_arg(&arg["--exclude=".len()..])?)
+                }
+                _ if arg.len() >= 2 && ["-A", "-B", "-C"].contains(&&arg[..2]) => {
+                    let count = if arg.len() > 2 {
+                        arg[2..].to_string()
+                    } else {
+                        args.next().ok_or("missing context line count")?
+                    };
+                    let count = count.parse().map_err(|_| "invalid context line count")?;
+                    match &arg[..2] {
+                        "-A" => options.after = count,
+                        "-B" => options.before = count,
+                        _ => (options.before, options.after) = (count, count),
+                    }
+                }
+                _ => positional.push(arg),
+            }
+        }
+        Ok((options, positional))
+    }
+
+    // 用 clap 的 derive 宏声明同样的命令行：字段就是参数，/// 注释是 --help 里的说明
+    // 和手写的 parse_options 相比，--help、-h、未知选项、缺参数的报错都是自动的，选项之间的约束也能直接声明：
+    // -c、-l、-q、--json 只能选一个（手写版本是后出现的覆盖前面的），--dry-run 必须和 --replace 一起用
+    #[derive(Parser, Debug)]
+    #[command(
+        name = "minigrep",
+        about = "Search for a pattern in a file, a directory or standard input"
+    )]
+    struct Cli {
+        /// Text to search for (a regular expression with --regex)
+        query: String,
+        /// File or directory to search; - or nothing reads standard input
+        #[arg(default_value = STDIN)]
+        filename: String,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Never highlight matches
+        #[arg(long)]
+        no_color: bool,
+        /// Print NUM lines of trailing context after each match
+        #[arg(short = 'A', value_name = "NUM")]
+        after: Option<usize>,
+        /// Print NUM lines of leading context before each match
+        #[arg(short = 'B', value_name = "NUM")]
+        before: Option<usize>,
+        /// Print NUM lines of context on both sides; -A and -B take precedence
+        #[arg(short = 'C', value_name = "NUM")]
+        context: Option<usize>,
+        /// Print only the number of matching lines
+        #[arg(short = 'c', group = "mode")]
+        count: bool,
+        /// Print only the names of files with matches
+        #[arg(short = 'l', group = "mode")]
+        files_with_matches: bool,
+        /// Print nothing; the exit status tells whether anything matched
+        #[arg(short = 'q', group = "mode")]
+        quiet: bool,
+        /// Print one JSON object per match
+        #[arg(long, group = "mode")]
+        json: bool,
+        /// Print the names of files with matches, each followed by a NUL byte
+        #[arg(short = 'Z', long = "null", group = "mode")]
+        null: bool,
+        /// Select lines that do not match
+        #[arg(long)]
+        invert_match: bool,
+        /// Match only whole words
+        #[arg(long)]
+        word: bool,
+        /// Print matching lines of binary files instead of a notice
+        #[arg(long)]
+        binary: bool,
+        /// Only search files whose name or path matches GLOB (repeatable)
+        #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+        include: Vec<Pattern>,
+        /// Skip files and directories matching GLOB (repeatable)
+        #[arg(long, value_name = "GLOB", value_parser = Pattern::new)]
+        exclude: Vec<Pattern>,
+        /// Replace matches with TEXT and write the files back ($1, ${name} with --regex)
+        #[arg(long, value_name = "TEXT")]
+        replace: Option<String>,
+        /// Show what --replace would change without writing
+        #[arg(long, requires = "replace")]
+        dry_run: bool,
+        /// Search files ignored by .gitignore and the .git directory
+        #[arg(long)]
+        no_ignore: bool,
+    }
+
+    impl Cli {
+        // 转成和手写版本相同的 Options，Config 的其余部分两个版本共用
+        fn into_options(self) -> (String, String, Options) {
+            let mode = if self.count {
+                Mode::Count
+            } else if self.files_with_matches {
+                Mode::FilesWithMatches
+            } else if self.quiet {
+                Mode::Quiet
+            } else if self.json {
+                Mode::Json
+            } else if self.null {
+                Mode::NullSeparated
+            } else {
+                Mode::Lines
+            };
+            let options = Options {
+                regex: self.regex,
+                no_color: self.no_color,
+                before: self.before.or(self.context).unwrap_or(0),
+                after: self.after.or(self.context).unwrap_or(0),
+                mode,
+                invert: self.invert_match,
+                word: self.word,
+                binary: self.binary,
+                include: self.include,
+                exclude: self.exclude,
+                replace: self.replace,
+                dry_run: self.dry_run,
+                no_ignore: self.no_ignore,
+            };
+            (self.query, self.filename, options)
+        }
     }
 
     impl Config {
         // 错误信息的生命周期：所有的字符串字面量都拥有 'static 生命周期
         fn new(args: &[String]) -> Result<Config, &'static str> {
-            if args.len() < 3 {
+            let (options, args) = parse_options(args.iter().cloned())?;
+            if args.len() < 2 {
                 return Err("not enough arguments");
             }
             // main 中的 args 变量是参数值的所有者并只允许 new 函数借用他们，这意味着如果 Config 尝试获取 args 中值的所有权将违反 Rust 的借用规则
             // 而最简单但有些不太高效的方式是调用这些值的 clone 方法。这会生成 Config 实例可以拥有的数据的完整拷贝，不过会比储存字符串数据的引用消耗更多的时间和内存
             // 不过拷贝数据使得代码显得更加直白因为无需管理引用的生命周期，所以在这种情况下牺牲一小部分性能来换取简洁性的取舍是值得的
             let query = args[1].clone();
-            let filename = args[2].clone();
-
-            // 读取环境变量，用 Result 的 is_err 方法来检查其是否是一个 error
-            let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+            let filename = args.get(2).cloned().unwrap_or_else(|| String::from(STDIN));
 
-            Ok(Config {
-                query,
-                filename,
-                case_sensitive,
-            })
+            Ok(Config::with_options(query, filename, options))
         }
 
         // 使用迭代器的方式获取 args 参数
-        fn new_instance(mut args: std::env::Args) -> Result<Config, &'static str> {
+        fn new_instance(args: std::env::Args) -> Result<Config, &'static str> {
+            // 选项分出去之后，位置参数仍然按值移动，不需要 clone
+            let (options, args) = parse_options(args)?;
+            let mut args = args.into_iter();
             // 将 new 函数改为获取一个有所有权的迭代器作为参数而不是借用 slice
             // 一旦 Config::new 获取了迭代器的所有权并不再使用借用的索引操作，就可以将迭代器中的 String 值移动到 Config 中，而不是调用 clone 分配新的空间
             let query = match args.next() {
@@ -44,49 +285,182 @@ mod tests {
                 None => return Err("Didn't get a query string"),
             };
 
-            let filename = match args.next() {
-                Some(arg) => arg,
-                None => return Err("Didn't get a file name"),
-            };
+            let filename = args.next().unwrap_or_else(|| String::from(STDIN));
+            Ok(Config::with_options(query, filename, options))
+        }
 
-            let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+        // clap 版本：解析和错误信息、--help 都由 Cli 上的属性生成
+        fn from_cli(cli: Cli) -> Config {
+            let (query, filename, options) = cli.into_options();
+            Config::with_options(query, filename, options)
+        }
 
-            Ok(Config {
+        fn with_options(query: String, filename: String, options: Options) -> Config {
+            // 读取环境变量，用 Result 的 is_err 方法来检查其是否是一个 error
+            let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+            Config {
                 query,
                 filename,
                 case_sensitive,
-            })
+                regex: options.regex,
+                color: !options.no_color && io::stdout().is_terminal(),
+                before: options.before,
+                after: options.after,
+                mode: options.mode,
+                invert: options.invert,
+                word: options.word,
+                binary: options.binary,
+                include: options.include,
+                exclude: options.exclude,
+                replace: options.replace,
+                dry_run: options.dry_run,
+                no_ignore: options.no_ignore,
+            }
         }
     }
 
+    // 一处匹配：行号从 1 开始，range 是第一处匹配在 line 里的字节范围，输出时用来高亮
+    #[derive(Debug, PartialEq)]
+    struct Match<'a> {
+        line_no: usize,
+        line: &'a str,
+        range: Range<usize>,
+    }
+
     // 告诉 Rust 函数 search 返回的数据将与 search 函数中的参数 contents 的数据存在的一样久。
     // 这是非常重要的！为了使这个引用有效那么 被 slice 引用的数据也需要保持有效；
     // 如果编译器认为我们是在创建 query 而不是 contents 的字符串 slice，那么安全检查将是不正确的
-    fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
         let mut results = Vec::new();
-        for line in contents.lines() {
-            if line.contains(query) {
-                results.push(line);
+        // enumerate 从 0 开始数，行号要加一
+        for (i, line) in contents.lines().enumerate() {
+            if let Some(start) = line.find(query) {
+                results.push(Match {
+                    line_no: i + 1,
+                    line,
+                    range: start..start + query.len(),
+                });
             }
         }
         results
     }
 
     // 使用迭代器适配器的方式编写代码，函数式编程风格
-    fn search_iter<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    fn search_iter<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+        contents
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let start = line.find(query)?;
+                Some(Match {
+                    line_no: i + 1,
+                    line,
+                    range: start..start + query.len(),
+                })
+            })
+            .collect()
+    }
+
+    // 原来的做法是把整行转成小写再 contains，但小写后的字节位置和原来的行对不上（例如 'İ' 转小写后变长），
+    // 没法用来高亮。这里从原来的行的每个字符边界开始，边转小写边和 query 比较，得到的范围落在原来的行上
+    fn find_case_insensitive(line: &str, query: &str) -> Option<Range<usize>> {
+        // 大多数行不匹配，先整行转小写排除掉，逐个位置比较只在确实匹配的行上做
+        if !line.to_lowercase().contains(query) {
+            return None;
+        }
+        // 空的 query 匹配任何一行
+        case_insensitive_matches(line, query)
+            .next()
+            .or_else(|| query.is_empty().then_some(0..0))
+    }
+
+    // 按顺序列出一行里所有忽略大小写的匹配，可以互相重叠
+    fn case_insensitive_matches<'a>(
+        line: &'a str,
+        query: &'a str,
+    ) -> impl Iterator<Item = Range<usize>> + 'a {
+        line.char_indices().filter_map(move |(start, _)| {
+            let mut lowered = String::new();
+            for (offset, c) in line[start..].char_indices() {
+                lowered.extend(c.to_lowercase());
+                if !query.starts_with(&lowered) {
+                    return None;
+                }
+                if lowered.len() == query.len() {
+                    return Some(start..start + offset + c.len_utf8());
+                }
+            }
+            None
+        })
+    }
+
+    fn is_word_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_'
+    }
+
+    // --word：在所有匹配里找第一处前后都不是单词字符的。行首、行尾和标点都算边界，
+    // 所以 "rust" 能匹配 "rust, fast" 和 "(rust)"，但不能匹配 "trusty"
+    fn find_word(line: &str, query: &str, case_sensitive: bool) -> Option<Range<usize>> {
+        let bounded = |range: &Range<usize>| {
+            !line[..range.start]
+                .chars()
+                .next_back()
+                .is_some_and(is_word_char)
+                && !line[range.end..].chars().next().is_some_and(is_word_char)
+        };
+        if case_sensitive {
+            line.char_indices()
+                .filter(|(start, _)| line[*start..].starts_with(query))
+                .map(|(start, _)| start..start + query.len())
+                .find(bounded)
+        } else {
+            case_insensitive_matches(line, query).find(bounded)
+        }
+    }
+
+    // 带 --word 或 --invert-match 时的搜索。反向匹配输出的是不匹配的行，没有要高亮的部分，范围是空的
+    fn search_with<'a>(config: &Config, contents: &'a str) -> Vec<Match<'a>> {
+        let query = if config.case_sensitive {
+            config.query.clone()
+        } else {
+            config.query.to_lowercase()
+        };
         contents
             .lines()
-            .filter(|line| line.contains(query))
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let found = if config.word {
+                    find_word(line, &query, config.case_sensitive)
+                } else if config.case_sensitive {
+                    line.find(&query).map(|start| start..start + query.len())
+                } else {
+                    find_case_insensitive(line, &query)
+                };
+                let range = match (found, config.invert) {
+                    (Some(range), false) => range,
+                    (None, true) => 0..0,
+                    _ => return None,
+                };
+                Some(Match {
+                    line_no: i + 1,
+                    line,
+                    range,
+                })
+            })
             .collect()
     }
 
-    fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
         let query = query.to_lowercase();
         let mut results = Vec::new();
 
-        for line in contents.lines() {
-            if line.to_lowercase().contains(&query) {
-                results.push(line);
+        for (i, line) in contents.lines().enumerate() {
+            if let Some(range) = find_case_insensitive(line, &query) {
+                results.push(Match {
+                    line_no: i + 1,
+                    line,
+                    range,
+                });
             }
         }
 
@@ -94,108 +468,2314 @@ mod tests {
     }
 
     // 使用迭代器适配器的方式编写代码，函数式编程风格
-    fn search_case_insensitive_iter<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    fn search_case_insensitive_iter<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
         let query = query.to_lowercase();
         contents
             .lines()
-            .filter(|line| line.to_lowercase().contains(&query))
+            .enumerate()
+            .filter_map(|(i, line)| {
+                Some(Match {
+                    line_no: i + 1,
+                    line,
+                    range: find_case_insensitive(line, &query)?,
+                })
+            })
             .collect()
     }
 
-    // trait 对象 Box<dyn Error> 意味着函数会返回实现了 Error trait 的类型，不过无需指定具体将会返回的值的类型
-    // 这提供了在不同的错误场景可能有不同类型的错误返回值的灵活性。这也就是 dyn，它是 “动态的”（“dynamic”）的缩写
-    // Ok(()) 表示成功则返回空元组，表明无需关注该函数的返回值，只需要处理其带来的副作用即可
-    fn run(config: Config) -> Result<(), Box<dyn Error>> {
-        // 不同于遇到错误就 panic!，? 会从函数中返回错误值并让调用者来处理它
-        let contents = fs::read_to_string(config.filename)?;
+    // 输出一处匹配：path:行号:内容，color 为 true 时高亮匹配到的部分
+    fn format_match(path: &str, found: &Match, color: bool) -> String {
+        let Match {
+            line_no,
+            line,
+            range,
+        } = found;
+        if !color || range.is_empty() {
+            return format!("{}:{}:{}", path, line_no, line);
+        }
+        format!(
+            "{}:{}:{}{}{}{}{}",
+            path,
+            line_no,
+            &line[..range.start],
+            HIGHLIGHT,
+            &line[range.clone()],
+            RESET,
+            &line[range.end..]
+        )
+    }
 
-        let results = if config.case_sensitive {
-            search(&config.query, &contents)
+    // 读取要搜索的内容；stdin 作为参数传进来，测试时可以换成内存里的数据
+    // 不是合法 UTF-8 的字节换成 U+FFFD，二进制文件也能搜，不会因为读不成字符串而报错
+    fn read_haystack(filename: &str, mut stdin: impl Read) -> io::Result<String> {
+        let bytes = if filename == STDIN {
+            let mut bytes = Vec::new();
+            stdin.read_to_end(&mut bytes)?;
+            bytes
         } else {
-            search_case_insensitive(&config.query, &contents)
+            fs::read(filename)?
         };
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    // 不小于这个大小的文件用 mmap 映射进内存直接搜索，省掉 read 把整个文件复制到堆上的那一遍
+    // 小文件还是 read 更快：建立映射、处理缺页都有开销，文件大了才划算
+    const MMAP_THRESHOLD: u64 = 1 << 20;
+
+    // 只读映射的一个文件，丢弃时解除映射
+    // 搜索过程中文件被别的进程截短的话，访问到新的文件末尾之后的页会收到 SIGBUS，ripgrep 之类的工具也接受这个风险
+    #[cfg(unix)]
+    struct Mapped {
+        ptr: *mut libc::c_void,
+        len: usize,
+    }
 
-        for line in results {
-            println!("line = {}", line);
+    #[cfg(unix)]
+    impl Mapped {
+        // len 不能是 0，长度为 0 的映射会失败
+        fn new(file: &File, len: usize) -> io::Result<Mapped> {
+            use std::os::unix::io::AsRawFd;
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Mapped { ptr, len })
         }
 
-        Ok(())
+        fn bytes(&self) -> &[u8] {
+            unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
     }
 
-    // 使用迭代器适配器的方式编写代码，函数式编程风格
-    fn run_iter(config: Config) -> Result<(), Box<dyn Error>> {
-        // 不同于遇到错误就 panic!，? 会从函数中返回错误值并让调用者来处理它
-        let contents = fs::read_to_string(config.filename)?;
+    #[cfg(unix)]
+    impl Drop for Mapped {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
 
-        let results = if config.case_sensitive {
-            search_iter(&config.query, &contents)
+    // 要搜索的内容：合法 UTF-8 的大文件是映射，其他情况是读进来的字符串
+    // 映射不能当作 &str 用：MAP_PRIVATE 只是不把我们的修改写回文件，别的进程改了文件，映射里看到的内容也会跟着变，
+    // 打开时校验过是 UTF-8，搜索的时候未必还是，from_utf8_unchecked 就成了未定义行为。所以映射只按字节搜索，见 search_mapped
+    enum Haystack {
+        #[cfg(unix)]
+        Mapped(Mapped),
+        Owned(String),
+    }
+
+    impl Haystack {
+        fn bytes(&self) -> &[u8] {
+            match self {
+                #[cfg(unix)]
+                Haystack::Mapped(mapped) => mapped.bytes(),
+                Haystack::Owned(text) => text.as_bytes(),
+            }
+        }
+    }
+
+    // 和 read_haystack 的内容相同，只是大文件走 mmap
+    fn open_haystack(filename: &str, stdin: impl Read) -> io::Result<Haystack> {
+        #[cfg(unix)]
+        if filename != STDIN && fs::metadata(filename)?.len() >= MMAP_THRESHOLD {
+            return map_haystack(filename);
+        }
+        read_haystack(filename, stdin).map(Haystack::Owned)
+    }
+
+    // 不是合法 UTF-8 时退回到复制一份、把非法字节换成 U+FFFD，和 read_haystack 的结果一样，
+    // 这样按字节搜索和按字符串搜索的结果才一致（例如 . 能不能匹配非法字节）
+    #[cfg(unix)]
+    fn map_haystack(filename: &str) -> io::Result<Haystack> {
+        let file = File::open(filename)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Haystack::Owned(String::new()));
+        }
+        // 映射建立以后关掉文件也没关系，映射一直有效到 munmap
+        let mapped = Mapped::new(&file, len)?;
+        if str::from_utf8(mapped.bytes()).is_ok() {
+            Ok(Haystack::Mapped(mapped))
         } else {
-            search_case_insensitive_iter(&config.query, &contents)
+            Ok(Haystack::Owned(
+                String::from_utf8_lossy(mapped.bytes()).into_owned(),
+            ))
+        }
+    }
+
+    // 和 grep 的判断方法一样：开头一块里有 NUL 字节就当作二进制文件。文本文件里几乎不会出现 NUL，
+    // 而可执行文件、图片、压缩包的文件头附近基本都有。NUL 是合法的 UTF-8，解码之后还在
+    const BINARY_PROBE: usize = 8192;
+
+    fn is_binary(contents: &[u8]) -> bool {
+        contents[..contents.len().min(BINARY_PROBE)].contains(&0)
+    }
+
+    // 输出里的文件名，标准输入和 grep 一样显示成 (standard input)
+    fn label(filename: &str) -> &str {
+        if filename == STDIN {
+            "(standard input)"
+        } else {
+            filename
+        }
+    }
+
+    // 输出时要用到的原文。读进来的字符串每一行都在；映射的文件只解码了匹配的行和它们的上下文
+    enum Text<'a> {
+        Whole {
+            contents: &'a str,
+            lines: Vec<&'a str>,
+        },
+        // 行号（从 0 开始）对应这一行在文件里的字节偏移和解码出来的内容，total 是文件的总行数
+        Excerpt {
+            lines: &'a BTreeMap<usize, (usize, String)>,
+            total: usize,
+            binary: bool,
+        },
+    }
+
+    impl<'a> Text<'a> {
+        fn whole(contents: &'a str) -> Text<'a> {
+            Text::Whole {
+                contents,
+                lines: contents.lines().collect(),
+            }
+        }
+
+        // 第 i 行，从 0 开始。Excerpt 里只有匹配附近的行，render 也只会取这些行
+        fn line(&self, i: usize) -> &str {
+            match self {
+                Text::Whole { lines, .. } => lines[i],
+                Text::Excerpt { lines, .. } => &lines[&i].1,
+            }
+        }
+
+        fn line_count(&self) -> usize {
+            match self {
+                Text::Whole { lines, .. } => lines.len(),
+                Text::Excerpt { total, .. } => *total,
+            }
+        }
+
+        // found.line 在文件里的字节偏移。Whole 里 found.line 是 contents 的一部分，两个指针相减就是偏移
+        fn offset(&self, found: &Match) -> usize {
+            match self {
+                Text::Whole { contents, .. } => {
+                    found.line.as_ptr() as usize - contents.as_ptr() as usize
+                }
+                Text::Excerpt { lines, .. } => lines[&(found.line_no - 1)].0,
+            }
+        }
+
+        fn is_binary(&self) -> bool {
+            match self {
+                Text::Whole { contents, .. } => is_binary(contents.as_bytes()),
+                Text::Excerpt { binary, .. } => *binary,
+            }
+        }
+    }
+
+    // 按 grep 的格式输出匹配的行和上下文：匹配的行是 path:行号:内容，上下文是 path-行号-内容，
+    // 不相连的两组之间用 -- 隔开。相邻匹配的上下文重叠时每行只输出一次
+    // annotate 返回紧跟在第 i 处匹配后面输出的附加行，例如正则的捕获组
+    fn render(
+        config: &Config,
+        text: &Text,
+        matches: &[&Match],
+        annotate: &dyn Fn(usize) -> Vec<String>,
+    ) -> Vec<String> {
+        let path = label(&config.filename);
+        let context = |i: usize| format!("{}-{}-{}", path, i + 1, text.line(i));
+        let mut out = Vec::new();
+        // 下一个还没输出的行（从 0 开始）
+        let mut next = 0;
+        for (k, found) in matches.iter().enumerate() {
+            let at = found.line_no - 1;
+            let start = at.saturating_sub(config.before).max(next);
+            // 没有上下文时 grep 也不输出分隔符
+            if k > 0 && start > next && (config.before > 0 || config.after > 0) {
+                out.push(String::from("--"));
+            }
+            out.extend((start..at).map(context));
+            out.push(format_match(path, found, config.color));
+            out.extend(annotate(k));
+            // 后面的上下文遇到下一处匹配就停下，那一行作为匹配输出
+            let end = (at + 1 + config.after)
+                .min(text.line_count())
+                .min(matches.get(k + 1).map_or(usize::MAX, |m| m.line_no - 1));
+            out.extend((at + 1..end).map(context));
+            next = end.max(at + 1);
+        }
+        out
+    }
+
+    // 正则匹配的结果：匹配的位置以及每个捕获组匹配到的文本，没有参与匹配的组是 None
+    struct RegexMatch<'a> {
+        found: Match<'a>,
+        groups: Vec<Option<&'a str>>,
+    }
+
+    // 逐行匹配正则表达式，一行只取第一处匹配的捕获组
+    fn search_regex<'a>(pattern: &Regex, contents: &'a str) -> Vec<RegexMatch<'a>> {
+        contents
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| regex_match(pattern, i + 1, line))
+            .collect()
+    }
+
+    // captures 同时做匹配和提取，不匹配时返回 None
+    fn regex_match<'a>(pattern: &Regex, line_no: usize, line: &'a str) -> Option<RegexMatch<'a>> {
+        let captures = pattern.captures(line)?;
+        // 第 0 组是整个匹配，一定存在，用来高亮；捕获组从第 1 组开始
+        let found = Match {
+            line_no,
+            line,
+            range: captures.get(0).unwrap().range(),
         };
+        let groups = captures
+            .iter()
+            .skip(1)
+            .map(|group| group.map(|m| m.as_str()))
+            .collect();
+        Some(RegexMatch { found, groups })
+    }
+
+    // 不区分大小写交给正则引擎处理，比把每一行都转成小写再匹配更准确（例如 \w 之类的字符类不受影响）
+    fn run_regex(config: &Config, contents: &str) -> Result<bool, Box<dyn Error>> {
+        // 正则表达式语法错误时返回 regex::Error，? 把它转换成 Box<dyn Error>
+        Ok(print_output(regex_output(config, contents, false)?))
+    }
 
-        for line in results {
-            println!("line = {}", line);
+    // 按 config 编译正则。不是 --regex 时 query 先转义成字面量，这样替换也可以统一用正则来做
+    // --word 时两边加上 \b，非捕获组保证 | 不会把边界只留给第一个分支
+    fn compile(config: &Config) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(&pattern_source(config))
+            .case_insensitive(!config.case_sensitive)
+            .build()
+    }
+
+    // 同一个正则的字节版本，在映射上搜索、在文件里替换时用
+    fn compile_bytes(config: &Config) -> Result<regex::bytes::Regex, regex::Error> {
+        regex::bytes::RegexBuilder::new(&pattern_source(config))
+            .case_insensitive(!config.case_sensitive)
+            .build()
+    }
+
+    fn pattern_source(config: &Config) -> String {
+        let query = if config.regex {
+            config.query.clone()
+        } else {
+            regex::escape(&config.query)
+        };
+        if config.word {
+            format!(r"\b(?:{})\b", query)
+        } else {
+            query
         }
+    }
 
-        Ok(())
+    // 不匹配正则的行，没有捕获组
+    fn invert_regex<'a>(pattern: &Regex, contents: &'a str) -> Vec<RegexMatch<'a>> {
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !pattern.is_match(line))
+            .map(|(i, line)| RegexMatch {
+                found: Match {
+                    line_no: i + 1,
+                    line,
+                    range: 0..0,
+                },
+                groups: Vec::new(),
+            })
+            .collect()
     }
 
-    #[test]
-    fn io_test() {
-        // args 函数返回一个传递给程序的命令行参数的 迭代器（iterator）
-        // 在迭代器上调用 collect 方法将其转换为一个集合，collect 可以被用来创建很多类型的集合，所以这里显式注明 args 的类型来指定我们需要一个字符串 vector
-        let args: Vec<String> = env::args().collect();
-        println!("args = {:?}", args);
+    fn regex_output(
+        config: &Config,
+        contents: &str,
+        with_path: bool,
+    ) -> Result<Output, regex::Error> {
+        let pattern = compile(config)?;
+        let matched = if config.invert {
+            invert_regex(&pattern, contents)
+        } else {
+            search_regex(&pattern, contents)
+        };
+        Ok(regex_matches_output(
+            config,
+            &Text::whole(contents),
+            &matched,
+            with_path,
+        ))
+    }
 
-        // unwrap_or_else 可以进行一些自定义的非 panic! 的错误处理：
-        // 1. 当 Result 是 Ok 时，这个方法的行为类似于 unwrap：它返回 Ok 内部封装的值
-        // 2. 当其值是 Err 时，该方法会调用一个 闭包（closure），也就是一个我们定义的作为参数传递给 unwrap_or_else 的匿名函数
-        let config = Config::new(&args).unwrap_or_else(|err| {
-            eprintln!("Problem parsing arguments: {}", err);
-            // 非零中断
-            process::exit(1);
-        });
+    // 捕获组跟在匹配的行后面输出
+    fn regex_matches_output(
+        config: &Config,
+        text: &Text,
+        matched: &[RegexMatch],
+        with_path: bool,
+    ) -> Output {
+        let found: Vec<&Match> = matched.iter().map(|m| &m.found).collect();
+        let groups = |k: usize| -> Vec<String> {
+            matched[k]
+                .groups
+                .iter()
+                .enumerate()
+                .map(|(i, group)| format!("  ${} = {}", i + 1, group.unwrap_or("")))
+                .collect()
+        };
+        output(config, text, &found, &groups, with_path)
+    }
 
-        // 因为 run 在成功时返回 ()，而我们只关心检测错误，所以并不需要 unwrap_or_else 来返回未封装的值，因为它只会是 ()
-        if let Err(e) = run(config) {
-            eprintln!("Application error: {}", e);
-            process::exit(1);
+    // 和 str::lines 一样切行：按 \n 切开，去掉 \n 前面的 \r。返回每一行在 bytes 里的范围
+    #[cfg(unix)]
+    fn line_ranges(bytes: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut start = 0;
+        bytes.split_inclusive(|&b| b == b'\n').map(move |chunk| {
+            let line = chunk
+                .strip_suffix(b"\n")
+                .map_or(chunk, |line| line.strip_suffix(b"\r").unwrap_or(line));
+            let range = start..start + line.len();
+            start += chunk.len();
+            range
+        })
+    }
+
+    // 在映射上搜索：regex::bytes 逐行在字节上匹配，只把匹配的行和要输出的上下文解码（复制）成字符串，
+    // 高亮、捕获组、输出格式都在解码出来的字符串上做，和读进来的字符串走同一套代码。
+    // 不是 --regex 时也用转义过的正则来搜，不区分大小写交给正则引擎，和 replace 一样。
+    // 文件在搜索过程中被改掉的话，结果可能混着新旧内容，解码出来的行也可能不再匹配（这样的行直接丢掉），但不会有未定义行为
+    #[cfg(unix)]
+    fn search_mapped(
+        config: &Config,
+        bytes: &[u8],
+        with_path: bool,
+    ) -> Result<Output, regex::Error> {
+        let pattern = compile(config)?;
+        let bytes_pattern = compile_bytes(config)?;
+        // 只有逐行输出时才需要上下文
+        let (before, after) = match config.mode {
+            Mode::Lines => (config.before, config.after),
+            _ => (0, 0),
+        };
+        let decode = |range: Range<usize>| {
+            (
+                range.start,
+                String::from_utf8_lossy(&bytes[range]).into_owned(),
+            )
+        };
+        let mut lines = BTreeMap::new();
+        let mut hits = Vec::new();
+        // 最近几行还没解码的行，遇到匹配时作为前面的上下文
+        let mut recent = VecDeque::new();
+        let mut pending = 0;
+        let mut total = 0;
+        for (i, range) in line_ranges(bytes).enumerate() {
+            total = i + 1;
+            if bytes_pattern.is_match(&bytes[range.clone()]) != config.invert {
+                for (j, range) in recent.drain(..) {
+                    lines.insert(j, decode(range));
+                }
+                lines.insert(i, decode(range));
+                hits.push(i);
+                pending = after;
+            } else if pending > 0 {
+                pending -= 1;
+                lines.insert(i, decode(range));
+            } else if before > 0 {
+                recent.push_back((i, range));
+                if recent.len() > before {
+                    recent.pop_front();
+                }
+            }
         }
 
-        // 迭代器版本
-        let config = Config::new_instance(env::args()).unwrap_or_else(|err| {
-            eprintln!("Problem parsing arguments: {}", err);
-            process::exit(1);
-        });
-        if let Err(e) = run_iter(config) {
-            eprintln!("Application error: {}", e);
-            process::exit(1);
+        let matched: Vec<RegexMatch> = hits
+            .iter()
+            .filter_map(|&i| {
+                let line = lines[&i].1.as_str();
+                if config.invert {
+                    return Some(RegexMatch {
+                        found: Match {
+                            line_no: i + 1,
+                            line,
+                            range: 0..0,
+                        },
+                        groups: Vec::new(),
+                    });
+                }
+                regex_match(&pattern, i + 1, line)
+            })
+            .collect();
+        let text = Text::Excerpt {
+            lines: &lines,
+            total,
+            binary: is_binary(bytes),
+        };
+        Ok(regex_matches_output(config, &text, &matched, with_path))
+    }
+
+    // 一个文件的输出：匹配了几行，按输出方式要打印的记录，以及每条记录后面跟的分隔符
+    #[derive(Debug, PartialEq)]
+    struct Output {
+        matches: usize,
+        lines: Vec<String>,
+        terminator: &'static str,
+    }
+
+    // 一个文件搜完以后交给输出格式的所有东西
+    struct Searched<'a> {
+        config: &'a Config,
+        text: &'a Text<'a>,
+        found: &'a [&'a Match<'a>],
+        annotate: &'a dyn Fn(usize) -> Vec<String>,
+        // 搜索多个文件时，-c 要带上文件名
+        with_path: bool,
+    }
+
+    // 输出格式：把一个文件的搜索结果变成要打印的记录。格式只生成文本，不直接打印，
+    // 测试里直接比较 format 返回的记录，不用去截获标准输出
+    trait OutputFormat {
+        fn format(&self, searched: &Searched) -> Vec<String>;
+
+        // 打印时跟在每条记录后面
+        fn terminator(&self) -> &'static str {
+            "\n"
         }
     }
 
-    #[test]
-    fn one_result() {
-        let query = "duct";
-        let contents = "\
-            Rust:
-            safe, fast, productive.
-            Pick three.";
+    // 默认的 grep 格式：匹配的行和上下文
+    struct Plain;
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+    impl OutputFormat for Plain {
+        fn format(&self, searched: &Searched) -> Vec<String> {
+            let config = searched.config;
+            // 二进制文件逐行输出只会把乱码打到终端上，只提示有匹配
+            if !config.binary && searched.text.is_binary() {
+                if searched.found.is_empty() {
+                    return Vec::new();
+                }
+                return vec![format!("Binary file {} matches", label(&config.filename))];
+            }
+            render(config, searched.text, searched.found, searched.annotate)
+        }
     }
 
-    #[test]
-    fn case_insensitive() {
-        let query = "rUsT";
-        let contents = "\
-            Rust:
-            safe, fast, productive.
-            Pick three.
-            Trust me.";
+    // -c 在多个文件时和 grep 一样写成 文件名:行数，只搜一个文件时只有行数
+    struct Count;
 
-        assert_eq!(
-            vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
-        );
+    impl OutputFormat for Count {
+        fn format(&self, searched: &Searched) -> Vec<String> {
+            let count = searched.found.len();
+            if searched.with_path {
+                vec![format!("{}:{}", label(&searched.config.filename), count)]
+            } else {
+                vec![count.to_string()]
+            }
+        }
+    }
+
+    struct FilesWithMatches;
+
+    impl OutputFormat for FilesWithMatches {
+        fn format(&self, searched: &Searched) -> Vec<String> {
+            if searched.found.is_empty() {
+                Vec::new()
+            } else {
+                vec![label(&searched.config.filename).to_string()]
+            }
+        }
+    }
+
+    struct NullSeparated;
+
+    impl OutputFormat for NullSeparated {
+        fn format(&self, searched: &Searched) -> Vec<String> {
+            FilesWithMatches.format(searched)
+        }
+
+        fn terminator(&self) -> &'static str {
+            "\0"
+        }
+    }
+
+    struct Quiet;
+
+    impl OutputFormat for Quiet {
+        fn format(&self, _: &Searched) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+    struct Json;
+
+    impl OutputFormat for Json {
+        fn format(&self, searched: &Searched) -> Vec<String> {
+            let path = label(&searched.config.filename);
+            searched
+                .found
+                .iter()
+                .map(|found| json_match(path, searched.text, found))
+                .collect()
+        }
+    }
+
+    // 命令行选的输出方式对应的格式
+    fn formatter(mode: Mode) -> Box<dyn OutputFormat> {
+        match mode {
+            Mode::Lines => Box::new(Plain),
+            Mode::Count => Box::new(Count),
+            Mode::FilesWithMatches => Box::new(FilesWithMatches),
+            Mode::NullSeparated => Box::new(NullSeparated),
+            Mode::Quiet => Box::new(Quiet),
+            Mode::Json => Box::new(Json),
+        }
+    }
+
+    // 按 config.mode 选的格式生成一个文件的输出
+    fn output(
+        config: &Config,
+        text: &Text,
+        found: &[&Match],
+        annotate: &dyn Fn(usize) -> Vec<String>,
+        with_path: bool,
+    ) -> Output {
+        let format = formatter(config.mode);
+        let searched = Searched {
+            config,
+            text,
+            found,
+            annotate,
+            with_path,
+        };
+        Output {
+            matches: found.len(),
+            lines: format.format(&searched),
+            terminator: format.terminator(),
+        }
+    }
+
+    // --json 输出的一处匹配。offset 是这一行在文件里的字节偏移，start、end 是匹配在这一行里的字节范围，
+    // 都按字节而不是字符计算，和 Match 里的范围一致；反向匹配的行没有匹配的部分，start 和 end 都是 0
+    #[derive(Serialize)]
+    struct JsonMatch<'a> {
+        path: &'a str,
+        line_number: usize,
+        line: &'a str,
+        offset: usize,
+        start: usize,
+        end: usize,
+    }
+
+    fn json_match(path: &str, text: &Text, found: &Match) -> String {
+        serde_json::to_string(&JsonMatch {
+            path,
+            line_number: found.line_no,
+            line: found.line,
+            offset: text.offset(found),
+            start: found.range.start,
+            end: found.range.end,
+        })
+        .unwrap()
+    }
+
+    // 写出输出的记录，返回是否有匹配
+    fn write_output(output: &Output, out: &mut impl Write) -> io::Result<bool> {
+        for line in &output.lines {
+            write!(out, "{}{}", line, output.terminator)?;
+        }
+        Ok(output.matches > 0)
+    }
+
+    // 用 print! 而不是直接写 io::stdout()：测试框架只截获 print! 的输出，直接写标准输出的内容会混进测试报告
+    fn print_output(output: Output) -> bool {
+        for line in &output.lines {
+            print!("{}{}", line, output.terminator);
+        }
+        output.matches > 0
+    }
+
+    // 和 grep 一样的退出码：有匹配是 0，没有匹配是 1，出错是 2
+    fn exit_code<E>(result: &Result<bool, E>) -> i32 {
+        match result {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(_) => 2,
+        }
+    }
+
+    // 一个文件的搜索结果：要输出的内容，或者读文件、编译正则时的错误。错误要能跨线程传回来，所以是 Send + Sync
+    type FileError = Box<dyn Error + Send + Sync>;
+    type FileResult = Result<Output, FileError>;
+
+    // 搜索 config.filename 这一个文件。读文件、查找、格式化都在这里完成，并行搜索时整个作为一个任务
+    fn search_file(config: &Config) -> FileResult {
+        let contents = match open_haystack(&config.filename, io::stdin())? {
+            #[cfg(unix)]
+            Haystack::Mapped(mapped) => return Ok(search_mapped(config, mapped.bytes(), true)?),
+            Haystack::Owned(text) => text,
+        };
+        if config.regex {
+            return Ok(regex_output(config, &contents, true)?);
+        }
+        let results = if config.invert || config.word {
+            search_with(config, &contents)
+        } else if config.case_sensitive {
+            search(&config.query, &contents)
+        } else {
+            search_case_insensitive(&config.query, &contents)
+        };
+        let found: Vec<&Match> = results.iter().collect();
+        Ok(output(
+            config,
+            &Text::whole(&contents),
+            &found,
+            &|_| Vec::new(),
+            true,
+        ))
+    }
+
+    // 顺序搜索多个文件，只在测试和基准里作为并行版本的对照
+    fn search_files_sequential(config: &Config, paths: &[String]) -> Vec<FileResult> {
+        paths
+            .iter()
+            .map(|path| {
+                search_file(&Config {
+                    filename: path.clone(),
+                    ..config.clone()
+                })
+            })
+            .collect()
+    }
+
+    // 每个文件一个任务提交到线程池，任务拥有自己的一份 Config（文件名换成这个文件）
+    // 按 paths 的顺序 join，不管哪个任务先完成，结果的顺序都和顺序搜索一样；某个文件出错不影响其他文件
+    fn search_files(pool: &ThreadPool, config: &Config, paths: &[String]) -> Vec<FileResult> {
+        let handles: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let config = Config {
+                    filename: path.clone(),
+                    ..config.clone()
+                };
+                pool.submit(move || search_file(&config))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|payload| Err(panic_message(&*payload).into()))
+            })
+            .collect()
+    }
+
+    // 和 grep 一样，不含 / 的 glob 只和文件名比较；含 / 的和相对于搜索起点的路径比较，例如 target/*
+    fn matches_any(patterns: &[Pattern], relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        patterns.iter().any(|pattern| {
+            if pattern.as_str().contains('/') {
+                pattern.matches(relative)
+            } else {
+                pattern.matches(name)
+            }
+        })
+    }
+
+    // 递归列出 dir 下要搜索的文件，按名字排序，每次的顺序都一样
+    // 匹配 exclude 或者被 .gitignore 忽略的目录整个跳过，不再往里走；符号链接不跟随，避免链接成环时死循环
+    // ignores 是从搜索起点到 dir 一路上遇到的 .gitignore，进入目录时压栈，离开时弹出
+    fn walk(
+        root: &Path,
+        dir: &Path,
+        config: &Config,
+        ignores: &mut IgnoreStack,
+        files: &mut Vec<String>,
+    ) -> io::Result<()> {
+        if !config.no_ignore {
+            ignores.enter(dir)?;
+        }
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap().to_string_lossy();
+            if matches_any(&config.exclude, &relative) {
+                continue;
+            }
+            let kind = entry.file_type()?;
+            if !config.no_ignore
+                && ((kind.is_dir() && entry.file_name() == ".git")
+                    || ignores.is_ignored(&path, kind.is_dir()))
+            {
+                continue;
+            }
+            if kind.is_dir() {
+                walk(root, &path, config, ignores, files)?;
+            } else if kind.is_file()
+                && (config.include.is_empty() || matches_any(&config.include, &relative))
+            {
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+        if !config.no_ignore {
+            ignores.leave();
+        }
+        Ok(())
+    }
+
+    // 把参数里的目录展开成下面的文件；直接给出的文件不经过 include/exclude 过滤
+    fn expand_paths(config: &Config, paths: &[String]) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        for path in paths {
+            if Path::new(path).is_dir() {
+                walk(
+                    Path::new(path),
+                    Path::new(path),
+                    config,
+                    &mut IgnoreStack::new(),
+                    &mut files,
+                )?;
+            } else {
+                files.push(path.clone());
+            }
+        }
+        Ok(files)
+    }
+
+    // 搜索一个目录：逐个文件输出，某个文件读不了时报告错误并继续，返回是否有匹配
+    fn run_dir(config: &Config) -> Result<bool, Box<dyn Error>> {
+        let mut matched = false;
+        for (path, result) in search_dir(config)? {
+            match result {
+                Ok(output) => matched |= print_output(output),
+                Err(e) => eprintln!("{}: {}", path, e),
+            }
+        }
+        Ok(matched)
+    }
+
+    // 展开目录下要搜索的文件，交给线程池并行搜索，线程数和 CPU 核数一样。
+    // search_files 按文件的顺序返回结果，输出的顺序和逐个搜索一样，不会因为哪个文件先搜完而打乱
+    fn search_dir(config: &Config) -> io::Result<Vec<(String, FileResult)>> {
+        let files = expand_paths(config, std::slice::from_ref(&config.filename))?;
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let pool = ThreadPool::new(threads);
+        let results = search_files(&pool, config, &files);
+        Ok(files.into_iter().zip(results).collect())
+    }
+
+    // 替换之后的内容，以及改动过的行：(行号, 原来的行, 替换后的行)
+    // 替换是在字节上做的，写回的内容除了被替换的部分和原文件一字节不差：
+    // 不是 UTF-8 的文件（例如 Latin-1）如果先解码成字符串，每个非法字节都会变成 U+FFFD 写回去
+    struct Substitution<'a> {
+        contents: Vec<u8>,
+        changes: Vec<(usize, &'a [u8], Vec<u8>)>,
+    }
+
+    // 逐行替换，和搜索一样不跨行匹配；换行符原样保留。字面量模式下替换文本里的 $ 没有特殊含义
+    fn substitute<'a>(config: &Config, contents: &'a [u8]) -> Result<Substitution<'a>, FileError> {
+        if config.invert {
+            return Err("--replace cannot be combined with --invert-match".into());
+        }
+        let pattern = compile_bytes(config)?;
+        let replacement = config.replace.as_deref().unwrap_or("").as_bytes();
+        let mut result = Substitution {
+            contents: Vec::with_capacity(contents.len()),
+            changes: Vec::new(),
+        };
+        for (i, chunk) in contents.split_inclusive(|&b| b == b'\n').enumerate() {
+            let line = chunk
+                .strip_suffix(b"\n")
+                .map_or(chunk, |line| line.strip_suffix(b"\r").unwrap_or(line));
+            let replaced = if config.regex {
+                pattern.replace_all(line, replacement)
+            } else {
+                pattern.replace_all(line, NoExpand(replacement))
+            };
+            result.contents.extend_from_slice(&replaced);
+            result.contents.extend_from_slice(&chunk[line.len()..]);
+            if replaced != line {
+                result.changes.push((i + 1, line, replaced.into_owned()));
+            }
+        }
+        Ok(result)
+    }
+
+    // 替换一个文件。--dry-run 时返回类似 diff 的预览：@@ path:行号，然后 -原来的行、+替换后的行；
+    // 否则写回文件，返回一行汇总。没有改动的文件不重写。二进制文件除非带上 --binary 否则跳过
+    fn replace_file(config: &Config, path: &str) -> Result<Output, FileError> {
+        let contents = fs::read(path)?;
+        if !config.binary && is_binary(&contents) {
+            return Err("binary file, skipped".into());
+        }
+        let substitution = substitute(config, &contents)?;
+        let changed = substitution.changes.len();
+        let lines = if config.dry_run {
+            substitution
+                .changes
+                .iter()
+                .flat_map(|(line_no, old, new)| {
+                    // 预览只是给人看的，不是 UTF-8 的字节显示成 U+FFFD
+                    [
+                        format!("@@ {}:{}", path, line_no),
+                        format!("-{}", String::from_utf8_lossy(old)),
+                        format!("+{}", String::from_utf8_lossy(new)),
+                    ]
+                })
+                .collect()
+        } else if changed > 0 {
+            // 中途出错或者崩溃时原文件要么是旧的要么是新的，不会只写了一半
+            atomic_write::write_atomic(Path::new(path), &substitution.contents)?;
+            vec![format!("{}: {} lines changed", path, changed)]
+        } else {
+            Vec::new()
+        };
+        Ok(Output {
+            matches: changed,
+            lines,
+            terminator: "\n",
+        })
+    }
+
+    // 替换模式：标准输入替换后写到标准输出，和 sed 一样；文件和目录逐个写回
+    fn run_replace(config: &Config) -> Result<bool, Box<dyn Error>> {
+        if config.filename == STDIN {
+            let mut contents = Vec::new();
+            io::stdin().read_to_end(&mut contents)?;
+            let substitution = substitute(config, &contents).map_err(|e| e.to_string())?;
+            // 文本照常用 print!；不是 UTF-8 的内容原样写出去，不能经过 String
+            match String::from_utf8(substitution.contents) {
+                Ok(text) => print!("{}", text),
+                Err(e) => io::stdout().write_all(e.as_bytes())?,
+            }
+            return Ok(!substitution.changes.is_empty());
+        }
+        let mut changed = false;
+        for path in expand_paths(config, std::slice::from_ref(&config.filename))? {
+            match replace_file(config, &path) {
+                Ok(output) => changed |= print_output(output),
+                Err(e) => eprintln!("{}: {}", path, e),
+            }
+        }
+        Ok(changed)
+    }
+
+    // trait 对象 Box<dyn Error> 意味着函数会返回实现了 Error trait 的类型，不过无需指定具体将会返回的值的类型
+    // 这提供了在不同的错误场景可能有不同类型的错误返回值的灵活性。这也就是 dyn，它是 “动态的”（“dynamic”）的缩写
+    // Ok 里的 bool 表示有没有匹配，调用者据此决定退出码
+    fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+        if config.replace.is_some() {
+            return run_replace(&config);
+        }
+        if Path::new(&config.filename).is_dir() {
+            return run_dir(&config);
+        }
+        // 不同于遇到错误就 panic!，? 会从函数中返回错误值并让调用者来处理它
+        let contents = match open_haystack(&config.filename, io::stdin())? {
+            #[cfg(unix)]
+            Haystack::Mapped(mapped) => {
+                return Ok(print_output(search_mapped(&config, mapped.bytes(), false)?))
+            }
+            Haystack::Owned(text) => text,
+        };
+
+        if config.regex {
+            return run_regex(&config, &contents);
+        }
+
+        let results = if config.invert || config.word {
+            search_with(&config, &contents)
+        } else if config.case_sensitive {
+            search(&config.query, &contents)
+        } else {
+            search_case_insensitive(&config.query, &contents)
+        };
+
+        let found: Vec<&Match> = results.iter().collect();
+        Ok(print_output(output(
+            &config,
+            &Text::whole(&contents),
+            &found,
+            &|_| Vec::new(),
+            false,
+        )))
+    }
+
+    // 使用迭代器适配器的方式编写代码，函数式编程风格
+    fn run_iter(config: Config) -> Result<bool, Box<dyn Error>> {
+        if config.replace.is_some() {
+            return run_replace(&config);
+        }
+        if Path::new(&config.filename).is_dir() {
+            return run_dir(&config);
+        }
+        // 不同于遇到错误就 panic!，? 会从函数中返回错误值并让调用者来处理它
+        let contents = match open_haystack(&config.filename, io::stdin())? {
+            #[cfg(unix)]
+            Haystack::Mapped(mapped) => {
+                return Ok(print_output(search_mapped(&config, mapped.bytes(), false)?))
+            }
+            Haystack::Owned(text) => text,
+        };
+
+        if config.regex {
+            return run_regex(&config, &contents);
+        }
+
+        let results = if config.invert || config.word {
+            search_with(&config, &contents)
+        } else if config.case_sensitive {
+            search_iter(&config.query, &contents)
+        } else {
+            search_case_insensitive_iter(&config.query, &contents)
+        };
+
+        let found: Vec<&Match> = results.iter().collect();
+        Ok(print_output(output(
+            &config,
+            &Text::whole(&contents),
+            &found,
+            &|_| Vec::new(),
+            false,
+        )))
+    }
+
+    #[test]
+    fn io_test() {
+        // args 函数返回一个传递给程序的命令行参数的 迭代器（iterator）
+        // 在迭代器上调用 collect 方法将其转换为一个集合，collect 可以被用来创建很多类型的集合，所以这里显式注明 args 的类型来指定我们需要一个字符串 vector
+        let args: Vec<String> = env::args().collect();
+        println!("args = {:?}", args);
+
+        // clap 版本：参数不对时 parse_from 自己打印错误和用法并以退出码 2 退出，--help 打印帮助后以 0 退出
+        let config = Config::from_cli(Cli::parse_from(&args));
+
+        // run 成功时返回是否有匹配，没有匹配和出错用不同的退出码区分
+        let result = run(config);
+        if let Err(e) = &result {
+            eprintln!("Application error: {}", e);
+        }
+        if exit_code(&result) != 0 {
+            process::exit(exit_code(&result));
+        }
+
+        // 手写解析的迭代器版本，留着和 clap 版本对照
+        // unwrap_or_else 可以进行一些自定义的非 panic! 的错误处理：
+        // 1. 当 Result 是 Ok 时，这个方法的行为类似于 unwrap：它返回 Ok 内部封装的值
+        // 2. 当其值是 Err 时，该方法会调用一个 闭包（closure），也就是一个我们定义的作为参数传递给 unwrap_or_else 的匿名函数
+        let config = Config::new_instance(env::args()).unwrap_or_else(|err| {
+            eprintln!("Problem parsing arguments: {}", err);
+            process::exit(1);
+        });
+        let result = run_iter(config);
+        if let Err(e) = &result {
+            eprintln!("Application error: {}", e);
+        }
+        process::exit(exit_code(&result));
+    }
+
+    #[test]
+    fn one_result() {
+        let query = "duct";
+        let contents = "\
+            Rust:
+            safe, fast, productive.
+            Pick three.";
+
+        let lines: Vec<&str> = search(query, contents).iter().map(|m| m.line).collect();
+        assert_eq!(vec!["safe, fast, productive."], lines);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let query = "rUsT";
+        let contents = "\
+            Rust:
+            safe, fast, productive.
+            Pick three.
+            Trust me.";
+
+        let lines: Vec<&str> = search_case_insensitive(query, contents)
+            .iter()
+            .map(|m| m.line)
+            .collect();
+        assert_eq!(vec!["Rust:", "Trust me."], lines);
+    }
+
+    #[test]
+    fn regex_flag() {
+        let args =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|arg| arg.to_string()).collect() };
+        let config = Config::new(&args(&["minigrep", "--regex", "fn (\\w+)", "poem.txt"])).unwrap();
+        assert!(config.regex);
+        assert_eq!(config.query, "fn (\\w+)");
+        assert_eq!(config.filename, "poem.txt");
+
+        // 默认仍然是子串查找；--regex 不算位置参数
+        let config = Config::new(&args(&["minigrep", "fn", "poem.txt"])).unwrap();
+        assert!(!config.regex);
+        assert!(Config::new(&args(&["minigrep", "--regex"])).is_err());
+    }
+
+    #[test]
+    fn regex_search_with_captures() {
+        let contents = "\
+fn main() {}
+let x = 1;
+pub fn search(query: &str) {}
+fn_name_only";
+        let pattern = Regex::new(r"fn (\w+)\((\w+)?").unwrap();
+        let found = search_regex(&pattern, contents);
+        let lines: Vec<(usize, &str)> = found
+            .iter()
+            .map(|m| (m.found.line_no, m.found.line))
+            .collect();
+        assert_eq!(
+            lines,
+            [(1, "fn main() {}"), (3, "pub fn search(query: &str) {}")]
+        );
+        // 高亮的是整个匹配，不只是捕获组
+        assert_eq!(
+            &found[1].found.line[found[1].found.range.clone()],
+            "fn search(query"
+        );
+        // main 没有参数，第二个捕获组没有参与匹配
+        assert_eq!(found[0].groups, [Some("main"), None]);
+        assert_eq!(found[1].groups, [Some("search"), Some("query")]);
+
+        // 非法的正则表达式在 run 里作为错误返回
+        let path = env::temp_dir().join(format!("minigrep-regex-{}.txt", process::id()));
+        fs::write(&path, contents).unwrap();
+        let config = |query: &str| Config {
+            query: query.to_string(),
+            filename: path.to_string_lossy().into_owned(),
+            case_sensitive: false,
+            regex: true,
+            color: false,
+            before: 0,
+            after: 0,
+            mode: Mode::Lines,
+            invert: false,
+            word: false,
+            binary: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            replace: None,
+            dry_run: false,
+            no_ignore: false,
+        };
+        assert!(run(config("FN (\\w+)")).is_ok());
+        let err = run(config("fn (")).unwrap_err();
+        assert!(err.to_string().contains("unclosed group"), "{}", err);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn matches_carry_line_numbers_and_ranges() {
+        let contents = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.";
+        let expected = vec![Match {
+            line_no: 2,
+            line: "safe, fast, productive.",
+            range: 15..19,
+        }];
+        assert_eq!(search("duct", contents), expected);
+        assert_eq!(search_iter("duct", contents), expected);
+
+        // 不区分大小写时范围落在原来的行上
+        let found = search_case_insensitive("rUsT", contents);
+        assert_eq!(found, search_case_insensitive_iter("rUsT", contents));
+        let spans: Vec<(usize, &str)> = found
+            .iter()
+            .map(|m| (m.line_no, &m.line[m.range.clone()]))
+            .collect();
+        assert_eq!(spans, [(1, "Rust"), (4, "rust")]);
+        // 'İ' 转小写后是两个字符、字节数也变了，范围仍然对应原来的字符
+        let found = search_case_insensitive("i̇stanbul", "in İstanbul");
+        assert_eq!(&found[0].line[found[0].range.clone()], "İstanbul");
+    }
+
+    #[test]
+    fn output_formatting() {
+        let found = Match {
+            line_no: 4,
+            line: "Trust me.",
+            range: 1..5,
+        };
+        assert_eq!(
+            format_match("poem.txt", &found, false),
+            "poem.txt:4:Trust me."
+        );
+        assert_eq!(
+            format_match("poem.txt", &found, true),
+            "poem.txt:4:T\x1b[1;31mrust\x1b[0m me."
+        );
+
+        // --no-color 和 --regex 一样可以出现在任意位置；测试的输出不是终端，本来也不会高亮
+        let args =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|arg| arg.to_string()).collect() };
+        let config = Config::new(&args(&["minigrep", "rust", "--no-color", "poem.txt"])).unwrap();
+        assert!(!config.color && !config.regex);
+        assert_eq!(
+            (config.query.as_str(), config.filename.as_str()),
+            ("rust", "poem.txt")
+        );
+    }
+
+    #[test]
+    fn context_options() {
+        let parse = |list: &[&str]| parse_options(list.iter().map(|arg| arg.to_string()));
+        let (options, positional) =
+            parse(&["minigrep", "-A", "2", "rust", "-B1", "poem.txt"]).unwrap();
+        assert_eq!((options.before, options.after), (1, 2));
+        assert_eq!(positional, ["minigrep", "rust", "poem.txt"]);
+        let (options, _) = parse(&["minigrep", "-C", "3", "--regex", "rust", "poem.txt"]).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                regex: true,
+                no_color: false,
+                before: 3,
+                after: 3,
+                mode: Mode::Lines,
+                invert: false,
+                word: false,
+                binary: false,
+                include: Vec::new(),
+                exclude: Vec::new(),
+                replace: None,
+                dry_run: false,
+                no_ignore: false,
+            }
+        );
+        assert_eq!(
+            parse(&["minigrep", "rust", "-A"]).unwrap_err(),
+            "missing context line count"
+        );
+        assert_eq!(
+            parse(&["minigrep", "-Cx", "rust"]).unwrap_err(),
+            "invalid context line count"
+        );
+    }
+
+    #[test]
+    fn context_lines_around_matches() {
+        let contents =
+            "one\ntwo\nmatch three\nfour\nfive\nsix\nseven\nmatch eight\nmatch nine\nten";
+        let config = |before, after| Config {
+            query: String::from("match"),
+            filename: String::from("f"),
+            case_sensitive: true,
+            regex: false,
+            color: false,
+            before,
+            after,
+            mode: Mode::Lines,
+            invert: false,
+            word: false,
+            binary: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            replace: None,
+            dry_run: false,
+            no_ignore: false,
+        };
+        let results = search("match", contents);
+        let found: Vec<&Match> = results.iter().collect();
+        let none = |_| Vec::new();
+        assert_eq!(
+            render(&config(1, 1), &Text::whole(contents), &found, &none),
+            [
+                "f-2-two",
+                "f:3:match three",
+                "f-4-four",
+                "--",
+                "f-7-seven",
+                "f:8:match eight",
+                "f:9:match nine",
+                "f-10-ten"
+            ]
+        );
+        // 上下文相连时不输出分隔符，重叠的行只输出一次
+        assert_eq!(
+            render(&config(0, 4), &Text::whole(contents), &found[..2], &none),
+            [
+                "f:3:match three",
+                "f-4-four",
+                "f-5-five",
+                "f-6-six",
+                "f-7-seven",
+                "f:8:match eight",
+                "f-9-match nine",
+                "f-10-ten"
+            ]
+        );
+        // 没有上下文时和原来一样，只有匹配的行
+        assert_eq!(
+            render(&config(0, 0), &Text::whole(contents), &found, &|k| vec![
+                format!("  #{}", k)
+            ]),
+            [
+                "f:3:match three",
+                "  #0",
+                "f:8:match eight",
+                "  #1",
+                "f:9:match nine",
+                "  #2"
+            ]
+        );
+    }
+
+    // 生成 files 个文件，每个 lines 行，大约每 50 行有一行包含 needle；用线性同余生成器保证每次相同
+    fn corpus(name: &str, files: usize, lines: usize) -> Vec<String> {
+        let dir = env::temp_dir().join(format!("minigrep-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut seed = 11u64;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        (0..files)
+            .map(|i| {
+                let text: String = (0..lines)
+                    .map(|j| {
+                        let word = if next() % 50 == 0 { "Needle" } else { "hay" };
+                        format!("file {} line {} {} {}\n", i, j, word, next())
+                    })
+                    .collect();
+                let path = dir.join(format!("{:03}.txt", i));
+                fs::write(&path, text).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    fn corpus_config(query: &str, regex: bool) -> Config {
+        Config {
+            query: query.to_string(),
+            filename: String::new(),
+            case_sensitive: false,
+            regex,
+            color: false,
+            before: 1,
+            after: 0,
+            mode: Mode::Lines,
+            invert: false,
+            word: false,
+            binary: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            replace: None,
+            dry_run: false,
+            no_ignore: false,
+        }
+    }
+
+    // Box<dyn Error> 不能比较，转成字符串
+    fn flatten(results: Vec<FileResult>) -> Vec<Result<Vec<String>, String>> {
+        results
+            .into_iter()
+            .map(|result| result.map(|output| output.lines).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parallel_search_matches_sequential() {
+        let mut paths = corpus("parallel", 12, 300);
+        // 不存在的文件在自己的位置上报错，不影响其他文件
+        paths.insert(5, String::from("/nonexistent/minigrep.txt"));
+        let pool = ThreadPool::new(4);
+        for config in [
+            corpus_config("needle", false),
+            corpus_config(r"line (\d+) needle", true),
+        ] {
+            let parallel = flatten(search_files(&pool, &config, &paths));
+            assert_eq!(parallel, flatten(search_files_sequential(&config, &paths)));
+            assert_eq!(parallel.len(), 13);
+            assert!(parallel[5].as_ref().unwrap_err().contains("No such file"));
+            // 每个文件的结果以它自己的路径开头
+            let first = parallel[0].as_ref().unwrap();
+            assert!(first
+                .iter()
+                .any(|line| line.starts_with(&format!("{}:", paths[0]))));
+            assert!(first.iter().all(|line| line == "--"
+                || line.starts_with(&paths[0])
+                || line.starts_with("  $1")));
+        }
+        let bad = flatten(search_files(&pool, &corpus_config("(", true), &paths[..1]));
+        assert!(bad[0].as_ref().unwrap_err().contains("unclosed group"));
+        fs::remove_dir_all(std::path::Path::new(&paths[0]).parent().unwrap()).unwrap();
+
+        // 命令行搜索目录时走的也是线程池，结果按文件名的顺序排好，和逐个搜索一样
+        let paths = corpus("parallel-dir", 12, 300);
+        let dir = Path::new(&paths[0])
+            .parent()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        let config = Config {
+            filename: dir.clone(),
+            ..corpus_config("needle", false)
+        };
+        let (files, results): (Vec<String>, Vec<FileResult>) =
+            search_dir(&config).unwrap().into_iter().unzip();
+        assert_eq!(files, paths);
+        let results = flatten(results);
+        assert_eq!(results, flatten(search_files_sequential(&config, &paths)));
+        for (path, lines) in paths.iter().zip(&results) {
+            assert!(lines.as_ref().unwrap().iter().all(|line| line == "--"
+                || line.starts_with(&format!("{}:", path))
+                || line.starts_with(&format!("{}-", path))));
+        }
+        assert!(run(config).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_parallel_search() {
+        const FILES: usize = 200;
+        const LINES: usize = 20_000;
+        let paths = corpus("bench", FILES, LINES);
+        let config = corpus_config("needle", false);
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let pool = ThreadPool::new(threads);
+
+        let start = Instant::now();
+        let sequential = flatten(search_files_sequential(&config, &paths));
+        let sequential_time = start.elapsed();
+        let start = Instant::now();
+        let parallel = flatten(search_files(&pool, &config, &paths));
+        let parallel_time = start.elapsed();
+        assert_eq!(parallel, sequential);
+
+        println!(
+            "{} files x {} lines, {} threads: sequential {:?}, parallel {:?}, speedup {:.2}x",
+            FILES,
+            LINES,
+            threads,
+            sequential_time,
+            parallel_time,
+            sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+        );
+        fs::remove_dir_all(std::path::Path::new(&paths[0]).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reads_stdin_without_a_filename() {
+        let args =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|arg| arg.to_string()).collect() };
+        // 没给文件名和给了 - 一样
+        assert_eq!(
+            Config::new(&args(&["minigrep", "body"])).unwrap().filename,
+            "-"
+        );
+        assert_eq!(
+            Config::new(&args(&["minigrep", "-C", "1", "body", "-"]))
+                .unwrap()
+                .filename,
+            "-"
+        );
+
+        let piped = "I'm nobody! Who are you?\nAre you nobody, too?\nThen there's a pair of us!";
+        let contents = read_haystack("-", piped.as_bytes()).unwrap();
+        assert_eq!(contents, piped);
+        let config = Config {
+            query: String::from("nobody"),
+            filename: String::from("-"),
+            case_sensitive: true,
+            regex: false,
+            color: false,
+            before: 0,
+            after: 1,
+            mode: Mode::Lines,
+            invert: false,
+            word: false,
+            binary: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            replace: None,
+            dry_run: false,
+            no_ignore: false,
+        };
+        let results = search(&config.query, &contents);
+        let found: Vec<&Match> = results.iter().collect();
+        assert_eq!(
+            render(&config, &Text::whole(&contents), &found, &|_| Vec::new()),
+            [
+                "(standard input):1:I'm nobody! Who are you?",
+                "(standard input):2:Are you nobody, too?",
+                "(standard input)-3-Then there's a pair of us!"
+            ]
+        );
+        // 有文件名时不读 stdin
+        assert!(read_haystack("/nonexistent/minigrep.txt", piped.as_bytes()).is_err());
+    }
+
+    // 映射和读进来的结果一样；不是合法 UTF-8 的大文件退回到替换过非法字节的副本
+    #[cfg(unix)]
+    #[test]
+    fn mmap_matches_read() {
+        let paths = corpus("mmap", 1, 40_000);
+        let path = &paths[0];
+        assert!(fs::metadata(path).unwrap().len() >= MMAP_THRESHOLD);
+        let mapped = open_haystack(path, io::empty()).unwrap();
+        assert!(matches!(mapped, Haystack::Mapped(_)));
+        let read = read_haystack(path, io::empty()).unwrap();
+        assert_eq!(mapped.bytes(), read.as_bytes());
+        let config = Config {
+            filename: path.clone(),
+            ..corpus_config("needle", false)
+        };
+        assert_eq!(
+            search_file(&config).unwrap().lines,
+            output(
+                &config,
+                &Text::whole(&read),
+                &search_case_insensitive("needle", &read)
+                    .iter()
+                    .collect::<Vec<_>>(),
+                &|_| Vec::new(),
+                true
+            )
+            .lines
+        );
+        // 按字节搜索映射和按字符串搜索读进来的内容，各种选项下输出都一样
+        for (query, regex, mutate) in [
+            (
+                "needle",
+                false,
+                (|c: &mut Config| c.after = 2) as fn(&mut Config),
+            ),
+            ("needle", false, |c| c.case_sensitive = true),
+            ("line 1\\d\\b", true, |c| c.before = 0),
+            ("(Needle) (\\d+)$", true, |c| c.color = true),
+            ("hay", false, |c| c.invert = true),
+            ("line 3", false, |c| c.word = true),
+            ("needle", false, |c| c.mode = Mode::Json),
+            ("needle", false, |c| c.mode = Mode::Count),
+            ("zebra", false, |c| c.mode = Mode::FilesWithMatches),
+        ] {
+            let mut config = Config {
+                filename: path.clone(),
+                ..corpus_config(query, regex)
+            };
+            mutate(&mut config);
+            let expected = if regex || config.invert || config.word || config.case_sensitive {
+                regex_output(&config, &read, true).unwrap()
+            } else {
+                let results = search_case_insensitive(query, &read);
+                let found: Vec<&Match> = results.iter().collect();
+                output(&config, &Text::whole(&read), &found, &|_| Vec::new(), true)
+            };
+            assert_eq!(search_file(&config).unwrap(), expected, "{}", query);
+        }
+        // 映射建立以后文件被别的进程改写，映射里会看到新的内容。搜索的是字节，非法字节只在解码要输出的行时换成 U+FFFD
+        let mapped = map_haystack(path).unwrap();
+        assert!(matches!(mapped, Haystack::Mapped(_)));
+        let mut file = fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.write_all(b"\xffneedle\n").unwrap();
+        drop(file);
+        let config = Config {
+            filename: path.clone(),
+            ..corpus_config("needle", false)
+        };
+        let rewritten = search_mapped(&config, mapped.bytes(), false).unwrap();
+        assert_eq!(rewritten.lines[0], format!("{}:1:\u{fffd}needle", path));
+        drop(mapped);
+
+        let mut bytes = fs::read(path).unwrap();
+        bytes.extend_from_slice(b"Needle \xff\xfe\n");
+        fs::write(path, &bytes).unwrap();
+        let lossy = open_haystack(path, io::empty()).unwrap();
+        let Haystack::Owned(lossy) = lossy else {
+            panic!("invalid UTF-8 should be copied");
+        };
+        assert!(lossy.ends_with("Needle \u{fffd}\u{fffd}\n"));
+        assert_eq!(lossy, read_haystack(path, io::empty()).unwrap());
+
+        fs::write(path, "").unwrap();
+        assert!(map_haystack(path).unwrap().bytes().is_empty());
+        // 小文件还是直接读
+        fs::write(path, "a needle\n").unwrap();
+        assert!(matches!(
+            open_haystack(path, io::empty()).unwrap(),
+            Haystack::Owned(_)
+        ));
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore]
+    fn bench_mmap_search() {
+        const LINES: usize = 3_000_000;
+        let paths = corpus("mmap-bench", 1, LINES);
+        let path = &paths[0];
+        let size = fs::metadata(path).unwrap().len();
+        let count = |contents: &str| search_case_insensitive("needle", contents).len();
+        // 各跑几遍取最快的一次，第一遍读盘之后文件都在页缓存里，比较的是复制和映射本身的开销
+        let best = |f: &dyn Fn() -> usize| {
+            (0..5)
+                .map(|_| {
+                    let start = Instant::now();
+                    let found = f();
+                    (start.elapsed(), found)
+                })
+                .min()
+                .unwrap()
+        };
+        let (read_time, read_found) = best(&|| count(&read_haystack(path, io::empty()).unwrap()));
+        let config = corpus_config("needle", false);
+        let (mmap_time, mmap_found) = best(&|| {
+            let mapped = map_haystack(path).unwrap();
+            search_mapped(&config, mapped.bytes(), false)
+                .unwrap()
+                .matches
+        });
+        assert_eq!(read_found, mmap_found);
+        println!(
+            "{} MiB, {} matches: read {:?}, mmap {:?}, speedup {:.2}x",
+            size >> 20,
+            read_found,
+            read_time,
+            mmap_time,
+            read_time.as_secs_f64() / mmap_time.as_secs_f64()
+        );
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn count_files_and_quiet_modes() {
+        let parse = |list: &[&str]| parse_options(list.iter().map(|arg| arg.to_string()));
+        for (flag, mode) in [
+            ("-c", Mode::Count),
+            ("-l", Mode::FilesWithMatches),
+            ("-q", Mode::Quiet),
+        ] {
+            let (options, positional) = parse(&["minigrep", flag, "rust", "poem.txt"]).unwrap();
+            assert_eq!(options.mode, mode);
+            assert_eq!(positional, ["minigrep", "rust", "poem.txt"]);
+        }
+        // -C 仍然是上下文行数，不会被当成 -c
+        assert_eq!(
+            parse(&["minigrep", "-C1", "x"]).unwrap().0.mode,
+            Mode::Lines
+        );
+
+        let mut paths = corpus("modes", 3, 200);
+        let empty = std::path::Path::new(&paths[0]).with_file_name("empty.txt");
+        fs::write(&empty, "nothing here\n").unwrap();
+        paths.push(empty.to_string_lossy().into_owned());
+        let run_mode = |mode| {
+            let config = Config {
+                mode,
+                ..corpus_config("needle", false)
+            };
+            search_files_sequential(&config, &paths)
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<Output>>()
+        };
+        let lines = run_mode(Mode::Lines);
+        let counts = run_mode(Mode::Count);
+        for (i, (lines, count)) in lines.iter().zip(&counts).enumerate() {
+            assert_eq!(lines.matches, count.matches);
+            assert_eq!(count.lines, [format!("{}:{}", paths[i], count.matches)]);
+        }
+        assert!(counts[0].matches > 0);
+        assert_eq!(counts[3].matches, 0);
+        let files: Vec<Vec<String>> = run_mode(Mode::FilesWithMatches)
+            .into_iter()
+            .map(|output| output.lines)
+            .collect();
+        assert_eq!(files, [&paths[..1], &paths[1..2], &paths[2..3], &[]]);
+        assert!(run_mode(Mode::Quiet)
+            .iter()
+            .all(|output| output.lines.is_empty()));
+
+        // 单个文件的 -c 只有行数；退出码区分有匹配、没匹配和出错
+        let config = Config {
+            mode: Mode::Count,
+            filename: paths[0].clone(),
+            ..corpus_config("needle", false)
+        };
+        let contents = fs::read_to_string(&paths[0]).unwrap();
+        let results = search_case_insensitive("needle", &contents);
+        let found: Vec<&Match> = results.iter().collect();
+        let single = output(
+            &config,
+            &Text::whole(&contents),
+            &found,
+            &|_| Vec::new(),
+            false,
+        );
+        assert_eq!(single.lines, [results.len().to_string()]);
+        assert_eq!(exit_code(&run(config.clone())), 0);
+        let missing = Config {
+            query: String::from("zebra"),
+            ..config.clone()
+        };
+        assert_eq!(exit_code(&run(missing)), 1);
+        let broken = Config {
+            filename: String::from("/nonexistent/minigrep.txt"),
+            ..config
+        };
+        assert_eq!(exit_code(&run(broken)), 2);
+        fs::remove_dir_all(empty.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn word_and_invert_match() {
+        // 行首、行尾、标点和空白都是边界，字母、数字和下划线不是
+        assert_eq!(find_word("rust, fast", "rust", true), Some(0..4));
+        assert_eq!(find_word("I like (rust)", "rust", true), Some(8..12));
+        assert_eq!(find_word("say rust", "rust", true), Some(4..8));
+        assert_eq!(find_word("trusty", "rust", true), None);
+        assert_eq!(find_word("rust_lang rust2", "rust", true), None);
+        // 第一处不在边界上时继续往后找
+        assert_eq!(find_word("rusty rust", "rust", true), Some(6..10));
+        assert_eq!(find_word("Rusty RUST!", "rust", false), Some(6..10));
+        // 非 ASCII 字母也算单词字符
+        assert_eq!(find_word("érust rust", "rust", true), Some(7..11));
+
+        let contents = "\
+Rust:
+trusty and fast.
+Pick three.
+Trust me.";
+        let config = Config {
+            query: String::from("rust"),
+            filename: String::from("poem.txt"),
+            case_sensitive: false,
+            regex: false,
+            color: true,
+            before: 0,
+            after: 0,
+            mode: Mode::Lines,
+            invert: false,
+            word: true,
+            binary: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            replace: None,
+            dry_run: false,
+            no_ignore: false,
+        };
+        let lines = |config: &Config| -> Vec<usize> {
+            search_with(config, contents)
+                .iter()
+                .map(|m| m.line_no)
+                .collect()
+        };
+        assert_eq!(lines(&config), [1]);
+        let invert = Config {
+            invert: true,
+            ..config.clone()
+        };
+        assert_eq!(lines(&invert), [2, 3, 4]);
+        assert_eq!(
+            lines(&Config {
+                word: false,
+                ..invert.clone()
+            }),
+            [3]
+        );
+        // 反向匹配的行没有匹配的部分，开着颜色也不加转义序列
+        let found = &search_with(&invert, contents)[1];
+        assert_eq!(
+            format_match("poem.txt", found, true),
+            "poem.txt:3:Pick three."
+        );
+
+        // 正则也支持这两个选项；--word 包住整个分支
+        let regex = Config {
+            query: String::from("rust|fast"),
+            regex: true,
+            color: false,
+            ..config
+        };
+        assert_eq!(
+            regex_output(&regex, contents, false).unwrap().lines,
+            ["poem.txt:1:Rust:", "poem.txt:2:trusty and fast."]
+        );
+        let inverted = Config {
+            invert: true,
+            ..regex
+        };
+        assert_eq!(
+            regex_output(&inverted, contents, false).unwrap().lines,
+            ["poem.txt:3:Pick three.", "poem.txt:4:Trust me."]
+        );
+    }
+
+    #[test]
+    fn binary_files_print_a_notice() {
+        let dir = env::temp_dir().join(format!("minigrep-binary-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("app.bin");
+        // 文件头里有 NUL，后面还有不是 UTF-8 的字节
+        let mut bytes = b"\x7fELF\x02\x01\x00\x00needle in a binary\n".to_vec();
+        bytes.extend_from_slice(&[0xff, 0xfe, b'\n']);
+        bytes.extend_from_slice(b"another needle\n");
+        fs::write(&binary, &bytes).unwrap();
+        let text = dir.join("notes.txt");
+        fs::write(&text, "a needle\nhay\n").unwrap();
+        let paths = [
+            binary.to_string_lossy().into_owned(),
+            text.to_string_lossy().into_owned(),
+        ];
+
+        let config = corpus_config("needle", false);
+        assert_eq!(
+            flatten(search_files_sequential(&config, &paths)),
+            [
+                Ok(vec![format!("Binary file {} matches", paths[0])]),
+                Ok(vec![format!("{}:1:a needle", paths[1])]),
+            ]
+        );
+        // 没有匹配时什么都不输出；-c 照常计数
+        let missing = corpus_config("zebra", false);
+        assert_eq!(
+            flatten(search_files_sequential(&missing, &paths[..1])),
+            [Ok(vec![])]
+        );
+        let count = Config {
+            mode: Mode::Count,
+            ..config.clone()
+        };
+        assert_eq!(
+            flatten(search_files_sequential(&count, &paths[..1])),
+            [Ok(vec![format!("{}:2", paths[0])])]
+        );
+
+        // --binary 强制逐行输出，不合法的字节变成 U+FFFD
+        let (options, _) = parse_options(
+            ["minigrep", "--binary", "needle"]
+                .map(String::from)
+                .into_iter(),
+        )
+        .unwrap();
+        assert!(options.binary);
+        let forced = Config {
+            binary: true,
+            before: 0,
+            ..config
+        };
+        let lines = flatten(search_files_sequential(&forced, &paths[..1]))
+            .remove(0)
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("needle in a binary"));
+        assert_eq!(lines[1], format!("{}:3:another needle", paths[0]));
+        assert!(is_binary(b"\0") && !is_binary("\u{fffd}text".as_bytes()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let parse = |list: &[&str]| parse_options(list.iter().map(|arg| arg.to_string()));
+        let (options, positional) = parse(&[
+            "minigrep",
+            "--include",
+            "*.rs",
+            "--exclude=target/*",
+            "--include=*.toml",
+            "fn",
+            "project",
+        ])
+        .unwrap();
+        let globs = |patterns: &[Pattern]| -> Vec<String> {
+            patterns.iter().map(|p| p.as_str().to_string()).collect()
+        };
+        assert_eq!(globs(&options.include), ["*.rs", "*.toml"]);
+        assert_eq!(globs(&options.exclude), ["target/*"]);
+        assert_eq!(positional, ["minigrep", "fn", "project"]);
+        assert_eq!(
+            parse(&["minigrep", "fn", "--exclude"]).unwrap_err(),
+            "missing glob pattern"
+        );
+        assert_eq!(
+            parse(&["minigrep", "fn", "--include=[a-z"]).unwrap_err(),
+            "invalid glob pattern"
+        );
+
+        let root = env::temp_dir().join(format!("minigrep-walk-{}", process::id()));
+        for file in [
+            "src/main.rs",
+            "src/notes.txt",
+            "src/nested/lib.rs",
+            "target/debug/build.rs",
+            "Cargo.toml",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "fn main() {}\n").unwrap();
+        }
+        let root_arg = [root.to_string_lossy().into_owned()];
+        let relative = |config: &Config| -> Vec<String> {
+            expand_paths(config, &root_arg)
+                .unwrap()
+                .iter()
+                .map(|path| path[root_arg[0].len() + 1..].to_string())
+                .collect()
+        };
+        let config = Config {
+            include: vec![Pattern::new("*.rs").unwrap()],
+            exclude: vec![Pattern::new("target/*").unwrap()],
+            ..corpus_config("fn", false)
+        };
+        assert_eq!(relative(&config), ["src/main.rs", "src/nested/lib.rs"]);
+        // 不给 include 时搜所有文件；exclude 一个目录名会跳过整个目录
+        let everything = Config {
+            exclude: vec![Pattern::new("nested").unwrap()],
+            ..corpus_config("fn", false)
+        };
+        assert_eq!(
+            relative(&everything),
+            [
+                "Cargo.toml",
+                "src/main.rs",
+                "src/notes.txt",
+                "target/debug/build.rs"
+            ]
+        );
+        // 直接给出的文件不过滤
+        assert_eq!(
+            expand_paths(&config, &[String::from("notes.txt")]).unwrap(),
+            ["notes.txt"]
+        );
+        assert!(run(Config {
+            filename: root_arg[0].clone(),
+            mode: Mode::Quiet,
+            ..config
+        })
+        .unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn json_output() {
+        let (options, _) =
+            parse_options(["minigrep", "--json", "fn"].map(String::from).into_iter()).unwrap();
+        assert_eq!(options.mode, Mode::Json);
+
+        let contents = "first line\nwhere is the café?\n\"quoted\" café\n";
+        let config = Config {
+            query: String::from("CAFÉ"),
+            filename: String::from("menu.txt"),
+            mode: Mode::Json,
+            ..corpus_config("", false)
+        };
+        let results = search_case_insensitive(&config.query, contents);
+        let found: Vec<&Match> = results.iter().collect();
+        let lines = output(
+            &config,
+            &Text::whole(contents),
+            &found,
+            &|_| Vec::new(),
+            true,
+        )
+        .lines;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"{"path":"menu.txt","line_number":2,"line":"where is the café?","offset":11,"start":13,"end":18}"#
+        );
+        // 引号等字符按 JSON 转义，读回来和原来的行一样；偏移能直接切出原文
+        for line in &lines {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            let offset = value["offset"].as_u64().unwrap() as usize;
+            let text = value["line"].as_str().unwrap();
+            assert_eq!(&contents[offset..offset + text.len()], text);
+            let (start, end) = (
+                value["start"].as_u64().unwrap() as usize,
+                value["end"].as_u64().unwrap() as usize,
+            );
+            assert_eq!(&text[start..end], "café");
+        }
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&lines[1]).unwrap()["line"],
+            "\"quoted\" café"
+        );
+        // 没有匹配就没有输出
+        assert!(
+            output(&config, &Text::whole(contents), &[], &|_| Vec::new(), true)
+                .lines
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn output_formatters() {
+        let parse = |flag: &str| {
+            let args = ["minigrep", flag, "fn"].map(String::from);
+            parse_options(args.into_iter()).unwrap().0.mode
+        };
+        assert_eq!(parse("-Z"), Mode::NullSeparated);
+        assert_eq!(parse("--null"), Mode::NullSeparated);
+        let cli = Cli::try_parse_from(["minigrep", "--null", "fn"]).unwrap();
+        assert_eq!(cli.into_options().2.mode, Mode::NullSeparated);
+        assert!(Cli::try_parse_from(["minigrep", "-Z", "-c", "fn"]).is_err());
+
+        // 同一份搜索结果交给每种格式，直接比较生成的记录和写出来的字节
+        let contents = "fn main() {}\nlet x = 1;\npub fn run() {}\n";
+        let config = Config {
+            query: String::from("fn"),
+            filename: String::from("src/main.rs"),
+            case_sensitive: true,
+            before: 0,
+            ..corpus_config("", false)
+        };
+        let results = search(&config.query, contents);
+        let found: Vec<&Match> = results.iter().collect();
+        let searched = Searched {
+            config: &config,
+            text: &Text::whole(contents),
+            found: &found,
+            annotate: &|_| Vec::new(),
+            with_path: true,
+        };
+        assert_eq!(
+            Plain.format(&searched),
+            [
+                "src/main.rs:1:fn main() {}",
+                "src/main.rs:3:pub fn run() {}"
+            ]
+        );
+        assert_eq!(Count.format(&searched), ["src/main.rs:2"]);
+        assert_eq!(FilesWithMatches.format(&searched), ["src/main.rs"]);
+        assert_eq!(NullSeparated.format(&searched), ["src/main.rs"]);
+        assert!(Quiet.format(&searched).is_empty());
+        assert_eq!(Json.format(&searched).len(), 2);
+        let single = Searched {
+            with_path: false,
+            ..searched
+        };
+        assert_eq!(Count.format(&single), ["2"]);
+
+        let written = |mode: Mode| {
+            let config = Config {
+                mode,
+                ..config.clone()
+            };
+            let mut bytes = Vec::new();
+            let output = output(
+                &config,
+                &Text::whole(contents),
+                &found,
+                &|_| Vec::new(),
+                true,
+            );
+            assert!(write_output(&output, &mut bytes).unwrap());
+            String::from_utf8(bytes).unwrap()
+        };
+        assert_eq!(written(Mode::NullSeparated), "src/main.rs\0");
+        assert_eq!(written(Mode::FilesWithMatches), "src/main.rs\n");
+        assert_eq!(written(Mode::Quiet), "");
+        // 没有匹配时 -Z 什么都不输出，写出也返回 false
+        let none = output(&config, &Text::whole(contents), &[], &|_| Vec::new(), true);
+        assert!(none.lines.is_empty());
+        assert!(!write_output(&none, &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn replace_in_place() {
+        let parse = |list: &[&str]| parse_options(list.iter().map(|arg| arg.to_string()));
+        let (options, positional) =
+            parse(&["minigrep", "--replace", "x", "--dry-run", "a", "f"]).unwrap();
+        assert_eq!(
+            (options.replace.as_deref(), options.dry_run),
+            (Some("x"), true)
+        );
+        assert_eq!(positional, ["minigrep", "a", "f"]);
+        assert_eq!(
+            parse(&["minigrep", "--replace=$1"])
+                .unwrap()
+                .0
+                .replace
+                .as_deref(),
+            Some("$1")
+        );
+
+        let contents = "fn main() {\r\n    helper(Fn);\n}\nfn helper(x: u8) {}";
+        let with = |query: &str, replace: &str, regex: bool| Config {
+            query: query.to_string(),
+            replace: Some(replace.to_string()),
+            regex,
+            ..corpus_config("", false)
+        };
+        // 字面量模式忽略大小写、$ 原样保留；换行符不变，最后一行没有换行也不会多出一个
+        let literal = substitute(&with("fn", "$fn", false), contents.as_bytes()).unwrap();
+        assert_eq!(
+            literal.contents,
+            b"$fn main() {\r\n    helper($fn);\n}\n$fn helper(x: u8) {}"
+        );
+        assert_eq!(
+            literal.changes.iter().map(|c| c.0).collect::<Vec<_>>(),
+            [1, 2, 4]
+        );
+        assert_eq!(literal.changes[0].1, b"fn main() {");
+        // 正则模式下可以引用捕获组
+        let regex = substitute(
+            &with(r"fn (\w+)\((\w*)", "func ${1}_v2($2", true),
+            contents.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            regex.contents.split(|&b| b == b'\n').nth(3),
+            Some(&b"func helper_v2(x: u8) {}"[..])
+        );
+        assert_eq!(
+            substitute(
+                &Config {
+                    word: true,
+                    ..with("help", "assist", false)
+                },
+                contents.as_bytes()
+            )
+            .unwrap()
+            .changes
+            .len(),
+            0
+        );
+        assert!(substitute(
+            &Config {
+                invert: true,
+                ..with("fn", "", false)
+            },
+            contents.as_bytes()
+        )
+        .is_err());
+
+        let dir = env::temp_dir().join(format!("minigrep-replace-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("main.rs");
+        fs::write(&file, contents).unwrap();
+        let path = file.to_string_lossy().into_owned();
+        let config = Config {
+            filename: path.clone(),
+            dry_run: true,
+            ..with("helper", "assist", false)
+        };
+        // 预览不改文件
+        let preview = replace_file(&config, &path).unwrap();
+        assert_eq!(preview.matches, 2);
+        assert_eq!(
+            preview.lines[..3],
+            [
+                format!("@@ {}:2", path),
+                String::from("-    helper(Fn);"),
+                String::from("+    assist(Fn);")
+            ]
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), contents);
+        // 写回之后目录里只剩原文件，临时文件已经 rename 掉了
+        assert!(run(Config {
+            dry_run: false,
+            ..config.clone()
+        })
+        .unwrap());
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            contents.replace("helper", "assist")
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(!run(config).unwrap());
+
+        // 不是 UTF-8 的文件（Latin-1 的 café、naïve）：只有被替换的部分变了，其余字节原样保留
+        let latin1 = dir.join("latin1.txt");
+        let original = b"caf\xe9 helper\nna\xefve \xff\n";
+        fs::write(&latin1, original).unwrap();
+        let path = latin1.to_string_lossy().into_owned();
+        let config = Config {
+            filename: path.clone(),
+            ..with("helper", "assist", false)
+        };
+        let preview = replace_file(
+            &Config {
+                dry_run: true,
+                ..config.clone()
+            },
+            &path,
+        )
+        .unwrap();
+        assert_eq!(preview.lines[1], "-caf\u{fffd} helper");
+        assert_eq!(fs::read(&latin1).unwrap(), original);
+        assert!(run(config).unwrap());
+        assert_eq!(
+            fs::read(&latin1).unwrap(),
+            b"caf\xe9 assist\nna\xefve \xff\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn respects_gitignore() {
+        let root = env::temp_dir().join(format!("minigrep-gitignore-{}", process::id()));
+        let write = |file: &str, text: &str| {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write(
+            ".gitignore",
+            "# 构建产物\ntarget/\n*.log\n!keep.log\n/build\ndocs/*.html\n",
+        );
+        write("src/.gitignore", "generated.rs\n");
+        for file in [
+            ".git/HEAD",
+            "target/debug/app.rs",
+            "app.log",
+            "keep.log",
+            "build/out.rs",
+            "sub/build/kept.rs",
+            "docs/index.html",
+            "docs/nested/page.html",
+            "src/main.rs",
+            "src/generated.rs",
+            "lib/generated.rs",
+        ] {
+            write(file, "needle\n");
+        }
+        let root_arg = [root.to_string_lossy().into_owned()];
+        let relative = |config: &Config| -> Vec<String> {
+            expand_paths(config, &root_arg)
+                .unwrap()
+                .iter()
+                .map(|path| path[root_arg[0].len() + 1..].to_string())
+                .collect()
+        };
+        let config = corpus_config("needle", false);
+        // /build 只在根目录生效；docs/*.html 不跨目录；src/.gitignore 只管 src 下面
+        assert_eq!(
+            relative(&config),
+            [
+                ".gitignore",
+                "docs/nested/page.html",
+                "keep.log",
+                "lib/generated.rs",
+                "src/.gitignore",
+                "src/main.rs",
+                "sub/build/kept.rs"
+            ]
+        );
+        let (options, _) = parse_options(
+            ["minigrep", "--no-ignore", "needle"]
+                .map(String::from)
+                .into_iter(),
+        )
+        .unwrap();
+        assert!(options.no_ignore);
+        let everything = relative(&Config {
+            no_ignore: true,
+            ..config
+        });
+        assert_eq!(everything.len(), 13);
+        assert!(everything.contains(&String::from(".git/HEAD")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn clap_cli_matches_manual_parsing() {
+        use clap::{error::ErrorKind, CommandFactory};
+        let args =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|arg| arg.to_string()).collect() };
+        // 同样的命令行，两个版本得到相同的选项和位置参数
+        for list in [
+            &["minigrep", "body", "poem.txt"][..],
+            &["minigrep", "--regex", "-C", "2", "-A", "5", "b.dy", "src"],
+            &[
+                "minigrep",
+                "-c",
+                "--word",
+                "--invert-match",
+                "--no-color",
+                "x",
+                "-",
+            ],
+            &["minigrep", "--json", "--binary", "--no-ignore", "x", "dir"],
+            &[
+                "minigrep",
+                "--include",
+                "*.rs",
+                "--include=*.toml",
+                "--exclude=target",
+                "x",
+                ".",
+            ],
+            &[
+                "minigrep",
+                "--replace",
+                "$1",
+                "--dry-run",
+                "--regex",
+                "(a)",
+                "f",
+            ],
+            &["minigrep", "-l", "-B1", "x", "f"],
+        ] {
+            let (manual, positional) = parse_options(args(list).into_iter()).unwrap();
+            let (query, filename, options) = Cli::try_parse_from(list).unwrap().into_options();
+            assert_eq!(options, manual, "{:?}", list);
+            assert_eq!([query, filename], positional[1..], "{:?}", list);
+        }
+        let config = Config::from_cli(Cli::try_parse_from(["minigrep", "body"]).unwrap());
+        assert_eq!(
+            (config.query.as_str(), config.filename.as_str()),
+            ("body", "-")
+        );
+
+        // 手写版本不检查的约束，clap 版本直接报错
+        let kind = |list: &[&str]| Cli::try_parse_from(list).unwrap_err().kind();
+        assert_eq!(kind(&["minigrep"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(
+            kind(&["minigrep", "-c", "-l", "x"]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            kind(&["minigrep", "--dry-run", "x"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(
+            kind(&["minigrep", "-A", "two", "x"]),
+            ErrorKind::ValueValidation
+        );
+        assert_eq!(
+            kind(&["minigrep", "--colour", "x"]),
+            ErrorKind::UnknownArgument
+        );
+        assert_eq!(kind(&["minigrep", "--help"]), ErrorKind::DisplayHelp);
+        assert!(parse_options(args(&["minigrep", "--colour", "x"]).into_iter()).is_ok());
+
+        let help = Cli::command().render_help().to_string();
+        for flag in [
+            "--regex",
+            "-C <NUM>",
+            "--json",
+            "--include <GLOB>",
+            "--dry-run",
+            "--no-ignore",
+        ] {
+            assert!(help.contains(flag), "{} missing from\n{}", flag, help);
+        }
+        assert!(help.contains("Usage: minigrep [OPTIONS] <QUERY> [FILENAME]"));
+        Cli::command().debug_assert();
     }
 }
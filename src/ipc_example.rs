@@ -0,0 +1,334 @@
+// 共享内存进程间通信：两个进程把同一个文件 mmap 到各自的地址空间，在上面实现一个单生产者单消费者的环形缓冲区
+// 映射的内存对两个进程都可见，写入不经过内核，所以需要自己用原子操作和序列号来同步，并校验数据是否损坏
+//
+// src/bin/ipc_writer.rs 和 src/bin/ipc_reader.rs 用 #[path] 引入这个文件，两个独立的程序通过同一个文件通信：
+// ipc_reader <文件> <消息数> [容量] 创建缓冲区，打印 ready 之后逐行打印读到的消息
+// ipc_writer <文件> <消息数> 打开已经创建好的缓冲区，写入 message 0、message 1……
+// 每个程序只用到一半的接口，主程序一个都没用到，所以关掉 dead_code 警告
+#[cfg(unix)]
+#[allow(dead_code)]
+pub(crate) mod shared_ring {
+
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    pub(crate) const MAGIC: u64 = 0x5249_4e47_4255_4631; // "RINGBUF1"
+    pub(crate) const SLOT_DATA: usize = 48;
+
+    // 文件开头的头部，#[repr(C)] 保证两个进程看到的内存布局一致
+    #[repr(C)]
+    pub(crate) struct Header {
+        magic: u64,
+        capacity: u64,
+        // 写者已经写入的消息总数和读者已经读走的消息总数，两者之差就是缓冲区里的消息数
+        write_seq: AtomicU64,
+        read_seq: AtomicU64,
+    }
+
+    // 每个槽位 64 字节，seq 是握手用的序列号：第 n 条消息（从 0 开始）写完后写者把 seq 设为 n + 1，
+    // 读者看到 seq == n + 1 才去读数据，Release/Acquire 保证读者读到的是写完的数据
+    #[repr(C)]
+    pub(crate) struct Slot {
+        pub(crate) seq: AtomicU64,
+        len: u32,
+        checksum: u32,
+        pub(crate) data: [u8; SLOT_DATA],
+    }
+
+    // FNV-1a 哈希，用来发现数据在共享内存里被意外改写
+    fn checksum(data: &[u8]) -> u32 {
+        data.iter().fold(0x811c_9dc5, |hash, &byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    fn corrupted(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+    }
+
+    pub(crate) struct SharedRing {
+        // 映射建立后文件描述符就可以关闭了，这里保留它只是为了让文件的生命周期和映射一致
+        _file: File,
+        ptr: *mut u8,
+        size: usize,
+    }
+
+    impl SharedRing {
+        // capacity 可能来自一个损坏的文件，算文件长度时溢出返回 None
+        fn size_for(capacity: usize) -> Option<usize> {
+            capacity
+                .checked_mul(mem::size_of::<Slot>())?
+                .checked_add(mem::size_of::<Header>())
+        }
+
+        fn map(file: File, size: usize) -> io::Result<SharedRing> {
+            // SAFETY: 映射长度和文件长度一致；MAP_SHARED 让写入对映射同一个文件的其他进程可见
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    size,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(SharedRing {
+                _file: file,
+                ptr: ptr as *mut u8,
+                size,
+            })
+        }
+
+        // 创建文件并初始化头部，文件新扩展出来的部分全是 0，所以所有槽位的 seq 都从 0 开始
+        pub(crate) fn create(path: &Path, capacity: usize) -> io::Result<SharedRing> {
+            // 槽位下标是 n % capacity，容量为 0 的缓冲区没法用
+            let size = SharedRing::size_for(capacity)
+                .filter(|_| capacity > 0)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid ring buffer capacity {}", capacity),
+                    )
+                })?;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            file.set_len(size as u64)?;
+            let ring = SharedRing::map(file, size)?;
+            // SAFETY: 映射至少有一个 Header 那么大，mmap 返回的地址按页对齐
+            unsafe {
+                let header = ring.ptr as *mut Header;
+                ptr::addr_of_mut!((*header).capacity).write(capacity as u64);
+                ptr::addr_of_mut!((*header).magic).write(MAGIC);
+            }
+            Ok(ring)
+        }
+
+        // 打开另一个进程创建好的缓冲区，先检查魔数和长度，防止把一个不相干的文件当成缓冲区
+        pub(crate) fn open(path: &Path) -> io::Result<SharedRing> {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            let len = file.metadata()?.len() as usize;
+            if len < mem::size_of::<Header>() {
+                return Err(corrupted("file is too small for a ring buffer header"));
+            }
+            let ring = SharedRing::map(file, len)?;
+            let header = ring.header();
+            if header.magic != MAGIC {
+                return Err(corrupted("bad magic number"));
+            }
+            if header.capacity == 0 {
+                return Err(corrupted("ring buffer capacity is 0"));
+            }
+            let size = usize::try_from(header.capacity)
+                .ok()
+                .and_then(SharedRing::size_for);
+            if size != Some(len) {
+                return Err(corrupted("capacity does not match the file size"));
+            }
+            Ok(ring)
+        }
+
+        pub(crate) fn header(&self) -> &Header {
+            // SAFETY: 映射的开头就是 Header，create/open 都保证了长度足够
+            unsafe { &*(self.ptr as *const Header) }
+        }
+
+        pub(crate) fn capacity(&self) -> u64 {
+            self.header().capacity
+        }
+
+        // 第 n 条消息所在的槽位
+        pub(crate) fn slot(&self, n: u64) -> *mut Slot {
+            let index = (n % self.capacity()) as usize;
+            // SAFETY: index 小于 capacity，槽位都在映射范围之内
+            unsafe { (self.ptr.add(mem::size_of::<Header>()) as *mut Slot).add(index) }
+        }
+
+        // 写者：缓冲区满时等待读者腾出位置
+        pub(crate) fn push(&self, message: &[u8]) -> io::Result<()> {
+            if message.len() > SLOT_DATA {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "message does not fit in a slot",
+                ));
+            }
+            let header = self.header();
+            // 只有写者会修改 write_seq，自己读自己的值不需要同步
+            let n = header.write_seq.load(Ordering::Relaxed);
+            while n - header.read_seq.load(Ordering::Acquire) >= self.capacity() {
+                thread::yield_now();
+            }
+
+            let slot = self.slot(n);
+            // SAFETY: 读者在 seq 变成 n + 1 之前不会读这个槽位，而上面的等待保证了读者已经读完上一轮的消息
+            unsafe {
+                ptr::addr_of_mut!((*slot).len).write(message.len() as u32);
+                ptr::addr_of_mut!((*slot).checksum).write(checksum(message));
+                let data = ptr::addr_of_mut!((*slot).data) as *mut u8;
+                ptr::copy_nonoverlapping(message.as_ptr(), data, message.len());
+                (*slot).seq.store(n + 1, Ordering::Release);
+            }
+            header.write_seq.store(n + 1, Ordering::Release);
+            Ok(())
+        }
+
+        // 读者：等待下一条消息，超时返回 TimedOut，发现数据不一致返回 InvalidData
+        pub(crate) fn pop(&self, timeout: Duration) -> io::Result<Vec<u8>> {
+            let header = self.header();
+            let n = header.read_seq.load(Ordering::Relaxed);
+            let slot = self.slot(n);
+            let started = Instant::now();
+
+            loop {
+                // SAFETY: seq 是原子变量，可以和写者并发访问
+                let seq = unsafe { (*slot).seq.load(Ordering::Acquire) };
+                if seq == n + 1 {
+                    break;
+                }
+                // 序列号跑到了读者前面，说明写者覆盖了还没读的消息，或者内存被改写了
+                if seq > n + 1 {
+                    return Err(corrupted("slot sequence number is ahead of the reader"));
+                }
+                if started.elapsed() > timeout {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "no message within timeout",
+                    ));
+                }
+                thread::yield_now();
+            }
+
+            // SAFETY: seq == n + 1 之后写者不会再碰这个槽位，直到我们推进 read_seq
+            let message = unsafe {
+                let len = ptr::addr_of!((*slot).len).read() as usize;
+                if len > SLOT_DATA {
+                    return Err(corrupted("message length exceeds slot size"));
+                }
+                let data = ptr::addr_of!((*slot).data) as *const u8;
+                let message = std::slice::from_raw_parts(data, len).to_vec();
+                if ptr::addr_of!((*slot).checksum).read() != checksum(&message) {
+                    return Err(corrupted("checksum mismatch"));
+                }
+                message
+            };
+            header.read_seq.store(n + 1, Ordering::Release);
+            Ok(message)
+        }
+    }
+
+    impl Drop for SharedRing {
+        fn drop(&mut self) {
+            // SAFETY: ptr 和 size 来自 mmap，只解除映射一次
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.size);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use super::shared_ring::*;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ipc-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let path = temp_file("corrupt");
+        let ring = SharedRing::create(&path, 4).unwrap();
+        let reader = SharedRing::open(&path).unwrap();
+
+        ring.push(b"hello").unwrap();
+        // 在读者读取之前改写共享内存里的一个字节
+        // SAFETY: 槽位 0 在映射范围内，这时没有其他线程在访问它
+        unsafe {
+            (*ring.slot(0)).data[0] = b'j';
+        }
+        let err = reader.pop(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "checksum mismatch");
+
+        // 序列号被改写成读者前面的值
+        unsafe {
+            (*ring.slot(0)).seq.store(99, Ordering::SeqCst);
+        }
+        assert_eq!(
+            reader.pop(Duration::from_secs(1)).unwrap_err().to_string(),
+            "slot sequence number is ahead of the reader"
+        );
+
+        let empty = SharedRing::create(&temp_file("empty"), 4).unwrap();
+        assert_eq!(
+            empty.pop(Duration::from_millis(10)).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(
+            ring.push(&[0; SLOT_DATA + 1]).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(temp_file("empty")).unwrap();
+    }
+
+    #[test]
+    fn open_rejects_foreign_files() {
+        let path = temp_file("foreign");
+        fs::write(&path, [0u8; 4]).unwrap();
+        assert!(SharedRing::open(&path).is_err());
+        fs::write(&path, [0xffu8; 128]).unwrap();
+        assert_eq!(
+            SharedRing::open(&path).err().unwrap().to_string(),
+            "bad magic number"
+        );
+        // 魔数对了，但是容量为 0 或者大到算文件长度时溢出
+        let header = |capacity: u64| {
+            let mut bytes = [0u8; 128];
+            bytes[..8].copy_from_slice(&MAGIC.to_ne_bytes());
+            bytes[8..16].copy_from_slice(&capacity.to_ne_bytes());
+            bytes
+        };
+        fs::write(&path, header(0)).unwrap();
+        assert_eq!(
+            SharedRing::open(&path).err().unwrap().to_string(),
+            "ring buffer capacity is 0"
+        );
+        for capacity in [u64::MAX, u64::MAX / 64 + 1, 3] {
+            fs::write(&path, header(capacity)).unwrap();
+            assert_eq!(
+                SharedRing::open(&path).err().unwrap().to_string(),
+                "capacity does not match the file size"
+            );
+        }
+        assert_eq!(
+            SharedRing::create(&path, 0).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(SharedRing::create(&path, usize::MAX).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+// 用租约文件选主：多个进程竞争同一个租约文件，写进自己的名字和过期时间的进程就是 leader
+// leader 在过期之前不断续约（心跳）；它崩溃或者卡住不再续约时租约过期，其他进程接手
+// 读、判断、写租约这一步用 flock 锁住，同一时刻只有一个进程在改租约；锁只在这一小段时间里持有，
+// leader 是否还活着完全靠过期时间判断，所以被 kill -9 的 leader 也会在一个租期之后被替换
+#[cfg(all(test, unix))]
+pub(crate) mod leader_election {
+
+    use crate::clock_example::clock::{self, Clock};
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 租约文件的内容：持有者、任期、过期时间（Unix 毫秒）。每换一个持有者任期加一，
+    // 可以作为 fencing token 交给下游，拒绝任期更小的旧 leader 发来的写入
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct Lease {
+        pub(crate) holder: String,
+        pub(crate) term: u64,
+        pub(crate) expires: u64,
+    }
+
+    // 各个进程用同一个系统时钟判断过期，所以用墙上时间而不是 Instant
+    pub(crate) fn now_ms(clock: &dyn Clock) -> u64 {
+        clock
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    // 加了排他锁的租约文件。锁跟着打开的文件走，File 关闭时内核自动释放，进程被杀也一样
+    struct Locked(File);
+
+    impl Locked {
+        fn open(path: &Path) -> io::Result<Locked> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            // SAFETY: fd 来自上面打开的文件，在调用期间有效
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Locked(file))
+        }
+
+        fn read(&mut self) -> io::Result<Option<Lease>> {
+            let mut text = String::new();
+            self.0.seek(SeekFrom::Start(0))?;
+            self.0.read_to_string(&mut text)?;
+            parse(&text)
+        }
+
+        // 先在开头覆盖写入再截断：一次很短的 write 不会被 kill 打断一半，
+        // 即使在 write 和 set_len 之间被杀，后面残留的旧内容也在第一行之后，读的时候会忽略
+        fn write(&mut self, lease: &Lease) -> io::Result<()> {
+            let text = format!("{} {} {}\n", lease.holder, lease.term, lease.expires);
+            self.0.seek(SeekFrom::Start(0))?;
+            self.0.write_all(text.as_bytes())?;
+            self.0.set_len(text.len() as u64)?;
+            self.0.sync_data()
+        }
+    }
+
+    // 空文件表示还没有人持有过租约
+    fn parse(text: &str) -> io::Result<Option<Lease>> {
+        let Some(line) = text.lines().next().filter(|line| !line.is_empty()) else {
+            return Ok(None);
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed lease file");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [holder, term, expires] = fields[..] else {
+            return Err(invalid());
+        };
+        Ok(Some(Lease {
+            holder: holder.to_string(),
+            term: term.parse().map_err(|_| invalid())?,
+            expires: expires.parse().map_err(|_| invalid())?,
+        }))
+    }
+
+    pub(crate) fn read_lease(path: &Path) -> io::Result<Option<Lease>> {
+        Locked::open(path)?.read()
+    }
+
+    pub(crate) struct Elector {
+        path: PathBuf,
+        id: String,
+        ttl: Duration,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl Elector {
+        pub(crate) fn new(path: &Path, id: &str, ttl: Duration) -> Elector {
+            Elector::with_clock(path, id, ttl, clock::system())
+        }
+
+        // id 不能包含空白，它要写进租约文件的一行里
+        // 同一个租约文件上的所有 Elector 要用同一个时钟，否则彼此对过期时间的判断对不上
+        pub(crate) fn with_clock(
+            path: &Path,
+            id: &str,
+            ttl: Duration,
+            clock: Arc<dyn Clock>,
+        ) -> Elector {
+            assert!(!id.is_empty() && !id.contains(char::is_whitespace));
+            Elector {
+                path: path.to_path_buf(),
+                id: id.to_string(),
+                ttl,
+                clock,
+            }
+        }
+
+        // 获取或续约租约，成功时返回任期。自己持有且没过期是续约，任期不变；
+        // 没人持有或者已经过期（包括自己的）是新的任期；别人持有且没过期时返回 None
+        pub(crate) fn try_acquire(&self) -> io::Result<Option<u64>> {
+            let mut file = Locked::open(&self.path)?;
+            let now = now_ms(&*self.clock);
+            let term = match file.read()? {
+                Some(lease) if lease.expires > now && lease.holder == self.id => lease.term,
+                Some(lease) if lease.expires > now => return Ok(None),
+                Some(lease) => lease.term + 1,
+                None => 1,
+            };
+            file.write(&Lease {
+                holder: self.id.clone(),
+                term,
+                expires: now + self.ttl.as_millis() as u64,
+            })?;
+            Ok(Some(term))
+        }
+
+        // 主动让出：把过期时间改成 0，其他进程下一次尝试就能拿到，不用等一个租期
+        pub(crate) fn release(&self) -> io::Result<()> {
+            let mut file = Locked::open(&self.path)?;
+            match file.read()? {
+                Some(lease) if lease.holder == self.id => file.write(&Lease {
+                    expires: 0,
+                    ..lease
+                }),
+                _ => Ok(()),
+            }
+        }
+
+        // 竞选循环：每个心跳周期尝试一次，leader 借此续约，其他进程借此观察租约是否过期
+        // 心跳要明显短于租期，否则一次调度延迟就会让 leader 的租约过期
+        // 角色变化时调用 on_change：成为 leader 时是 Some(任期)，失去租约时是 None
+        // stop 返回 true 时退出，如果还是 leader 就主动让出
+        pub(crate) fn campaign(
+            &self,
+            heartbeat: Duration,
+            mut on_change: impl FnMut(Option<u64>),
+            stop: impl Fn() -> bool,
+        ) -> io::Result<()> {
+            assert!(heartbeat < self.ttl);
+            let mut leading = None;
+            while !stop() {
+                let term = self.try_acquire()?;
+                if term != leading {
+                    on_change(term);
+                    leading = term;
+                }
+                self.clock.sleep(heartbeat);
+            }
+            if leading.is_some() {
+                self.release()?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use super::leader_election::*;
+    use crate::clock_example::clock::{SimClock, SystemClock};
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+    use std::process::{self, Child, Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const TTL: Duration = Duration::from_millis(400);
+    const HEARTBEAT: Duration = Duration::from_millis(100);
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("lease-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn lease_expires_and_changes_hands() {
+        let path = temp_file("unit");
+        let _ = fs::remove_file(&path);
+        // 模拟时钟：过期靠拨动时钟，不用真的睡一个租期
+        let sim = SimClock::new();
+        let ttl = Duration::from_millis(150);
+        let a = Elector::with_clock(&path, "a", ttl, sim.clone());
+        let b = Elector::with_clock(&path, "b", ttl, sim.clone());
+        assert_eq!(read_lease(&path).unwrap(), None);
+        assert_eq!(a.try_acquire().unwrap(), Some(1));
+        assert_eq!(b.try_acquire().unwrap(), None);
+        // 续约不改变任期
+        assert_eq!(a.try_acquire().unwrap(), Some(1));
+        sim.advance(Duration::from_millis(149));
+        assert_eq!(b.try_acquire().unwrap(), None);
+        sim.advance(Duration::from_millis(1));
+        assert_eq!(b.try_acquire().unwrap(), Some(2));
+        assert_eq!(a.try_acquire().unwrap(), None);
+        let lease = read_lease(&path).unwrap().unwrap();
+        assert_eq!((lease.holder.as_str(), lease.term), ("b", 2));
+        assert_eq!(lease.expires, now_ms(&*sim) + 150);
+        // 不是持有者时 release 什么也不做；持有者 release 之后马上可以被拿走
+        a.release().unwrap();
+        assert_eq!(a.try_acquire().unwrap(), None);
+        b.release().unwrap();
+        assert_eq!(a.try_acquire().unwrap(), Some(3));
+
+        fs::write(&path, "a 1\n").unwrap();
+        assert_eq!(
+            a.try_acquire().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    // 候选进程：由 one_leader_and_takeover_after_kill 重新启动测试程序并只运行这个测试，直接运行时什么也不做
+    // 每次角色变化往事件文件追加一行：acquired <id> <任期> <毫秒> 或者 lost <id>
+    #[test]
+    #[ignore]
+    fn leader_election_candidate() {
+        let (Ok(path), Ok(id), Ok(events), Ok(stop)) = (
+            env::var("LEASE_PATH"),
+            env::var("LEASE_ID"),
+            env::var("LEASE_EVENTS"),
+            env::var("LEASE_STOP"),
+        ) else {
+            return;
+        };
+        let elector = Elector::new(Path::new(&path), &id, TTL);
+        let deadline = Instant::now() + Duration::from_secs(60);
+        elector
+            .campaign(
+                HEARTBEAT,
+                |term| {
+                    let line = match term {
+                        Some(term) => {
+                            format!("acquired {} {} {}\n", id, term, now_ms(&SystemClock))
+                        }
+                        None => format!("lost {}\n", id),
+                    };
+                    // O_APPEND 下每次 write 都追加到文件末尾，几个进程的短行不会互相覆盖
+                    let mut file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&events)
+                        .unwrap();
+                    file.write_all(line.as_bytes()).unwrap();
+                },
+                || Path::new(&stop).exists() || Instant::now() > deadline,
+            )
+            .unwrap();
+    }
+
+    // 事件文件里所有的 acquired 行：(id, 任期, 毫秒)
+    fn acquisitions(events: &Path) -> Vec<(String, u64, u64)> {
+        let text = fs::read_to_string(events).unwrap_or_default();
+        text.lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields[..] {
+                    ["acquired", id, term, at] => {
+                        Some((id.to_string(), term.parse().unwrap(), at.parse().unwrap()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn wait_for(what: &str, mut ready: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while !ready() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn one_leader_and_takeover_after_kill() {
+        let (path, events, stop) = (temp_file("lease"), temp_file("events"), temp_file("stop"));
+        for file in [&path, &events, &stop] {
+            let _ = fs::remove_file(file);
+        }
+        let ids = ["p0", "p1", "p2", "p3"];
+        let mut children: Vec<Child> = ids
+            .iter()
+            .map(|id| {
+                Command::new(env::current_exe().unwrap())
+                    .args([
+                        "--exact",
+                        "leader_election_example::tests::leader_election_candidate",
+                        "--ignored",
+                        "--quiet",
+                    ])
+                    .env("LEASE_PATH", &path)
+                    .env("LEASE_ID", id)
+                    .env("LEASE_EVENTS", &events)
+                    .env("LEASE_STOP", &stop)
+                    .stdout(Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+
+        wait_for("a leader", || !acquisitions(&events).is_empty());
+        // 几个租期过去，leader 一直在续约，其他进程都没有拿到租约
+        thread::sleep(TTL * 3);
+        let first = acquisitions(&events);
+        assert_eq!(first.len(), 1, "{:?}", first);
+        let (leader, term, _) = first[0].clone();
+        assert_eq!(term, 1);
+
+        // kill -9：leader 没有机会让出租约，其他进程只能等它过期
+        let index = ids.iter().position(|id| *id == leader).unwrap();
+        children[index].kill().unwrap();
+        children[index].wait().unwrap();
+        let old = read_lease(&path).unwrap().unwrap();
+        assert_eq!(old.holder, leader);
+
+        wait_for("a takeover", || acquisitions(&events).len() == 2);
+        let (next, next_term, at) = acquisitions(&events)[1].clone();
+        assert_ne!(next, leader);
+        assert_eq!(next_term, term + 1);
+        assert!(at >= old.expires, "taken over before the lease expired");
+
+        fs::write(&stop, "").unwrap();
+        for (i, child) in children.iter_mut().enumerate() {
+            if i != index {
+                assert!(child.wait().unwrap().success());
+            }
+        }
+        // 新 leader 退出前主动让出了租约
+        assert_eq!(read_lease(&path).unwrap().unwrap().expires, 0);
+        assert_eq!(acquisitions(&events).len(), 2);
+        for file in [&path, &events, &stop] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
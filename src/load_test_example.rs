@@ -0,0 +1,327 @@
+// 压测客户端：按目标速率并发发送请求，用 HDR 风格的直方图统计延迟分位数
+#[cfg(test)]
+mod tests {
+
+    use std::fmt;
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // 每个 2 的幂区间再线性切分成 16 个桶，相对误差不超过 1/16，而桶的总数只随数值范围对数增长
+    // 这就是 HdrHistogram 的思路：既能覆盖从微秒到分钟的范围，又能给出足够精确的分位数
+    const SUB_BUCKET_BITS: u32 = 4;
+    const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+    struct Histogram {
+        counts: Vec<u64>,
+        total: u64,
+        min: u64,
+        max: u64,
+        sum: u64,
+    }
+
+    impl Histogram {
+        fn new() -> Histogram {
+            Histogram {
+                counts: vec![0; Histogram::index(u64::MAX) + 1],
+                total: 0,
+                min: u64::MAX,
+                max: 0,
+                sum: 0,
+            }
+        }
+
+        // 小于 16 的值每个值一个桶；更大的值先按最高位所在的区间分组，再取接下来的 4 位作为组内编号
+        fn index(value: u64) -> usize {
+            if value < SUB_BUCKETS {
+                return value as usize;
+            }
+            let magnitude = 63 - value.leading_zeros();
+            let shift = magnitude - SUB_BUCKET_BITS;
+            let sub = (value >> shift) - SUB_BUCKETS;
+            (SUB_BUCKETS + u64::from(shift) * SUB_BUCKETS + sub) as usize
+        }
+
+        // 桶能容纳的最大值，报告分位数时用它作为上界
+        fn upper_bound(index: usize) -> u64 {
+            let index = index as u64;
+            if index < SUB_BUCKETS {
+                return index;
+            }
+            let shift = (index - SUB_BUCKETS) / SUB_BUCKETS;
+            let sub = (index - SUB_BUCKETS) % SUB_BUCKETS;
+            ((SUB_BUCKETS + sub + 1) << shift) - 1
+        }
+
+        fn record(&mut self, value: u64) {
+            self.counts[Histogram::index(value)] += 1;
+            self.total += 1;
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.sum += value;
+        }
+
+        fn merge(&mut self, other: &Histogram) {
+            for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+                *count += other;
+            }
+            self.total += other.total;
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+            self.sum += other.sum;
+        }
+
+        // 第 p 百分位：从小到大累加，直到覆盖 p% 的样本
+        fn percentile(&self, p: f64) -> u64 {
+            if self.total == 0 {
+                return 0;
+            }
+            let target = ((p / 100.0 * self.total as f64).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (index, count) in self.counts.iter().enumerate() {
+                seen += count;
+                if seen >= target {
+                    return Histogram::upper_bound(index).min(self.max);
+                }
+            }
+            self.max
+        }
+
+        fn mean(&self) -> f64 {
+            if self.total == 0 {
+                return 0.0;
+            }
+            self.sum as f64 / self.total as f64
+        }
+    }
+
+    struct Config {
+        addr: SocketAddr,
+        path: String,
+        connections: usize,
+        // 所有连接合计的目标速率（请求/秒）
+        rate: f64,
+        requests: usize,
+    }
+
+    impl Config {
+        // 参数形如：load_test [--connections N] [--rate R] [--requests M] [--path /x] ADDR
+        fn new(args: &[String]) -> Result<Config, &'static str> {
+            let mut config = Config {
+                addr: "127.0.0.1:7878".parse().unwrap(),
+                path: String::from("/"),
+                connections: 4,
+                rate: 100.0,
+                requests: 100,
+            };
+
+            let mut args = args.iter().skip(1);
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--connections" | "-c" => {
+                        config.connections = parse_next(&mut args, "invalid --connections")?
+                    }
+                    "--rate" | "-r" => config.rate = parse_next(&mut args, "invalid --rate")?,
+                    "--requests" | "-n" => {
+                        config.requests = parse_next(&mut args, "invalid --requests")?
+                    }
+                    "--path" => config.path = parse_next(&mut args, "invalid --path")?,
+                    addr => config.addr = addr.parse().map_err(|_| "invalid address")?,
+                }
+            }
+
+            if config.connections == 0 || config.rate <= 0.0 {
+                return Err("connections and rate must be positive");
+            }
+            Ok(config)
+        }
+    }
+
+    fn parse_next<'a, T: std::str::FromStr>(
+        args: &mut impl Iterator<Item = &'a String>,
+        error: &'static str,
+    ) -> Result<T, &'static str> {
+        args.next().and_then(|arg| arg.parse().ok()).ok_or(error)
+    }
+
+    struct Report {
+        latency: Histogram,
+        errors: usize,
+        elapsed: Duration,
+    }
+
+    impl fmt::Display for Report {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let throughput = self.latency.total as f64 / self.elapsed.as_secs_f64();
+            writeln!(
+                f,
+                "requests: {} ok, {} errors in {:.2?} ({:.1} req/s)",
+                self.latency.total, self.errors, self.elapsed, throughput
+            )?;
+            writeln!(
+                f,
+                "latency (us): min {} mean {:.0} max {}",
+                self.latency.min,
+                self.latency.mean(),
+                self.latency.max
+            )?;
+            for p in [50.0, 90.0, 99.0, 99.9] {
+                writeln!(f, "  p{:<5} {}", p, self.latency.percentile(p))?;
+            }
+            Ok(())
+        }
+    }
+
+    // 一次请求：新建连接、发送请求、读完响应，只把 2xx 当作成功
+    fn request(addr: SocketAddr, path: &str) -> io::Result<()> {
+        let mut stream = TcpStream::connect(addr)?;
+        // 整个请求一次写出，write! 会把格式化的各段分成多次 write，服务器可能只读到一半就回应并关闭连接
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr);
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        if response.starts_with(b"HTTP/1.1 2") {
+            Ok(())
+        } else {
+            Err(io::Error::other("non-2xx response"))
+        }
+    }
+
+    fn run(config: &Config) -> Report {
+        // 每个连接负责一份请求，按固定间隔发出，合起来就是目标速率
+        let interval = Duration::from_secs_f64(config.connections as f64 / config.rate);
+        let per_connection = config.requests / config.connections;
+        let extra = config.requests % config.connections;
+
+        let started = Instant::now();
+        let workers: Vec<_> = (0..config.connections)
+            .map(|i| {
+                let addr = config.addr;
+                let path = config.path.clone();
+                let count = per_connection + usize::from(i < extra);
+                thread::spawn(move || {
+                    let mut latency = Histogram::new();
+                    let mut errors = 0;
+                    for n in 0..count {
+                        // 延迟从“计划发出的时间”算起而不是实际发出的时间：服务器变慢导致请求发晚了，
+                        // 这段排队时间也要算进去，否则会低估延迟（即所谓的 coordinated omission）
+                        let scheduled = started + interval * n as u32;
+                        if let Some(wait) = scheduled.checked_duration_since(Instant::now()) {
+                            thread::sleep(wait);
+                        }
+                        match request(addr, &path) {
+                            Ok(()) => latency.record(scheduled.elapsed().as_micros() as u64),
+                            Err(_) => errors += 1,
+                        }
+                    }
+                    (latency, errors)
+                })
+            })
+            .collect();
+
+        let mut report = Report {
+            latency: Histogram::new(),
+            errors: 0,
+            elapsed: Duration::ZERO,
+        };
+        for worker in workers {
+            let (latency, errors) = worker.join().unwrap();
+            report.latency.merge(&latency);
+            report.errors += errors;
+        }
+        report.elapsed = started.elapsed();
+        report
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        // 桶的上界和下一个桶的起点紧挨着，没有空隙
+        for index in 0..200 {
+            let upper = Histogram::upper_bound(index);
+            assert_eq!(Histogram::index(upper), index);
+            assert_eq!(Histogram::index(upper + 1), index + 1);
+        }
+        assert_eq!(Histogram::index(15), 15);
+        assert_eq!(Histogram::index(1000), Histogram::index(1023));
+    }
+
+    #[test]
+    fn histogram_percentiles() {
+        let mut histogram = Histogram::new();
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.total, 1000);
+        assert_eq!((histogram.min, histogram.max), (1, 1000));
+        assert_eq!(histogram.mean(), 500.5);
+
+        // 分位数是桶的上界，相对误差在 1/16 以内
+        for (p, exact) in [(50.0, 500.0), (90.0, 900.0), (99.0, 990.0)] {
+            let value = histogram.percentile(p) as f64;
+            assert!(
+                value >= exact && value <= exact * (1.0 + 1.0 / 16.0),
+                "p{} = {}",
+                p,
+                value
+            );
+        }
+        assert_eq!(histogram.percentile(100.0), 1000);
+    }
+
+    #[test]
+    fn config_from_args() {
+        let args: Vec<String> =
+            "load_test -c 8 --rate 500 -n 1000 --path /api/health 127.0.0.1:9000"
+                .split_whitespace()
+                .map(String::from)
+                .collect();
+        let config = Config::new(&args).unwrap();
+        assert_eq!(config.connections, 8);
+        assert_eq!(config.rate, 500.0);
+        assert_eq!(config.requests, 1000);
+        assert_eq!(config.path, "/api/health");
+        assert_eq!(config.addr, "127.0.0.1:9000".parse().unwrap());
+
+        let args = vec![String::from("load_test"), String::from("--rate")];
+        assert!(Config::new(&args).is_err());
+    }
+
+    #[test]
+    fn load_test_against_local_server() {
+        // 一个最简单的服务器：每个连接读一次请求、回一个响应，用一个错误路径制造失败
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let response: &[u8] = if buf[..n].starts_with(b"GET /fail") {
+                    b"HTTP/1.1 500 INTERNAL SERVER ERROR\r\nContent-Length: 0\r\n\r\n"
+                } else {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                };
+                let _ = stream.write_all(response);
+            }
+        });
+
+        let mut config = Config {
+            addr,
+            path: String::from("/"),
+            connections: 4,
+            rate: 400.0,
+            requests: 40,
+        };
+        let report = run(&config);
+        assert_eq!(report.latency.total, 40);
+        assert_eq!(report.errors, 0);
+        // 40 个请求按 400 req/s 发送，至少需要 (40 / 4 - 1) * 10ms = 90ms
+        assert!(report.elapsed >= Duration::from_millis(90));
+        println!("{}", report);
+
+        config.path = String::from("/fail");
+        config.requests = 4;
+        assert_eq!(run(&config).errors, 4);
+    }
+}
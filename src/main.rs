@@ -24,6 +24,50 @@ mod variables_example;
 mod webserver_example;
 mod runtime_example;
 mod task_example;
+mod mux_example;
+mod event_loop_example;
+mod thread_per_core_example;
+mod load_test_example;
+mod http_client_example;
+mod prefork_example;
+mod ipc_example;
+mod template_example;
+mod tsdb_example;
+mod index_example;
+mod cooccurrence_example;
+mod thread_pool_example;
+mod sketch_example;
+mod streaming_example;
+mod windows_example;
+mod pipeline_example;
+mod dag_runner_example;
+mod build_rules_example;
+mod cas_example;
+mod merkle_example;
+mod crdt_example;
+mod leader_election_example;
+mod raft_example;
+mod gossip_example;
+mod hash_ring_example;
+mod clock_example;
+mod task_scope_example;
+mod watchdog_example;
+mod dispatcher_example;
+mod batcher_example;
+mod codec_example;
+mod i18n_example;
+mod format_locale_example;
+mod hash_map_example;
+mod byte_size_example;
+mod my_vec_example;
+mod glob_example;
+mod ignore_example;
+mod ring_buffer_example;
+mod heap_example;
+mod path_util_example;
+mod tempfs_example;
+mod trie_example;
+mod atomic_write_example;
 
 // cargo new xxx 新建项目
 // cargo build 编译
@@ -0,0 +1,364 @@
+// Merkle 树：把数据切成固定大小的块，每块的哈希是叶子，两两拼起来再哈希得到上一层，最后只剩一个根
+// 只要知道可信的根，任何一块都可以单独验证：附上从叶子到根路径上每一层的兄弟节点（包含证明，inclusion proof），
+// 证明的大小是 O(log n)。两棵树比较时从根往下只进入哈希不同的子树，很快就能找出哪些块不一样
+// 叶子和内部节点的哈希加上不同的前缀字节（RFC 6962 的做法），防止把内部节点冒充成叶子
+#[cfg(test)]
+pub(crate) mod merkle {
+
+    use sha2::{Digest, Sha256};
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    pub(crate) type Hash = [u8; 32];
+
+    pub(crate) fn leaf_hash(data: &[u8]) -> Hash {
+        Sha256::new()
+            .chain_update([0x00])
+            .chain_update(data)
+            .finalize()
+            .into()
+    }
+
+    fn node_hash(left: &Hash, right: &Hash) -> Hash {
+        Sha256::new()
+            .chain_update([0x01])
+            .chain_update(left)
+            .chain_update(right)
+            .finalize()
+            .into()
+    }
+
+    pub(crate) fn to_hex(hash: &Hash) -> String {
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub(crate) struct MerkleTree {
+        // levels[0] 是叶子，最后一层只有根；某一层的节点数是奇数时，最后一个节点原样升到上一层
+        levels: Vec<Vec<Hash>>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Side {
+        Left,
+        Right,
+    }
+
+    // 第 index 块的包含证明：从叶子往上每一层的兄弟节点以及它在左边还是右边；升上去的那一层没有兄弟
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct Proof {
+        pub(crate) index: usize,
+        pub(crate) siblings: Vec<(Side, Hash)>,
+    }
+
+    impl MerkleTree {
+        pub(crate) fn from_leaves(leaves: Vec<Hash>) -> MerkleTree {
+            let mut levels = vec![leaves];
+            while levels.last().unwrap().len() > 1 {
+                let next = levels
+                    .last()
+                    .unwrap()
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [left, right] => node_hash(left, right),
+                        [single] => *single,
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                levels.push(next);
+            }
+            MerkleTree { levels }
+        }
+
+        pub(crate) fn from_chunks(data: &[u8], chunk_size: usize) -> MerkleTree {
+            assert!(chunk_size > 0);
+            MerkleTree::from_leaves(data.chunks(chunk_size).map(leaf_hash).collect())
+        }
+
+        // 流式读取，内存里只有一块数据和所有的哈希，适合大文件
+        pub(crate) fn from_reader(
+            mut reader: impl Read,
+            chunk_size: usize,
+        ) -> io::Result<MerkleTree> {
+            assert!(chunk_size > 0);
+            let mut leaves = Vec::new();
+            let mut chunk = vec![0; chunk_size];
+            loop {
+                let n = read_chunk(&mut reader, &mut chunk)?;
+                if n == 0 {
+                    break;
+                }
+                leaves.push(leaf_hash(&chunk[..n]));
+            }
+            Ok(MerkleTree::from_leaves(leaves))
+        }
+
+        // 空的树（没有数据）的根是空串的哈希
+        pub(crate) fn root(&self) -> Hash {
+            match self.levels.last().unwrap().first() {
+                Some(root) => *root,
+                None => Sha256::digest(b"").into(),
+            }
+        }
+
+        pub(crate) fn leaves(&self) -> &[Hash] {
+            &self.levels[0]
+        }
+
+        pub(crate) fn proof(&self, index: usize) -> Option<Proof> {
+            if index >= self.leaves().len() {
+                return None;
+            }
+            let mut siblings = Vec::new();
+            let mut i = index;
+            for level in &self.levels[..self.levels.len() - 1] {
+                let sibling = i ^ 1;
+                if sibling < level.len() {
+                    let side = if sibling < i { Side::Left } else { Side::Right };
+                    siblings.push((side, level[sibling]));
+                }
+                i /= 2;
+            }
+            Some(Proof { index, siblings })
+        }
+
+        // 两棵树不同的叶子的下标，只进入哈希不同的子树。叶子数不同时多出来的叶子都算不同
+        pub(crate) fn diff(&self, other: &MerkleTree) -> Vec<usize> {
+            let mut out = Vec::new();
+            if self.levels.len() == other.levels.len() {
+                self.diff_node(other, self.levels.len() - 1, 0, &mut out);
+            } else {
+                // 高度不同的树节点对不上，逐个比较叶子
+                let (a, b) = (self.leaves(), other.leaves());
+                out.extend((0..a.len().min(b.len())).filter(|&i| a[i] != b[i]));
+            }
+            let common = self.leaves().len().min(other.leaves().len());
+            out.extend(common..self.leaves().len().max(other.leaves().len()));
+            out
+        }
+
+        fn diff_node(&self, other: &MerkleTree, level: usize, i: usize, out: &mut Vec<usize>) {
+            let (a, b) = (self.levels[level].get(i), other.levels[level].get(i));
+            match (a, b) {
+                (Some(a), Some(b)) if a == b => {}
+                (Some(_), Some(_)) if level == 0 => out.push(i),
+                (Some(_), Some(_)) => {
+                    self.diff_node(other, level - 1, i * 2, out);
+                    self.diff_node(other, level - 1, i * 2 + 1, out);
+                }
+                // 只有一边有的节点下面的叶子在 diff 里统一按“多出来的”处理
+                _ => {}
+            }
+        }
+    }
+
+    // 用数据和证明算出根，和可信的根比较
+    pub(crate) fn verify(root: &Hash, data: &[u8], proof: &Proof) -> bool {
+        let computed = proof
+            .siblings
+            .iter()
+            .fold(leaf_hash(data), |hash, (side, sibling)| match side {
+                Side::Left => node_hash(sibling, &hash),
+                Side::Right => node_hash(&hash, sibling),
+            });
+        computed == *root
+    }
+
+    // 读满一块，文件末尾时可能不满
+    fn read_chunk(reader: &mut impl Read, chunk: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match reader.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct CopyReport {
+        // 目标文件里已经正确、不用再传的块数，和实际传输的块数
+        pub(crate) reused: usize,
+        pub(crate) copied: usize,
+    }
+
+    // 可以续传的复制：目标文件已经存在（例如上次复制到一半中断了）时，只传和源文件不同的块
+    // 每一块传过去时带着包含证明，接收方只相信源文件的根，验证通过才写入；最后再校验整个文件的根
+    pub(crate) fn copy_verified(
+        src: &Path,
+        dst: &Path,
+        chunk_size: usize,
+    ) -> io::Result<CopyReport> {
+        let source = MerkleTree::from_reader(File::open(src)?, chunk_size)?;
+        let root = source.root();
+        let existing = match File::open(dst) {
+            Ok(file) => MerkleTree::from_reader(file, chunk_size)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => MerkleTree::from_leaves(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        // 目标文件比源文件多出来的块不用传，截断就行
+        let missing: Vec<usize> = source
+            .diff(&existing)
+            .into_iter()
+            .filter(|&i| i < source.leaves().len())
+            .collect();
+
+        let mut input = File::open(src)?;
+        let mut output = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(dst)?;
+        let mut chunk = vec![0; chunk_size];
+        for &i in &missing {
+            let offset = (i * chunk_size) as u64;
+            input.seek(SeekFrom::Start(offset))?;
+            let n = read_chunk(&mut input, &mut chunk)?;
+            let proof = source.proof(i).unwrap();
+            if !verify(&root, &chunk[..n], &proof) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("chunk {} failed verification", i),
+                ));
+            }
+            output.seek(SeekFrom::Start(offset))?;
+            output.write_all(&chunk[..n])?;
+        }
+        output.set_len(fs::metadata(src)?.len())?;
+        output.sync_all()?;
+
+        if MerkleTree::from_reader(File::open(dst)?, chunk_size)?.root() != root {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "copy does not match the source root",
+            ));
+        }
+        Ok(CopyReport {
+            reused: source.leaves().len() - missing.len(),
+            copied: missing.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::merkle::*;
+    use crate::tempfs_example::tempfs::TempDir;
+    use std::fs;
+
+    // 用线性同余生成器造数据，每次相同
+    fn data(len: usize) -> Vec<u8> {
+        let mut seed = 3u64;
+        (0..len)
+            .map(|_| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (seed >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn proofs_verify_every_chunk() {
+        // 各种叶子数，包括奇数个（有节点要升层）和只有一个
+        for chunks in [1, 2, 3, 5, 8, 13] {
+            let data = data(chunks * 16 - 7);
+            let tree = MerkleTree::from_chunks(&data, 16);
+            assert_eq!(tree.leaves().len(), chunks);
+            let root = tree.root();
+            for (i, chunk) in data.chunks(16).enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(proof.siblings.len() <= 4);
+                assert!(
+                    verify(&root, chunk, &proof),
+                    "{} chunks, chunk {}",
+                    chunks,
+                    i
+                );
+                // 换一块数据、或者拿别的块的证明都通不过
+                let mut tampered = chunk.to_vec();
+                tampered[0] ^= 1;
+                assert!(!verify(&root, &tampered, &proof));
+                if chunks > 1 {
+                    let other = tree.proof((i + 1) % chunks).unwrap();
+                    assert!(!verify(&root, chunk, &other));
+                }
+            }
+            assert_eq!(tree.proof(chunks), None);
+            // 流式构建和一次性构建结果一样
+            assert_eq!(MerkleTree::from_reader(&data[..], 16).unwrap().root(), root);
+        }
+        // 单独一块时根就是叶子的哈希
+        let single = MerkleTree::from_chunks(b"abc", 16);
+        assert_eq!(single.root(), leaf_hash(b"abc"));
+        assert_eq!(
+            to_hex(&MerkleTree::from_chunks(b"", 16).root()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn diff_finds_changed_chunks() {
+        let original = data(100 * 8);
+        let mut changed = original.clone();
+        changed[3 * 8] ^= 1;
+        changed[77 * 8 + 5] ^= 1;
+        let a = MerkleTree::from_chunks(&original, 8);
+        let b = MerkleTree::from_chunks(&changed, 8);
+        assert_eq!(a.diff(&b), [3, 77]);
+        assert!(a.diff(&a).is_empty());
+        // 截短的一方少的块算不同，改动也照样找到
+        let shorter = MerkleTree::from_chunks(&changed[..40 * 8], 8);
+        let diff = a.diff(&shorter);
+        assert_eq!(diff[0], 3);
+        assert_eq!(diff[1..], (40..100).collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn resumes_partial_copies() {
+        let dir = TempDir::new("merkle-copy").unwrap();
+        let (src, dst) = (dir.join("src.bin"), dir.join("dst.bin"));
+        let contents = data(10_000);
+        fs::write(&src, &contents).unwrap();
+
+        assert_eq!(
+            copy_verified(&src, &dst, 1024).unwrap(),
+            CopyReport {
+                reused: 0,
+                copied: 10
+            }
+        );
+        assert_eq!(fs::read(&dst).unwrap(), contents);
+
+        // 上次只传了一半，中间还有一块坏了：只重传坏的和缺的
+        let mut partial = contents[..5000].to_vec();
+        partial[2100] ^= 0xff;
+        fs::write(&dst, &partial).unwrap();
+        assert_eq!(
+            copy_verified(&src, &dst, 1024).unwrap(),
+            CopyReport {
+                reused: 3,
+                copied: 7
+            }
+        );
+        assert_eq!(fs::read(&dst).unwrap(), contents);
+
+        // 目标比源长：截断多出来的部分。源文件的最后一块不满，目标里同一位置的块带着多出来的尾巴，哈希不同，要重传这一块
+        let mut longer = contents.clone();
+        longer.extend_from_slice(b"trailing garbage");
+        fs::write(&dst, &longer).unwrap();
+        assert_eq!(
+            copy_verified(&src, &dst, 1024).unwrap(),
+            CopyReport {
+                reused: 9,
+                copied: 1
+            }
+        );
+        assert_eq!(fs::read(&dst).unwrap(), contents);
+    }
+}
@@ -0,0 +1,635 @@
+// 多路复用：在一条 TCP 连接上承载多个编号的逻辑通道
+#[cfg(test)]
+mod tests {
+
+    use rand::Rng;
+    use std::collections::{HashMap, VecDeque};
+    use std::io::{self, Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    // 帧格式：通道号（u32，大端）+ 帧类型（u8）+ 负载长度（u32，大端）+ 负载
+    const HEADER_LEN: usize = 9;
+    // 单帧负载的上限，防止对端发来一个巨大的长度字段让我们分配海量内存
+    const MAX_PAYLOAD_LEN: usize = 16 * 1024;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum FrameKind {
+        Open,
+        Data,
+        Close,
+        // 接收方消费数据后归还发送窗口（credit），负载是 u32 的字节数
+        WindowUpdate,
+    }
+
+    impl FrameKind {
+        fn to_byte(self) -> u8 {
+            match self {
+                FrameKind::Open => 0,
+                FrameKind::Data => 1,
+                FrameKind::Close => 2,
+                FrameKind::WindowUpdate => 3,
+            }
+        }
+
+        fn from_byte(byte: u8) -> Option<FrameKind> {
+            match byte {
+                0 => Some(FrameKind::Open),
+                1 => Some(FrameKind::Data),
+                2 => Some(FrameKind::Close),
+                3 => Some(FrameKind::WindowUpdate),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Frame {
+        channel: u32,
+        kind: FrameKind,
+        payload: Vec<u8>,
+    }
+
+    impl Frame {
+        fn new(channel: u32, kind: FrameKind, payload: Vec<u8>) -> Frame {
+            Frame {
+                channel,
+                kind,
+                payload,
+            }
+        }
+
+        fn encode(&self) -> Vec<u8> {
+            let mut buf = Vec::with_capacity(HEADER_LEN + self.payload.len());
+            buf.extend_from_slice(&self.channel.to_be_bytes());
+            buf.push(self.kind.to_byte());
+            buf.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&self.payload);
+            buf
+        }
+    }
+
+    // 增量解码器的状态：要么在收集头部，要么在收集负载
+    // TCP 是字节流，一次 read 可能只读到半个头部，也可能一次读到好几帧，所以解码器要能在任意位置暂停并在下次继续
+    enum DecodeState {
+        Header {
+            buf: [u8; HEADER_LEN],
+            filled: usize,
+        },
+        Payload {
+            channel: u32,
+            kind: FrameKind,
+            len: usize,
+            payload: Vec<u8>,
+        },
+        // 一旦遇到非法数据，后续字节已经无法确定帧边界，解码器停在这个状态
+        Failed,
+    }
+
+    struct FrameDecoder {
+        state: DecodeState,
+    }
+
+    impl FrameDecoder {
+        fn new() -> FrameDecoder {
+            FrameDecoder {
+                state: DecodeState::Header {
+                    buf: [0; HEADER_LEN],
+                    filled: 0,
+                },
+            }
+        }
+
+        // 喂入任意长度的一段字节，返回其中所有已经完整的帧，不完整的部分留在内部状态中等待下一次喂入
+        fn feed(&mut self, mut input: &[u8]) -> io::Result<Vec<Frame>> {
+            let mut frames = Vec::new();
+            while !input.is_empty() {
+                if let Some(frame) = self.advance(&mut input)? {
+                    frames.push(frame);
+                }
+            }
+            Ok(frames)
+        }
+
+        // 状态机的一步：尽可能多地消费 input，凑齐一帧时返回 Some
+        fn advance(&mut self, input: &mut &[u8]) -> io::Result<Option<Frame>> {
+            match &mut self.state {
+                DecodeState::Header { buf, filled } => {
+                    let n = take_into(&mut buf[*filled..], input);
+                    *filled += n;
+                    if *filled < HEADER_LEN {
+                        return Ok(None);
+                    }
+
+                    let channel = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                    let kind = FrameKind::from_byte(buf[4]);
+                    let len = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) as usize;
+                    // 先校验再分配，非法的长度字段不会导致分配巨大的缓冲区
+                    let kind = match kind {
+                        Some(kind) if len <= MAX_PAYLOAD_LEN => kind,
+                        Some(_) => return self.fail("frame payload too large"),
+                        None => return self.fail("unknown frame kind"),
+                    };
+
+                    self.state = DecodeState::Payload {
+                        channel,
+                        kind,
+                        len,
+                        payload: Vec::with_capacity(len),
+                    };
+                    // 负载长度为 0 的帧在头部读完时就已经完整了
+                    if len == 0 {
+                        return Ok(Some(self.finish()));
+                    }
+                    Ok(None)
+                }
+                DecodeState::Payload { len, payload, .. } => {
+                    let wanted = *len - payload.len();
+                    let n = wanted.min(input.len());
+                    payload.extend_from_slice(&input[..n]);
+                    *input = &input[n..];
+                    if n < wanted {
+                        return Ok(None);
+                    }
+                    Ok(Some(self.finish()))
+                }
+                DecodeState::Failed => self.fail("decoder is in a failed state"),
+            }
+        }
+
+        // 当前帧收集完毕，取出它并回到等待头部的状态
+        fn finish(&mut self) -> Frame {
+            let state = std::mem::replace(
+                &mut self.state,
+                DecodeState::Header {
+                    buf: [0; HEADER_LEN],
+                    filled: 0,
+                },
+            );
+            match state {
+                DecodeState::Payload {
+                    channel,
+                    kind,
+                    payload,
+                    ..
+                } => Frame::new(channel, kind, payload),
+                _ => unreachable!("finish is only called once a payload is complete"),
+            }
+        }
+
+        fn fail<T>(&mut self, message: &str) -> io::Result<T> {
+            self.state = DecodeState::Failed;
+            Err(io::Error::new(io::ErrorKind::InvalidData, message))
+        }
+    }
+
+    // 从 input 头部拷贝尽可能多的字节到 dst，并把 input 前移，返回拷贝的字节数
+    fn take_into(dst: &mut [u8], input: &mut &[u8]) -> usize {
+        let n = dst.len().min(input.len());
+        dst[..n].copy_from_slice(&input[..n]);
+        *input = &input[n..];
+        n
+    }
+
+    // 连接的发起方使用奇数通道号，接受方使用偶数通道号，双方同时 open 时也不会冲突
+    #[derive(Clone, Copy)]
+    enum Side {
+        Client,
+        Server,
+    }
+
+    struct ChannelState {
+        inbox: VecDeque<Vec<u8>>,
+        // 还能向对端发送多少字节，为 0 时 send 阻塞，这就是每个通道独立的背压
+        send_window: usize,
+        remote_closed: bool,
+        local_closed: bool,
+    }
+
+    impl ChannelState {
+        fn new(window: usize) -> ChannelState {
+            ChannelState {
+                inbox: VecDeque::new(),
+                send_window: window,
+                remote_closed: false,
+                local_closed: false,
+            }
+        }
+    }
+
+    struct State {
+        channels: HashMap<u32, ChannelState>,
+        // 对端打开、尚未被 accept 的通道
+        pending: VecDeque<u32>,
+        next_id: u32,
+        // 底层连接已经断开
+        disconnected: bool,
+    }
+
+    // 读线程、Mux 和所有 Channel 共享的状态，所有等待都挂在同一个条件变量上
+    struct Shared {
+        writer: Mutex<TcpStream>,
+        state: Mutex<State>,
+        changed: Condvar,
+        window: usize,
+    }
+
+    impl Shared {
+        fn write_frame(&self, frame: Frame) -> io::Result<()> {
+            // 整帧在持有锁的情况下写出，保证不同通道的帧不会在字节流中交错
+            self.writer.lock().unwrap().write_all(&frame.encode())
+        }
+
+        // 读线程：每次 read 读到多少就喂给解码器多少，再把解出的帧分发到各个通道
+        fn read_loop(&self, mut reader: TcpStream) {
+            let mut decoder = FrameDecoder::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                // 协议错误时直接断开连接，对端发来的后续数据已经无法解释
+                let frames = match decoder.feed(&buf[..n]) {
+                    Ok(frames) => frames,
+                    Err(_) => break,
+                };
+
+                let mut state = self.state.lock().unwrap();
+                for frame in frames {
+                    self.dispatch(&mut state, frame);
+                }
+                self.changed.notify_all();
+            }
+
+            // 读到 EOF 或出错都意味着连接不可用了，唤醒所有等待者让它们返回
+            self.state.lock().unwrap().disconnected = true;
+            self.changed.notify_all();
+        }
+
+        fn dispatch(&self, state: &mut State, frame: Frame) {
+            match frame.kind {
+                FrameKind::Open => {
+                    state
+                        .channels
+                        .insert(frame.channel, ChannelState::new(self.window));
+                    state.pending.push_back(frame.channel);
+                }
+                FrameKind::Data => {
+                    if let Some(channel) = state.channels.get_mut(&frame.channel) {
+                        channel.inbox.push_back(frame.payload);
+                    }
+                }
+                FrameKind::Close => {
+                    if let Some(channel) = state.channels.get_mut(&frame.channel) {
+                        channel.remote_closed = true;
+                    }
+                }
+                FrameKind::WindowUpdate => {
+                    if let (Some(channel), Ok(bytes)) = (
+                        state.channels.get_mut(&frame.channel),
+                        <[u8; 4]>::try_from(frame.payload.as_slice()),
+                    ) {
+                        channel.send_window += u32::from_be_bytes(bytes) as usize;
+                    }
+                }
+            }
+        }
+    }
+
+    struct Mux {
+        shared: Arc<Shared>,
+    }
+
+    impl Mux {
+        // window 是每个通道的初始发送窗口，对端最多可以有这么多字节未被消费
+        fn new(stream: TcpStream, side: Side, window: usize) -> io::Result<Mux> {
+            let reader = stream.try_clone()?;
+            let shared = Arc::new(Shared {
+                writer: Mutex::new(stream),
+                state: Mutex::new(State {
+                    channels: HashMap::new(),
+                    pending: VecDeque::new(),
+                    next_id: match side {
+                        Side::Client => 1,
+                        Side::Server => 2,
+                    },
+                    disconnected: false,
+                }),
+                changed: Condvar::new(),
+                window,
+            });
+
+            let reader_shared = Arc::clone(&shared);
+            thread::spawn(move || reader_shared.read_loop(reader));
+
+            Ok(Mux { shared })
+        }
+
+        fn open(&self) -> io::Result<Channel> {
+            let id = {
+                let mut state = self.shared.state.lock().unwrap();
+                let id = state.next_id;
+                state.next_id += 2;
+                state
+                    .channels
+                    .insert(id, ChannelState::new(self.shared.window));
+                id
+            };
+            self.shared
+                .write_frame(Frame::new(id, FrameKind::Open, Vec::new()))?;
+            Ok(Channel {
+                id,
+                shared: Arc::clone(&self.shared),
+            })
+        }
+
+        // 阻塞等待对端打开一个新通道，连接断开时返回 None
+        fn accept(&self) -> Option<Channel> {
+            let mut state = self.shared.state.lock().unwrap();
+            loop {
+                if let Some(id) = state.pending.pop_front() {
+                    return Some(Channel {
+                        id,
+                        shared: Arc::clone(&self.shared),
+                    });
+                }
+                if state.disconnected {
+                    return None;
+                }
+                state = self.shared.changed.wait(state).unwrap();
+            }
+        }
+    }
+
+    impl Drop for Mux {
+        // 关闭底层连接，读线程会因此读到 EOF 并退出
+        fn drop(&mut self) {
+            let _ = self.shared.writer.lock().unwrap().shutdown(Shutdown::Both);
+        }
+    }
+
+    struct Channel {
+        id: u32,
+        shared: Arc<Shared>,
+    }
+
+    impl Channel {
+        // 按发送窗口切分数据，窗口耗尽时阻塞，直到对端消费数据并归还窗口
+        fn send(&self, mut data: &[u8]) -> io::Result<()> {
+            while !data.is_empty() {
+                let n = {
+                    let mut state = self.shared.state.lock().unwrap();
+                    loop {
+                        let disconnected = state.disconnected;
+                        let channel = state.channels.get_mut(&self.id).unwrap();
+                        if channel.local_closed || channel.remote_closed || disconnected {
+                            return Err(io::Error::new(
+                                io::ErrorKind::BrokenPipe,
+                                "channel closed",
+                            ));
+                        }
+                        if channel.send_window > 0 {
+                            let n = data.len().min(channel.send_window).min(MAX_PAYLOAD_LEN);
+                            channel.send_window -= n;
+                            break n;
+                        }
+                        state = self.shared.changed.wait(state).unwrap();
+                    }
+                };
+
+                self.shared.write_frame(Frame::new(
+                    self.id,
+                    FrameKind::Data,
+                    data[..n].to_vec(),
+                ))?;
+                data = &data[n..];
+            }
+            Ok(())
+        }
+
+        // 取出下一块数据并把对应的窗口归还给对端，通道关闭且数据读完时返回 None
+        fn recv(&self) -> Option<Vec<u8>> {
+            let data = {
+                let mut state = self.shared.state.lock().unwrap();
+                loop {
+                    let disconnected = state.disconnected;
+                    let channel = state.channels.get_mut(&self.id).unwrap();
+                    if let Some(data) = channel.inbox.pop_front() {
+                        break data;
+                    }
+                    if channel.remote_closed || disconnected {
+                        return None;
+                    }
+                    state = self.shared.changed.wait(state).unwrap();
+                }
+            };
+
+            let credit = (data.len() as u32).to_be_bytes().to_vec();
+            // 连接已断开时归还窗口失败也无妨，数据本身已经收到了
+            let _ = self
+                .shared
+                .write_frame(Frame::new(self.id, FrameKind::WindowUpdate, credit));
+            Some(data)
+        }
+
+        fn close(&self) -> io::Result<()> {
+            let mut state = self.shared.state.lock().unwrap();
+            let channel = state.channels.get_mut(&self.id).unwrap();
+            if channel.local_closed {
+                return Ok(());
+            }
+            channel.local_closed = true;
+            drop(state);
+            self.shared.changed.notify_all();
+            self.shared
+                .write_frame(Frame::new(self.id, FrameKind::Close, Vec::new()))
+        }
+    }
+
+    // 建立一对通过本地回环连接起来的 Mux
+    fn mux_pair(window: usize) -> (Mux, Mux) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (
+            Mux::new(client, Side::Client, window).unwrap(),
+            Mux::new(server, Side::Server, window).unwrap(),
+        )
+    }
+
+    // 随机生成一组帧，覆盖所有帧类型和空负载
+    fn random_frames(rng: &mut impl Rng, count: usize) -> Vec<Frame> {
+        let kinds = [
+            FrameKind::Open,
+            FrameKind::Data,
+            FrameKind::Close,
+            FrameKind::WindowUpdate,
+        ];
+        (0..count)
+            .map(|_| {
+                let len = rng.gen_range(0..300);
+                let payload = (0..len).map(|_| rng.gen()).collect();
+                Frame::new(rng.gen(), kinds[rng.gen_range(0..kinds.len())], payload)
+            })
+            .collect()
+    }
+
+    fn encode_all(frames: &[Frame]) -> Vec<u8> {
+        frames.iter().flat_map(|frame| frame.encode()).collect()
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = Frame::new(7, FrameKind::Data, b"hello".to_vec());
+        let bytes = frame.encode();
+        assert_eq!(bytes.len(), HEADER_LEN + 5);
+        assert_eq!(FrameDecoder::new().feed(&bytes).unwrap(), vec![frame]);
+
+        // 长度字段超过上限的帧被拒绝，之后解码器一直处于失败状态
+        let mut bytes = Frame::new(1, FrameKind::Data, Vec::new()).encode();
+        bytes[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut decoder = FrameDecoder::new();
+        let err = decoder.feed(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decoder
+            .feed(&Frame::new(1, FrameKind::Open, Vec::new()).encode())
+            .is_err());
+
+        // 未知的帧类型同样被拒绝
+        let mut bytes = Frame::new(1, FrameKind::Data, Vec::new()).encode();
+        bytes[4] = 0xff;
+        assert!(FrameDecoder::new().feed(&bytes).is_err());
+    }
+
+    #[test]
+    fn decoder_one_byte_at_a_time() {
+        let frames = random_frames(&mut rand::thread_rng(), 20);
+        let bytes = encode_all(&frames);
+
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in bytes.chunks(1) {
+            decoded.extend(decoder.feed(byte).unwrap());
+        }
+        assert_eq!(decoded, frames);
+    }
+
+    #[test]
+    fn decoder_random_split_points() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let count = rng.gen_range(1..10);
+            let frames = random_frames(&mut rng, count);
+            let bytes = encode_all(&frames);
+
+            // 在随机位置把字节流切成若干段，模拟 TCP 任意的分片方式
+            let mut decoder = FrameDecoder::new();
+            let mut decoded = Vec::new();
+            let mut rest = bytes.as_slice();
+            while !rest.is_empty() {
+                let n = rng.gen_range(1..=rest.len().min(64));
+                decoded.extend(decoder.feed(&rest[..n]).unwrap());
+                rest = &rest[n..];
+            }
+            assert_eq!(decoded, frames);
+        }
+    }
+
+    #[test]
+    fn decoder_survives_garbage() {
+        // 随机字节不能让解码器 panic，只可能解出一些帧或者返回错误
+        let mut rng = rand::thread_rng();
+        for _ in 0..500 {
+            let len = rng.gen_range(0..200);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            let mut decoder = FrameDecoder::new();
+            for chunk in bytes.chunks(rng.gen_range(1..16)) {
+                if decoder.feed(chunk).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn channels_are_independent() {
+        let (client, server) = mux_pair(1024);
+
+        let a = client.open().unwrap();
+        let b = client.open().unwrap();
+        assert_eq!((a.id, b.id), (1, 3));
+
+        let server_a = server.accept().unwrap();
+        let server_b = server.accept().unwrap();
+
+        b.send(b"to b").unwrap();
+        a.send(b"to a").unwrap();
+        assert_eq!(server_a.recv().unwrap(), b"to a");
+        assert_eq!(server_b.recv().unwrap(), b"to b");
+
+        // 服务端打开的通道使用偶数编号
+        let c = server.open().unwrap();
+        assert_eq!(c.id, 2);
+        let client_c = client.accept().unwrap();
+        c.send(b"from server").unwrap();
+        assert_eq!(client_c.recv().unwrap(), b"from server");
+    }
+
+    #[test]
+    fn close_semantics() {
+        let (client, server) = mux_pair(1024);
+        let channel = client.open().unwrap();
+        let remote = server.accept().unwrap();
+
+        channel.send(b"last words").unwrap();
+        channel.close().unwrap();
+        assert!(channel.send(b"too late").is_err());
+
+        // 关闭前发送的数据仍然能读到，之后 recv 返回 None
+        assert_eq!(remote.recv().unwrap(), b"last words");
+        assert_eq!(remote.recv(), None);
+        assert!(remote.send(b"reply").is_err());
+
+        // 整个连接断开后 accept 返回 None
+        drop(client);
+        assert!(server.accept().is_none());
+    }
+
+    #[test]
+    fn per_channel_backpressure() {
+        let (client, server) = mux_pair(8);
+        let slow = Arc::new(client.open().unwrap());
+        let fast = client.open().unwrap();
+        let remote_slow = server.accept().unwrap();
+        let remote_fast = server.accept().unwrap();
+
+        // 向慢通道发送超过窗口大小的数据，发送线程会在窗口耗尽后阻塞
+        let done = Arc::new(AtomicBool::new(false));
+        let sender = {
+            let slow = Arc::clone(&slow);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                slow.send(&[1; 32]).unwrap();
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+
+        // 慢通道被堵住时，快通道不受影响
+        fast.send(b"ping").unwrap();
+        assert_eq!(remote_fast.recv().unwrap(), b"ping");
+        thread::sleep(Duration::from_millis(50));
+        assert!(!done.load(Ordering::SeqCst));
+
+        // 接收方开始消费后，窗口被归还，发送方得以继续
+        let mut received = 0;
+        while received < 32 {
+            received += remote_slow.recv().unwrap().len();
+        }
+        sender.join().unwrap();
+        assert!(done.load(Ordering::SeqCst));
+    }
+}
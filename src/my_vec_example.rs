@@ -0,0 +1,376 @@
+// 自己管理内存的 Vec：用 std::alloc 的 alloc/realloc/dealloc 分配缓冲区，用裸指针读写元素
+// MyVec 始终保持这几条不变量，所有 unsafe 代码都依赖它们：
+// 1. ptr 非空且按 T 对齐。capacity 为 0 或者 T 是零大小类型时 ptr 是 NonNull::dangling()，没有分配过内存
+// 2. capacity > 0 且 T 不是零大小类型时，ptr 指向一块用 Layout::array::<T>(capacity) 分配的内存
+// 3. len <= capacity，前 len 个槽位是已经初始化的元素，后面的槽位是未初始化的内存
+// 4. 缓冲区的字节数不超过 isize::MAX（Layout::array 会检查），指针加偏移不会溢出
+// 零大小类型不占内存，push 多少个都不需要分配，capacity 直接当作 usize::MAX
+#[cfg(test)]
+pub(crate) mod my_vec {
+
+    use std::alloc::{self, Layout};
+    use std::marker::PhantomData;
+    use std::mem;
+    use std::ops::{Deref, DerefMut};
+    use std::ptr::{self, NonNull};
+    use std::slice;
+
+    pub(crate) struct MyVec<T> {
+        ptr: NonNull<T>,
+        capacity: usize,
+        len: usize,
+        // 告诉编译器 MyVec 拥有 T 类型的值，drop MyVec 的时候会 drop 其中的 T（drop 检查需要知道这一点）
+        _owns: PhantomData<T>,
+    }
+
+    // NonNull 默认既不是 Send 也不是 Sync。MyVec 独占它的缓冲区，和 Vec<T> 一样，T 能做到的它都能做到
+    unsafe impl<T: Send> Send for MyVec<T> {}
+    unsafe impl<T: Sync> Sync for MyVec<T> {}
+
+    impl<T> MyVec<T> {
+        pub(crate) fn new() -> MyVec<T> {
+            let capacity = if mem::size_of::<T>() == 0 {
+                usize::MAX
+            } else {
+                0
+            };
+            MyVec {
+                ptr: NonNull::dangling(),
+                capacity,
+                len: 0,
+                _owns: PhantomData,
+            }
+        }
+
+        pub(crate) fn with_capacity(capacity: usize) -> MyVec<T> {
+            let mut vec = MyVec::new();
+            if capacity > vec.capacity {
+                vec.grow_to(capacity);
+            }
+            vec
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.len
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub(crate) fn capacity(&self) -> usize {
+            self.capacity
+        }
+
+        // 把缓冲区扩大到 capacity 个元素。已有的元素由 realloc 搬到新的位置（可能原地扩大）
+        fn grow_to(&mut self, capacity: usize) {
+            // 零大小类型的 capacity 已经是 usize::MAX，还要扩大说明元素个数溢出了
+            assert!(mem::size_of::<T>() != 0, "capacity overflow");
+            let new_layout = Layout::array::<T>(capacity).expect("capacity overflow");
+            let new_ptr = if self.capacity == 0 {
+                // SAFETY: T 不是零大小类型，capacity > 0，布局的大小不为 0
+                unsafe { alloc::alloc(new_layout) }
+            } else {
+                let old_layout = Layout::array::<T>(self.capacity).unwrap();
+                // SAFETY: 不变量 2，ptr 是用 old_layout 分配的；新的大小由 Layout::array 检查过不超过 isize::MAX
+                unsafe {
+                    alloc::realloc(self.ptr.as_ptr() as *mut u8, old_layout, new_layout.size())
+                }
+            };
+            // 分配失败时返回空指针，交给全局的处理函数（默认打印错误后终止进程）
+            self.ptr = match NonNull::new(new_ptr as *mut T) {
+                Some(ptr) => ptr,
+                None => alloc::handle_alloc_error(new_layout),
+            };
+            self.capacity = capacity;
+        }
+
+        pub(crate) fn push(&mut self, value: T) {
+            if self.len == self.capacity {
+                // 容量翻倍，push 的均摊时间是 O(1)
+                let capacity = if self.capacity == 0 {
+                    4
+                } else {
+                    self.capacity.checked_mul(2).expect("capacity overflow")
+                };
+                self.grow_to(capacity);
+            }
+            // SAFETY: len < capacity，这个槽位在缓冲区之内而且未初始化。用 write 而不是赋值，
+            // 赋值会先 drop 槽位里原来的"值"，而那是一块未初始化的内存
+            unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+            self.len += 1;
+        }
+
+        pub(crate) fn pop(&mut self) -> Option<T> {
+            if self.len == 0 {
+                return None;
+            }
+            self.len -= 1;
+            // SAFETY: 原来的最后一个元素是初始化过的。read 把它按位复制出来，len 已经减一，
+            // 这个槽位从此算作未初始化，不会再被 drop 第二次
+            Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.len)) })
+        }
+    }
+
+    impl<T> Default for MyVec<T> {
+        fn default() -> MyVec<T> {
+            MyVec::new()
+        }
+    }
+
+    // 实现了 Deref<Target = [T]> 以后，切片的方法（索引、iter、sort、len 等）都可以直接在 MyVec 上用
+    impl<T> Deref for MyVec<T> {
+        type Target = [T];
+
+        fn deref(&self) -> &[T] {
+            // SAFETY: 不变量 1 和 3，ptr 非空对齐，前 len 个元素都初始化了
+            unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+        }
+    }
+
+    impl<T> DerefMut for MyVec<T> {
+        fn deref_mut(&mut self) -> &mut [T] {
+            // SAFETY: 同上，&mut self 保证了没有别的引用
+            unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+        }
+    }
+
+    impl<T> Drop for MyVec<T> {
+        fn drop(&mut self) {
+            // 先 drop 所有元素，再释放缓冲区。drop_in_place 对一个切片调用，会依次 drop 每个元素
+            // SAFETY: 前 len 个元素都初始化了，这之后不会再访问它们
+            unsafe { ptr::drop_in_place(&mut **self as *mut [T]) };
+            if mem::size_of::<T>() != 0 && self.capacity > 0 {
+                // SAFETY: 不变量 2
+                unsafe {
+                    alloc::dealloc(
+                        self.ptr.as_ptr() as *mut u8,
+                        Layout::array::<T>(self.capacity).unwrap(),
+                    )
+                };
+            }
+        }
+    }
+
+    impl<'a, T> IntoIterator for &'a MyVec<T> {
+        type Item = &'a T;
+        type IntoIter = slice::Iter<'a, T>;
+
+        fn into_iter(self) -> slice::Iter<'a, T> {
+            self.iter()
+        }
+    }
+
+    // 按值迭代：把缓冲区的所有权转给 IntoIter，元素从两头依次 read 出来，
+    // 只有 start..end 之间的槽位还是初始化的
+    pub(crate) struct IntoIter<T> {
+        ptr: NonNull<T>,
+        capacity: usize,
+        start: usize,
+        end: usize,
+        _owns: PhantomData<T>,
+    }
+
+    impl<T> IntoIterator for MyVec<T> {
+        type Item = T;
+        type IntoIter = IntoIter<T>;
+
+        fn into_iter(self) -> IntoIter<T> {
+            // 缓冲区交给 IntoIter 释放，MyVec 自己的 drop 不能再运行
+            let vec = mem::ManuallyDrop::new(self);
+            IntoIter {
+                ptr: vec.ptr,
+                capacity: vec.capacity,
+                start: 0,
+                end: vec.len,
+                _owns: PhantomData,
+            }
+        }
+    }
+
+    impl<T> Iterator for IntoIter<T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            if self.start == self.end {
+                return None;
+            }
+            self.start += 1;
+            // SAFETY: start 之前的槽位初始化过，读出来之后 start 已经越过它，不会再被读或者 drop
+            Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.start - 1)) })
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            let remaining = self.end - self.start;
+            (remaining, Some(remaining))
+        }
+    }
+
+    impl<T> DoubleEndedIterator for IntoIter<T> {
+        fn next_back(&mut self) -> Option<T> {
+            if self.start == self.end {
+                return None;
+            }
+            self.end -= 1;
+            // SAFETY: 同 next
+            Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.end)) })
+        }
+    }
+
+    impl<T> ExactSizeIterator for IntoIter<T> {}
+
+    impl<T> Drop for IntoIter<T> {
+        fn drop(&mut self) {
+            // 没迭代完就丢掉的时候，剩下的元素也要 drop。把它们重新交给一个 MyVec，借用它的 drop 释放元素和缓冲区
+            let remaining = self.end - self.start;
+            // SAFETY: start..end 是初始化过的元素，copy 允许重叠，把它们挪到缓冲区开头以后正好满足 MyVec 的不变量 3
+            unsafe {
+                ptr::copy(
+                    self.ptr.as_ptr().add(self.start),
+                    self.ptr.as_ptr(),
+                    remaining,
+                )
+            };
+            drop(MyVec {
+                ptr: self.ptr,
+                capacity: self.capacity,
+                len: remaining,
+                _owns: PhantomData,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::my_vec::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn push_pop_and_slice_methods() {
+        let mut vec = MyVec::new();
+        assert!(vec.is_empty() && vec.capacity() == 0 && vec.pop().is_none());
+        let mut capacities = Vec::new();
+        for i in 0..100 {
+            vec.push(i);
+            if capacities.last() != Some(&vec.capacity()) {
+                capacities.push(vec.capacity());
+            }
+        }
+        assert_eq!(capacities, [4, 8, 16, 32, 64, 128]);
+        // 通过 Deref 得到的切片方法
+        assert_eq!(vec.len(), 100);
+        assert_eq!(vec[42], 42);
+        assert_eq!(vec.iter().sum::<i32>(), 4950);
+        assert_eq!(vec.first(), Some(&0));
+        vec[0] = 1000;
+        vec.sort();
+        assert_eq!(vec.last(), Some(&1000));
+        assert_eq!((&vec).into_iter().count(), 100);
+        assert_eq!(vec.pop(), Some(1000));
+        assert_eq!(vec.pop(), Some(99));
+        assert_eq!(vec.len(), 98);
+
+        let vec: MyVec<String> = MyVec::with_capacity(10);
+        assert_eq!(vec.capacity(), 10);
+
+        // 零大小类型不分配内存
+        let mut units = MyVec::new();
+        assert_eq!(units.capacity(), usize::MAX);
+        for _ in 0..1000 {
+            units.push(());
+        }
+        assert_eq!(units.len(), 1000);
+        assert_eq!(units.pop(), Some(()));
+        assert_eq!(units.into_iter().count(), 999);
+
+        // 按值迭代，两头都可以取
+        let mut vec = MyVec::new();
+        for word in ["a", "b", "c", "d"] {
+            vec.push(word.to_string());
+        }
+        let mut iter = vec.into_iter();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next().as_deref(), Some("a"));
+        assert_eq!(iter.next_back().as_deref(), Some("d"));
+        assert_eq!(iter.collect::<Vec<_>>(), ["b", "c"]);
+    }
+
+    // 被 drop 时把自己的编号记下来，用来检查每个元素正好被 drop 一次
+    struct Tracked(u32, Rc<RefCell<Vec<u32>>>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    #[test]
+    fn every_element_is_dropped_exactly_once() {
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        let tracked = |n| Tracked(n, dropped.clone());
+        let sorted = |dropped: &Rc<RefCell<Vec<u32>>>| {
+            let mut ids = dropped.borrow_mut().split_off(0);
+            ids.sort();
+            ids
+        };
+
+        // drop 整个 MyVec
+        let mut vec = MyVec::new();
+        for n in 0..10 {
+            vec.push(tracked(n));
+        }
+        drop(vec.pop());
+        assert_eq!(sorted(&dropped), [9]);
+        drop(vec);
+        assert_eq!(sorted(&dropped), (0..9).collect::<Vec<_>>());
+
+        // 迭代了一半就丢掉的 IntoIter：取出来的由调用方 drop，剩下的由 IntoIter drop
+        let mut vec = MyVec::new();
+        for n in 0..10 {
+            vec.push(tracked(n));
+        }
+        let mut iter = vec.into_iter();
+        let first = iter.next().unwrap();
+        let last = iter.next_back().unwrap();
+        drop(iter);
+        assert_eq!(sorted(&dropped), (1..9).collect::<Vec<_>>());
+        drop((first, last));
+        assert_eq!(sorted(&dropped), [0, 9]);
+
+        // 空的 MyVec 和空的 IntoIter
+        drop(MyVec::<Tracked>::new());
+        drop(MyVec::<Tracked>::new().into_iter());
+        assert!(dropped.borrow().is_empty());
+    }
+
+    #[test]
+    fn behaves_like_std_vec() {
+        let mut seed: u64 = 91;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        for _ in 0..20 {
+            let mut mine = MyVec::new();
+            let mut std = Vec::new();
+            for _ in 0..2_000 {
+                // push 比 pop 多一些，长度会慢慢涨上去，经过好几次扩容
+                if next() % 5 < 3 {
+                    let value = next().to_string();
+                    mine.push(value.clone());
+                    std.push(value);
+                } else {
+                    assert_eq!(mine.pop(), std.pop());
+                }
+                assert_eq!(&mine[..], &std[..]);
+            }
+            assert!(mine.capacity() >= mine.len());
+            assert!(mine.into_iter().rev().eq(std.into_iter().rev()));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// 路径处理的几个工具函数，静态文件服务器和内容寻址存储（CAS）用它们把外部给的路径限制在某个目录下
+// - normalize：纯字符串层面的规范化，去掉 . 和多余的分隔符，a/b/../c 变成 a/c，不访问文件系统
+// - safe_join：把用户给的相对路径接到 root 下面，规范化之后还要往 root 外面走（../secret、/etc/passwd）的一律拒绝
+// - safe_join_resolved：在 safe_join 的基础上顺着路径检查已经存在的部分，
+//   root 里指向外面的符号链接也会被拒绝；还不存在的部分照样接上，写文件之前也能用来检查
+// - relative_to：从 base 出发到 path 的相对路径，/a/b/c 相对 /a/d 是 ../b/c
+#[cfg(test)]
+pub(crate) mod path_util {
+
+    use std::error::Error;
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::{Component, Path, PathBuf};
+
+    #[derive(Debug)]
+    pub(crate) enum PathError {
+        // 用户给的路径规范化之后不在 root 下面
+        Escapes(PathBuf),
+        // 检查符号链接时访问文件系统出错，例如 root 不存在
+        Io(io::Error),
+    }
+
+    impl fmt::Display for PathError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                PathError::Escapes(path) => write!(f, "path {:?} escapes the root", path),
+                PathError::Io(e) => write!(f, "{}", e),
+            }
+        }
+    }
+
+    impl Error for PathError {}
+
+    impl From<io::Error> for PathError {
+        fn from(e: io::Error) -> PathError {
+            PathError::Io(e)
+        }
+    }
+
+    // 遇到 .. 时退掉前一个普通组成部分；根目录之上没有东西，/.. 就是 /；
+    // 相对路径开头的 .. 没有东西可退，只能保留下来。空路径规范化成 "."
+    pub(crate) fn normalize(path: &Path) -> PathBuf {
+        let mut parts: Vec<Component> = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => match parts.last() {
+                    Some(Component::Normal(_)) => {
+                        parts.pop();
+                    }
+                    Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                    _ => parts.push(component),
+                },
+                _ => parts.push(component),
+            }
+        }
+        if parts.is_empty() {
+            return PathBuf::from(".");
+        }
+        parts.iter().collect()
+    }
+
+    // 规范化后只剩普通组成部分的才算在 root 下面；结果是 root 本身也可以（"" 或者 "a/.."）
+    pub(crate) fn safe_join(root: &Path, user_path: &Path) -> Result<PathBuf, PathError> {
+        let normalized = normalize(user_path);
+        let escapes = normalized
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            return Err(PathError::Escapes(user_path.to_path_buf()));
+        }
+        if normalized == Path::new(".") {
+            return Ok(root.to_path_buf());
+        }
+        Ok(root.join(normalized))
+    }
+
+    // 逐级往下走：存在的部分遇到符号链接就解析出真实位置，只要离开了 root 就拒绝；
+    // 悬空的符号链接也拒绝，往里面写文件会在链接指向的地方创建出来。
+    // 第一个不存在的组成部分之后不可能再有符号链接，剩下的直接接上。返回的路径以 root 的真实路径开头
+    pub(crate) fn safe_join_resolved(root: &Path, user_path: &Path) -> Result<PathBuf, PathError> {
+        let relative = safe_join(Path::new(""), user_path)?;
+        let root = root.canonicalize()?;
+        let mut current = root.clone();
+        let mut components = relative.components();
+        for component in components.by_ref() {
+            current.push(component);
+            match fs::symlink_metadata(&current) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    current = current
+                        .canonicalize()
+                        .map_err(|_| PathError::Escapes(user_path.to_path_buf()))?;
+                    if !current.starts_with(&root) {
+                        return Err(PathError::Escapes(user_path.to_path_buf()));
+                    }
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        current.extend(components);
+        Ok(current)
+    }
+
+    // 两个路径都先规范化；一个绝对一个相对，或者 base 开头有退不掉的 ..（不知道退到了哪里）时算不出来
+    pub(crate) fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+        let (path, base) = (normalize(path), normalize(base));
+        if path.is_absolute() != base.is_absolute() {
+            return None;
+        }
+        let real = |c: &Component| *c != Component::CurDir;
+        let path: Vec<Component> = path.components().filter(real).collect();
+        let base: Vec<Component> = base.components().filter(real).collect();
+        let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+        if base[common..].contains(&Component::ParentDir) {
+            return None;
+        }
+        let mut relative: PathBuf = base[common..]
+            .iter()
+            .map(|_| Component::ParentDir)
+            .collect();
+        relative.extend(&path[common..]);
+        if relative.as_os_str().is_empty() {
+            relative.push(".");
+        }
+        Some(relative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::path_util::*;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    #[test]
+    fn normalizes_lexically() {
+        let cases = [
+            ("a/b/../c", "a/c"),
+            ("./a//b/./c/", "a/b/c"),
+            ("a/..", "."),
+            ("", "."),
+            (".", "."),
+            ("../a/../../b", "../../b"),
+            ("a/../../b", "../b"),
+            ("/a/../../b", "/b"),
+            ("/..", "/"),
+            ("/./a/b/..", "/a"),
+            // 不访问文件系统，a 是不是符号链接都一样处理
+            ("a/b/c/../../d", "a/d"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(normalize(Path::new(path)), Path::new(expected), "{}", path);
+        }
+
+        let cases = [
+            ("/a/b/c", "/a/d", Some("../b/c")),
+            ("/a/b", "/a/b", Some(".")),
+            ("/a/b", "/a", Some("b")),
+            ("/a", "/a/b/c", Some("../..")),
+            ("/", "/a", Some("..")),
+            ("src/main.rs", "src/../tests", Some("../src/main.rs")),
+            ("../x", ".", Some("../x")),
+            ("../x", "../y", Some("../x")),
+            ("x", "../y", None),
+            ("/a", "a", None),
+            ("a", "/a", None),
+        ];
+        for (path, base, expected) in cases {
+            assert_eq!(
+                relative_to(Path::new(path), Path::new(base)),
+                expected.map(PathBuf::from),
+                "{} from {}",
+                path,
+                base
+            );
+            // 算出来的相对路径接回 base 上，规范化后就是 path
+            if let Some(relative) = relative_to(Path::new(path), Path::new(base)) {
+                assert_eq!(
+                    normalize(&Path::new(base).join(relative)),
+                    normalize(Path::new(path))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn safe_join_rejects_escapes() {
+        let root = Path::new("/srv/www");
+        for (user, expected) in [
+            ("index.html", "/srv/www/index.html"),
+            ("css/../js/app.js", "/srv/www/js/app.js"),
+            ("./a/./b", "/srv/www/a/b"),
+            ("a/..", "/srv/www"),
+            ("", "/srv/www"),
+        ] {
+            assert_eq!(
+                safe_join(root, Path::new(user)).unwrap(),
+                Path::new(expected)
+            );
+        }
+        for user in ["..", "../www/index.html", "a/../../b", "/etc/passwd", "/"] {
+            let error = safe_join(root, Path::new(user)).unwrap_err();
+            assert!(matches!(error, PathError::Escapes(_)), "{}", user);
+            assert!(error.to_string().contains("escapes the root"));
+        }
+    }
+
+    #[test]
+    fn safe_join_resolved_follows_symlinks() {
+        let base = env::temp_dir().join(format!("path-util-{}", process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(root.join("docs/a.txt"), "a").unwrap();
+        fs::write(base.join("outside/secret.txt"), "secret").unwrap();
+        // 指向 root 里面的链接可以用，指向外面的和悬空的都不行
+        symlink(root.join("docs"), root.join("inner")).unwrap();
+        symlink(base.join("outside"), root.join("outer")).unwrap();
+        symlink("../outside/secret.txt", root.join("secret.txt")).unwrap();
+        symlink(base.join("missing"), root.join("dangling")).unwrap();
+
+        let real_root = root.canonicalize().unwrap();
+        let resolve = |user: &str| safe_join_resolved(&root, Path::new(user));
+        assert_eq!(resolve("docs/a.txt").unwrap(), real_root.join("docs/a.txt"));
+        assert_eq!(
+            resolve("inner/a.txt").unwrap(),
+            real_root.join("docs/a.txt")
+        );
+        // 不存在的部分直接接上
+        assert_eq!(
+            resolve("docs/new/file.txt").unwrap(),
+            real_root.join("docs/new/file.txt")
+        );
+        assert_eq!(resolve("").unwrap(), real_root);
+        for user in [
+            "outer/secret.txt",
+            "outer",
+            "secret.txt",
+            "dangling",
+            "dangling/x",
+            "../outside/secret.txt",
+        ] {
+            assert!(
+                matches!(resolve(user), Err(PathError::Escapes(_))),
+                "{}: {:?}",
+                user,
+                resolve(user)
+            );
+        }
+        // 只做字符串检查的 safe_join 拦不住符号链接
+        assert!(safe_join(&root, Path::new("outer/secret.txt")).is_ok());
+        assert!(matches!(
+            safe_join_resolved(&base.join("nope"), Path::new("a")),
+            Err(PathError::Io(_))
+        ));
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+// 数据管道：Source 产生数据，Transform 逐条加工，Sink 负责输出，每个阶段一个线程，阶段之间用有界通道连接
+// 同一条管道既可以批处理（读完文件就结束），也可以流式运行（跟随不断增长的日志、读 TCP 连接），区别只在 Source：
+// FollowFile 从文件开头读起时先把已有的内容处理完（回填，backfill），读到末尾后继续等待新写入的行
+// 停止是逐级传递的：Source 停下后丢弃通道的发送端，下游读完通道里剩下的数据就会发现通道关闭，收尾后再关闭自己的下游，
+// 所以已经产生的数据不会丢；反过来某个阶段出错退出时丢弃接收端，上游发送失败也会停下
+#[cfg(test)]
+pub(crate) mod pipeline {
+
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+    use std::net::TcpStream;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    // 阶段之间的通道容量，下游处理不过来时上游阻塞，内存占用有上限（背压）
+    const CHANNEL_CAPACITY: usize = 256;
+
+    pub(crate) enum Next<T> {
+        Item(T),
+        // 暂时没有数据（流式数据源），让 Source 所在的线程有机会检查是否该停止
+        Idle,
+        // 数据结束（批处理读完了）
+        End,
+    }
+
+    pub(crate) trait Source: Send + 'static {
+        type Item: Send + 'static;
+
+        // 流式的数据源不能无限期地阻塞，否则停止信号送不到，要定期返回 Next::Idle
+        fn next(&mut self) -> io::Result<Next<Self::Item>>;
+    }
+
+    // 每条输入产生零条或一条输出；finish 在输入结束后调用，聚合类的 Transform 在这里输出结果
+    pub(crate) trait Transform<In>: Send + 'static {
+        type Out: Send + 'static;
+
+        fn apply(&mut self, item: In) -> Option<Self::Out>;
+
+        fn finish(&mut self) -> Vec<Self::Out> {
+            Vec::new()
+        }
+    }
+
+    // 闭包可以直接当作 Transform 使用
+    impl<In, Out, F> Transform<In> for F
+    where
+        F: FnMut(In) -> Option<Out> + Send + 'static,
+        Out: Send + 'static,
+    {
+        type Out = Out;
+
+        fn apply(&mut self, item: In) -> Option<Out> {
+            self(item)
+        }
+    }
+
+    pub(crate) trait Sink<T>: Send + 'static {
+        fn write(&mut self, item: T) -> io::Result<()>;
+
+        // 所有数据写完之后调用，例如刷新缓冲区
+        fn finish(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    type StageThread = thread::JoinHandle<io::Result<()>>;
+
+    // 已经连好的一段管道，输出是 T，可以继续接 Transform 或者接上 Sink 启动
+    pub(crate) struct Stage<T> {
+        receiver: mpsc::Receiver<T>,
+        threads: Vec<StageThread>,
+        stop: Arc<AtomicBool>,
+    }
+
+    pub(crate) fn from_source<S: Source>(mut source: S) -> Stage<S::Item> {
+        let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            while !flag.load(Ordering::SeqCst) {
+                match source.next()? {
+                    Next::Item(item) => {
+                        // 下游已经退出了，没必要继续读
+                        if sender.send(item).is_err() {
+                            break;
+                        }
+                    }
+                    Next::Idle => {}
+                    Next::End => break,
+                }
+            }
+            // 返回时 sender 被丢弃，下游读完剩下的数据后就知道结束了
+            Ok(())
+        });
+        Stage {
+            receiver,
+            threads: vec![thread],
+            stop,
+        }
+    }
+
+    impl<T: Send + 'static> Stage<T> {
+        pub(crate) fn then<X: Transform<T>>(self, mut transform: X) -> Stage<X::Out> {
+            let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+            let input = self.receiver;
+            let thread = thread::spawn(move || {
+                // 上游关闭通道后 for 循环结束
+                for item in input {
+                    if let Some(out) = transform.apply(item) {
+                        if sender.send(out).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                for out in transform.finish() {
+                    if sender.send(out).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            });
+            let mut threads = self.threads;
+            threads.push(thread);
+            Stage {
+                receiver,
+                threads,
+                stop: self.stop,
+            }
+        }
+
+        pub(crate) fn into_sink<K: Sink<T>>(self, mut sink: K) -> Running {
+            let input = self.receiver;
+            let thread = thread::spawn(move || {
+                for item in input {
+                    // 出错时直接返回，接收端被丢弃，上游的发送随之失败
+                    sink.write(item)?;
+                }
+                sink.finish()
+            });
+            let mut threads = self.threads;
+            threads.push(thread);
+            Running {
+                threads,
+                stop: self.stop,
+            }
+        }
+    }
+
+    // 正在运行的管道
+    pub(crate) struct Running {
+        threads: Vec<StageThread>,
+        stop: Arc<AtomicBool>,
+    }
+
+    impl Running {
+        // 只通知 Source 停止，已经在管道里的数据仍然会流到 Sink
+        pub(crate) fn stop(&self) {
+            self.stop.store(true, Ordering::SeqCst);
+        }
+
+        // 等待所有阶段结束，返回最靠上游的那个错误
+        pub(crate) fn join(self) -> io::Result<()> {
+            let mut result = Ok(());
+            for thread in self.threads {
+                let outcome = thread
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("pipeline stage panicked")));
+                if result.is_ok() {
+                    result = outcome;
+                }
+            }
+            result
+        }
+    }
+
+    // 批处理：按行读取，读到末尾就结束
+    pub(crate) struct Lines<R> {
+        reader: R,
+    }
+
+    impl Lines<BufReader<File>> {
+        pub(crate) fn open(path: &Path) -> io::Result<Self> {
+            Ok(Lines {
+                reader: BufReader::new(File::open(path)?),
+            })
+        }
+    }
+
+    impl<R: BufRead + Send + 'static> Source for Lines<R> {
+        type Item = String;
+
+        fn next(&mut self) -> io::Result<Next<String>> {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(Next::End);
+            }
+            Ok(Next::Item(line.trim_end_matches(['\r', '\n']).to_string()))
+        }
+    }
+
+    // 流式：像 tail -f 一样跟随文件，读到末尾后每隔 poll 检查一次有没有新内容
+    // 写入方可能只写了半行，没有换行符的部分先攒着，等这一行写完整再输出
+    pub(crate) struct FollowFile {
+        reader: BufReader<File>,
+        partial: String,
+        poll: Duration,
+    }
+
+    impl FollowFile {
+        // from_start 为 true 时先处理文件里已有的内容（回填），否则只处理之后追加的行
+        pub(crate) fn open(
+            path: &Path,
+            from_start: bool,
+            poll: Duration,
+        ) -> io::Result<FollowFile> {
+            let mut file = File::open(path)?;
+            if !from_start {
+                file.seek(SeekFrom::End(0))?;
+            }
+            Ok(FollowFile {
+                reader: BufReader::new(file),
+                partial: String::new(),
+                poll,
+            })
+        }
+    }
+
+    impl Source for FollowFile {
+        type Item = String;
+
+        fn next(&mut self) -> io::Result<Next<String>> {
+            // read_line 在文件末尾返回 0，不会阻塞；之后文件变长了，再读就能读到新内容
+            if self.reader.read_line(&mut self.partial)? == 0 || !self.partial.ends_with('\n') {
+                thread::sleep(self.poll);
+                return Ok(Next::Idle);
+            }
+            let line = self.partial.trim_end_matches(['\r', '\n']).to_string();
+            self.partial.clear();
+            Ok(Next::Item(line))
+        }
+    }
+
+    // 流式：从 TCP 连接按行读取，对端关闭连接时结束
+    // 读超时让阻塞的 read 定期返回，Source 线程借机检查停止信号
+    pub(crate) struct TcpLines {
+        reader: BufReader<TcpStream>,
+        partial: String,
+    }
+
+    impl TcpLines {
+        pub(crate) fn new(stream: TcpStream, poll: Duration) -> io::Result<TcpLines> {
+            stream.set_read_timeout(Some(poll))?;
+            Ok(TcpLines {
+                reader: BufReader::new(stream),
+                partial: String::new(),
+            })
+        }
+    }
+
+    impl Source for TcpLines {
+        type Item = String;
+
+        fn next(&mut self) -> io::Result<Next<String>> {
+            match self.reader.read_line(&mut self.partial) {
+                // 连接关闭；最后一行可能没有换行符
+                Ok(0) if self.partial.is_empty() => Ok(Next::End),
+                Ok(_) => {
+                    let line = self.partial.trim_end_matches(['\r', '\n']).to_string();
+                    self.partial.clear();
+                    Ok(Next::Item(line))
+                }
+                // 超时时已经读到的半行留在 partial 里，下次接着读
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    Ok(Next::Idle)
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    // 收集到共享的 Vec 里，管道运行中也可以从外面查看
+    pub(crate) struct Collect<T>(pub(crate) Arc<Mutex<Vec<T>>>);
+
+    impl<T: Send + 'static> Sink<T> for Collect<T> {
+        fn write(&mut self, item: T) -> io::Result<()> {
+            self.0.lock().unwrap().push(item);
+            Ok(())
+        }
+    }
+
+    // 每条数据写成一行
+    pub(crate) struct WriteLines<W>(pub(crate) W);
+
+    impl<T: std::fmt::Display, W: Write + Send + 'static> Sink<T> for WriteLines<W> {
+        fn write(&mut self, item: T) -> io::Result<()> {
+            writeln!(self.0, "{}", item)
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::pipeline::*;
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, BufWriter, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const POLL: Duration = Duration::from_millis(5);
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("pipeline-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // 访问日志里的 path 字段
+    fn path_of(line: String) -> Option<String> {
+        line.split(' ')
+            .find_map(|field| field.strip_prefix("path="))
+            .map(String::from)
+    }
+
+    // 聚合类的 Transform：统计每个路径的请求数，输入结束后按路径顺序输出
+    #[derive(Default)]
+    struct CountByPath(BTreeMap<String, usize>);
+
+    impl Transform<String> for CountByPath {
+        type Out = String;
+
+        fn apply(&mut self, path: String) -> Option<String> {
+            *self.0.entry(path).or_insert(0) += 1;
+            None
+        }
+
+        fn finish(&mut self) -> Vec<String> {
+            self.0
+                .iter()
+                .map(|(path, n)| format!("{} {}", path, n))
+                .collect()
+        }
+    }
+
+    fn wait_for(collected: &Arc<Mutex<Vec<String>>>, len: usize) {
+        let started = Instant::now();
+        while collected.lock().unwrap().len() < len {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(POLL);
+        }
+    }
+
+    #[test]
+    fn batch_over_a_file() {
+        let log = temp_file(
+            "batch.log",
+            "worker=0 method=GET path=/ status=200\nnot a log line\nworker=1 method=GET path=/a status=200\nworker=0 method=GET path=/ status=304\n",
+        );
+        let output = temp_file("batch.out", "");
+        let running = from_source(Lines::open(&log).unwrap())
+            .then(path_of)
+            .then(CountByPath::default())
+            .into_sink(WriteLines(BufWriter::new(File::create(&output).unwrap())));
+        // 读完文件后整条管道自己结束，finish 把统计结果送到 Sink，Sink 的 finish 刷新缓冲区
+        running.join().unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "/ 2\n/a 1\n");
+        fs::remove_file(log).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn follows_a_growing_file() {
+        let log = temp_file("follow.log", "path=/old\n");
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let running = from_source(FollowFile::open(&log, true, POLL).unwrap())
+            .then(path_of)
+            .into_sink(Collect(Arc::clone(&collected)));
+        // 回填已有的内容
+        wait_for(&collected, 1);
+
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        // 先写半行，写完整之前不会输出
+        file.write_all(b"path=/ne").unwrap();
+        thread::sleep(POLL * 4);
+        assert_eq!(collected.lock().unwrap().len(), 1);
+        file.write_all(b"w\npath=/newer\n").unwrap();
+        wait_for(&collected, 3);
+
+        // 流式的管道不会自己结束，stop 之后各个阶段依次退出
+        running.stop();
+        running.join().unwrap();
+        assert_eq!(*collected.lock().unwrap(), ["/old", "/new", "/newer"]);
+
+        // 不回填时只处理打开之后追加的行
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let running = from_source(FollowFile::open(&log, false, POLL).unwrap())
+            .into_sink(Collect(Arc::clone(&collected)));
+        file.write_all(b"path=/latest\n").unwrap();
+        wait_for(&collected, 1);
+        running.stop();
+        running.join().unwrap();
+        assert_eq!(*collected.lock().unwrap(), ["path=/latest"]);
+        fs::remove_file(log).unwrap();
+    }
+
+    #[test]
+    fn streams_from_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let running = from_source(TcpLines::new(stream, POLL).unwrap())
+            .then(|line: String| Some(line.to_uppercase()))
+            .into_sink(Collect(Arc::clone(&collected)));
+        client.write_all(b"first\nsec").unwrap();
+        wait_for(&collected, 1);
+        // 半行在读超时之后仍然保留着
+        thread::sleep(POLL * 4);
+        client.write_all(b"ond\nlast without newline").unwrap();
+        // 对端关闭连接，管道自己结束
+        drop(client);
+        running.join().unwrap();
+        assert_eq!(
+            *collected.lock().unwrap(),
+            ["FIRST", "SECOND", "LAST WITHOUT NEWLINE"]
+        );
+    }
+
+    // 写到第 n 条时失败的 Sink
+    struct FailAfter(usize);
+
+    impl Sink<String> for FailAfter {
+        fn write(&mut self, _: String) -> io::Result<()> {
+            if self.0 == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            self.0 -= 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_errors_stop_the_pipeline() {
+        // 一个永远不会结束的数据源，Sink 出错后它也要停下来
+        let log = temp_file("error.log", &"path=/\n".repeat(1000));
+        let running = from_source(FollowFile::open(&log, true, POLL).unwrap())
+            .then(path_of)
+            .into_sink(FailAfter(10));
+        let err = running.join().unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+        fs::remove_file(log).unwrap();
+    }
+}
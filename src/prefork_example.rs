@@ -0,0 +1,307 @@
+// 预派生（pre-fork）多进程服务器：父进程绑定监听 socket 后 fork 出 N 个 worker 进程，worker 在同一个 socket 上 accept
+// 和线程池相比，进程之间不共享内存，一个 worker 崩溃不会影响其他 worker，由父进程（supervisor）负责把它重新拉起来
+#[cfg(all(test, unix))]
+mod tests {
+
+    use crate::http_client_example::client;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // 把数字写成十进制，不分配内存
+    fn format_u32(mut n: u32, buf: &mut [u8; 10]) -> &[u8] {
+        let mut i = buf.len();
+        loop {
+            i -= 1;
+            buf[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                return &buf[i..];
+            }
+        }
+    }
+
+    // 把若干片段拼接到 out 中，返回总长度
+    fn concat(out: &mut [u8], parts: &[&[u8]]) -> usize {
+        let mut len = 0;
+        for part in parts {
+            out[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        len
+    }
+
+    // 子进程里运行的循环。测试进程是多线程的，fork 只复制调用 fork 的那个线程，
+    // 其他线程可能正持有内存分配器的锁，所以子进程里不能分配内存，只用栈上的缓冲区和 libc 调用
+    fn worker_loop(listener: RawFd) -> ! {
+        // SAFETY: getpid 没有参数，总是成功
+        let pid = unsafe { libc::getpid() } as u32;
+        let mut pid_buf = [0; 10];
+        let body = format_u32(pid, &mut pid_buf);
+        let mut len_buf = [0; 10];
+        let content_length = format_u32(body.len() as u32, &mut len_buf);
+        let mut response = [0; 128];
+        let response_len = concat(
+            &mut response,
+            &[
+                b"HTTP/1.1 200 OK\r\nContent-Length: ",
+                content_length,
+                b"\r\n\r\n",
+                body,
+            ],
+        );
+
+        loop {
+            // SAFETY: 不需要对端地址，地址参数传空指针
+            let fd = unsafe { libc::accept(listener, std::ptr::null_mut(), std::ptr::null_mut()) };
+            if fd < 0 {
+                continue;
+            }
+
+            let mut request = [0u8; 1024];
+            let mut filled = 0;
+            while filled < request.len() && !request[..filled].windows(4).any(|w| w == b"\r\n\r\n")
+            {
+                // SAFETY: 写入范围不超过 request 剩余的长度
+                let n = unsafe {
+                    libc::read(
+                        fd,
+                        request[filled..].as_mut_ptr() as *mut libc::c_void,
+                        request.len() - filled,
+                    )
+                };
+                if n <= 0 {
+                    break;
+                }
+                filled += n as usize;
+            }
+
+            // 模拟 worker 崩溃：进程被信号杀死，正在处理的连接随之断开
+            if request[..filled].starts_with(b"GET /crash ") {
+                // SAFETY: raise 只是给自己发信号
+                unsafe {
+                    libc::raise(libc::SIGKILL);
+                }
+            }
+
+            // SAFETY: response 的前 response_len 个字节都已初始化；fd 由 accept 返回，只关闭一次
+            unsafe {
+                libc::write(fd, response.as_ptr() as *const libc::c_void, response_len);
+                libc::close(fd);
+            }
+        }
+    }
+
+    // 描述符编号的上限，在 fork 之前取好，子进程里只用 close
+    fn max_fd() -> RawFd {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: limit 是栈上的有效变量
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+            || limit.rlim_cur == libc::RLIM_INFINITY
+        {
+            return 1024;
+        }
+        limit.rlim_cur.min(RawFd::MAX as libc::rlim_t) as RawFd
+    }
+
+    // 子进程继承了测试进程里所有打开的描述符：别的测试正在用的 socket、管道、临时文件。
+    // worker 一直开着它们的话，那些测试关掉连接后对端收不到 EOF，文件也一直被占着，所以除了 listener 全部关掉，
+    // 标准输入输出也不例外，worker 不打印任何东西
+    fn close_inherited_fds(listener: RawFd, max_fd: RawFd) {
+        // close_range 一次关掉一段，不用逐个 close 到上限；老内核不支持时退回到下面的循环
+        #[cfg(target_os = "linux")]
+        {
+            // SAFETY: close_range 只关闭描述符，两段都不包括 listener
+            let closed = unsafe {
+                (listener == 0 || libc::syscall(libc::SYS_close_range, 0, listener - 1, 0) == 0)
+                    && libc::syscall(libc::SYS_close_range, listener + 1, libc::c_uint::MAX, 0) == 0
+            };
+            if closed {
+                return;
+            }
+        }
+        for fd in (0..max_fd).filter(|&fd| fd != listener) {
+            // SAFETY: 子进程里没有别的代码在用这些描述符，没打开的会返回 EBADF，忽略即可
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+
+    fn spawn_worker(listener: RawFd) -> io::Result<libc::pid_t> {
+        let max_fd = max_fd();
+        // SAFETY: 子进程只运行 worker_loop，永远不会返回到调用方，也不会运行测试框架的代码
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => {
+                close_inherited_fds(listener, max_fd);
+                worker_loop(listener)
+            }
+            pid => Ok(pid),
+        }
+    }
+
+    struct Supervisor {
+        listener: TcpListener,
+        workers: Vec<libc::pid_t>,
+        // 重启过多少个 worker
+        restarts: usize,
+    }
+
+    impl Supervisor {
+        fn start(workers: usize) -> io::Result<Supervisor> {
+            // 监听 socket 在 fork 之前创建，所有子进程继承同一个描述符，内核在它们之间分配新连接
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let fd = listener.as_raw_fd();
+            let workers = (0..workers)
+                .map(|_| spawn_worker(fd))
+                .collect::<io::Result<_>>()?;
+            Ok(Supervisor {
+                listener,
+                workers,
+                restarts: 0,
+            })
+        }
+
+        fn addr(&self) -> SocketAddr {
+            self.listener.local_addr().unwrap()
+        }
+
+        // 不阻塞地回收已经退出的 worker，并派生新的进程顶替它，返回退出的进程号和原因
+        fn supervise(&mut self) -> io::Result<Option<(libc::pid_t, String)>> {
+            let mut status = 0;
+            // 逐个检查自己的 worker，而不是用 waitpid(-1, ..)，以免回收了同一进程里其他代码创建的子进程
+            // SAFETY: status 是栈上的有效变量；WNOHANG 表示子进程还在运行时立即返回 0
+            let Some(index) = self.workers.iter().position(
+                |&worker| unsafe { libc::waitpid(worker, &mut status, libc::WNOHANG) } > 0,
+            ) else {
+                return Ok(None);
+            };
+            let pid = self.workers[index];
+
+            let reason = if libc::WIFSIGNALED(status) {
+                format!("killed by signal {}", libc::WTERMSIG(status))
+            } else {
+                format!("exited with status {}", libc::WEXITSTATUS(status))
+            };
+            self.workers[index] = spawn_worker(self.listener.as_raw_fd())?;
+            self.restarts += 1;
+            Ok(Some((pid, reason)))
+        }
+
+        // 父进程的主循环：定期检查子进程，直到 deadline 或者有 worker 被重启
+        fn supervise_until(
+            &mut self,
+            deadline: Duration,
+        ) -> io::Result<Option<(libc::pid_t, String)>> {
+            let started = Instant::now();
+            while started.elapsed() < deadline {
+                if let Some(exited) = self.supervise()? {
+                    return Ok(Some(exited));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(None)
+        }
+    }
+
+    impl Drop for Supervisor {
+        fn drop(&mut self) {
+            for &pid in &self.workers {
+                // SAFETY: pid 是我们 fork 出来的子进程，kill 之后用 waitpid 回收，避免留下僵尸进程
+                unsafe {
+                    libc::kill(pid, libc::SIGTERM);
+                    libc::waitpid(pid, std::ptr::null_mut(), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn format_numbers_without_allocating() {
+        let mut buf = [0; 10];
+        assert_eq!(format_u32(0, &mut buf), b"0");
+        assert_eq!(format_u32(4294967295, &mut buf), b"4294967295");
+        let mut out = [0; 16];
+        let len = concat(&mut out, &[b"ab", b"", b"cde"]);
+        assert_eq!(&out[..len], b"abcde");
+    }
+
+    // worker 打开的描述符，子进程刚 fork 出来时可能还没关完，等一会儿
+    #[cfg(target_os = "linux")]
+    fn open_fds(pid: libc::pid_t, expected: &[RawFd]) -> Vec<RawFd> {
+        let started = Instant::now();
+        loop {
+            let mut fds: Vec<RawFd> = fs::read_dir(format!("/proc/{}/fd", pid))
+                .unwrap()
+                .map(|entry| {
+                    entry
+                        .unwrap()
+                        .file_name()
+                        .to_str()
+                        .unwrap()
+                        .parse()
+                        .unwrap()
+                })
+                .collect();
+            fds.sort();
+            if fds == expected || started.elapsed() > Duration::from_secs(5) {
+                return fds;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn prefork_workers_share_listener_and_restart() {
+        // 测试进程里别的描述符不会泄漏到 worker 里
+        let unrelated = fs::File::open("Cargo.toml").unwrap();
+        let mut supervisor = Supervisor::start(2).unwrap();
+        let addr = supervisor.addr();
+        let original: HashSet<_> = supervisor.workers.iter().copied().collect();
+        #[cfg(target_os = "linux")]
+        for &pid in &supervisor.workers {
+            let listener = supervisor.listener.as_raw_fd();
+            assert_eq!(open_fds(pid, &[listener]), [listener]);
+        }
+        drop(unrelated);
+
+        // 每个响应体是处理它的 worker 的进程号，它们都来自同一组子进程
+        for _ in 0..10 {
+            let response = client::get(addr, "/").unwrap();
+            assert_eq!(response.status, 200);
+            let pid: libc::pid_t = response.text().parse().unwrap();
+            assert!(original.contains(&pid));
+        }
+
+        // 让一个 worker 崩溃：客户端读到连接关闭，拿不到响应
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /crash HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.is_empty());
+
+        let (pid, reason) = supervisor
+            .supervise_until(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert!(original.contains(&pid));
+        assert_eq!(reason, format!("killed by signal {}", libc::SIGKILL));
+        assert_eq!(supervisor.restarts, 1);
+        assert!(!supervisor.workers.contains(&pid));
+
+        // 新的 worker 接替了崩溃的那个，服务不受影响
+        for _ in 0..10 {
+            let response = client::get(addr, "/").unwrap();
+            let pid: libc::pid_t = response.text().parse().unwrap();
+            assert!(supervisor.workers.contains(&pid));
+        }
+    }
+}
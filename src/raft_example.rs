@@ -0,0 +1,630 @@
+// 简化的 Raft：领导者选举和日志复制，没有快照和成员变更
+// 节点是纯粹的状态机：收到消息或者时钟前进时改变状态，把要发的消息放进 outbox，不直接碰网络和时间
+// 这样整个集群可以在一个确定性的模拟器里运行：时钟是整数 tick，网络延迟、丢包、节点崩溃都由一个固定种子的随机数决定，
+// 出了问题可以原样重现。模拟器每一步都检查 Raft 的两条安全性：同一任期最多一个 leader，已提交的日志在所有节点上一致
+#[cfg(test)]
+pub(crate) mod raft {
+
+    use std::collections::HashMap;
+    use std::mem;
+
+    pub(crate) type NodeId = usize;
+
+    // 选举超时在这个范围里随机选，避免几个节点总是同时发起选举、互相瓜分选票
+    const ELECTION_TIMEOUT: (u64, u64) = (10, 20);
+    // leader 发心跳的间隔，要明显短于选举超时
+    const HEARTBEAT: u64 = 3;
+    // 网络延迟，单位 tick
+    const LATENCY: (u64, u64) = (1, 4);
+
+    // 线性同余发生器，同一个种子每次运行的结果都一样
+    pub(crate) struct Lcg(pub(crate) u64);
+
+    impl Lcg {
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+
+        pub(crate) fn range(&mut self, (low, high): (u64, u64)) -> u64 {
+            low + self.next() % (high - low)
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct Entry {
+        pub(crate) term: u64,
+        pub(crate) command: String,
+    }
+
+    // 日志下标从 1 开始，0 表示日志开头之前的位置，它的任期是 0
+    #[derive(Clone, Debug)]
+    enum Message {
+        RequestVote {
+            term: u64,
+            last_index: usize,
+            last_term: u64,
+        },
+        Vote {
+            term: u64,
+            granted: bool,
+        },
+        // 心跳就是不带日志的 AppendEntries
+        AppendEntries {
+            term: u64,
+            prev_index: usize,
+            prev_term: u64,
+            entries: Vec<Entry>,
+            commit: usize,
+        },
+        // 成功时 index 是已经和 leader 一致的最后一条；失败时是建议 leader 下次从哪一条之后重试
+        AppendReply {
+            term: u64,
+            success: bool,
+            index: usize,
+        },
+    }
+
+    struct Envelope {
+        from: NodeId,
+        to: NodeId,
+        message: Message,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Role {
+        Follower,
+        Candidate,
+        Leader,
+    }
+
+    pub(crate) struct Node {
+        id: NodeId,
+        size: usize,
+        // 持久状态，重启后还在。真实的实现要先写盘再回复消息
+        term: u64,
+        voted_for: Option<NodeId>,
+        log: Vec<Entry>,
+        // 易失状态，重启后从头开始
+        role: Role,
+        commit: usize,
+        votes: usize,
+        // follower 和 candidate 是选举超时的时刻，leader 是下一次心跳的时刻
+        deadline: u64,
+        // leader 记录每个节点下一条要发的日志和已知一致的最后一条
+        next_index: Vec<usize>,
+        match_index: Vec<usize>,
+        outbox: Vec<Envelope>,
+    }
+
+    impl Node {
+        fn new(id: NodeId, size: usize, now: u64, rng: &mut Lcg) -> Node {
+            let mut node = Node {
+                id,
+                size,
+                term: 0,
+                voted_for: None,
+                log: Vec::new(),
+                role: Role::Follower,
+                commit: 0,
+                votes: 0,
+                deadline: 0,
+                next_index: Vec::new(),
+                match_index: Vec::new(),
+                outbox: Vec::new(),
+            };
+            node.reset_timer(now, rng);
+            node
+        }
+
+        fn reset_timer(&mut self, now: u64, rng: &mut Lcg) {
+            self.deadline = now + rng.range(ELECTION_TIMEOUT);
+        }
+
+        fn term_at(&self, index: usize) -> u64 {
+            if index == 0 {
+                0
+            } else {
+                self.log[index - 1].term
+            }
+        }
+
+        fn peers(&self) -> impl Iterator<Item = NodeId> {
+            let (id, size) = (self.id, self.size);
+            (0..size).filter(move |&peer| peer != id)
+        }
+
+        fn send(&mut self, to: NodeId, message: Message) {
+            self.outbox.push(Envelope {
+                from: self.id,
+                to,
+                message,
+            });
+        }
+
+        // 看到更大的任期就退回 follower，并且在新任期里还没有投过票
+        fn step_down(&mut self, term: u64) {
+            if term > self.term {
+                self.term = term;
+                self.voted_for = None;
+            }
+            self.role = Role::Follower;
+        }
+
+        fn tick(&mut self, now: u64, rng: &mut Lcg) {
+            if now < self.deadline {
+                return;
+            }
+            if self.role == Role::Leader {
+                self.broadcast();
+                self.deadline = now + HEARTBEAT;
+                return;
+            }
+            // 超时没有收到 leader 的消息：任期加一，投自己一票，向其他节点拉票
+            self.term += 1;
+            self.role = Role::Candidate;
+            self.voted_for = Some(self.id);
+            self.votes = 1;
+            self.reset_timer(now, rng);
+            let (last_index, last_term) = (self.log.len(), self.term_at(self.log.len()));
+            for peer in self.peers() {
+                self.send(
+                    peer,
+                    Message::RequestVote {
+                        term: self.term,
+                        last_index,
+                        last_term,
+                    },
+                );
+            }
+        }
+
+        fn handle(&mut self, now: u64, rng: &mut Lcg, from: NodeId, message: Message) {
+            match message {
+                Message::RequestVote {
+                    term,
+                    last_index,
+                    last_term,
+                } => {
+                    if term > self.term {
+                        self.step_down(term);
+                    }
+                    // 只投给日志至少和自己一样新的候选人：先比最后一条的任期，再比长度
+                    // 这保证了当选的 leader 一定拥有所有已提交的日志
+                    let up_to_date =
+                        (last_term, last_index) >= (self.term_at(self.log.len()), self.log.len());
+                    let granted = term == self.term
+                        && self.voted_for.is_none_or(|voted| voted == from)
+                        && up_to_date;
+                    if granted {
+                        self.voted_for = Some(from);
+                        self.reset_timer(now, rng);
+                    }
+                    self.send(
+                        from,
+                        Message::Vote {
+                            term: self.term,
+                            granted,
+                        },
+                    );
+                }
+                Message::Vote { term, granted } => {
+                    if term > self.term {
+                        self.step_down(term);
+                    } else if self.role == Role::Candidate && term == self.term && granted {
+                        self.votes += 1;
+                        if self.votes * 2 > self.size {
+                            self.become_leader(now);
+                        }
+                    }
+                }
+                Message::AppendEntries {
+                    term,
+                    prev_index,
+                    prev_term,
+                    entries,
+                    commit,
+                } => {
+                    if term < self.term {
+                        return self.send(
+                            from,
+                            Message::AppendReply {
+                                term: self.term,
+                                success: false,
+                                index: 0,
+                            },
+                        );
+                    }
+                    self.step_down(term);
+                    self.reset_timer(now, rng);
+                    // 前一条对不上：让 leader 往前退，最多退到自己日志的末尾
+                    if prev_index > self.log.len() || self.term_at(prev_index) != prev_term {
+                        let index = self.log.len().min(prev_index - 1);
+                        return self.send(
+                            from,
+                            Message::AppendReply {
+                                term: self.term,
+                                success: false,
+                                index,
+                            },
+                        );
+                    }
+                    let matched = prev_index + entries.len();
+                    for (i, entry) in entries.into_iter().enumerate() {
+                        let index = prev_index + 1 + i;
+                        if index <= self.log.len() {
+                            if self.log[index - 1].term == entry.term {
+                                continue;
+                            }
+                            // 冲突的日志以及它后面的都是没有提交的旧 leader 留下的，删掉
+                            assert!(index > self.commit, "truncating a committed entry");
+                            self.log.truncate(index - 1);
+                        }
+                        self.log.push(entry);
+                    }
+                    self.commit = self.commit.max(commit.min(matched));
+                    self.send(
+                        from,
+                        Message::AppendReply {
+                            term: self.term,
+                            success: true,
+                            index: matched,
+                        },
+                    );
+                }
+                Message::AppendReply {
+                    term,
+                    success,
+                    index,
+                } => {
+                    if term > self.term {
+                        return self.step_down(term);
+                    }
+                    if self.role != Role::Leader || term != self.term {
+                        return;
+                    }
+                    if success {
+                        self.match_index[from] = self.match_index[from].max(index);
+                        self.next_index[from] = self.match_index[from] + 1;
+                        self.advance_commit();
+                    } else {
+                        self.next_index[from] = (index + 1).min(self.next_index[from] - 1).max(1);
+                        self.replicate(from);
+                    }
+                }
+            }
+        }
+
+        fn become_leader(&mut self, now: u64) {
+            self.role = Role::Leader;
+            self.next_index = vec![self.log.len() + 1; self.size];
+            self.match_index = vec![0; self.size];
+            self.match_index[self.id] = self.log.len();
+            self.broadcast();
+            self.deadline = now + HEARTBEAT;
+        }
+
+        fn broadcast(&mut self) {
+            for peer in self.peers() {
+                self.replicate(peer);
+            }
+        }
+
+        // 从 next_index 开始把后面的日志全部发过去
+        fn replicate(&mut self, peer: NodeId) {
+            let prev_index = self.next_index[peer] - 1;
+            self.send(
+                peer,
+                Message::AppendEntries {
+                    term: self.term,
+                    prev_index,
+                    prev_term: self.term_at(prev_index),
+                    entries: self.log[prev_index..].to_vec(),
+                    commit: self.commit,
+                },
+            );
+        }
+
+        // 多数节点都有了的日志就提交。只按当前任期的日志计数：旧任期的日志即使已经在多数节点上，
+        // 也可能被之后的 leader 覆盖，要等当前任期的一条日志提交时才跟着一起提交
+        fn advance_commit(&mut self) {
+            for index in (self.commit + 1..=self.log.len()).rev() {
+                if self.log[index - 1].term != self.term {
+                    break;
+                }
+                let replicas = self.match_index.iter().filter(|&&m| m >= index).count();
+                if replicas * 2 > self.size {
+                    self.commit = index;
+                    break;
+                }
+            }
+        }
+
+        // 只有 leader 接受新的命令，返回这条日志的下标。日志在下一次心跳时发出去
+        fn propose(&mut self, command: &str) -> Option<usize> {
+            if self.role != Role::Leader {
+                return None;
+            }
+            self.log.push(Entry {
+                term: self.term,
+                command: command.to_string(),
+            });
+            self.match_index[self.id] = self.log.len();
+            Some(self.log.len())
+        }
+
+        // 崩溃后重启：持久状态还在，其他的从头开始
+        fn restart(&mut self, now: u64, rng: &mut Lcg) {
+            self.role = Role::Follower;
+            self.commit = 0;
+            self.votes = 0;
+            self.outbox.clear();
+            self.reset_timer(now, rng);
+        }
+    }
+
+    pub(crate) struct Cluster {
+        nodes: Vec<Node>,
+        alive: Vec<bool>,
+        now: u64,
+        rng: Lcg,
+        // 在路上的消息和它们到达的时刻
+        network: Vec<(u64, Envelope)>,
+        // 每条消息被丢掉的概率，百分比
+        pub(crate) drop_percent: u64,
+        // 用来检查安全性：每个任期的 leader，以及目前为止所有节点提交过的最长日志
+        leaders: HashMap<u64, NodeId>,
+        committed: Vec<Entry>,
+    }
+
+    impl Cluster {
+        pub(crate) fn new(size: usize, seed: u64) -> Cluster {
+            let mut rng = Lcg(seed);
+            let nodes = (0..size)
+                .map(|id| Node::new(id, size, 0, &mut rng))
+                .collect();
+            Cluster {
+                nodes,
+                alive: vec![true; size],
+                now: 0,
+                rng,
+                network: Vec::new(),
+                drop_percent: 0,
+                leaders: HashMap::new(),
+                committed: Vec::new(),
+            }
+        }
+
+        // 时钟前进一个 tick：各节点处理超时，到达的消息送给收件人，新发出的消息放进网络
+        pub(crate) fn step(&mut self) {
+            self.now += 1;
+            let now = self.now;
+            for (node, &alive) in self.nodes.iter_mut().zip(&self.alive) {
+                if alive {
+                    node.tick(now, &mut self.rng);
+                }
+            }
+            let (due, later) = mem::take(&mut self.network)
+                .into_iter()
+                .partition(|(at, _)| *at <= now);
+            self.network = later;
+            for (_, envelope) in due {
+                // 发给已经崩溃的节点的消息丢掉
+                if self.alive[envelope.to] {
+                    let node = &mut self.nodes[envelope.to];
+                    node.handle(now, &mut self.rng, envelope.from, envelope.message);
+                }
+            }
+            for node in &mut self.nodes {
+                for envelope in mem::take(&mut node.outbox) {
+                    if self.rng.next() % 100 >= self.drop_percent {
+                        let at = now + self.rng.range(LATENCY);
+                        self.network.push((at, envelope));
+                    }
+                }
+            }
+            self.check();
+        }
+
+        pub(crate) fn run(&mut self, ticks: u64) {
+            for _ in 0..ticks {
+                self.step();
+            }
+        }
+
+        fn check(&mut self) {
+            for node in self.nodes.iter().filter(|node| node.role == Role::Leader) {
+                let leader = *self.leaders.entry(node.term).or_insert(node.id);
+                assert_eq!(leader, node.id, "two leaders in term {}", node.term);
+            }
+            for node in &self.nodes {
+                let committed = &node.log[..node.commit];
+                let common = committed.len().min(self.committed.len());
+                assert_eq!(
+                    committed[..common],
+                    self.committed[..common],
+                    "node {} committed a different log",
+                    node.id
+                );
+                if committed.len() > self.committed.len() {
+                    self.committed = committed.to_vec();
+                }
+            }
+        }
+
+        // 还活着的 leader 里任期最大的那个；旧任期的 leader 可能还没发现自己已经过时
+        pub(crate) fn leader(&self) -> Option<NodeId> {
+            self.nodes
+                .iter()
+                .filter(|node| self.alive[node.id] && node.role == Role::Leader)
+                .max_by_key(|node| node.term)
+                .map(|node| node.id)
+        }
+
+        pub(crate) fn propose(&mut self, command: &str) -> Option<usize> {
+            let leader = self.leader()?;
+            self.nodes[leader].propose(command)
+        }
+
+        pub(crate) fn kill(&mut self, id: NodeId) {
+            self.alive[id] = false;
+        }
+
+        pub(crate) fn restart(&mut self, id: NodeId) {
+            self.nodes[id].restart(self.now, &mut self.rng);
+            self.alive[id] = true;
+        }
+
+        pub(crate) fn is_alive(&self, id: NodeId) -> bool {
+            self.alive[id]
+        }
+
+        pub(crate) fn term(&self, id: NodeId) -> u64 {
+            self.nodes[id].term
+        }
+
+        pub(crate) fn role(&self, id: NodeId) -> Role {
+            self.nodes[id].role
+        }
+
+        // 某个节点已经提交的命令
+        pub(crate) fn committed(&self, id: NodeId) -> Vec<&str> {
+            let node = &self.nodes[id];
+            node.log[..node.commit]
+                .iter()
+                .map(|entry| entry.command.as_str())
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::raft::*;
+
+    const SIZE: usize = 5;
+
+    #[test]
+    fn elects_a_leader_and_replicates() {
+        let mut cluster = Cluster::new(SIZE, 1);
+        cluster.run(60);
+        let leader = cluster.leader().expect("no leader elected");
+        let term = cluster.term(leader);
+        for id in 0..SIZE {
+            assert_eq!(cluster.term(id), term);
+            if id != leader {
+                assert_eq!(cluster.role(id), Role::Follower);
+            }
+        }
+        let commands: Vec<String> = (0..5).map(|i| format!("set x {}", i)).collect();
+        for (i, command) in commands.iter().enumerate() {
+            assert_eq!(cluster.propose(command), Some(i + 1));
+        }
+        cluster.run(20);
+        for id in 0..SIZE {
+            assert_eq!(cluster.committed(id), commands);
+        }
+    }
+
+    #[test]
+    fn leader_failover_and_catch_up() {
+        let mut cluster = Cluster::new(SIZE, 2);
+        cluster.run(60);
+        let old = cluster.leader().unwrap();
+        cluster.propose("a");
+        cluster.run(20);
+        assert_eq!(cluster.committed(old), ["a"]);
+
+        // leader 和另一个节点崩溃，剩下的三个仍然是多数，选出新的 leader 继续提交
+        cluster.kill(old);
+        let other = (old + 1) % SIZE;
+        cluster.kill(other);
+        cluster.run(60);
+        let new = cluster.leader().expect("no leader after failover");
+        assert_ne!(new, old);
+        assert!(cluster.term(new) > cluster.term(old));
+        cluster.propose("b");
+        cluster.run(20);
+        assert_eq!(cluster.committed(new), ["a", "b"]);
+
+        // 再崩溃一个就不够多数了：leader 还能接收命令，但是提交不了
+        let third = (0..SIZE)
+            .find(|&id| cluster.is_alive(id) && id != new)
+            .unwrap();
+        cluster.kill(third);
+        cluster.propose("c");
+        cluster.run(40);
+        assert_eq!(cluster.committed(new), ["a", "b"]);
+
+        // 节点重启后从新的 leader 那里补齐日志，"c" 也终于提交了
+        for id in [old, other, third] {
+            cluster.restart(id);
+        }
+        cluster.run(100);
+        let leader = cluster.leader().unwrap();
+        cluster.propose("d");
+        cluster.run(40);
+        let expected = cluster.committed(leader);
+        assert!(expected.starts_with(&["a", "b"]) && expected.ends_with(&["d"]));
+        for id in 0..SIZE {
+            assert_eq!(cluster.committed(id), expected);
+        }
+    }
+
+    // 随机的崩溃、重启和丢包下运行很久，模拟器每一步都检查安全性；最后恢复所有节点，日志收敛成同一份
+    #[test]
+    fn single_committed_log_under_random_failures() {
+        for seed in 0..5 {
+            let mut cluster = Cluster::new(SIZE, seed);
+            let mut chaos = Lcg(seed + 100);
+            cluster.drop_percent = 10;
+            let mut proposed = 0;
+            for tick in 0..3000 {
+                if tick % 50 == 0 {
+                    let id = (chaos.next() % SIZE as u64) as usize;
+                    let dead = (0..SIZE).filter(|&id| !cluster.is_alive(id)).count();
+                    if cluster.is_alive(id) && dead < 2 {
+                        cluster.kill(id);
+                    } else if !cluster.is_alive(id) {
+                        cluster.restart(id);
+                    }
+                }
+                if tick % 7 == 0 && cluster.propose(&format!("cmd {}", proposed)).is_some() {
+                    proposed += 1;
+                }
+                cluster.step();
+            }
+            cluster.drop_percent = 0;
+            for id in 0..SIZE {
+                if !cluster.is_alive(id) {
+                    cluster.restart(id);
+                }
+            }
+            cluster.run(100);
+            assert!(cluster.propose("final").is_some());
+            cluster.run(50);
+
+            let log = cluster.committed(0);
+            assert_eq!(log.last(), Some(&"final"));
+            for id in 1..SIZE {
+                assert_eq!(cluster.committed(id), log, "seed {}", seed);
+            }
+            // 每条命令最多提交一次，并且按提出的顺序
+            let numbers: Vec<usize> = log[..log.len() - 1]
+                .iter()
+                .map(|command| command["cmd ".len()..].parse().unwrap())
+                .collect();
+            assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(
+                numbers.len() > proposed / 2,
+                "{} of {}",
+                numbers.len(),
+                proposed
+            );
+        }
+    }
+}
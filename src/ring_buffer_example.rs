@@ -0,0 +1,240 @@
+// 环形缓冲区（循环队列）：容量固定，分配一次以后不再增长
+// 槽位排成一个环，head 指向最旧的元素，新元素放在 head + len 的位置（对容量取模），
+// 走到数组末尾就绕回开头，所以两端的插入和删除都是 O(1)，也不需要像 Vec::remove(0) 那样搬动元素
+// 满了以后有两种选择：push 拒绝新元素，push_overwrite 覆盖最旧的元素（适合只关心最近 N 条的场景，例如最近的访问日志）
+#[cfg(test)]
+pub(crate) mod ring_buffer {
+
+    pub(crate) struct RingBuffer<T> {
+        // 没有元素的槽位是 None，不需要 unsafe 的未初始化内存
+        slots: Box<[Option<T>]>,
+        head: usize,
+        len: usize,
+    }
+
+    impl<T> RingBuffer<T> {
+        pub(crate) fn with_capacity(capacity: usize) -> RingBuffer<T> {
+            assert!(capacity > 0, "capacity must be greater than 0");
+            RingBuffer {
+                slots: (0..capacity).map(|_| None).collect(),
+                head: 0,
+                len: 0,
+            }
+        }
+
+        pub(crate) fn capacity(&self) -> usize {
+            self.slots.len()
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.len
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        pub(crate) fn is_full(&self) -> bool {
+            self.len == self.capacity()
+        }
+
+        // 从最旧的元素数起第 i 个所在的槽位
+        fn slot(&self, i: usize) -> usize {
+            (self.head + i) % self.capacity()
+        }
+
+        // 满了的时候把值原样还给调用方
+        pub(crate) fn push(&mut self, value: T) -> Result<(), T> {
+            if self.is_full() {
+                return Err(value);
+            }
+            let tail = self.slot(self.len);
+            self.slots[tail] = Some(value);
+            self.len += 1;
+            Ok(())
+        }
+
+        // 满了的时候覆盖最旧的元素，返回被挤掉的那个
+        pub(crate) fn push_overwrite(&mut self, value: T) -> Option<T> {
+            if !self.is_full() {
+                let _ = self.push(value);
+                return None;
+            }
+            // 最旧的槽位变成最新的，head 往前走一格
+            let evicted = self.slots[self.head].replace(value);
+            self.head = self.slot(1);
+            evicted
+        }
+
+        // 取出最旧的元素
+        pub(crate) fn pop(&mut self) -> Option<T> {
+            if self.is_empty() {
+                return None;
+            }
+            let value = self.slots[self.head].take();
+            self.head = self.slot(1);
+            self.len -= 1;
+            value
+        }
+
+        // 第 i 旧的元素，0 是最旧的
+        pub(crate) fn get(&self, i: usize) -> Option<&T> {
+            if i >= self.len {
+                return None;
+            }
+            self.slots[self.slot(i)].as_ref()
+        }
+
+        pub(crate) fn front(&self) -> Option<&T> {
+            self.get(0)
+        }
+
+        pub(crate) fn back(&self) -> Option<&T> {
+            self.get(self.len.checked_sub(1)?)
+        }
+
+        pub(crate) fn clear(&mut self) {
+            while self.pop().is_some() {}
+            self.head = 0;
+        }
+
+        // 从最旧到最新
+        pub(crate) fn iter(&self) -> Iter<'_, T> {
+            Iter {
+                ring: self,
+                front: 0,
+                back: self.len,
+            }
+        }
+    }
+
+    pub(crate) struct Iter<'a, T> {
+        ring: &'a RingBuffer<T>,
+        // 还没迭代到的是 front..back
+        front: usize,
+        back: usize,
+    }
+
+    impl<'a, T> Iterator for Iter<'a, T> {
+        type Item = &'a T;
+
+        fn next(&mut self) -> Option<&'a T> {
+            if self.front == self.back {
+                return None;
+            }
+            self.front += 1;
+            self.ring.get(self.front - 1)
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            let remaining = self.back - self.front;
+            (remaining, Some(remaining))
+        }
+    }
+
+    impl<T> DoubleEndedIterator for Iter<'_, T> {
+        fn next_back(&mut self) -> Option<Self::Item> {
+            if self.front == self.back {
+                return None;
+            }
+            self.back -= 1;
+            self.ring.get(self.back)
+        }
+    }
+
+    impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+    impl<'a, T> IntoIterator for &'a RingBuffer<T> {
+        type Item = &'a T;
+        type IntoIter = Iter<'a, T>;
+
+        fn into_iter(self) -> Iter<'a, T> {
+            self.iter()
+        }
+    }
+
+    // 按值迭代就是不停地 pop
+    impl<T> Iterator for RingBuffer<T> {
+        type Item = T;
+
+        fn next(&mut self) -> Option<T> {
+            self.pop()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::ring_buffer::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn push_pop_and_overwrite() {
+        let mut ring = RingBuffer::with_capacity(3);
+        assert!(ring.is_empty() && ring.pop().is_none() && ring.back().is_none());
+        assert_eq!(ring.push(1), Ok(()));
+        assert_eq!(ring.push(2), Ok(()));
+        assert_eq!(ring.push(3), Ok(()));
+        assert!(ring.is_full());
+        // 满了：push 把值还回来，push_overwrite 挤掉最旧的
+        assert_eq!(ring.push(4), Err(4));
+        assert_eq!(ring.push_overwrite(4), Some(1));
+        assert_eq!(ring.push_overwrite(5), Some(2));
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [3, 4, 5]);
+        assert_eq!((ring.front(), ring.back()), (Some(&3), Some(&5)));
+        assert_eq!(ring.iter().rev().copied().collect::<Vec<_>>(), [5, 4, 3]);
+        assert_eq!(ring.pop(), Some(3));
+        // 绕过数组末尾以后顺序还是对的
+        assert_eq!(ring.push(6), Ok(()));
+        assert_eq!((&ring).into_iter().len(), 3);
+        assert_eq!(ring.get(2), Some(&6));
+        assert_eq!(ring.get(3), None);
+        let mut drained = RingBuffer::with_capacity(2);
+        drained.push_overwrite("a");
+        drained.push_overwrite("b");
+        drained.push_overwrite("c");
+        assert_eq!(drained.collect::<Vec<_>>(), ["b", "c"]);
+        ring.clear();
+        assert!(ring.is_empty());
+        assert_eq!(ring.capacity(), 3);
+    }
+
+    #[test]
+    fn matches_a_vec_deque_model() {
+        // 和标准库的 VecDeque 对照，随机的 push、push_overwrite、pop
+        let mut seed: u64 = 95;
+        for capacity in 1..=6 {
+            let mut ring = RingBuffer::with_capacity(capacity);
+            let mut model = VecDeque::new();
+            for step in 0..2_000u32 {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                match (seed >> 33) % 3 {
+                    0 => {
+                        let expected = if model.len() == capacity {
+                            Err(step)
+                        } else {
+                            model.push_back(step);
+                            Ok(())
+                        };
+                        assert_eq!(ring.push(step), expected);
+                    }
+                    1 => {
+                        let evicted = if model.len() == capacity {
+                            model.pop_front()
+                        } else {
+                            None
+                        };
+                        model.push_back(step);
+                        assert_eq!(ring.push_overwrite(step), evicted);
+                    }
+                    _ => assert_eq!(ring.pop(), model.pop_front()),
+                }
+                assert!(ring.iter().eq(model.iter()), "step {}", step);
+                assert_eq!(ring.len(), model.len());
+            }
+        }
+    }
+}
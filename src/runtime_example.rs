@@ -1,4 +1,133 @@
 // 异步运行时
+// 运行时工厂：各个例子要的运行时不外乎几种，按用途起名字（Preset），工作线程数、线程名、阻塞线程池的上限、
+// 线程启动和退出时的回调都在这里统一设置，不用每个例子各自拼一遍 Builder
+#[cfg(test)]
+pub(crate) mod runtime_factory {
+
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use tokio::runtime::{Builder, Runtime};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Preset {
+        // 网络服务：大量连接，大部分时间在等 IO。工作线程数等于 CPU 核数，阻塞线程池用 tokio 的默认上限 512，
+        // 留给 spawn_blocking 里的文件读写、DNS 解析之类的调用
+        IoHeavy,
+        // 计算为主：工作线程数同样等于核数，阻塞线程池限制在核数以内，长时间的计算放进 spawn_blocking 时不会开出比核数多的线程互相抢 CPU
+        CpuHeavy,
+        // 单线程：所有任务都在调用 block_on 的线程上执行，调度顺序确定，测试里用来等待异步的结果；
+        // thread-per-core 的每个线程也是一个这样的运行时
+        SingleThreadTest,
+    }
+
+    type Hook = Arc<dyn Fn() + Send + Sync>;
+
+    // 和 ThreadPoolBuilder 一样用链式调用修改预设的参数，build 可以调用多次，每次得到一个新的运行时
+    pub(crate) struct RuntimeFactory {
+        preset: Preset,
+        name: String,
+        worker_threads: Option<usize>,
+        max_blocking_threads: usize,
+        keep_alive: Duration,
+        on_thread_start: Option<Hook>,
+        on_thread_stop: Option<Hook>,
+    }
+
+    impl RuntimeFactory {
+        pub(crate) fn new(preset: Preset) -> RuntimeFactory {
+            let cores = thread::available_parallelism().map_or(1, |n| n.get());
+            let (name, max_blocking_threads, keep_alive) = match preset {
+                Preset::IoHeavy => ("io", 512, Duration::from_secs(10)),
+                Preset::CpuHeavy => ("cpu", cores, Duration::from_secs(1)),
+                Preset::SingleThreadTest => ("test", 8, Duration::from_secs(1)),
+            };
+            RuntimeFactory {
+                preset,
+                name: name.to_string(),
+                worker_threads: None,
+                max_blocking_threads,
+                keep_alive,
+                on_thread_start: None,
+                on_thread_stop: None,
+            }
+        }
+
+        // 运行时里的线程（工作线程和阻塞线程）都叫 {name}-{序号}，在调试器和 top -H 里一眼能看出是哪个运行时的
+        pub(crate) fn name(mut self, name: &str) -> RuntimeFactory {
+            self.name = name.to_string();
+            self
+        }
+
+        // 单线程的预设没有工作线程，设置了也没有意义
+        pub(crate) fn worker_threads(mut self, threads: usize) -> RuntimeFactory {
+            assert!(self.preset != Preset::SingleThreadTest);
+            assert!(threads > 0);
+            self.worker_threads = Some(threads);
+            self
+        }
+
+        // 阻塞线程都在忙时，新的 spawn_blocking 任务排队等待，而不是再开线程
+        pub(crate) fn max_blocking_threads(mut self, threads: usize) -> RuntimeFactory {
+            assert!(threads > 0);
+            self.max_blocking_threads = threads;
+            self
+        }
+
+        // 在运行时的每个线程上调用，可以用来设置线程局部的状态、统计线程数
+        pub(crate) fn on_thread_start(
+            mut self,
+            hook: impl Fn() + Send + Sync + 'static,
+        ) -> RuntimeFactory {
+            self.on_thread_start = Some(Arc::new(hook));
+            self
+        }
+
+        pub(crate) fn on_thread_stop(
+            mut self,
+            hook: impl Fn() + Send + Sync + 'static,
+        ) -> RuntimeFactory {
+            self.on_thread_stop = Some(Arc::new(hook));
+            self
+        }
+
+        // IO 和计时器总是打开，例子里用到哪个都不用再想
+        pub(crate) fn build(&self) -> io::Result<Runtime> {
+            let mut builder = match self.preset {
+                Preset::SingleThreadTest => Builder::new_current_thread(),
+                Preset::IoHeavy | Preset::CpuHeavy => Builder::new_multi_thread(),
+            };
+            if let Some(threads) = self.worker_threads {
+                builder.worker_threads(threads);
+            }
+            let name = self.name.clone();
+            let next = AtomicUsize::new(0);
+            builder
+                .enable_all()
+                .thread_name_fn(move || format!("{}-{}", name, next.fetch_add(1, Ordering::SeqCst)))
+                .max_blocking_threads(self.max_blocking_threads)
+                .thread_keep_alive(self.keep_alive);
+            if let Some(hook) = &self.on_thread_start {
+                let hook = Arc::clone(hook);
+                builder.on_thread_start(move || hook());
+            }
+            if let Some(hook) = &self.on_thread_stop {
+                let hook = Arc::clone(hook);
+                builder.on_thread_stop(move || hook());
+            }
+            builder.build()
+        }
+    }
+
+    // 按预设的默认参数创建运行时，例子里最常见的用法
+    pub(crate) fn runtime(preset: Preset) -> Runtime {
+        RuntimeFactory::new(preset)
+            .build()
+            .expect("failed to build the tokio runtime")
+    }
+}
 #[cfg(test)]
 mod tests {
     
@@ -6,6 +135,7 @@ mod tests {
     use std::time::Duration;
     use chrono::Local;
     use tokio::{self, runtime::Runtime, time};
+    use super::runtime_factory::{runtime, Preset, RuntimeFactory};
 
     // 要使用tokio，需要先创建它提供的异步运行时环境(Runtime)，然后在这个Runtime中执行异步任务
     #[test]
@@ -27,6 +157,10 @@ mod tests {
         // 创建单一线程的runtime
         let rt3 = tokio::runtime::Builder::new_current_thread().build().unwrap();
         println!("{:?}", rt3);
+
+        // 其他例子不直接拼 Builder，而是用 runtime_factory 里按用途命名的预设
+        let rt4 = runtime(Preset::CpuHeavy);
+        println!("{:?}", rt4);
     }
 
     // 可手动创建线程，并在不同线程内创建互相独立的runtime
@@ -35,14 +169,14 @@ mod tests {
     fn multi_runtime_test() {
         // 在第一个线程内创建一个多线程的runtime
         let t1 = thread::spawn(|| {
-            let rt = Runtime::new().unwrap();
+            let rt = runtime(Preset::IoHeavy);
             println!("{:?}", rt);
             thread::sleep(Duration::from_secs(10));
         });
 
         // 在第二个线程内创建一个多线程的runtime
         let t2 = thread::spawn(|| {
-            let rt = Runtime::new().unwrap();
+            let rt = runtime(Preset::IoHeavy);
             println!("{:?}", rt);
             thread::sleep(Duration::from_secs(10));
         });
@@ -55,7 +189,7 @@ mod tests {
     // 每一个异步任务都是一个线程内的【协程】，单一线程的runtime是在单个线程内调度管理这些任务，多线程runtime则是在多个线程内不断地分配和跨线程传递这些任务
     #[test]
     fn async_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
         // block_on会阻塞当前线程，直到其指定的异步任务树(可能有子任务)全部完成
         // block_on是等待异步任务完成，而不是等待runtime中的所有任务都完成
         // 直接将async {}作为block_on()的参数，这个async {}本质上是一个Future，即一个异步任务
@@ -98,7 +232,7 @@ mod tests {
 
     #[test]
     fn spawn_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
         // 在这个最外层的异步任务内部，还可以创建新的异步任务，它们都将在同一个runtime中执行
         rt.block_on(async {
             // 调用函数，该函数内创建了一个异步任务，将在当前runtime内执行
@@ -112,7 +246,7 @@ mod tests {
 
     #[test]
     fn enter_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
         
         // 进入runtime，但不阻塞当前线程
         // block_on()进入runtime时，会阻塞当前线程
@@ -150,7 +284,7 @@ mod tests {
     fn blocking_test() {
         // 单个线程或多个线程的runtime，指的都是工作线程，即只用于执行异步任务的线程，这些任务主要是IO密集型的任务。tokio默认会将每一个工作线程均匀地绑定到每一个CPU核心上。
         // 有些必要的任务可能会长时间计算而占用线程，甚至任务可能是同步的，它会直接阻塞整个线程(比如thread::time::sleep())，这类任务如果计算时间或阻塞时间较短，勉强可以考虑留在异步队列中，但如果任务计算时间或阻塞时间可能会较长，它们将不适合放在异步队列中，因为它们会破坏异步调度，使得同线程中的其它异步任务处于长时间等待状态，也就是说，这些异步任务可能会被饿很长一段时间
-        let rt1 = Runtime::new().unwrap();
+        let rt1 = runtime(Preset::IoHeavy);
         
         // 创建一个blocking thread，可立即执行（由操作系统调度系统决定何时执行）
         // 例如，直接在runtime中执行阻塞线程的操作，由于这类阻塞操作不在tokio系统内，tokio无法识别这类线程阻塞的操作，tokio只能等待该线程阻塞操作的结束，才能重新获得那个线程的管理权。换句话说，worker thread被线程阻塞的时候，它已经脱离了tokio的控制，在一定程度上破坏了tokio的调度系统
@@ -176,7 +310,7 @@ mod tests {
 
     #[test]
     fn shutdown_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
 
         // 一个运行5秒的blocking thread，drop rt时，该任务将继续运行，直到自己终止
         rt.spawn_blocking(|| {
@@ -210,7 +344,7 @@ mod tests {
 
     #[test]
     fn handle_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
 
         // tokio提供了一个称为runtime Handle的东西，它实际上是runtime的一个引用，可以随意被clone。它可以spawn()生成异步任务，这些异步任务将绑定在其所引用的runtime中，还可以block_on()或enter()进入其所引用的runtime，此外，还可以生成blocking thread
         let handle = rt.handle();
@@ -226,4 +360,61 @@ mod tests {
         let eg = handle.enter();
         drop(eg);
     }
+
+    #[test]
+    fn runtime_factory_presets() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let factory = {
+            let (started, stopped) = (Arc::clone(&started), Arc::clone(&stopped));
+            RuntimeFactory::new(Preset::IoHeavy)
+                .name("api")
+                .worker_threads(2)
+                .max_blocking_threads(1)
+                .on_thread_start(move || {
+                    started.fetch_add(1, Ordering::SeqCst);
+                })
+                .on_thread_stop(move || {
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                })
+        };
+        let rt = factory.build().unwrap();
+        let name = || thread::current().name().unwrap_or("").to_string();
+        let worker = rt.block_on(async move { tokio::spawn(async move { name() }).await });
+        let worker = worker.unwrap();
+        assert!(worker.starts_with("api-"), "{}", worker);
+
+        // 只有一个阻塞线程：几个阻塞任务一个接一个地执行，从来不会同时运行
+        let (running, most) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let (running, most) = (Arc::clone(&running), Arc::clone(&most));
+                rt.spawn_blocking(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    name()
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert!(rt.block_on(task).unwrap().starts_with("api-"));
+        }
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+
+        // 两个工作线程加一个阻塞线程，关闭之后每个启动过的线程都调用过退出的回调
+        rt.shutdown_timeout(Duration::from_secs(5));
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+        assert_eq!(stopped.load(Ordering::SeqCst), 3);
+
+        // 单线程预设：spawn 出去的任务也在调用 block_on 的线程上执行
+        let rt = runtime(Preset::SingleThreadTest);
+        let here = thread::current().id();
+        let there = rt.block_on(async { tokio::spawn(async { thread::current().id() }).await });
+        assert_eq!(here, there.unwrap());
+    }
 }
@@ -0,0 +1,262 @@
+// 概率数据结构：在无界的数据流（例如不断增长的访问日志）上统计频率，内存大小固定，不随不同元素的个数增长
+// Count-Min Sketch 估计任意元素出现的次数，只会多估不会少估；Space-Saving 用 k 个计数器找出出现最多的元素
+// 两者都拿精确度换空间，误差有可以证明的上界，测试里对照精确计数检查这些上界
+#[cfg(test)]
+mod tests {
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+
+    // depth 行、每行 width 个计数器。每个元素在每一行里用不同的哈希函数选中一个计数器加一，
+    // 查询时取这些计数器中最小的那个：别的元素撞到同一个计数器只会让它变大，最小值最接近真实值
+    struct CountMinSketch {
+        width: usize,
+        rows: Vec<Vec<u64>>,
+        total: u64,
+    }
+
+    impl CountMinSketch {
+        // 以至少 1 - delta 的概率，估计值比真实值多出的部分不超过 epsilon * 总数
+        // 需要 width = ⌈e / epsilon⌉，depth = ⌈ln(1 / delta)⌉
+        fn with_error(epsilon: f64, delta: f64) -> CountMinSketch {
+            assert!(epsilon > 0.0 && delta > 0.0 && delta < 1.0);
+            let width = (std::f64::consts::E / epsilon).ceil() as usize;
+            let depth = (1.0 / delta).ln().ceil().max(1.0) as usize;
+            CountMinSketch::new(width, depth)
+        }
+
+        fn new(width: usize, depth: usize) -> CountMinSketch {
+            assert!(width > 0 && depth > 0);
+            CountMinSketch {
+                width,
+                rows: vec![vec![0; width]; depth],
+                total: 0,
+            }
+        }
+
+        // 行号作为种子和元素一起哈希，相当于每一行一个不同的哈希函数
+        // DefaultHasher::new() 的密钥是固定的，同一个元素每次得到相同的位置
+        fn column<T: Hash + ?Sized>(&self, row: usize, item: &T) -> usize {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            item.hash(&mut hasher);
+            (hasher.finish() % self.width as u64) as usize
+        }
+
+        fn add<T: Hash + ?Sized>(&mut self, item: &T, count: u64) {
+            for row in 0..self.rows.len() {
+                let column = self.column(row, item);
+                self.rows[row][column] += count;
+            }
+            self.total += count;
+        }
+
+        fn estimate<T: Hash + ?Sized>(&self, item: &T) -> u64 {
+            (0..self.rows.len())
+                .map(|row| self.rows[row][self.column(row, item)])
+                .min()
+                .unwrap()
+        }
+    }
+
+    // Space-Saving：最多保留 k 个计数器。新元素到来时如果计数器满了，就顶替计数最小的那个，
+    // 并且从被顶替者的计数开始累加，同时记下这部分可能多算的量（error）
+    // 保证：真实次数超过 N / k 的元素一定在里面；count - error <= 真实次数 <= count
+    struct SpaceSaving<T> {
+        capacity: usize,
+        counters: HashMap<T, Counter>,
+        total: u64,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Counter {
+        count: u64,
+        error: u64,
+    }
+
+    impl<T: Hash + Eq + Ord + Clone> SpaceSaving<T> {
+        fn new(capacity: usize) -> SpaceSaving<T> {
+            assert!(capacity > 0);
+            SpaceSaving {
+                capacity,
+                counters: HashMap::with_capacity(capacity),
+                total: 0,
+            }
+        }
+
+        // 找最小的计数器是 O(k) 的线性扫描；论文里用按计数分组的链表（stream-summary）做到 O(1)，这里 k 很小，保持简单
+        fn add(&mut self, item: &T) {
+            self.total += 1;
+            if let Some(counter) = self.counters.get_mut(item) {
+                counter.count += 1;
+                return;
+            }
+            if self.counters.len() < self.capacity {
+                self.counters
+                    .insert(item.clone(), Counter { count: 1, error: 0 });
+                return;
+            }
+            let (victim, min) = self
+                .counters
+                .iter()
+                .min_by(|a, b| a.1.count.cmp(&b.1.count).then(b.0.cmp(a.0)))
+                .map(|(item, counter)| (item.clone(), counter.count))
+                .unwrap();
+            self.counters.remove(&victim);
+            self.counters.insert(
+                item.clone(),
+                Counter {
+                    count: min + 1,
+                    error: min,
+                },
+            );
+        }
+
+        // 按估计次数从大到小，次数相同时按元素排序
+        fn top(&self, n: usize) -> Vec<(T, Counter)> {
+            let mut top: Vec<(T, Counter)> = self
+                .counters
+                .iter()
+                .map(|(item, counter)| (item.clone(), *counter))
+                .collect();
+            top.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(&b.0)));
+            top.truncate(n);
+            top
+        }
+    }
+
+    // 访问日志里的 path 字段，格式和 webserver_example 的结构化日志一样（key=value，空格分隔）
+    fn path_of(line: &str) -> Option<&str> {
+        line.split(' ')
+            .find_map(|field| field.strip_prefix("path="))
+    }
+
+    // 模拟一段访问日志：少数页面很热门，大量页面只被访问几次（长尾），用线性同余生成器保证每次相同
+    fn access_log(lines: usize) -> Vec<String> {
+        let mut seed = 7u64;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        (0..lines)
+            .map(|i| {
+                let path = match next() % 100 {
+                    0..=29 => String::from("/"),
+                    30..=44 => String::from("/api/health"),
+                    45..=54 => String::from("/static/app.js"),
+                    55..=59 => String::from("/search?q=pool"),
+                    _ => format!("/posts/{}", next() % 5000),
+                };
+                format!(
+                    "worker={} method=GET path={} status=200 bytes=512 elapsed_ms=0.250",
+                    i % 4,
+                    path
+                )
+            })
+            .collect()
+    }
+
+    fn exact_counts(log: &[String]) -> HashMap<&str, u64> {
+        let mut counts = HashMap::new();
+        for path in log.iter().filter_map(|line| path_of(line)) {
+            *counts.entry(path).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn count_min_error_bound() {
+        let log = access_log(50_000);
+        let exact = exact_counts(&log);
+        let (epsilon, delta) = (0.001, 0.01);
+        let mut sketch = CountMinSketch::with_error(epsilon, delta);
+        assert_eq!((sketch.width, sketch.rows.len()), (2719, 5));
+        for path in log.iter().filter_map(|line| path_of(line)) {
+            sketch.add(path, 1);
+        }
+        assert_eq!(sketch.total, 50_000);
+
+        let bound = (epsilon * sketch.total as f64) as u64;
+        let mut over_bound = 0;
+        for (path, &count) in &exact {
+            let estimate = sketch.estimate(*path);
+            // 永远不会少估
+            assert!(estimate >= count, "{}: {} < {}", path, estimate, count);
+            if estimate - count > bound {
+                over_bound += 1;
+            }
+        }
+        // 每个元素超出误差上界的概率不超过 delta
+        assert!(
+            over_bound as f64 <= delta * exact.len() as f64,
+            "{} of {} estimates over the bound",
+            over_bound,
+            exact.len()
+        );
+        // 热门页面的相对误差很小
+        let home = sketch.estimate("/") as f64 / exact["/"] as f64;
+        assert!(home < 1.01, "{}", home);
+        // 没出现过的元素估计值也不超过上界（大概率）
+        assert!(sketch.estimate("/never-requested") <= bound);
+    }
+
+    #[test]
+    fn space_saving_finds_heavy_hitters() {
+        let log = access_log(50_000);
+        let exact = exact_counts(&log);
+        let k = 20;
+        let mut tracker = SpaceSaving::new(k);
+        for path in log.iter().filter_map(|line| path_of(line)) {
+            tracker.add(&path.to_string());
+        }
+        assert_eq!(tracker.total, 50_000);
+        assert_eq!(tracker.counters.len(), k);
+
+        // 每个计数器的真实次数落在 [count - error, count] 之间
+        for (path, counter) in &tracker.counters {
+            let count = exact[path.as_str()];
+            assert!(counter.count >= count && counter.count - counter.error <= count);
+            assert!(counter.error <= tracker.total / k as u64);
+        }
+        // 真实次数超过 N / k 的都被找到了，而且排在最前面
+        let threshold = tracker.total / k as u64;
+        let mut heavy: Vec<&str> = exact
+            .iter()
+            .filter(|(_, &count)| count > threshold)
+            .map(|(path, _)| *path)
+            .collect();
+        heavy.sort_by_key(|path| std::cmp::Reverse(exact[path]));
+        let top: Vec<String> = tracker
+            .top(heavy.len())
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(top, heavy);
+        assert_eq!(
+            heavy,
+            ["/", "/api/health", "/static/app.js", "/search?q=pool"]
+        );
+    }
+
+    #[test]
+    fn space_saving_is_exact_when_everything_fits() {
+        let mut tracker = SpaceSaving::new(3);
+        for item in ["a", "b", "a", "c", "a", "b"] {
+            tracker.add(&item);
+        }
+        let zero = |count| Counter { count, error: 0 };
+        assert_eq!(
+            tracker.top(3),
+            [("a", zero(3)), ("b", zero(2)), ("c", zero(1))]
+        );
+
+        // 满了以后 d 顶替计数最小的 c，继承它的计数作为误差
+        tracker.add(&"d");
+        assert_eq!(tracker.top(2), [("a", zero(3)), ("b", zero(2))]);
+        assert_eq!(tracker.counters[&"d"], Counter { count: 2, error: 1 });
+        assert!(!tracker.counters.contains_key(&"c"));
+    }
+}
@@ -0,0 +1,254 @@
+// 流式统计：数据一条一条地从通道里来，不能也不需要全部保存下来
+// Welford 算法一遍扫描就能得到均值和方差，而且不会像“平方和减去和的平方”那样在数值很大时损失精度；
+// 蓄水池抽样（reservoir sampling）在不知道总数的情况下，保证流里的每个元素以相同的概率留在固定大小的样本里，
+// 分位数就从样本里估计。统计结果定期发布到 watch 通道上，读的一方（例如 /metrics）随时拿到最新的一份
+#[cfg(test)]
+pub(crate) mod streaming {
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tokio::sync::watch;
+
+    #[derive(Default)]
+    pub(crate) struct Welford {
+        count: u64,
+        mean: f64,
+        // 与均值之差的平方和
+        m2: f64,
+        min: f64,
+        max: f64,
+    }
+
+    impl Welford {
+        // 每来一个值，用它和旧均值、新均值的差修正 m2
+        pub(crate) fn push(&mut self, value: f64) {
+            if self.count == 0 {
+                self.min = value;
+                self.max = value;
+            }
+            self.count += 1;
+            let delta = value - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (value - self.mean);
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+
+        pub(crate) fn mean(&self) -> f64 {
+            self.mean
+        }
+
+        // 样本方差，除以 n - 1
+        pub(crate) fn variance(&self) -> f64 {
+            if self.count < 2 {
+                0.0
+            } else {
+                self.m2 / (self.count - 1) as f64
+            }
+        }
+    }
+
+    // Algorithm R：前 capacity 个元素直接放进样本；之后第 n 个元素以 capacity / n 的概率替换样本中随机的一个
+    pub(crate) struct Reservoir<T> {
+        capacity: usize,
+        seen: u64,
+        items: Vec<T>,
+        rng: StdRng,
+    }
+
+    impl<T> Reservoir<T> {
+        // 固定种子让测试可以重现
+        pub(crate) fn new(capacity: usize, seed: u64) -> Reservoir<T> {
+            assert!(capacity > 0);
+            Reservoir {
+                capacity,
+                seen: 0,
+                items: Vec::with_capacity(capacity),
+                rng: StdRng::seed_from_u64(seed),
+            }
+        }
+
+        pub(crate) fn push(&mut self, item: T) {
+            self.seen += 1;
+            if self.items.len() < self.capacity {
+                self.items.push(item);
+                return;
+            }
+            let slot = self.rng.gen_range(0..self.seen);
+            if slot < self.capacity as u64 {
+                self.items[slot as usize] = item;
+            }
+        }
+
+        pub(crate) fn items(&self) -> &[T] {
+            &self.items
+        }
+    }
+
+    // 发布到 watch 通道上的快照，sample 已经排好序
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub(crate) struct Summary {
+        pub(crate) count: u64,
+        pub(crate) mean: f64,
+        pub(crate) stddev: f64,
+        pub(crate) min: f64,
+        pub(crate) max: f64,
+        pub(crate) sample: Vec<f64>,
+    }
+
+    impl Summary {
+        // 从样本估计分位数（最近秩法），还没有数据时返回 None
+        pub(crate) fn quantile(&self, q: f64) -> Option<f64> {
+            if self.sample.is_empty() {
+                return None;
+            }
+            let rank = (q.clamp(0.0, 1.0) * self.sample.len() as f64).ceil() as usize;
+            Some(self.sample[rank.max(1) - 1])
+        }
+    }
+
+    // 启动统计线程：从 events 读数据，每隔 interval 把当前的统计结果发布一次；
+    // 所有发送端都被丢弃后发布最后一份结果，然后线程退出
+    // watch 通道只保留最新的值，读的一方不会因为来不及读而积压，也不需要每次发布都去读
+    pub(crate) fn spawn_stage(
+        events: mpsc::Receiver<f64>,
+        reservoir: usize,
+        interval: Duration,
+    ) -> (watch::Receiver<Summary>, thread::JoinHandle<()>) {
+        let (publisher, summaries) = watch::channel(Summary::default());
+        let handle = thread::spawn(move || {
+            let mut stats = Welford::default();
+            let mut sample = Reservoir::new(reservoir, 0);
+            let publish = |stats: &Welford, sample: &Reservoir<f64>| {
+                let mut sorted = sample.items().to_vec();
+                sorted.sort_by(f64::total_cmp);
+                // 没有人在读的时候 send 返回错误，统计照常进行
+                let _ = publisher.send(Summary {
+                    count: stats.count,
+                    mean: stats.mean(),
+                    stddev: stats.variance().sqrt(),
+                    min: stats.min,
+                    max: stats.max,
+                    sample: sorted,
+                });
+            };
+
+            let mut deadline = Instant::now() + interval;
+            loop {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match events.recv_timeout(timeout) {
+                    Ok(value) => {
+                        stats.push(value);
+                        sample.push(value);
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        publish(&stats, &sample);
+                        deadline += interval;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        publish(&stats, &sample);
+                        return;
+                    }
+                }
+            }
+        });
+        (summaries, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::streaming::*;
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn welford_matches_two_pass() {
+        // 很大的偏移量加上很小的波动：平方和的做法在这里会把方差算成 0 甚至负数
+        let values: Vec<f64> = (0..1000).map(|i| 1e9 + (i % 7) as f64).collect();
+        let mut stats = Welford::default();
+        for &value in &values {
+            stats.push(value);
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance =
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+        assert!((stats.mean() - mean).abs() < 1e-6);
+        assert!((stats.variance() - variance).abs() < 1e-6);
+
+        let mut single = Welford::default();
+        single.push(3.0);
+        assert_eq!((single.mean(), single.variance()), (3.0, 0.0));
+    }
+
+    #[test]
+    fn reservoir_sample_is_uniform() {
+        let (n, k, runs) = (1000u64, 50, 400);
+        // 每个元素留在样本里的概率都应该是 k / n，按十个区间统计被选中的次数
+        let mut hits = [0u64; 10];
+        for seed in 0..runs {
+            let mut reservoir = Reservoir::new(k, seed);
+            for i in 0..n {
+                reservoir.push(i);
+            }
+            assert_eq!(reservoir.items().len(), k);
+            for &i in reservoir.items() {
+                hits[(i * 10 / n) as usize] += 1;
+            }
+        }
+        let expected = (runs * k as u64 / 10) as f64;
+        for (bucket, &count) in hits.iter().enumerate() {
+            let ratio = count as f64 / expected;
+            assert!((0.9..1.1).contains(&ratio), "bucket {}: {}", bucket, ratio);
+        }
+
+        // 元素个数少于容量时全部保留
+        let mut small = Reservoir::new(10, 1);
+        for i in 0..3 {
+            small.push(i);
+        }
+        assert_eq!(small.items(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn stage_publishes_summaries() {
+        let (events, receiver) = mpsc::channel();
+        let (mut summaries, handle) = spawn_stage(receiver, 100, Duration::from_millis(20));
+        for i in 1..=1000 {
+            events.send(i as f64).unwrap();
+        }
+
+        // 等到包含全部数据的那一份发布出来
+        let rt = runtime(Preset::SingleThreadTest);
+        let summary = rt.block_on(async {
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                summaries.wait_for(|summary| summary.count == 1000),
+            )
+            .await
+            .unwrap()
+            .unwrap()
+            .clone()
+        });
+        assert_eq!(summary.mean, 500.5);
+        assert_eq!((summary.min, summary.max), (1.0, 1000.0));
+        assert!((summary.stddev - 288.82).abs() < 0.01);
+        assert_eq!(summary.sample.len(), 100);
+        assert!(summary.sample.windows(2).all(|w| w[0] <= w[1]));
+        // 样本估计的中位数大致在中间
+        let median = summary.quantile(0.5).unwrap();
+        assert!((300.0..700.0).contains(&median), "{}", median);
+
+        // 发送端全部丢弃后，线程发布最后一份结果并退出
+        events.send(1001.0).unwrap();
+        drop(events);
+        handle.join().unwrap();
+        assert_eq!(summaries.borrow().count, 1001);
+        assert_eq!(Summary::default().quantile(0.5), None);
+    }
+}
@@ -4,7 +4,8 @@ mod tests {
 
     use chrono::Local;
     use std::{thread};
-    use tokio::{self, task, runtime::Runtime, runtime::Handle, time};
+    use tokio::{self, task, runtime::Handle, time};
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
 
     fn now() -> String {
         Local::now().format("%F %T").to_string()
@@ -12,7 +13,7 @@ mod tests {
 
     #[test]
     fn spawn_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
         let _guard = rt.enter();
         // 直接在当前的runtime中生成一个异步任务
         task::spawn(async {
@@ -24,7 +25,7 @@ mod tests {
 
     #[test]
     fn spawn_blocking_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
         let _guard = rt.enter();
         // 直接在当前的runtime中生成一个异步任务
         let join = task::spawn_blocking(|| {
@@ -52,7 +53,7 @@ mod tests {
 
     #[test]
     fn yield_now_test() {
-        let rt = Runtime::new().unwrap();
+        let rt = runtime(Preset::IoHeavy);
         rt.spawn(async {
             task::spawn(async {
                 println!("spawned task done!");
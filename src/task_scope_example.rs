@@ -0,0 +1,235 @@
+// 结构化并发（nursery 模式）：在 thread::scope 上再包一层 TaskScope，scoped 返回时它启动的所有线程都已经结束，
+// 任务可以借用调用方栈上的数据；任务之间是一个整体：
+// - 第一个失败（返回 Err 或者 panic）的任务的错误交给 scoped 的调用方，后面的错误丢弃
+// - 有任务失败时取消令牌被触发，其他任务看到以后尽快退出，不用等它们白白跑完
+// - 调用方自己的闭包 panic 时同样先取消所有任务，等它们退出后再把 panic 继续传上去
+// TaskScope 只以借用的形式交给闭包，存不到外面去，也就不可能在 scoped 返回之后再 spawn
+#[cfg(test)]
+pub(crate) mod task_scope {
+
+    use crate::thread_pool_example::thread_pool::panic_message;
+    use std::fmt;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // 取消令牌：克隆出来的令牌共享同一个状态，任何一个调用 cancel 所有持有者都能看到
+    // 任务在循环里检查 is_cancelled，或者用 wait_timeout 代替 sleep，取消时马上醒来
+    #[derive(Clone, Default)]
+    pub(crate) struct CancelToken {
+        inner: Arc<(Mutex<bool>, Condvar)>,
+    }
+
+    impl CancelToken {
+        pub(crate) fn new() -> CancelToken {
+            CancelToken::default()
+        }
+
+        pub(crate) fn cancel(&self) {
+            let (cancelled, wakeup) = &*self.inner;
+            *cancelled.lock().unwrap() = true;
+            wakeup.notify_all();
+        }
+
+        pub(crate) fn is_cancelled(&self) -> bool {
+            *self.inner.0.lock().unwrap()
+        }
+
+        // 最多等 timeout，返回是否已经被取消
+        pub(crate) fn wait_timeout(&self, timeout: Duration) -> bool {
+            let (cancelled, wakeup) = &*self.inner;
+            let deadline = Instant::now() + timeout;
+            let mut guard = cancelled.lock().unwrap();
+            // 条件变量可能虚假唤醒，按截止时间重新算剩下的等待时间
+            while !*guard {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                guard = wakeup.wait_timeout(guard, deadline - now).unwrap().0;
+            }
+            *guard
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum TaskError<E> {
+        Failed(E),
+        Panicked(String),
+    }
+
+    impl<E: fmt::Display> fmt::Display for TaskError<E> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                TaskError::Failed(e) => write!(f, "task failed: {}", e),
+                TaskError::Panicked(message) => write!(f, "task panicked: {}", message),
+            }
+        }
+    }
+
+    // 'scope 是 thread::scope 的作用域，'env 是任务可以借用的外部数据的生命周期
+    pub(crate) struct TaskScope<'scope, 'env: 'scope, E> {
+        scope: &'scope thread::Scope<'scope, 'env>,
+        token: CancelToken,
+        first: Arc<Mutex<Option<TaskError<E>>>>,
+    }
+
+    impl<'scope, 'env, E: Send + 'scope> TaskScope<'scope, 'env, E> {
+        // 任务拿到取消令牌，需要自己配合检查；不检查的任务会一直运行到结束，scoped 也会等它
+        pub(crate) fn spawn<F>(&self, f: F)
+        where
+            F: FnOnce(&CancelToken) -> Result<(), E> + Send + 'scope,
+        {
+            let token = self.token.clone();
+            let first = Arc::clone(&self.first);
+            self.scope.spawn(move || {
+                let error = match panic::catch_unwind(AssertUnwindSafe(|| f(&token))) {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => TaskError::Failed(e),
+                    Err(payload) => TaskError::Panicked(panic_message(&*payload).to_string()),
+                };
+                first.lock().unwrap().get_or_insert(error);
+                token.cancel();
+            });
+        }
+
+        // 调用方也可以主动取消整个作用域，例如已经拿到了想要的结果
+        pub(crate) fn token(&self) -> &CancelToken {
+            &self.token
+        }
+    }
+
+    // 运行 f，等它 spawn 的所有任务结束后返回：都成功时是 f 的返回值，否则是第一个失败的任务的错误
+    pub(crate) fn scoped<'env, E, T, F>(f: F) -> Result<T, TaskError<E>>
+    where
+        E: Send + 'env,
+        F: for<'scope> FnOnce(&TaskScope<'scope, 'env, E>) -> T,
+    {
+        let token = CancelToken::new();
+        let first = Arc::new(Mutex::new(None));
+        let result = thread::scope(|scope| {
+            let tasks = TaskScope {
+                scope,
+                token: token.clone(),
+                first: Arc::clone(&first),
+            };
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&tasks)));
+            if result.is_err() {
+                token.cancel();
+            }
+            result
+        });
+        // 走到这里所有任务都已经结束了
+        let value = result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+        let error = first.lock().unwrap().take();
+        match error {
+            Some(error) => Err(error),
+            None => Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::task_scope::*;
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn tasks_borrow_from_the_caller() {
+        let numbers: Vec<u64> = (1..=1000).collect();
+        let total = Mutex::new(0);
+        let result: Result<&str, TaskError<String>> = scoped(|tasks| {
+            for chunk in numbers.chunks(100) {
+                let total = &total;
+                tasks.spawn(move |_| {
+                    *total.lock().unwrap() += chunk.iter().sum::<u64>();
+                    Ok(())
+                });
+            }
+            "spawned"
+        });
+        // scoped 返回时所有任务都已经结束，结果可以直接读
+        assert_eq!(result, Ok("spawned"));
+        assert_eq!(total.into_inner().unwrap(), 500_500);
+    }
+
+    #[test]
+    fn first_error_cancels_siblings() {
+        let stopped = AtomicUsize::new(0);
+        let start = Instant::now();
+        let result = scoped(|tasks| {
+            for _ in 0..4 {
+                // 不被取消的话这些任务会一直跑下去
+                tasks.spawn(|token| {
+                    while !token.wait_timeout(Duration::from_millis(10)) {}
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                    // 取消之后再失败的错误不会盖过第一个
+                    Err("cancelled")
+                });
+            }
+            tasks.spawn(|token| {
+                token.wait_timeout(Duration::from_millis(30));
+                Err("disk full")
+            });
+        });
+        assert_eq!(result, Err(TaskError::Failed("disk full")));
+        assert_eq!(result.unwrap_err().to_string(), "task failed: disk full");
+        assert_eq!(stopped.load(Ordering::SeqCst), 4);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let token = CancelToken::new();
+        assert!(!token.wait_timeout(Duration::from_millis(1)));
+        token.clone().cancel();
+        assert!(token.is_cancelled() && token.wait_timeout(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn panics_are_captured_and_propagated() {
+        let result: Result<(), TaskError<()>> = scoped(|tasks| {
+            tasks.spawn(|token| {
+                while !token.is_cancelled() {
+                    std::thread::yield_now();
+                }
+                Ok(())
+            });
+            tasks.spawn(|_| panic!("worker exploded"));
+        });
+        assert_eq!(
+            result,
+            Err(TaskError::Panicked(String::from("worker exploded")))
+        );
+
+        // 调用方自己 panic 时先取消任务、等它们退出，panic 再照常传出去
+        let exited = AtomicUsize::new(0);
+        let owner = panic::catch_unwind(|| {
+            let _: Result<(), TaskError<()>> = scoped(|tasks| {
+                tasks.spawn(|token| {
+                    while !token.wait_timeout(Duration::from_millis(10)) {}
+                    exited.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                });
+                panic!("owner exploded");
+            });
+        });
+        assert_eq!(
+            *owner.unwrap_err().downcast_ref::<&str>().unwrap(),
+            "owner exploded"
+        );
+        assert_eq!(exited.load(Ordering::SeqCst), 1);
+
+        // 调用方也可以主动取消
+        let result: Result<(), TaskError<()>> = scoped(|tasks| {
+            tasks.spawn(|token| {
+                while !token.wait_timeout(Duration::from_millis(10)) {}
+                Ok(())
+            });
+            tasks.token().cancel();
+        });
+        assert_eq!(result, Ok(()));
+    }
+}
@@ -0,0 +1,288 @@
+// 临时文件和临时目录：创建时取一个不会重复的名字，离开作用域（Drop）时自动删除，
+// 测试中途 assert 失败、panic 展开时也一样会删，不用在每个测试末尾手写 remove_dir_all
+// 名字由前缀、进程 id 和进程内的计数器组成，同一个进程里并行跑的测试、同时跑的多个测试进程都不会撞上；
+// 万一撞上了（上次崩溃留下的同名目录），create_new/create_dir 会报 AlreadyExists，换下一个名字重试
+// 调试模式：TempDir 的 keep_on_failure 打开时，如果是因为 panic 而被丢弃，就把目录留下并打印路径，方便事后查看失败现场；
+// 设置环境变量 TEMPFS_KEEP_ON_FAILURE=1 可以对所有临时文件默认打开
+#[cfg(test)]
+pub(crate) mod tempfs {
+
+    use std::env;
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // 换名字重试的次数上限，正常情况下第一次就能成功
+    const ATTEMPTS: usize = 100;
+
+    // 在 parent 下找一个还没被占用的名字，create 负责原子地创建出来（已存在时返回 AlreadyExists）
+    fn create_unique(
+        parent: &Path,
+        prefix: &str,
+        mut create: impl FnMut(&Path) -> io::Result<()>,
+    ) -> io::Result<PathBuf> {
+        for _ in 0..ATTEMPTS {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = parent.join(format!("{}-{}-{}", prefix, process::id(), n));
+            match create(&path) {
+                Ok(()) => return Ok(path),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("no unused temporary name for {:?}", parent.join(prefix)),
+        ))
+    }
+
+    fn keep_on_failure_by_default() -> bool {
+        env::var_os("TEMPFS_KEEP_ON_FAILURE").is_some_and(|value| value != "0")
+    }
+
+    // TempDir 和 TempFile 共用的清理逻辑
+    struct Cleanup {
+        path: PathBuf,
+        is_dir: bool,
+        keep_on_failure: bool,
+        // into_path 或 close 之后不再由 Drop 负责
+        disarmed: bool,
+    }
+
+    impl Cleanup {
+        fn remove(&mut self) -> io::Result<()> {
+            self.disarmed = true;
+            if self.is_dir {
+                fs::remove_dir_all(&self.path)
+            } else {
+                fs::remove_file(&self.path)
+            }
+        }
+    }
+
+    impl Drop for Cleanup {
+        fn drop(&mut self) {
+            if self.disarmed {
+                return;
+            }
+            if self.keep_on_failure && thread::panicking() {
+                eprintln!("tempfs: keeping {} for debugging", self.path.display());
+                return;
+            }
+            // Drop 里没法返回错误，删不掉（比如已经被测试自己删了）就算了；要知道结果用 close
+            let _ = self.remove();
+        }
+    }
+
+    pub(crate) struct TempDir {
+        cleanup: Cleanup,
+    }
+
+    impl TempDir {
+        // 在系统的临时目录下创建
+        pub(crate) fn new(prefix: &str) -> io::Result<TempDir> {
+            TempDir::new_in(&env::temp_dir(), prefix)
+        }
+
+        pub(crate) fn new_in(parent: &Path, prefix: &str) -> io::Result<TempDir> {
+            let path = create_unique(parent, prefix, |path| fs::create_dir(path))?;
+            Ok(TempDir {
+                cleanup: Cleanup {
+                    path,
+                    is_dir: true,
+                    keep_on_failure: keep_on_failure_by_default(),
+                    disarmed: false,
+                },
+            })
+        }
+
+        pub(crate) fn keep_on_failure(mut self, keep: bool) -> TempDir {
+            self.cleanup.keep_on_failure = keep;
+            self
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.cleanup.path
+        }
+
+        pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+            self.path().join(path)
+        }
+
+        // 不再自动删除，目录从此归调用方管
+        pub(crate) fn into_path(mut self) -> PathBuf {
+            self.cleanup.disarmed = true;
+            self.cleanup.path.clone()
+        }
+
+        // 和 Drop 一样删除，但是把删除时的错误返回给调用方
+        pub(crate) fn close(mut self) -> io::Result<()> {
+            self.cleanup.remove()
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            self.path()
+        }
+    }
+
+    pub(crate) struct TempFile {
+        file: File,
+        cleanup: Cleanup,
+    }
+
+    impl TempFile {
+        pub(crate) fn new(prefix: &str) -> io::Result<TempFile> {
+            TempFile::new_in(&env::temp_dir(), prefix)
+        }
+
+        // create_new 保证文件是这次新建的，不会打开别人的同名文件
+        pub(crate) fn new_in(parent: &Path, prefix: &str) -> io::Result<TempFile> {
+            let mut file = None;
+            let path = create_unique(parent, prefix, |path| {
+                file = Some(
+                    OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create_new(true)
+                        .open(path)?,
+                );
+                Ok(())
+            })?;
+            Ok(TempFile {
+                file: file.unwrap(),
+                cleanup: Cleanup {
+                    path,
+                    is_dir: false,
+                    keep_on_failure: keep_on_failure_by_default(),
+                    disarmed: false,
+                },
+            })
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.cleanup.path
+        }
+
+        // 创建时打开的读写句柄；也可以不用它，按路径重新打开
+        pub(crate) fn file(&self) -> &File {
+            &self.file
+        }
+
+        pub(crate) fn close(mut self) -> io::Result<()> {
+            self.cleanup.remove()
+        }
+    }
+
+    impl AsRef<Path> for TempFile {
+        fn as_ref(&self) -> &Path {
+            self.path()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::tempfs::*;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::PathBuf;
+    use std::thread;
+
+    #[test]
+    fn directories_are_unique_and_removed_on_drop() {
+        // 多个线程同时创建，名字互不相同
+        let paths: Vec<PathBuf> = (0..8)
+            .map(|_| thread::spawn(|| TempDir::new("tempfs-unique").unwrap().into_path()))
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(paths.iter().collect::<HashSet<_>>().len(), 8);
+        // into_path 之后不会自动删除
+        for path in &paths {
+            assert!(path.is_dir());
+            fs::remove_dir(path).unwrap();
+        }
+
+        let dir = TempDir::new("tempfs-drop").unwrap();
+        let path = dir.path().to_path_buf();
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::write(dir.join("a/b/c.txt"), "c").unwrap();
+        let nested = TempDir::new_in(dir.path(), "nested").unwrap();
+        assert!(nested.path().starts_with(&path));
+        drop(dir);
+        // 连同里面的东西一起删掉；nested 的目录已经不在了，它再 Drop 也不会出错
+        assert!(!path.exists());
+        drop(nested);
+
+        // close 把删除的错误交给调用方
+        let dir = TempDir::new("tempfs-close").unwrap();
+        fs::remove_dir(dir.path()).unwrap();
+        assert_eq!(
+            dir.close().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn files_are_created_fresh_and_removed() {
+        let temp = TempFile::new("tempfs-file").unwrap();
+        let path = temp.path().to_path_buf();
+        let mut file = temp.file();
+        file.write_all(b"hello").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello");
+        assert_eq!(fs::read_to_string(&temp).unwrap(), "hello");
+
+        // 计数器一直往前走，已经存在的同名文件不会被拿来用
+        let other = TempFile::new_in(path.parent().unwrap(), "tempfs-file").unwrap();
+        assert_ne!(other.path(), path);
+        assert_eq!(fs::metadata(other.path()).unwrap().len(), 0);
+        drop(temp);
+        assert!(!path.exists());
+        other.close().unwrap();
+    }
+
+    #[test]
+    fn keep_on_failure_preserves_the_scene() {
+        let run = |keep: bool| {
+            let mut path = PathBuf::new();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let dir = TempDir::new("tempfs-failure")
+                    .unwrap()
+                    .keep_on_failure(keep);
+                path = dir.path().to_path_buf();
+                fs::write(dir.join("state.log"), "what went wrong").unwrap();
+                panic!("simulated test failure");
+            }));
+            assert!(result.is_err());
+            path
+        };
+        // 出错时留下来的目录里还有当时的文件
+        let kept = run(true);
+        assert_eq!(
+            fs::read_to_string(kept.join("state.log")).unwrap(),
+            "what went wrong"
+        );
+        fs::remove_dir_all(kept).unwrap();
+        assert!(!run(false).exists());
+
+        // 没有 panic 时照常删除
+        let dir = TempDir::new("tempfs-success")
+            .unwrap()
+            .keep_on_failure(true);
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
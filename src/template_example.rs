@@ -0,0 +1,256 @@
+// 模板引擎：{{name}} 替换成上下文里的值，{{#each items}}...{{/each}} 对列表中的每一项重复渲染中间的内容
+// 先把模板解析成节点树，渲染时只需要遍历节点，同一个模板可以用不同的上下文渲染多次
+#[cfg(test)]
+pub(crate) mod template {
+
+    use std::collections::HashMap;
+    use std::fmt;
+
+    pub(crate) type Context = HashMap<String, Value>;
+
+    pub(crate) enum Value {
+        Text(String),
+        List(Vec<Value>),
+        Map(Context),
+    }
+
+    impl From<&str> for Value {
+        fn from(text: &str) -> Value {
+            Value::Text(text.to_string())
+        }
+    }
+
+    impl From<String> for Value {
+        fn from(text: String) -> Value {
+            Value::Text(text)
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Node {
+        Text(String),
+        // {{name}}，{{.}} 表示循环中的当前项本身
+        Var(String),
+        Each(String, Vec<Node>),
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct TemplateError {
+        message: String,
+    }
+
+    impl fmt::Display for TemplateError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "template error: {}", self.message)
+        }
+    }
+
+    fn error(message: String) -> TemplateError {
+        TemplateError { message }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct Template {
+        nodes: Vec<Node>,
+    }
+
+    impl Template {
+        pub(crate) fn parse(source: &str) -> Result<Template, TemplateError> {
+            // 栈顶是当前正在收集子节点的块，最底下是整个模板
+            let mut stack: Vec<(Option<String>, Vec<Node>)> = vec![(None, Vec::new())];
+            let mut rest = source;
+
+            while let Some(start) = rest.find("{{") {
+                if start > 0 {
+                    push(&mut stack, Node::Text(rest[..start].to_string()));
+                }
+                let end = rest[start..]
+                    .find("}}")
+                    .ok_or_else(|| error(String::from("unclosed {{")))?;
+                let tag = rest[start + 2..start + end].trim();
+                rest = &rest[start + end + 2..];
+
+                if let Some(name) = tag.strip_prefix("#each ") {
+                    stack.push((Some(name.trim().to_string()), Vec::new()));
+                } else if tag == "/each" {
+                    // 最底下的那一层不是块，不能被 {{/each}} 关闭
+                    if stack.len() == 1 {
+                        return Err(error(String::from("{{/each}} without {{#each}}")));
+                    }
+                    let (name, children) = stack.pop().unwrap();
+                    push(&mut stack, Node::Each(name.unwrap(), children));
+                } else if tag.is_empty() {
+                    return Err(error(String::from("empty tag")));
+                } else {
+                    push(&mut stack, Node::Var(tag.to_string()));
+                }
+            }
+            if !rest.is_empty() {
+                push(&mut stack, Node::Text(rest.to_string()));
+            }
+
+            if stack.len() > 1 {
+                let (name, _) = stack.pop().unwrap();
+                return Err(error(format!("unclosed {{{{#each {}}}}}", name.unwrap())));
+            }
+            Ok(Template {
+                nodes: stack.pop().unwrap().1,
+            })
+        }
+
+        // 缺失的变量渲染成空字符串；替换进来的文本会做 HTML 转义，避免上下文里的内容被当成标签
+        pub(crate) fn render(&self, context: &Context) -> String {
+            let mut out = String::new();
+            let root = Value::Map(Context::new());
+            // 作用域链：循环里先查当前项，找不到再查外层
+            let mut scopes = vec![&root];
+            render_nodes(&self.nodes, context, &mut scopes, &mut out);
+            out
+        }
+    }
+
+    fn push(stack: &mut [(Option<String>, Vec<Node>)], node: Node) {
+        stack.last_mut().unwrap().1.push(node);
+    }
+
+    fn lookup<'a>(name: &str, context: &'a Context, scopes: &[&'a Value]) -> Option<&'a Value> {
+        if name == "." {
+            return scopes.last().copied();
+        }
+        scopes
+            .iter()
+            .rev()
+            .find_map(|scope| match scope {
+                Value::Map(map) => map.get(name),
+                _ => None,
+            })
+            .or_else(|| context.get(name))
+    }
+
+    fn render_nodes<'a>(
+        nodes: &'a [Node],
+        context: &'a Context,
+        scopes: &mut Vec<&'a Value>,
+        out: &mut String,
+    ) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Var(name) => {
+                    if let Some(Value::Text(text)) = lookup(name, context, scopes) {
+                        escape_html(text, out);
+                    }
+                }
+                Node::Each(name, children) => {
+                    if let Some(Value::List(items)) = lookup(name, context, scopes) {
+                        for item in items {
+                            scopes.push(item);
+                            render_nodes(children, context, scopes, out);
+                            scopes.pop();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn escape_html(text: &str, out: &mut String) {
+        for c in text.chars() {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&#39;"),
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::template::*;
+
+    fn context(pairs: Vec<(&str, Value)>) -> Context {
+        pairs
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let template = Template::parse("Hello, {{ name }}! {{missing}}bye").unwrap();
+        let rendered = template.render(&context(vec![("name", "Ferris".into())]));
+        assert_eq!(rendered, "Hello, Ferris! bye");
+    }
+
+    #[test]
+    fn escapes_html() {
+        let template = Template::parse("<p>{{comment}}</p>").unwrap();
+        let rendered = template.render(&context(vec![(
+            "comment",
+            "<script>alert('x') & \"y\"</script>".into(),
+        )]));
+        assert_eq!(
+            rendered,
+            "<p>&lt;script&gt;alert(&#39;x&#39;) &amp; &quot;y&quot;&lt;/script&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn loops_over_lists() {
+        let template = Template::parse(
+            "{{#each users}}<li>{{name}} ({{role}}) @ {{site}}</li>{{/each}}|{{#each tags}}[{{.}}]{{/each}}",
+        )
+        .unwrap();
+        let user = |name: &str, role: &str| {
+            Value::Map(context(vec![("name", name.into()), ("role", role.into())]))
+        };
+        let ctx = context(vec![
+            ("site", "example.com".into()),
+            (
+                "users",
+                Value::List(vec![user("alice", "admin"), user("bob", "dev")]),
+            ),
+            ("tags", Value::List(vec!["a".into(), "b".into()])),
+        ]);
+        // 循环里查不到的变量（site）回到外层上下文中查找
+        assert_eq!(
+            template.render(&ctx),
+            "<li>alice (admin) @ example.com</li><li>bob (dev) @ example.com</li>|[a][b]"
+        );
+    }
+
+    #[test]
+    fn nested_loops() {
+        let template =
+            Template::parse("{{#each rows}}{{#each cells}}{{.}},{{/each}};{{/each}}").unwrap();
+        let row = |cells: &[&str]| {
+            Value::Map(context(vec![(
+                "cells",
+                Value::List(cells.iter().map(|&cell| cell.into()).collect()),
+            )]))
+        };
+        let ctx = context(vec![(
+            "rows",
+            Value::List(vec![row(&["1", "2"]), row(&["3"]), row(&[])]),
+        )]);
+        assert_eq!(template.render(&ctx), "1,2,;3,;;");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (source, message) in [
+            ("{{name", "unclosed {{"),
+            ("{{/each}}", "{{/each}} without {{#each}}"),
+            ("{{#each items}}x", "unclosed {{#each items}}"),
+            ("{{ }}", "empty tag"),
+        ] {
+            let err = Template::parse(source).unwrap_err();
+            assert_eq!(err.to_string(), format!("template error: {}", message));
+        }
+    }
+}
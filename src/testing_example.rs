@@ -17,8 +17,13 @@ mod tests {
         a + 2
     }
 
+    // 问候语来自 i18n 的消息目录，greeting_in 可以指定语言
     pub fn greeting(name: &str) -> String {
-        format!("Hello {}!", name)
+        greeting_in("en", name)
+    }
+
+    pub fn greeting_in(locale: &str, name: &str) -> String {
+        crate::i18n_example::i18n::builtin().text(locale, "greeting", &[("name", name)])
     }
 
     pub struct Guess {
@@ -111,6 +116,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn greeting_is_localized() {
+        assert_eq!(greeting("Carol"), "Hello, Carol!");
+        assert_eq!(greeting_in("fr", "Carol"), "Bonjour, Carol !");
+        // 没有翻译的语言用英语
+        assert_eq!(greeting_in("de", "Carol"), greeting("Carol"));
+    }
+
     #[test]
     // should_panic检查代码是否按期望处理错误
     // expected匹配具体的错误信息
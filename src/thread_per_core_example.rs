@@ -0,0 +1,239 @@
+// 每核一线程（thread-per-core）：每个核心一个单线程运行时和一个独立的监听 socket，任务从不跨线程迁移
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+
+    use crate::runtime_example::runtime_factory::{Preset, RuntimeFactory};
+    use std::io::{self, Read, Write};
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpSocket, TcpStream};
+    use tokio::runtime::Runtime;
+    use tokio::sync::watch;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+    // 当前进程允许运行在哪些 CPU 核心上（容器里可能只分配了一部分核心）
+    fn allowed_cores() -> Vec<usize> {
+        // SAFETY: cpu_set_t 是纯位图，全零是合法的初始值；sched_getaffinity 只写入我们传入大小的内存
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return vec![0];
+            }
+            (0..libc::CPU_SETSIZE as usize)
+                .filter(|&core| libc::CPU_ISSET(core, &set))
+                .collect()
+        }
+    }
+
+    // 把当前线程绑定到指定核心，线程不会再被调度到其他核心上，CPU 缓存始终是热的
+    fn pin_to_core(core: usize) -> io::Result<()> {
+        // SAFETY: 同上，set 是栈上的有效位图，pid 为 0 表示当前线程
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            libc::CPU_SET(core, &mut set);
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // 设置了 SO_REUSEPORT 的多个 socket 可以绑定同一个端口，内核按连接的四元组哈希把新连接分给其中一个监听者，
+    // 这样每个核心都有自己的 accept 队列，不需要在线程之间争抢同一个监听 socket
+    fn reuseport_socket(addr: SocketAddr) -> io::Result<TcpSocket> {
+        let socket = TcpSocket::new_v4()?;
+        socket.set_reuseaddr(true)?;
+        socket.set_reuseport(true)?;
+        socket.bind(addr)?;
+        Ok(socket)
+    }
+
+    // 一个连接上可以连续发送多个请求（keep-alive），每读到一个完整的请求头就回一个响应
+    async fn serve_connection(mut stream: TcpStream) {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            while let Some(pos) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                buf.drain(..pos + 4);
+                if stream.write_all(RESPONSE).await.is_err() {
+                    return;
+                }
+            }
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    // 不停地接受连接，直到收到关闭通知
+    async fn accept_loop(
+        listener: TcpListener,
+        mut shutdown: watch::Receiver<bool>,
+        accepted: Arc<AtomicUsize>,
+    ) {
+        loop {
+            tokio::select! {
+                result = listener.accept() => {
+                    if let Ok((stream, _)) = result {
+                        accepted.fetch_add(1, Ordering::SeqCst);
+                        // 在单线程运行时中，spawn 出来的任务也只会在这个线程上执行
+                        tokio::spawn(serve_connection(stream));
+                    }
+                }
+                _ = shutdown.changed() => return,
+            }
+        }
+    }
+
+    struct ThreadPerCore {
+        addr: SocketAddr,
+        shutdown: watch::Sender<bool>,
+        threads: Vec<JoinHandle<()>>,
+        // 每个核心各自接受了多少个连接
+        accepted: Vec<Arc<AtomicUsize>>,
+    }
+
+    impl ThreadPerCore {
+        fn start(threads: usize) -> io::Result<ThreadPerCore> {
+            let cores = allowed_cores();
+            // 先绑定一个端口号为 0 的 socket 拿到实际端口，其余线程绑定同一个端口
+            let first = reuseport_socket("127.0.0.1:0".parse().unwrap())?;
+            let addr = first.local_addr()?;
+            let mut sockets = vec![first];
+            for _ in 1..threads {
+                sockets.push(reuseport_socket(addr)?);
+            }
+
+            let (shutdown, receiver) = watch::channel(false);
+            let mut handles = Vec::new();
+            let mut accepted = Vec::new();
+            for (i, socket) in sockets.into_iter().enumerate() {
+                let core = cores[i % cores.len()];
+                let receiver = receiver.clone();
+                let counter = Arc::new(AtomicUsize::new(0));
+                accepted.push(Arc::clone(&counter));
+
+                handles.push(thread::spawn(move || {
+                    if let Err(e) = pin_to_core(core) {
+                        eprintln!("failed to pin thread {} to core {}: {}", i, core, e);
+                    }
+                    // 每个线程一个 current_thread 运行时：没有工作窃取，也没有跨线程唤醒
+                    let rt = RuntimeFactory::new(Preset::SingleThreadTest)
+                        .name(&format!("core-{}", i))
+                        .build()
+                        .unwrap();
+                    rt.block_on(async move {
+                        // listen 需要把 socket 注册到当前运行时的 reactor 上，所以在运行时内部调用
+                        let listener = socket.listen(1024).unwrap();
+                        accept_loop(listener, receiver, counter).await;
+                    });
+                }));
+            }
+
+            Ok(ThreadPerCore {
+                addr,
+                shutdown,
+                threads: handles,
+                accepted,
+            })
+        }
+
+        // 通知所有线程退出并等待它们结束，返回每个核心接受的连接数
+        fn stop(self) -> Vec<usize> {
+            self.shutdown.send(true).unwrap();
+            for handle in self.threads {
+                handle.join().unwrap();
+            }
+            self.accepted
+                .iter()
+                .map(|counter| counter.load(Ordering::SeqCst))
+                .collect()
+        }
+    }
+
+    // 对照组：runtime_example 里默认的多线程运行时，所有工作线程共享一个监听 socket，任务可以被其他线程窃取
+    fn start_multi_thread(workers: usize) -> (Runtime, SocketAddr, watch::Sender<bool>) {
+        let rt = RuntimeFactory::new(Preset::IoHeavy)
+            .worker_threads(workers)
+            .build()
+            .unwrap();
+        let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, receiver) = watch::channel(false);
+        rt.spawn(accept_loop(
+            listener,
+            receiver,
+            Arc::new(AtomicUsize::new(0)),
+        ));
+        (rt, addr, shutdown)
+    }
+
+    // 压测：connections 个阻塞客户端线程，每个在同一个连接上顺序发送 requests 个请求，返回总耗时
+    fn run_load(addr: SocketAddr, connections: usize, requests: usize) -> Duration {
+        let started = Instant::now();
+        let clients: Vec<_> = (0..connections)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+                    stream.set_nodelay(true).unwrap();
+                    let mut response = [0; RESPONSE.len()];
+                    for _ in 0..requests {
+                        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+                        stream.read_exact(&mut response).unwrap();
+                        assert_eq!(response, RESPONSE);
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        started.elapsed()
+    }
+
+    #[test]
+    fn thread_per_core_serves_requests() {
+        let server = ThreadPerCore::start(2).unwrap();
+        run_load(server.addr, 8, 20);
+        let accepted = server.stop();
+        // 内核把 8 个连接分给了两个监听者，具体怎么分取决于哈希结果
+        assert_eq!(accepted.iter().sum::<usize>(), 8);
+        println!("connections accepted per core: {:?}", accepted);
+    }
+
+    // cargo test bench_thread_per_core -- --ignored --show-output
+    #[test]
+    #[ignore]
+    fn bench_thread_per_core() {
+        const CONNECTIONS: usize = 32;
+        const REQUESTS: usize = 2000;
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        let total = (CONNECTIONS * REQUESTS) as f64;
+
+        let server = ThreadPerCore::start(cores).unwrap();
+        let per_core = run_load(server.addr, CONNECTIONS, REQUESTS);
+        let accepted = server.stop();
+
+        let (rt, addr, shutdown) = start_multi_thread(cores);
+        let multi_thread = run_load(addr, CONNECTIONS, REQUESTS);
+        shutdown.send(true).unwrap();
+        drop(rt);
+
+        println!(
+            "{} cores, {} connections x {} requests: thread-per-core {:.0} req/s (accepted {:?}), multi-thread runtime {:.0} req/s",
+            cores,
+            CONNECTIONS,
+            REQUESTS,
+            total / per_core.as_secs_f64(),
+            accepted,
+            total / multi_thread.as_secs_f64()
+        );
+    }
+}
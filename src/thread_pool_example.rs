@@ -0,0 +1,1385 @@
+// 线程池：固定数量的 worker 线程从一个共享的任务队列里取任务执行，最早写在 webserver_example 里处理连接，
+// 搬到这里之后其他例子也可以直接使用
+//
+// 用法：
+// - ThreadPool::new(size) 创建 size 个 worker；with_queue 还可以指定队列容量和队列满时的 RejectionPolicy
+// - ThreadPoolBuilder 可以设置以上所有参数以及 worker 线程的栈大小，worker 线程名为 worker-{id}
+// - execute 提交不需要结果的任务，execute_with_priority 让高优先级的任务插队
+// - submit 提交有返回值的任务，返回的 JobHandle 可以 join 等待结果，任务 panic 时得到 panic 的负载
+// - schedule / schedule_repeating 延迟或者周期性地提交任务，时间取自 ThreadPoolBuilder::clock 设置的时钟，测试里可以换成模拟时钟
+// - stats 返回提交、完成、panic、拒绝的任务数和每个 worker 的忙碌时间
+// - 线程池被丢弃时等待队列里剩下的任务执行完，再 join 所有 worker
+// - WorkStealingPool 是工作窃取的版本，适合大量在任务内部继续提交的小任务，spawner 返回的句柄在 worker 上提交时进入该 worker 自己的队列
+#[cfg(test)]
+pub(crate) mod thread_pool {
+
+    use crate::clock_example::clock::{self, Clock};
+    use std::{
+        any::Any,
+        cell::Cell,
+        collections::{BinaryHeap, VecDeque},
+        fmt, io,
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            mpsc, Arc, Condvar, Mutex, OnceLock,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    pub(crate) struct ThreadPool {
+        workers: Vec<Worker>,
+        queue: Arc<JobQueue>,
+        policy: RejectionPolicy,
+        counters: Arc<PoolCounters>,
+        started: Instant,
+        clock: Arc<dyn Clock>,
+        // 第一次调用 schedule 时才启动定时器线程，不用定时任务的线程池不多占一个线程
+        timer: OnceLock<Timer>,
+    }
+
+    // 线程池和所有 worker 共享的计数器，只用原子操作更新，不影响任务的并发执行
+    struct PoolCounters {
+        submitted: AtomicUsize,
+        completed: AtomicUsize,
+        panicked: AtomicUsize,
+        rejected: AtomicUsize,
+        // 每个 worker 执行任务花费的总时间（纳秒）
+        busy_ns: Vec<AtomicU64>,
+    }
+
+    // stats() 返回的快照，各个数字是分别读取的，并发运行时彼此之间可能差一两个任务
+    #[derive(Debug)]
+    pub(crate) struct PoolStats {
+        pub(crate) submitted: usize,
+        pub(crate) completed: usize,
+        pub(crate) panicked: usize,
+        pub(crate) rejected: usize,
+        pub(crate) queued: usize,
+        pub(crate) busy: Vec<Duration>,
+        pub(crate) uptime: Duration,
+    }
+
+    impl PoolStats {
+        // 所有 worker 的忙碌时间占线程池运行时间的比例
+        pub(crate) fn utilization(&self) -> f64 {
+            let capacity = self.uptime.as_secs_f64() * self.busy.len() as f64;
+            if capacity == 0.0 {
+                return 0.0;
+            }
+            self.busy.iter().map(Duration::as_secs_f64).sum::<f64>() / capacity
+        }
+    }
+
+    impl fmt::Display for PoolStats {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(
+                f,
+                "jobs: {} submitted, {} completed, {} panicked, {} rejected, {} queued",
+                self.submitted, self.completed, self.panicked, self.rejected, self.queued
+            )?;
+            writeln!(
+                f,
+                "utilization: {:.1}% over {:.2?}",
+                self.utilization() * 100.0,
+                self.uptime
+            )?;
+            for (id, busy) in self.busy.iter().enumerate() {
+                writeln!(f, "  worker {} busy {:.2?}", id, busy)?;
+            }
+            Ok(())
+        }
+    }
+
+    // 队列满了（所有 worker 都在忙，排队的任务也到了上限）时怎么处理新任务
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum RejectionPolicy {
+        // 提交任务的线程阻塞，直到队列腾出位置
+        Block,
+        // 立即拒绝，由调用方决定怎么办
+        Reject,
+        // 在提交任务的线程上直接运行，调用方忙着执行任务就顾不上继续提交，自然就慢下来了
+        RunOnCaller,
+    }
+
+    // 任务被 RejectionPolicy::Reject 拒绝
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct Rejected;
+
+    impl fmt::Display for Rejected {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "job rejected: the thread pool queue is full")
+        }
+    }
+
+    // ThreadPool::new 的队列容量，比服务器允许排队的连接数（max_pending）大得多，服务器的准入控制先起作用
+    const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+    // Job 是一个有着 execute 接收到的闭包类型的 trait 对象的类型别名
+    pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    // 任务的优先级，声明的顺序决定了大小：Low < Normal < High
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub(crate) enum Priority {
+        Low,
+        Normal,
+        High,
+    }
+
+    // 队列里的任务，先比优先级，优先级相同时序号小的（先提交的）排在前面
+    struct Queued {
+        priority: Priority,
+        seq: u64,
+        job: Job,
+    }
+
+    impl PartialEq for Queued {
+        fn eq(&self, other: &Queued) -> bool {
+            self.cmp(other) == std::cmp::Ordering::Equal
+        }
+    }
+
+    impl Eq for Queued {}
+
+    impl PartialOrd for Queued {
+        fn partial_cmp(&self, other: &Queued) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    // BinaryHeap 是大顶堆，pop 出“最大”的元素，所以序号要反过来比较
+    impl Ord for Queued {
+        fn cmp(&self, other: &Queued) -> std::cmp::Ordering {
+            self.priority
+                .cmp(&other.priority)
+                .then(other.seq.cmp(&self.seq))
+        }
+    }
+
+    // 任务队列：mpsc 通道只能先进先出，要让高优先级的任务插队，就得自己用 Mutex 保护一个优先队列（BinaryHeap），
+    // 再用条件变量（Condvar）让 worker 在队列空时睡眠、让提交任务的线程在队列满时睡眠，有变化时互相唤醒
+    struct JobQueue {
+        state: Mutex<QueueState>,
+        not_empty: Condvar,
+        not_full: Condvar,
+        capacity: usize,
+    }
+
+    struct QueueState {
+        heap: BinaryHeap<Queued>,
+        next_seq: u64,
+        // 线程池被丢弃时设置，worker 做完队列里剩下的任务后退出
+        shutdown: bool,
+    }
+
+    impl JobQueue {
+        fn new(capacity: usize) -> JobQueue {
+            JobQueue {
+                state: Mutex::new(QueueState {
+                    heap: BinaryHeap::new(),
+                    next_seq: 0,
+                    shutdown: false,
+                }),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+                capacity,
+            }
+        }
+
+        // 队列满时 block 为 true 就等待，否则把任务原样还给调用方
+        fn push(&self, job: Job, priority: Priority, block: bool) -> Result<(), Job> {
+            let mut state = self.state.lock().unwrap();
+            while state.heap.len() >= self.capacity {
+                if !block {
+                    return Err(job);
+                }
+                // wait 会释放锁并睡眠，被唤醒后重新拿到锁；醒来时队列可能又被别人填满了，所以要用 while 重新检查
+                state = self.not_full.wait(state).unwrap();
+            }
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.heap.push(Queued { priority, seq, job });
+            self.not_empty.notify_one();
+            Ok(())
+        }
+
+        // 取出优先级最高的任务；队列空了并且线程池已经关闭时返回 None
+        fn pop(&self) -> Option<Job> {
+            let mut state = self.state.lock().unwrap();
+            loop {
+                if let Some(queued) = state.heap.pop() {
+                    self.not_full.notify_one();
+                    return Some(queued.job);
+                }
+                if state.shutdown {
+                    return None;
+                }
+                state = self.not_empty.wait(state).unwrap();
+            }
+        }
+
+        fn len(&self) -> usize {
+            self.state.lock().unwrap().heap.len()
+        }
+
+        fn shutdown(&self) {
+            self.state.lock().unwrap().shutdown = true;
+            self.not_empty.notify_all();
+        }
+    }
+
+    impl ThreadPool {
+        // 选择 usize 作为 size 参数的类型，因为我们知道为负的线程数没有意义
+        pub(crate) fn new(size: usize) -> ThreadPool {
+            ThreadPool::with_queue(size, DEFAULT_QUEUE_CAPACITY, RejectionPolicy::Block)
+        }
+
+        // capacity 是最多能排队等待的任务数，不包括 worker 正在执行的任务
+        pub(crate) fn with_queue(
+            size: usize,
+            capacity: usize,
+            policy: RejectionPolicy,
+        ) -> ThreadPool {
+            ThreadPoolBuilder::new()
+                .threads(size)
+                .queue_capacity(capacity)
+                .rejection_policy(policy)
+                .build()
+                .expect("failed to spawn a worker thread")
+        }
+
+        // spawn 使用 FnOnce 作为 F 的 trait bound，最终会将传递给 execute 的参数传给 spawn，处理请求的线程只会执行闭包一次，这也进一步确认了 FnOnce 是我们需要的 trait，这里符合 FnOnce 中 Once 的意思
+        // 需要 Send 来将闭包从一个线程转移到另一个线程，而 'static 是因为并不知道线程会执行多久
+        // FnOnce trait 仍然需要之后的 ()，因为这里的 FnOnce 代表一个没有参数也没有返回值的闭包。正如函数的定义，返回值类型可以从签名中省略，不过即便没有参数也需要括号
+        // 只有 RejectionPolicy::Reject 会返回错误，被拒绝的任务直接丢弃
+        pub(crate) fn execute<F>(&self, f: F) -> Result<(), Rejected>
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.execute_with_priority(f, Priority::Normal)
+        }
+
+        // 高优先级的任务排在所有低优先级的任务前面，同一优先级内先提交的先执行
+        // 已经在执行的任务不会被打断，插队只影响还在队列里等待的任务
+        pub(crate) fn execute_with_priority<F>(
+            &self,
+            f: F,
+            priority: Priority,
+        ) -> Result<(), Rejected>
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            // 把传递过来的闭包包装成 Box 放进队列
+            let job = Box::new(f);
+            let counters = &self.counters;
+            counters.submitted.fetch_add(1, Ordering::SeqCst);
+            let block = self.policy == RejectionPolicy::Block;
+            let Err(job) = self.queue.push(job, priority, block) else {
+                return Ok(());
+            };
+            if self.policy == RejectionPolicy::Reject {
+                counters.rejected.fetch_add(1, Ordering::SeqCst);
+                return Err(Rejected);
+            }
+            // 在调用方的线程上运行，任务 panic 时和调用方自己的代码 panic 一样向上传播
+            job();
+            counters.completed.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        // execute 提交后就不管了，拿不到任务的返回值；submit 额外建一个只发送一次的通道，任务结束后把结果送回来
+        // 任务 panic 时在任务内部截住，把 panic 的负载当作错误送给调用方，和 thread::JoinHandle::join 的行为一致
+        pub(crate) fn submit<F, T>(&self, f: F) -> JobHandle<T>
+        where
+            F: FnOnce() -> T + Send + 'static,
+            T: Send + 'static,
+        {
+            let (sender, receiver) = mpsc::sync_channel(1);
+            // 任务被拒绝时 sender 随任务一起被丢弃，join 会得到 JOB_LOST 错误
+            let _ = self.execute(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(f));
+                // 调用方可能已经丢弃了 JobHandle，这时结果没人要，发送失败也无妨
+                let _ = sender.send(result);
+            });
+            JobHandle { receiver }
+        }
+
+        // worker 线程的个数
+        pub(crate) fn size(&self) -> usize {
+            self.workers.len()
+        }
+
+        // 计数器的快照。submit 的任务在内部截住了 panic，交给调用方处理，所以算作 completed 而不是 panicked
+        pub(crate) fn stats(&self) -> PoolStats {
+            let counters = &self.counters;
+            PoolStats {
+                submitted: counters.submitted.load(Ordering::SeqCst),
+                completed: counters.completed.load(Ordering::SeqCst),
+                panicked: counters.panicked.load(Ordering::SeqCst),
+                rejected: counters.rejected.load(Ordering::SeqCst),
+                queued: self.queue.len(),
+                busy: counters
+                    .busy_ns
+                    .iter()
+                    .map(|ns| Duration::from_nanos(ns.load(Ordering::SeqCst)))
+                    .collect(),
+                uptime: self.started.elapsed(),
+            }
+        }
+    }
+
+    impl ThreadPool {
+        // delay 之后把任务放进队列，真正开始执行的时间还取决于队列里排在前面的任务
+        pub(crate) fn schedule<F>(&self, delay: Duration, f: F) -> Scheduled
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.timer()
+                .add(self.clock.now() + delay, Timed::Once(Box::new(f)))
+        }
+
+        // 每隔 interval 把任务放进队列一次，直到调用 Scheduled::cancel 或者线程池被丢弃
+        // 任务可能同时在好几个 worker 上运行（上一次还没结束下一次就到期了），所以要求 Fn + Sync
+        pub(crate) fn schedule_repeating<F>(&self, interval: Duration, f: F) -> Scheduled
+        where
+            F: Fn() + Send + Sync + 'static,
+        {
+            assert!(!interval.is_zero());
+            let task = Timed::Repeating(interval, Arc::new(f));
+            self.timer().add(self.clock.now() + interval, task)
+        }
+
+        fn timer(&self) -> &Timer {
+            self.timer.get_or_init(|| {
+                Timer::new(
+                    Arc::clone(&self.queue),
+                    Arc::clone(&self.counters),
+                    Arc::clone(&self.clock),
+                )
+            })
+        }
+    }
+
+    enum Timed {
+        Once(Job),
+        Repeating(Duration, Arc<dyn Fn() + Send + Sync>),
+    }
+
+    // 定时器堆里的一项，最早到期的排在堆顶；同时到期的按加入的顺序
+    struct TimerEntry {
+        due: Instant,
+        seq: u64,
+        cancelled: Arc<AtomicBool>,
+        task: Timed,
+    }
+
+    impl PartialEq for TimerEntry {
+        fn eq(&self, other: &TimerEntry) -> bool {
+            self.cmp(other) == std::cmp::Ordering::Equal
+        }
+    }
+
+    impl Eq for TimerEntry {}
+
+    impl PartialOrd for TimerEntry {
+        fn partial_cmp(&self, other: &TimerEntry) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    // 和 Queued 一样，大顶堆要 pop 出最早的，所以两个字段都反过来比较
+    impl Ord for TimerEntry {
+        fn cmp(&self, other: &TimerEntry) -> std::cmp::Ordering {
+            other.due.cmp(&self.due).then(other.seq.cmp(&self.seq))
+        }
+    }
+
+    struct TimerState {
+        heap: BinaryHeap<TimerEntry>,
+        next_seq: u64,
+        shutdown: bool,
+    }
+
+    // schedule 返回的句柄，丢弃句柄不会取消任务
+    pub(crate) struct Scheduled {
+        cancelled: Arc<AtomicBool>,
+    }
+
+    impl Scheduled {
+        // 已经放进队列的那一次仍然会执行，之后不再触发
+        pub(crate) fn cancel(&self) {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // 定时器线程：到期的任务不在这个线程上执行，而是放进线程池的队列，由 worker 执行，
+    // 定时器线程只负责等待，一个很慢的任务不会耽误其他任务按时触发
+    struct Timer {
+        shared: Arc<(Mutex<TimerState>, Condvar)>,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl Timer {
+        fn new(queue: Arc<JobQueue>, counters: Arc<PoolCounters>, clock: Arc<dyn Clock>) -> Timer {
+            let shared = Arc::new((
+                Mutex::new(TimerState {
+                    heap: BinaryHeap::new(),
+                    next_seq: 0,
+                    shutdown: false,
+                }),
+                Condvar::new(),
+            ));
+            // 时钟被拨动时叫醒定时器线程重新检查堆顶。先拿锁再通知：定时器线程读时间和开始等待之间一直持有锁，
+            // 不会漏掉这次通知。只持有弱引用，线程池丢弃以后回调什么也不做
+            let weak = Arc::downgrade(&shared);
+            clock.on_advance(Box::new(move || {
+                if let Some(shared) = weak.upgrade() {
+                    let _guard = shared.0.lock().unwrap();
+                    shared.1.notify_one();
+                }
+            }));
+            let thread = {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(String::from("thread-pool-timer"))
+                    .spawn(move || Timer::run(&shared, &queue, &counters, &*clock))
+                    .expect("failed to spawn the timer thread")
+            };
+            Timer {
+                shared,
+                thread: Some(thread),
+            }
+        }
+
+        fn add(&self, due: Instant, task: Timed) -> Scheduled {
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let (state, wakeup) = &*self.shared;
+            let mut state = state.lock().unwrap();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.heap.push(TimerEntry {
+                due,
+                seq,
+                cancelled: Arc::clone(&cancelled),
+                task,
+            });
+            // 新任务可能比定时器线程正在等待的那个更早到期，叫醒它重新计算等待时间
+            wakeup.notify_one();
+            Scheduled { cancelled }
+        }
+
+        fn run(
+            shared: &(Mutex<TimerState>, Condvar),
+            queue: &JobQueue,
+            counters: &PoolCounters,
+            clock: &dyn Clock,
+        ) {
+            let (state, wakeup) = shared;
+            let mut guard = state.lock().unwrap();
+            loop {
+                if guard.shutdown {
+                    return;
+                }
+                let now = clock.now();
+                let due = match guard.heap.peek() {
+                    None => {
+                        guard = wakeup.wait(guard).unwrap();
+                        continue;
+                    }
+                    Some(entry) => entry.due,
+                };
+                if due > now {
+                    // 被 add 叫醒、时钟被拨动或者超时都回到循环开头重新检查堆顶
+                    guard = match clock.timer(due) {
+                        Some(timeout) => wakeup.wait_timeout(guard, timeout).unwrap().0,
+                        None => wakeup.wait(guard).unwrap(),
+                    };
+                    continue;
+                }
+
+                let entry = guard.heap.pop().unwrap();
+                if entry.cancelled.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let job: Job = match entry.task {
+                    Timed::Once(job) => job,
+                    Timed::Repeating(interval, f) => {
+                        // 下一次的时间从这一次应该触发的时间算起，不随处理的延迟漂移；
+                        // 落后超过一个周期（例如队列堵住了）时跳过错过的那几次，而不是一口气补上
+                        let mut next = entry.due + interval;
+                        while next <= now {
+                            next += interval;
+                        }
+                        let job = Arc::clone(&f);
+                        guard.heap.push(TimerEntry {
+                            due: next,
+                            task: Timed::Repeating(interval, f),
+                            ..entry
+                        });
+                        Box::new(move || job())
+                    }
+                };
+                // 队列满时 push 会等待，先释放锁，不挡住 schedule 的调用方
+                drop(guard);
+                counters.submitted.fetch_add(1, Ordering::SeqCst);
+                // 不管线程池是哪种 RejectionPolicy，定时任务都等到队列有位置为止，不在定时器线程上执行
+                let _ = queue.push(job, Priority::Normal, true);
+                guard = state.lock().unwrap();
+            }
+        }
+    }
+
+    impl Drop for Timer {
+        // 还没到期的任务直接丢弃
+        fn drop(&mut self) {
+            let (state, wakeup) = &*self.shared;
+            state.lock().unwrap().shutdown = true;
+            wakeup.notify_one();
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    // 用链式调用逐项设置参数，最后 build 创建线程池，没有设置的参数使用默认值
+    // 和 std::thread::Builder 一样，每个设置方法都取得 self 的所有权再返回
+    pub(crate) struct ThreadPoolBuilder {
+        threads: usize,
+        stack_size: Option<usize>,
+        queue_capacity: usize,
+        policy: RejectionPolicy,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl ThreadPoolBuilder {
+        // 默认的线程数是 CPU 核数，取不到时用 1
+        pub(crate) fn new() -> ThreadPoolBuilder {
+            ThreadPoolBuilder {
+                threads: thread::available_parallelism().map_or(1, |n| n.get()),
+                stack_size: None,
+                queue_capacity: DEFAULT_QUEUE_CAPACITY,
+                policy: RejectionPolicy::Block,
+                clock: clock::system(),
+            }
+        }
+
+        pub(crate) fn threads(mut self, threads: usize) -> ThreadPoolBuilder {
+            self.threads = threads;
+            self
+        }
+
+        // worker 线程的栈大小（字节），不设置时使用标准库的默认值（2 MiB，可以用 RUST_MIN_STACK 环境变量修改）
+        // 任务里有深递归或者大的栈上数组时需要调大
+        pub(crate) fn stack_size(mut self, bytes: usize) -> ThreadPoolBuilder {
+            self.stack_size = Some(bytes);
+            self
+        }
+
+        pub(crate) fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+            self.queue_capacity = capacity;
+            self
+        }
+
+        pub(crate) fn rejection_policy(mut self, policy: RejectionPolicy) -> ThreadPoolBuilder {
+            self.policy = policy;
+            self
+        }
+
+        // schedule 用来计算到期时间的时钟，默认是系统时钟
+        pub(crate) fn clock(mut self, clock: Arc<dyn Clock>) -> ThreadPoolBuilder {
+            self.clock = clock;
+            self
+        }
+
+        // 创建线程失败时返回错误，参数不合法（线程数或队列容量为 0）时 panic
+        pub(crate) fn build(self) -> io::Result<ThreadPool> {
+            assert!(self.threads > 0);
+            assert!(self.queue_capacity > 0);
+
+            // 这里 JobQueue 充当任务队列的作用，execute 将任务放进队列，由正在寻找工作的 Worker 实例取走
+            // 队列最多容纳 capacity 个任务，满了以后按 RejectionPolicy 阻塞、拒绝或者在调用方线程上运行
+            // 为了在多个线程间共享所有权，需要使用 Arc，队列内部的 Mutex 确保一次只有一个线程能修改队列
+            let queue = Arc::new(JobQueue::new(self.queue_capacity));
+
+            // with_capacity 为 vector 预先分配空间。因为已经知道了 vector 中需要 size 个元素
+            // 预先进行分配比仅仅 Vec::new 要稍微有效率一些，因为 Vec::new 随着插入元素而重新改变大小
+            let size = self.threads;
+            let counters = Arc::new(PoolCounters {
+                submitted: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                panicked: AtomicUsize::new(0),
+                rejected: AtomicUsize::new(0),
+                busy_ns: (0..size).map(|_| AtomicU64::new(0)).collect(),
+            });
+
+            let mut pool = ThreadPool {
+                workers: Vec::with_capacity(size),
+                queue,
+                policy: self.policy,
+                counters,
+                started: Instant::now(),
+                clock: self.clock,
+                timer: OnceLock::new(),
+            };
+
+            for id in 0..size {
+                // 对于每一个新 worker，克隆 Arc 来增加引用计数，如此这些 worker 就可以共享队列的所有权了
+                // 创建线程失败（例如达到了系统的线程数上限）时直接返回错误，pool 被丢弃，已经启动的 worker 会被正常关闭
+                let worker = Worker::new(
+                    id,
+                    Arc::clone(&pool.queue),
+                    Arc::clone(&pool.counters),
+                    self.stack_size,
+                )?;
+                pool.workers.push(worker);
+            }
+            Ok(pool)
+        }
+    }
+
+    // submit 返回的句柄，可以阻塞等待结果，也可以不阻塞地查询任务是否已经完成
+    pub(crate) struct JobHandle<T> {
+        receiver: mpsc::Receiver<thread::Result<T>>,
+    }
+
+    impl<T> JobHandle<T> {
+        pub(crate) fn join(self) -> thread::Result<T> {
+            self.receiver
+                .recv()
+                .unwrap_or_else(|_| Err(Box::new(JOB_LOST)))
+        }
+
+        // 任务还没完成时返回 None
+        pub(crate) fn try_join(&self) -> Option<thread::Result<T>> {
+            match self.receiver.try_recv() {
+                Ok(result) => Some(result),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => Some(Err(Box::new(JOB_LOST))),
+            }
+        }
+    }
+
+    // 发送端在送出结果之前就被丢弃了，说明任务没有运行（例如线程池已经关闭）
+    pub(crate) const JOB_LOST: &str = "job was dropped before it produced a result";
+
+    // 为 ThreadPool 实现 Drop Trait，当线程池被丢弃时，应该 join 所有线程以确保他们完成其操作
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            // 先停掉定时器，它不会再往队列里放任务
+            drop(self.timer.take());
+
+            println!("Sending terminate message to all workers.");
+
+            // 通知所有 worker：队列里剩下的任务做完就退出
+            // 为什么通知要和 join 操作分开？
+            // 1. 如果逐个通知并立即 join，还没收到通知的 worker 会一直等待新任务，先被 join 的 worker 可能永远不会退出
+            // 2. 先一次性通知所有 worker，再逐个 join，每个 worker 都能在做完手头的任务后自行退出
+            self.queue.shutdown();
+
+            println!("Shutting down all workers.");
+
+            // 这里使用了 &mut 因为 self 本身是一个可变引用而且也需要能够修改 worker
+            for worker in &mut self.workers {
+                println!("Shutting down worker {}", worker.id);
+
+                // join 需要获取参数的所有权，worker 中的 thread 需要存放 Option<thread::JoinHandle<()> 而不是直接存放 thread::JoinHandle
+                // 如果 Worker 存放的是 Option<thread::JoinHandle<()>，就可以在 Option 上调用 take 方法将值从 Some 成员中移动出来而对 None 成员不做处理
+                // 正在运行的 Worker 的 thread 将是 Some 成员值，而当需要清理 worker 时，将 Some 替换为 None，这样 worker 就没有可以运行的线程了
+                // Option 上的 take 方法会取出 Some 而留下 None。使用 if let 解构 Some 并得到线程，接着在线程上调用 join。如果 worker 的线程已然是 None，就知道此时这个 worker 已经清理了其线程所以无需做任何操作
+                if let Some(thread) = worker.thread.take() {
+                    thread.join().unwrap();
+                }
+            }
+        }
+    }
+
+    // thread_local! 声明的变量每个线程各有一份，worker 线程启动时写入自己的 id，其他线程读到的是 None
+    thread_local! {
+        pub(crate) static WORKER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    // 任务 panic 时线程会一路展开到线程函数之外然后退出，线程池就少了一个 worker，而且没有任何提示
+    // catch_unwind 把 panic 截在任务这一层，记录下来之后 worker 继续接收下一个任务，线程池的容量保持不变
+    // 任务是 FnOnce，panic 之后就被丢弃了，不会有人再看到它可能处于不一致状态的数据，所以可以用 AssertUnwindSafe
+    // 任务正常结束时返回 true
+    pub(crate) fn run_job(job: Job, worker: &str, id: usize) -> bool {
+        match panic::catch_unwind(AssertUnwindSafe(job)) {
+            Ok(()) => true,
+            Err(payload) => {
+                eprintln!(
+                    "{} {} panicked while running a job: {}",
+                    worker,
+                    id,
+                    panic_message(&*payload)
+                );
+                false
+            }
+        }
+    }
+
+    // panic! 的参数是字面量时负载是 &str，带格式化参数时是 String，其他类型（panic_any）取不到文字
+    pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+        if let Some(message) = payload.downcast_ref::<&str>() {
+            message
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message
+        } else {
+            "non-string panic payload"
+        }
+    }
+
+    // 实现的行为是创建线程并稍后发送代码，这会在 ThreadPool 和线程间引入一个新数据类型来管理这种新行为。这个数据结构称为 Worker
+    struct Worker {
+        id: usize,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl Worker {
+        // spawn 返回 JoinHandle<T>，其中 T 是闭包返回的类型
+        // 我们的情况中，传递给线程池的闭包会处理连接并不返回任何值，所以 T 将会是单元类型 ()
+        // 用 thread::Builder 而不是 thread::spawn 创建线程，这样可以给线程命名、设置栈大小
+        // 线程名会出现在 panic 信息（thread 'worker-3' panicked at ...）和调试器的线程列表里，一眼就能看出是哪个 worker
+        fn new(
+            id: usize,
+            queue: Arc<JobQueue>,
+            counters: Arc<PoolCounters>,
+            stack_size: Option<usize>,
+        ) -> io::Result<Worker> {
+            let mut builder = thread::Builder::new().name(format!("worker-{}", id));
+            if let Some(bytes) = stack_size {
+                builder = builder.stack_size(bytes);
+            }
+            let thread = builder.spawn(move || {
+                // 记下当前线程属于哪个 worker，任务内部（例如访问日志）可以借此知道自己运行在哪个 worker 上
+                WORKER_ID.with(|worker_id| worker_id.set(Some(id)));
+
+                // 需要闭包一直循环，向队列请求任务，并在得到任务时执行他们
+                loop {
+                    // pop 在队列内部对互斥器调用 lock，接着 unwrap 在出现任何错误时 panic
+                    // 如果互斥器处于一种叫做 被污染（poisoned）的状态时获取锁可能会失败，这可能发生于其他线程在持有锁时 panic 了且没有释放锁
+                    // 队列为空时 pop 在条件变量上等待，阻塞当前线程直到有可用的任务；线程池关闭并且队列已空时返回 None
+                    let message = queue.pop();
+
+                    // loop循环的写法可以并发执行job：
+                    // 1. 使用 loop 并在循环块之内而不是之外获取锁和任务，pop 返回之前 MutexGuard 就被丢弃了
+                    // 2. 这确保了取任务的过程中持有锁，而在 job() 调用前锁就被释放了，这就允许并发处理多个请求了。
+                    match message {
+                        Some(job) => {
+                            println!("Worker {} got a job; executing.", id);
+                            let started = Instant::now();
+                            let finished = run_job(job, "Worker", id);
+                            counters.busy_ns[id]
+                                .fetch_add(started.elapsed().as_nanos() as u64, Ordering::SeqCst);
+                            let outcome = if finished {
+                                &counters.completed
+                            } else {
+                                &counters.panicked
+                            };
+                            outcome.fetch_add(1, Ordering::SeqCst);
+                        }
+                        None => {
+                            println!("Worker {} was told to terminate.", id);
+                            break;
+                        }
+                    }
+                }
+
+                // 下面这种写法无法让job的执行并发起来：
+                // 1. Mutex 结构体没有公有 unlock 方法，因为锁的所有权依赖 lock 方法返回的 LockResult<MutexGuard<T>> 中 MutexGuard<T> 的生命周期
+                // 2. 这允许借用检查器在编译时确保绝不会在没有持有锁的情况下访问由 Mutex 守护的资源，不过如果没有认真的思考 MutexGuard<T> 的生命周期的话，也可能会导致比预期更久的持有锁
+                // 3. 因为 while 表达式中的值 job 在整个块一直处于作用域中，job() 调用的过程中其仍然持有锁，这意味着其他 worker 不能接收任务
+                // 下面是队列还是 mpsc 通道时的写法
+                // while let Ok(job) = receiver.lock().unwrap().recv() {
+                //     println!("Worker {} got a job; executing.", id);
+                //     job();
+                // }
+            })?;
+
+            Ok(Worker {
+                id,
+                thread: Some(thread),
+            })
+        }
+    }
+
+    // 工作窃取线程池：上面 ThreadPool 的所有 worker 从同一个加锁的队列里取任务，任务很小时大部分时间都花在争抢这把锁上
+    // 这里每个 worker 有自己的双端队列，自己从队尾取（LIFO，刚放进去的任务数据还在缓存里），
+    // 自己的队列空了再去全局队列（injector）取，最后从其他 worker 的队头“偷”（FIFO，偷走最老的任务）
+    pub(crate) struct WorkStealingPool {
+        shared: Arc<StealShared>,
+        threads: Vec<thread::JoinHandle<()>>,
+    }
+
+    pub(crate) struct StealShared {
+        // 从线程池外部提交的任务
+        injector: Mutex<VecDeque<Job>>,
+        // 每个 worker 自己的队列，任务在 worker 内部提交时放在这里
+        locals: Vec<Mutex<VecDeque<Job>>>,
+        // false 时退化成只有一个全局队列的线程池，用来在基准测试中做对照
+        stealing: bool,
+        // 正在等待唤醒的 worker 数
+        sleeping: AtomicUsize,
+        sleep: Mutex<()>,
+        wakeup: Condvar,
+        shutdown: AtomicBool,
+    }
+
+    thread_local! {
+        // 当前线程是哪个工作窃取线程池的第几个 worker，用共享状态的地址区分不同的线程池
+        pub(crate) static STEAL_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+    }
+
+    impl StealShared {
+        fn id(&self) -> usize {
+            self as *const StealShared as usize
+        }
+
+        pub(crate) fn push(&self, job: Job) {
+            let local = STEAL_WORKER
+                .with(|worker| worker.get())
+                .filter(|&(pool, _)| self.stealing && pool == self.id());
+            match local {
+                Some((_, index)) => self.locals[index].lock().unwrap().push_back(job),
+                None => self.injector.lock().unwrap().push_back(job),
+            }
+            // 没有 worker 在睡眠时不碰 sleep 锁，否则每次提交都要争这把锁，又回到了一把锁的瓶颈
+            // worker 先把 sleeping 加一再检查一遍所有队列，这里先放入队列再检查 sleeping，
+            // 队列的锁保证了两边至少有一方能看到对方：要么 worker 找到这个任务，要么这里看到有人在睡眠
+            if self.sleeping.load(Ordering::SeqCst) > 0 {
+                // 拿到 sleep 锁再通知，保证 worker 已经开始等待，不会丢掉这次唤醒
+                let _guard = self.sleep.lock().unwrap();
+                self.wakeup.notify_one();
+            }
+        }
+
+        fn find_job(&self, index: usize) -> Option<Job> {
+            if self.stealing {
+                if let Some(job) = self.locals[index].lock().unwrap().pop_back() {
+                    return Some(job);
+                }
+            }
+            if let Some(job) = self.injector.lock().unwrap().pop_front() {
+                return Some(job);
+            }
+            if self.stealing {
+                // 从下一个 worker 开始依次尝试，避免所有空闲的 worker 都去偷同一个
+                let n = self.locals.len();
+                for offset in 1..n {
+                    let victim = (index + offset) % n;
+                    if let Some(job) = self.locals[victim].lock().unwrap().pop_front() {
+                        return Some(job);
+                    }
+                }
+            }
+            None
+        }
+
+        fn run_worker(&self, index: usize) {
+            STEAL_WORKER.with(|worker| worker.set(Some((self.id(), index))));
+            loop {
+                if let Some(job) = self.find_job(index) {
+                    run_job(job, "Work-stealing worker", index);
+                    continue;
+                }
+                let guard = self.sleep.lock().unwrap();
+                self.sleeping.fetch_add(1, Ordering::SeqCst);
+                if let Some(job) = self.find_job(index) {
+                    self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                    drop(guard);
+                    run_job(job, "Work-stealing worker", index);
+                    continue;
+                }
+                // 关闭时先把剩下的任务做完再退出
+                if self.shutdown.load(Ordering::SeqCst) {
+                    self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                    break;
+                }
+                let _guard = self.wakeup.wait(guard).unwrap();
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    impl WorkStealingPool {
+        pub(crate) fn new(size: usize) -> WorkStealingPool {
+            WorkStealingPool::with_stealing(size, true)
+        }
+
+        // 所有任务都进同一个全局队列，相当于 ThreadPool 的设计
+        pub(crate) fn shared_queue(size: usize) -> WorkStealingPool {
+            WorkStealingPool::with_stealing(size, false)
+        }
+
+        fn with_stealing(size: usize, stealing: bool) -> WorkStealingPool {
+            assert!(size > 0);
+            let shared = Arc::new(StealShared {
+                injector: Mutex::new(VecDeque::new()),
+                locals: (0..size).map(|_| Mutex::new(VecDeque::new())).collect(),
+                stealing,
+                sleeping: AtomicUsize::new(0),
+                sleep: Mutex::new(()),
+                wakeup: Condvar::new(),
+                shutdown: AtomicBool::new(false),
+            });
+            let threads = (0..size)
+                .map(|index| {
+                    let shared = Arc::clone(&shared);
+                    thread::spawn(move || shared.run_worker(index))
+                })
+                .collect();
+            WorkStealingPool { shared, threads }
+        }
+
+        pub(crate) fn execute<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.shared.push(Box::new(f));
+        }
+
+        // 任务内部用来继续提交任务的句柄，在 worker 线程上提交的任务进入该 worker 自己的队列
+        pub(crate) fn spawner(&self) -> Arc<StealShared> {
+            Arc::clone(&self.shared)
+        }
+    }
+
+    impl Drop for WorkStealingPool {
+        fn drop(&mut self) {
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            {
+                let _guard = self.shared.sleep.lock().unwrap();
+                self.shared.wakeup.notify_all();
+            }
+            for thread in self.threads.drain(..) {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::thread_pool::*;
+    use crate::clock_example::clock::SimClock;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Barrier, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn panicking_jobs_do_not_shrink_the_pool() {
+        let pool = ThreadPool::new(2);
+        for i in 0..4 {
+            pool.execute(move || panic!("job {} failed", i)).unwrap();
+        }
+        // 两个任务同时在屏障上等待主线程，只有两个 worker 都还活着时才能全部通过
+        let barrier = Arc::new(Barrier::new(3));
+        for _ in 0..2 {
+            let barrier = Arc::clone(&barrier);
+            pool.execute(move || {
+                barrier.wait();
+            })
+            .unwrap();
+        }
+        barrier.wait();
+
+        assert_eq!(panic_message(&"literal"), "literal");
+        assert_eq!(panic_message(&String::from("formatted")), "formatted");
+        assert_eq!(panic_message(&42), "non-string panic payload");
+    }
+
+    #[test]
+    fn submit_returns_results_and_panics() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (1..=5u64).map(|n| pool.submit(move || n * n)).collect();
+        let results: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, [1, 4, 9, 16, 25]);
+
+        let failed = pool.submit(|| -> u32 { panic!("bad input {}", 7) });
+        let payload = failed.join().unwrap_err();
+        assert_eq!(panic_message(&*payload), "bad input 7");
+
+        // 任务被屏障挡住时 try_join 立即返回 None，放行之后能取到结果
+        let barrier = Arc::new(Barrier::new(2));
+        let gate = Arc::clone(&barrier);
+        let handle = pool.submit(move || {
+            gate.wait();
+            "done"
+        });
+        assert!(handle.try_join().is_none());
+        barrier.wait();
+        let started = Instant::now();
+        let result = loop {
+            if let Some(result) = handle.try_join() {
+                break result;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(result.unwrap(), "done");
+    }
+
+    // 一个 worker、队列容量为 1 的线程池：第一个任务占住 worker，第二个任务排队，返回时队列已满
+    fn saturated_pool(policy: RejectionPolicy) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::with_queue(1, 1, policy);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        // 等 worker 真正取走第一个任务，否则它还占着队列的位置
+        running.recv().unwrap();
+        pool.execute(|| {}).unwrap();
+        (pool, release)
+    }
+
+    #[test]
+    fn rejection_policies() {
+        let (pool, release) = saturated_pool(RejectionPolicy::Reject);
+        let err = pool.execute(|| {}).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "job rejected: the thread pool queue is full"
+        );
+        // 被拒绝的任务交给 submit 时，句柄报告任务丢失
+        let lost = pool.submit(|| 1).join().unwrap_err();
+        assert_eq!(panic_message(&*lost), JOB_LOST);
+        drop(release);
+        drop(pool);
+
+        let (pool, release) = saturated_pool(RejectionPolicy::RunOnCaller);
+        let caller = thread::current().id();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(thread::current().id()).unwrap())
+            .unwrap();
+        assert_eq!(receiver.recv().unwrap(), caller);
+        drop(release);
+        drop(pool);
+
+        // Block：提交的线程一直等到 worker 空出来、队列有了位置
+        let (pool, release) = saturated_pool(RejectionPolicy::Block);
+        let pool = Arc::new(pool);
+        let (sender, receiver) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                pool.execute(|| {}).unwrap();
+                sender.send(()).unwrap();
+            })
+        };
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        release.send(()).unwrap();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        submitter.join().unwrap();
+    }
+
+    #[test]
+    fn high_priority_jobs_jump_the_queue() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        running.recv().unwrap();
+
+        // worker 被占住时按 低、普通、高 的顺序提交，同一优先级内保持提交顺序
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for (name, priority) in [
+            ("low", Priority::Low),
+            ("normal 1", Priority::Normal),
+            ("high 1", Priority::High),
+            ("normal 2", Priority::Normal),
+            ("high 2", Priority::High),
+        ] {
+            let order = Arc::clone(&order);
+            pool.execute_with_priority(move || order.lock().unwrap().push(name), priority)
+                .unwrap();
+        }
+        assert_eq!(pool.stats().queued, 5);
+        release.send(()).unwrap();
+        drop(pool);
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["high 1", "high 2", "normal 1", "normal 2", "low"]
+        );
+    }
+
+    #[test]
+    fn scheduled_jobs() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+        let start = Instant::now();
+        // 后加入但更早到期的任务先触发
+        for (name, delay) in [("late", 150), ("early", 50)] {
+            let sender = sender.clone();
+            pool.schedule(Duration::from_millis(delay), move || {
+                sender.send((name, start.elapsed())).unwrap();
+            });
+        }
+        let cancelled = {
+            let sender = sender.clone();
+            pool.schedule(Duration::from_millis(100), move || {
+                sender.send(("cancelled", start.elapsed())).unwrap();
+            })
+        };
+        cancelled.cancel();
+
+        let (name, elapsed) = receiver.recv().unwrap();
+        assert_eq!(name, "early");
+        assert!(elapsed >= Duration::from_millis(50));
+        let (name, elapsed) = receiver.recv().unwrap();
+        assert_eq!(name, "late");
+        assert!(elapsed >= Duration::from_millis(150));
+
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let repeating = {
+            let ticks = Arc::clone(&ticks);
+            pool.schedule_repeating(Duration::from_millis(20), move || {
+                ticks.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(150));
+        repeating.cancel();
+        // 取消时可能有一次已经进了队列，等它执行完再读
+        thread::sleep(Duration::from_millis(50));
+        let count = ticks.load(Ordering::SeqCst);
+        assert!(count >= 3, "only {} ticks", count);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(ticks.load(Ordering::SeqCst), count);
+
+        // 线程池被丢弃时还没到期的任务不会执行
+        pool.schedule(Duration::from_secs(60), move || {
+            sender.send(("never", start.elapsed())).unwrap();
+        });
+        drop(pool);
+        assert!(receiver.recv().is_err());
+    }
+
+    // 模拟时钟下一个小时的定时任务不用真的等，也不会因为机器慢而提前或者错过
+    #[test]
+    fn scheduled_jobs_on_simulated_clock() {
+        let sim = SimClock::new();
+        let pool = ThreadPoolBuilder::new()
+            .threads(1)
+            .clock(sim.clone())
+            .build()
+            .unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let quiet = Duration::from_millis(100);
+        let minute = Duration::from_secs(60);
+        {
+            let sender = sender.clone();
+            pool.schedule(60 * minute, move || sender.send("hour").unwrap());
+        }
+        sim.advance(59 * minute);
+        assert!(receiver.recv_timeout(quiet).is_err());
+        sim.advance(minute);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("hour"));
+
+        let ticks = pool.schedule_repeating(minute, move || sender.send("tick").unwrap());
+        for _ in 0..3 {
+            sim.advance(minute);
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("tick"));
+        }
+        // 一下子跳过十分钟只补一次，错过的那几次不会一口气补上
+        sim.advance(10 * minute);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("tick"));
+        assert!(receiver.recv_timeout(quiet).is_err());
+        ticks.cancel();
+        sim.advance(minute);
+        assert!(receiver.recv_timeout(quiet).is_err());
+    }
+
+    #[test]
+    fn pool_stats() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..6)
+            .map(|_| pool.submit(|| thread::sleep(Duration::from_millis(20))))
+            .collect();
+        pool.execute(|| panic!("counted")).unwrap();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // panic 的任务可能还没跑完，等计数器追上来
+        let started = Instant::now();
+        while pool.stats().completed + pool.stats().panicked < 7 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.submitted, 7);
+        assert_eq!(stats.completed, 6);
+        assert_eq!(stats.panicked, 1);
+        assert_eq!((stats.rejected, stats.queued), (0, 0));
+        // 6 个任务各睡 20ms，分到两个 worker 上
+        let busy: Duration = stats.busy.iter().sum();
+        assert!(busy >= Duration::from_millis(120), "{:?}", stats.busy);
+        assert!(stats.utilization() > 0.0 && stats.utilization() <= 1.0);
+        let text = stats.to_string();
+        assert!(
+            text.starts_with("jobs: 7 submitted, 6 completed, 1 panicked, 0 rejected, 0 queued\n")
+        );
+        assert!(text.contains("  worker 1 busy "));
+
+        // 被拒绝的任务和排在队列里的任务
+        let (pool, release) = saturated_pool(RejectionPolicy::Reject);
+        assert!(pool.execute(|| {}).is_err());
+        let stats = pool.stats();
+        assert_eq!((stats.submitted, stats.rejected, stats.queued), (3, 1, 1));
+        drop(release);
+    }
+
+    #[test]
+    fn builder_names_workers() {
+        let pool = ThreadPoolBuilder::new()
+            .threads(3)
+            .stack_size(16 * 1024 * 1024)
+            .queue_capacity(16)
+            .rejection_policy(RejectionPolicy::Reject)
+            .build()
+            .unwrap();
+        assert_eq!(pool.size(), 3);
+
+        // 三个任务在屏障上互相等待，保证分别运行在三个不同的 worker 上
+        let barrier = Arc::new(Barrier::new(3));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                    thread::current().name().map(String::from)
+                })
+            })
+            .collect();
+        let mut names: Vec<String> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["worker-0", "worker-1", "worker-2"]);
+
+        // 16 MiB 的栈上放得下 3 MiB 的数组（未优化的构建里还会复制一份），默认的 2 MiB 栈会溢出
+        let sum = pool.submit(|| {
+            let buffer = std::hint::black_box([1u8; 3 * 1024 * 1024]);
+            buffer.iter().map(|&b| b as u64).sum::<u64>()
+        });
+        assert_eq!(sum.join().unwrap(), 3 * 1024 * 1024);
+
+        let default = ThreadPoolBuilder::new().build().unwrap();
+        assert_eq!(
+            default.size(),
+            thread::available_parallelism().unwrap().get()
+        );
+    }
+
+    // 递归地把 [start, end) 拆成不超过 leaf 个数的小块，任务在 worker 上提交的新任务进入该 worker 自己的队列
+    fn split_sum(
+        spawner: Arc<StealShared>,
+        (start, end): (u64, u64),
+        leaf: u64,
+        total: Arc<AtomicU64>,
+    ) {
+        if end - start <= leaf {
+            total.fetch_add((start..end).sum::<u64>(), Ordering::SeqCst);
+            return;
+        }
+        let mid = (start + end) / 2;
+        let (left, left_total) = (Arc::clone(&spawner), Arc::clone(&total));
+        let right = Arc::clone(&spawner);
+        spawner.push(Box::new(move || {
+            split_sum(left, (start, mid), leaf, left_total)
+        }));
+        spawner.push(Box::new(move || split_sum(right, (mid, end), leaf, total)));
+    }
+
+    #[test]
+    fn work_stealing_pool_runs_nested_jobs() {
+        let total = Arc::new(AtomicU64::new(0));
+        {
+            let pool = WorkStealingPool::new(4);
+            let (spawner, sum) = (pool.spawner(), Arc::clone(&total));
+            pool.execute(move || split_sum(spawner, (0, 100_000), 100, sum));
+            // drop 会等所有任务（包括任务中提交的任务）执行完
+        }
+        assert_eq!(total.load(Ordering::SeqCst), (0..100_000u64).sum::<u64>());
+    }
+
+    #[test]
+    fn idle_workers_steal_from_busy_ones() {
+        let pool = WorkStealingPool::new(3);
+        let spawner = pool.spawner();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || {
+            let owner = STEAL_WORKER.with(|worker| worker.get()).unwrap().1;
+            // 在 worker 上提交的任务进入它自己的队列，然后它一直忙着，其他 worker 只能来偷
+            for _ in 0..20 {
+                let sender = sender.clone();
+                spawner.push(Box::new(move || {
+                    let index = STEAL_WORKER.with(|worker| worker.get()).unwrap().1;
+                    sender.send((owner, index)).unwrap();
+                }));
+            }
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        let ran: Vec<(usize, usize)> = receiver.iter().take(20).collect();
+        assert!(ran.iter().all(|&(owner, index)| owner != index));
+    }
+
+    // cargo test bench_work_stealing -- --ignored --show-output
+    #[test]
+    #[ignore]
+    fn bench_work_stealing() {
+        // 每个叶子任务只加 16 个数，拆分出来的任务有几十万个，调度开销占了绝大部分时间
+        // 锁争用只在多个核心同时运行 worker 时才会出现，单核机器上两种线程池的结果差不多
+        const RANGE: u64 = 4_000_000;
+        const LEAF: u64 = 16;
+        let workers = thread::available_parallelism().map_or(4, |n| n.get().max(4));
+
+        let run = |pool: WorkStealingPool| {
+            let total = Arc::new(AtomicU64::new(0));
+            let started = Instant::now();
+            // 所有 worker 都在不停地提交和执行任务：共享队列时每次入队出队都要争同一把锁，
+            // 工作窃取时 worker 大多只碰自己的队列，只有空闲时才去别人那里偷
+            let (spawner, sum) = (pool.spawner(), Arc::clone(&total));
+            pool.execute(move || split_sum(spawner, (0, RANGE), LEAF, sum));
+            drop(pool);
+            assert_eq!(total.load(Ordering::SeqCst), (0..RANGE).sum::<u64>());
+            started.elapsed()
+        };
+
+        // 叶子任务 RANGE / LEAF 个，加上拆分用的中间任务，总数约为它的两倍
+        let jobs = 2 * RANGE / LEAF;
+        let shared = run(WorkStealingPool::shared_queue(workers));
+        let stealing = run(WorkStealingPool::new(workers));
+        println!(
+            "{} workers, ~{} tiny jobs: shared queue {:?} ({:.0} jobs/s), work stealing {:?} ({:.0} jobs/s)",
+            workers,
+            jobs,
+            shared,
+            jobs as f64 / shared.as_secs_f64(),
+            stealing,
+            jobs as f64 / stealing.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn stealing_workers_survive_panicking_jobs() {
+        let stealing = WorkStealingPool::new(2);
+        stealing.execute(|| panic!("stealing job failed"));
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..10 {
+            let sender = sender.clone();
+            stealing.execute(move || sender.send(i).unwrap());
+        }
+        assert_eq!(receiver.iter().take(10).sum::<i32>(), 45);
+    }
+}
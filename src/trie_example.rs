@@ -0,0 +1,267 @@
+// 字典树（Trie，前缀树）：每条边是一个字符，从根走到某个节点经过的字符连起来就是一个前缀，
+// 节点上的 terminal 标记表示这个前缀本身也是一个插入过的单词
+// 查找、插入都只和单词长度有关，和存了多少单词无关；有共同前缀的单词共用一段路径，
+// 所以找出某个前缀下的所有单词（自动补全）只需要先走到前缀对应的节点，再遍历这棵子树
+// 孩子用 BTreeMap 按字符排序，先序遍历（先输出节点自己再依次进入孩子）得到的单词正好是字典序
+#[cfg(test)]
+pub(crate) mod trie {
+
+    use std::collections::BTreeMap;
+    use std::sync::OnceLock;
+
+    #[derive(Default)]
+    struct Node {
+        children: BTreeMap<char, Node>,
+        terminal: bool,
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Trie {
+        root: Node,
+        len: usize,
+    }
+
+    impl Trie {
+        pub(crate) fn new() -> Trie {
+            Trie::default()
+        }
+
+        // 单词的个数，不是节点的个数
+        pub(crate) fn len(&self) -> usize {
+            self.len
+        }
+
+        pub(crate) fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        // 返回 false 表示这个单词已经在里面了
+        pub(crate) fn insert(&mut self, word: &str) -> bool {
+            let mut node = &mut self.root;
+            for c in word.chars() {
+                node = node.children.entry(c).or_default();
+            }
+            let inserted = !node.terminal;
+            node.terminal = true;
+            self.len += inserted as usize;
+            inserted
+        }
+
+        fn find(&self, prefix: &str) -> Option<&Node> {
+            prefix
+                .chars()
+                .try_fold(&self.root, |node, c| node.children.get(&c))
+        }
+
+        pub(crate) fn contains(&self, word: &str) -> bool {
+            self.find(word).is_some_and(|node| node.terminal)
+        }
+
+        // 有没有以 prefix 开头的单词
+        pub(crate) fn starts_with(&self, prefix: &str) -> bool {
+            self.find(prefix).is_some()
+        }
+
+        // 所有单词，按字典序
+        pub(crate) fn iter(&self) -> Words<'_> {
+            self.words_with_prefix("")
+        }
+
+        // 以 prefix 开头的单词（包括 prefix 自己），按需生成，只要前几个时不用遍历整棵子树
+        pub(crate) fn words_with_prefix(&self, prefix: &str) -> Words<'_> {
+            Words {
+                stack: self
+                    .find(prefix)
+                    .map(|node| (prefix.to_string(), node))
+                    .into_iter()
+                    .collect(),
+            }
+        }
+
+        pub(crate) fn complete(&self, prefix: &str) -> Vec<String> {
+            self.words_with_prefix(prefix).collect()
+        }
+    }
+
+    impl<'a> FromIterator<&'a str> for Trie {
+        fn from_iter<I: IntoIterator<Item = &'a str>>(words: I) -> Trie {
+            let mut trie = Trie::new();
+            for word in words {
+                trie.insert(word);
+            }
+            trie
+        }
+    }
+
+    impl<'a> IntoIterator for &'a Trie {
+        type Item = String;
+        type IntoIter = Words<'a>;
+
+        fn into_iter(self) -> Words<'a> {
+            self.iter()
+        }
+    }
+
+    // 用栈做先序遍历，栈里是还没访问的节点和走到它的前缀；孩子倒着压栈，字符小的先弹出来
+    pub(crate) struct Words<'a> {
+        stack: Vec<(String, &'a Node)>,
+    }
+
+    impl Iterator for Words<'_> {
+        type Item = String;
+
+        fn next(&mut self) -> Option<String> {
+            while let Some((word, node)) = self.stack.pop() {
+                for (c, child) in node.children.iter().rev() {
+                    let mut next = word.clone();
+                    next.push(*c);
+                    self.stack.push((next, child));
+                }
+                if node.terminal {
+                    return Some(word);
+                }
+            }
+            None
+        }
+    }
+
+    // 编译进程序里的词表 words.txt，一行一个单词
+    pub(crate) fn builtin() -> &'static Trie {
+        static BUILTIN: OnceLock<Trie> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            include_str!("../words.txt")
+                .lines()
+                .map(str::trim)
+                .filter(|word| !word.is_empty())
+                .collect()
+        })
+    }
+
+    // 输入提示：最多 limit 个以 input 开头的单词。
+    // 一个都没有时多半是打错了字，就把末尾的字符一个个去掉，用还能匹配上的最长前缀来提示
+    pub(crate) fn suggest(trie: &Trie, input: &str, limit: usize) -> Vec<String> {
+        let input = input.trim().to_lowercase();
+        let mut prefix = input.as_str();
+        while !prefix.is_empty() && !trie.starts_with(prefix) {
+            let mut chars = prefix.chars();
+            chars.next_back();
+            prefix = chars.as_str();
+        }
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        trie.words_with_prefix(prefix).take(limit).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::trie::*;
+    use std::collections::BTreeSet;
+    use std::io::{self, BufRead, Write};
+
+    #[test]
+    fn insert_contains_and_complete() {
+        let mut trie = Trie::new();
+        assert!(trie.is_empty() && trie.complete("").is_empty());
+        for word in ["tea", "ten", "to", "inn", "in", "tea"] {
+            trie.insert(word);
+        }
+        assert_eq!(trie.len(), 5);
+        assert!(!trie.insert("to"));
+        assert!(trie.insert("t"));
+
+        assert!(trie.contains("tea") && trie.contains("t") && trie.contains("in"));
+        // 前缀不等于单词
+        assert!(!trie.contains("te") && !trie.contains("i") && !trie.contains("teas"));
+        assert!(trie.starts_with("te") && !trie.starts_with("tx"));
+        assert!(!trie.contains("") && trie.starts_with(""));
+
+        assert_eq!(trie.complete("te"), ["tea", "ten"]);
+        assert_eq!(trie.complete("t"), ["t", "tea", "ten", "to"]);
+        assert_eq!(trie.complete("inn"), ["inn"]);
+        assert!(trie.complete("x").is_empty());
+        assert_eq!(
+            trie.iter().collect::<Vec<_>>(),
+            ["in", "inn", "t", "tea", "ten", "to"]
+        );
+
+        // 多字节字符按字符走，不按字节
+        let chinese: Trie = ["中国", "中文", "中", "英文"].into_iter().collect();
+        assert_eq!(chinese.complete("中"), ["中", "中国", "中文"]);
+        assert!(chinese.starts_with("英") && !chinese.contains("英"));
+    }
+
+    #[test]
+    fn iterates_in_sorted_order_like_a_btree_set() {
+        // 随机单词和 BTreeSet 对照：迭代顺序、去重、任意前缀的补全结果都一样
+        let mut seed: u64 = 99;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+        let mut trie = Trie::new();
+        let mut model = BTreeSet::new();
+        for _ in 0..2_000 {
+            let len = next() % 6;
+            let word: String = (0..len).map(|_| b"abcd"[next() % 4] as char).collect();
+            assert_eq!(trie.insert(&word), model.insert(word));
+        }
+        assert_eq!(trie.len(), model.len());
+        assert!(trie.iter().eq(model.iter().cloned()));
+        assert!((&trie).into_iter().eq(model.iter().cloned()));
+        for prefix in ["", "a", "ab", "dcb", "abcd", "abcda"] {
+            let expected: Vec<String> = model
+                .iter()
+                .filter(|word| word.starts_with(prefix))
+                .cloned()
+                .collect();
+            assert_eq!(trie.complete(prefix), expected, "{:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn suggests_words_from_the_bundled_list() {
+        let words = builtin();
+        assert_eq!(words.len(), include_str!("../words.txt").lines().count());
+        assert!(words.contains("computer") && !words.contains("comput"));
+
+        assert_eq!(
+            suggest(words, "com", 10),
+            ["come", "common", "company", "computer"]
+        );
+        assert_eq!(suggest(words, "wh", 3), ["what", "wheel", "when"]);
+        // 大小写和首尾空白不影响
+        assert_eq!(suggest(words, "  MOUN ", 5), ["mountain"]);
+        // 打错了的时候退回到还能匹配的最长前缀
+        assert_eq!(suggest(words, "mountian", 5), ["mountain"]);
+        assert_eq!(suggest(words, "appel", 5), ["apple"]);
+        assert!(suggest(words, "zzz", 5).is_empty());
+        assert!(suggest(words, "", 5).is_empty());
+    }
+
+    // 交互式演示：cargo test word_suggestion_demo -- --ignored --nocapture，每输入一行给出提示，空行结束
+    #[test]
+    #[ignore]
+    fn word_suggestion_demo() {
+        let words = builtin();
+        println!(
+            "{} words loaded, type a prefix (empty line to quit)",
+            words.len()
+        );
+        for line in io::stdin().lock().lines() {
+            let line = line.unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            match suggest(words, &line, 8).as_slice() {
+                [] => println!("no suggestions"),
+                suggestions => println!("{}", suggestions.join("  ")),
+            }
+            io::stdout().flush().unwrap();
+        }
+    }
+}
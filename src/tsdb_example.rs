@@ -0,0 +1,318 @@
+// 时序数据库：每个指标是一串按时间递增追加的 (时间戳, 数值) 样本，只追加、不修改
+// 样本按固定大小分块，块内时间戳和数值分成两列存放（列式存储），范围查询先按块的时间跨度跳过无关的块，
+// 再在块内二分查找；降采样把一段时间内的样本按固定步长分桶，每个桶只保留 最小/最大/平均 值，用来画趋势图
+// 长时间运行的进程可以给每个指标设一个样本数上限，超过后整块丢掉最旧的样本，内存不会无限增长
+#[cfg(test)]
+pub(crate) mod tsdb {
+
+    use serde::Serialize;
+    use std::collections::HashMap;
+    use std::fmt;
+
+    // 每块最多存放的样本数，写满后封存，之后的样本写入新块
+    const CHUNK_SIZE: usize = 128;
+
+    // 一个块里的样本，两列长度总是相同；时间戳单调不减，所以块内可以二分查找
+    struct Chunk {
+        timestamps: Vec<u64>,
+        values: Vec<f64>,
+    }
+
+    impl Chunk {
+        fn new() -> Chunk {
+            Chunk {
+                timestamps: Vec::with_capacity(CHUNK_SIZE),
+                values: Vec::with_capacity(CHUNK_SIZE),
+            }
+        }
+
+        fn first(&self) -> u64 {
+            self.timestamps[0]
+        }
+
+        fn last(&self) -> u64 {
+            self.timestamps[self.timestamps.len() - 1]
+        }
+
+        // 时间戳落在 [from, to) 内的样本的下标范围
+        fn span(&self, from: u64, to: u64) -> std::ops::Range<usize> {
+            let start = self.timestamps.partition_point(|&ts| ts < from);
+            let end = self.timestamps.partition_point(|&ts| ts < to);
+            start..end
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum TsdbError {
+        // 只能追加，时间戳比这个指标的最后一个样本还早的写入被拒绝
+        OutOfOrder {
+            metric: String,
+            last: u64,
+            timestamp: u64,
+        },
+        // 降采样的步长不能为 0
+        ZeroStep,
+    }
+
+    impl fmt::Display for TsdbError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                TsdbError::OutOfOrder {
+                    metric,
+                    last,
+                    timestamp,
+                } => write!(
+                    f,
+                    "out-of-order sample for {}: {} is before the last timestamp {}",
+                    metric, timestamp, last
+                ),
+                TsdbError::ZeroStep => write!(f, "downsampling step must be positive"),
+            }
+        }
+    }
+
+    // 降采样后的一个桶，覆盖 [start, start + step) 这段时间
+    #[derive(Serialize, Debug, PartialEq)]
+    pub(crate) struct Bucket {
+        pub(crate) start: u64,
+        pub(crate) count: usize,
+        pub(crate) min: f64,
+        pub(crate) max: f64,
+        pub(crate) avg: f64,
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Store {
+        series: HashMap<String, Vec<Chunk>>,
+        // 每个指标最多保留多少块，None 表示不限
+        max_chunks: Option<usize>,
+    }
+
+    impl Store {
+        pub(crate) fn new() -> Store {
+            Store::default()
+        }
+
+        // 每个指标只保留最近的样本：上限是 max_samples 向上取整到整块，丢弃时整块丢，所以最少时比上限少将近一个块
+        pub(crate) fn with_max_samples(max_samples: usize) -> Store {
+            Store {
+                series: HashMap::new(),
+                max_chunks: Some(max_samples.div_ceil(CHUNK_SIZE).max(1)),
+            }
+        }
+
+        // 时间戳相同的样本允许追加（同一毫秒内可能有多个请求完成），比最后一个样本早的拒绝
+        pub(crate) fn append(
+            &mut self,
+            metric: &str,
+            timestamp: u64,
+            value: f64,
+        ) -> Result<(), TsdbError> {
+            let chunks = self.series.entry(metric.to_string()).or_default();
+            if let Some(last) = chunks.last().map(Chunk::last) {
+                if timestamp < last {
+                    return Err(TsdbError::OutOfOrder {
+                        metric: metric.to_string(),
+                        last,
+                        timestamp,
+                    });
+                }
+            }
+            if chunks
+                .last()
+                .is_none_or(|chunk| chunk.timestamps.len() == CHUNK_SIZE)
+            {
+                chunks.push(Chunk::new());
+                // 新开一块时才检查上限，丢掉的总是写满封存了的旧块
+                if let Some(max_chunks) = self.max_chunks {
+                    let excess = chunks.len().saturating_sub(max_chunks);
+                    chunks.drain(..excess);
+                }
+            }
+            let chunk = chunks.last_mut().unwrap();
+            chunk.timestamps.push(timestamp);
+            chunk.values.push(value);
+            Ok(())
+        }
+
+        pub(crate) fn metrics(&self) -> Vec<&str> {
+            let mut names: Vec<&str> = self.series.keys().map(String::as_str).collect();
+            names.sort();
+            names
+        }
+
+        pub(crate) fn len(&self, metric: &str) -> usize {
+            self.series
+                .get(metric)
+                .map_or(0, |chunks| chunks.iter().map(|c| c.timestamps.len()).sum())
+        }
+
+        // 依次访问 [from, to) 内的样本。块按时间排好了序，先跳过整块都在 from 之前的块，遇到整块都在 to 之后的块就停
+        fn scan(&self, metric: &str, from: u64, to: u64, mut visit: impl FnMut(u64, f64)) {
+            let Some(chunks) = self.series.get(metric) else {
+                return;
+            };
+            let first = chunks.partition_point(|chunk| chunk.last() < from);
+            for chunk in chunks[first..]
+                .iter()
+                .take_while(|chunk| chunk.first() < to)
+            {
+                for i in chunk.span(from, to) {
+                    visit(chunk.timestamps[i], chunk.values[i]);
+                }
+            }
+        }
+
+        pub(crate) fn range(&self, metric: &str, from: u64, to: u64) -> Vec<(u64, f64)> {
+            let mut samples = Vec::new();
+            self.scan(metric, from, to, |ts, value| samples.push((ts, value)));
+            samples
+        }
+
+        // 桶的边界对齐到 step 的整数倍，这样不同时间发起的查询得到的桶是一致的；没有样本的桶不输出
+        pub(crate) fn downsample(
+            &self,
+            metric: &str,
+            from: u64,
+            to: u64,
+            step: u64,
+        ) -> Result<Vec<Bucket>, TsdbError> {
+            if step == 0 {
+                return Err(TsdbError::ZeroStep);
+            }
+            let mut buckets: Vec<Bucket> = Vec::new();
+            self.scan(metric, from, to, |ts, value| {
+                let start = ts - ts % step;
+                match buckets.last_mut() {
+                    Some(bucket) if bucket.start == start => {
+                        bucket.count += 1;
+                        bucket.min = bucket.min.min(value);
+                        bucket.max = bucket.max.max(value);
+                        // 先累加总和，最后再除以个数
+                        bucket.avg += value;
+                    }
+                    _ => buckets.push(Bucket {
+                        start,
+                        count: 1,
+                        min: value,
+                        max: value,
+                        avg: value,
+                    }),
+                }
+            });
+            for bucket in &mut buckets {
+                bucket.avg /= bucket.count as f64;
+            }
+            Ok(buckets)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::tsdb::*;
+
+    #[test]
+    fn append_and_range_query() {
+        let mut store = Store::new();
+        // 跨越好几个块
+        for i in 0..1000u64 {
+            store.append("latency", i * 10, i as f64).unwrap();
+        }
+        store.append("errors", 5, 1.0).unwrap();
+        assert_eq!(store.metrics(), ["errors", "latency"]);
+        assert_eq!(store.len("latency"), 1000);
+        assert_eq!(store.len("missing"), 0);
+
+        // 区间左闭右开，边界正好落在块的分界附近
+        let samples = store.range("latency", 1275, 1300);
+        assert_eq!(samples, [(1280, 128.0), (1290, 129.0)]);
+        assert_eq!(store.range("latency", 0, 30).len(), 3);
+        assert_eq!(store.range("latency", 9990, u64::MAX), [(9990, 999.0)]);
+        assert!(store.range("latency", 20_000, 30_000).is_empty());
+        assert!(store.range("missing", 0, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn rejects_out_of_order_samples() {
+        let mut store = Store::new();
+        store.append("cpu", 100, 0.5).unwrap();
+        // 同一时刻的样本可以追加
+        store.append("cpu", 100, 0.7).unwrap();
+        let err = store.append("cpu", 99, 0.1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "out-of-order sample for cpu: 99 is before the last timestamp 100"
+        );
+        // 每个指标的顺序是独立的
+        store.append("mem", 1, 1.0).unwrap();
+        assert_eq!(store.range("cpu", 0, 1000), [(100, 0.5), (100, 0.7)]);
+    }
+
+    #[test]
+    fn drops_oldest_chunks_beyond_the_limit() {
+        let mut store = Store::with_max_samples(300);
+        for i in 0..10_000u64 {
+            store.append("latency", i, i as f64).unwrap();
+            // 3 块：两个写满的旧块加上正在写的块
+            assert!(store.len("latency") <= 384, "{}", i);
+        }
+        assert_eq!(store.len("latency"), 256 + 10_000 % 128);
+        // 留下的是最新的样本，查询照常
+        let oldest = 10_000 - store.len("latency") as u64;
+        assert_eq!(
+            store.range("latency", 0, oldest + 2),
+            [(oldest, oldest as f64), (oldest + 1, (oldest + 1) as f64)]
+        );
+        assert_eq!(store.range("latency", 9_999, u64::MAX), [(9_999, 9_999.0)]);
+        // 每个指标单独计数
+        store.append("errors", 10_000, 1.0).unwrap();
+        assert_eq!(store.len("errors"), 1);
+    }
+
+    #[test]
+    fn downsamples_into_buckets() {
+        let mut store = Store::new();
+        for (ts, value) in [
+            (1000, 4.0),
+            (1200, 2.0),
+            (1900, 6.0),
+            (3100, 10.0),
+            (3500, 0.0),
+        ] {
+            store.append("latency", ts, value).unwrap();
+        }
+        let buckets = store.downsample("latency", 0, 10_000, 1000).unwrap();
+        assert_eq!(
+            buckets,
+            [
+                Bucket {
+                    start: 1000,
+                    count: 3,
+                    min: 2.0,
+                    max: 6.0,
+                    avg: 4.0
+                },
+                // 2000 这个桶没有样本，不输出
+                Bucket {
+                    start: 3000,
+                    count: 2,
+                    min: 0.0,
+                    max: 10.0,
+                    avg: 5.0
+                },
+            ]
+        );
+
+        // 查询范围从桶的中间开始时，第一个桶只包含范围内的样本
+        let buckets = store.downsample("latency", 1100, 3200, 1000).unwrap();
+        assert_eq!(buckets[0].count, 2);
+        assert_eq!(buckets[1].count, 1);
+
+        assert_eq!(
+            store.downsample("latency", 0, 10, 0).unwrap_err(),
+            TsdbError::ZeroStep
+        );
+    }
+}
@@ -0,0 +1,247 @@
+// 阻塞检测：runtime_example 的 blocking_test 讲过，在异步任务里调用会阻塞线程的函数（thread::sleep、同步 IO、长时间的计算）
+// 会让整个工作线程停下来，同一个线程上的其他任务都被饿着。这里用看门狗把这种情况实际测出来：
+// - 每个工作线程一个心跳任务，每隔 interval 醒一次，记下醒来的时间和所在的线程。工作线程被占住时心跳醒不过来，迟到的时间就是阻塞的时间
+// - 一个普通线程（不在运行时里，不受阻塞影响）定期检查心跳，超过 threshold 没有跳就打印警告，阻塞还没结束就能看到
+// - 心跳恢复时把这次阻塞了多久记下来，stop 时返回
+// 多线程运行时里任务会在线程之间迁移，心跳不一定一直待在同一个线程上，这里只能说明"有一个工作线程被占住了"；
+// 单线程运行时只在有线程调用 block_on 时运行任务，所以只在 block_on 期间有意义
+#[cfg(test)]
+pub(crate) mod watchdog {
+
+    use crate::task_scope_example::task_scope::CancelToken;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tokio::runtime::Handle;
+    use tokio::task::JoinHandle;
+
+    // 一次阻塞：心跳最后在哪个线程上跳的，以及比预定的时间晚了多久
+    #[derive(Clone, Debug)]
+    pub(crate) struct Stall {
+        pub(crate) worker: String,
+        pub(crate) late: Duration,
+    }
+
+    struct Beat {
+        // 最近一次心跳应该醒来的时间，从 Shared::started 算起的纳秒数
+        due: AtomicU64,
+        worker: Mutex<String>,
+    }
+
+    struct Shared {
+        started: Instant,
+        beats: Vec<Beat>,
+        stalls: Mutex<Vec<Stall>>,
+    }
+
+    impl Shared {
+        fn since_start(&self, at: Instant) -> u64 {
+            at.duration_since(self.started).as_nanos() as u64
+        }
+    }
+
+    pub(crate) struct Watchdog {
+        shared: Arc<Shared>,
+        stop: CancelToken,
+        monitor: Option<thread::JoinHandle<()>>,
+        heartbeats: Vec<JoinHandle<()>>,
+    }
+
+    fn thread_name() -> String {
+        thread::current().name().unwrap_or("unnamed").to_string()
+    }
+
+    impl Watchdog {
+        // 心跳比预定时间晚了 threshold 以上就算阻塞。threshold 要比计时器的误差（毫秒级）和正常的调度延迟大得多，否则会误报
+        pub(crate) fn start(handle: &Handle, interval: Duration, threshold: Duration) -> Watchdog {
+            let workers = handle.metrics().num_workers();
+            let started = Instant::now();
+            let shared = Arc::new(Shared {
+                started,
+                beats: (0..workers)
+                    .map(|_| Beat {
+                        due: AtomicU64::new(0),
+                        worker: Mutex::new(String::new()),
+                    })
+                    .collect(),
+                stalls: Mutex::new(Vec::new()),
+            });
+            let heartbeats = (0..workers)
+                .map(|i| {
+                    handle.spawn(Watchdog::heartbeat(
+                        Arc::clone(&shared),
+                        i,
+                        interval,
+                        threshold,
+                    ))
+                })
+                .collect();
+
+            let stop = CancelToken::new();
+            let monitor = {
+                let (shared, stop) = (Arc::clone(&shared), stop.clone());
+                thread::Builder::new()
+                    .name(String::from("watchdog"))
+                    .spawn(move || Watchdog::monitor(&shared, &stop, threshold))
+                    .expect("failed to spawn the watchdog thread")
+            };
+            Watchdog {
+                shared,
+                stop,
+                monitor: Some(monitor),
+                heartbeats,
+            }
+        }
+
+        async fn heartbeat(shared: Arc<Shared>, i: usize, interval: Duration, threshold: Duration) {
+            let beat = &shared.beats[i];
+            let mut due = Instant::now();
+            loop {
+                let now = Instant::now();
+                let late = now.saturating_duration_since(due);
+                // 锁不能跨过 await 持有（MutexGuard 不是 Send），放在单独的块里
+                {
+                    let mut worker = beat.worker.lock().unwrap();
+                    if late > threshold {
+                        eprintln!(
+                            "watchdog: {} was blocked for {:?}; a task is blocking the runtime",
+                            worker, late
+                        );
+                        shared.stalls.lock().unwrap().push(Stall {
+                            worker: worker.clone(),
+                            late,
+                        });
+                    }
+                    *worker = thread_name();
+                }
+                due = now + interval;
+                beat.due.store(shared.since_start(due), Ordering::SeqCst);
+                tokio::time::sleep_until(due.into()).await;
+            }
+        }
+
+        // 阻塞期间心跳自己报告不了，由这个线程先发出警告；同一次阻塞只警告一次
+        fn monitor(shared: &Shared, stop: &CancelToken, threshold: Duration) {
+            let mut warned = vec![0; shared.beats.len()];
+            while !stop.wait_timeout(threshold / 2) {
+                let now = shared.since_start(Instant::now());
+                for (i, beat) in shared.beats.iter().enumerate() {
+                    let due = beat.due.load(Ordering::SeqCst);
+                    let late = Duration::from_nanos(now.saturating_sub(due));
+                    if due != 0 && late > threshold && warned[i] != due {
+                        warned[i] = due;
+                        eprintln!(
+                            "watchdog: {} has not made progress for {:?}; a task may be blocking it",
+                            beat.worker.lock().unwrap(),
+                            late
+                        );
+                    }
+                }
+            }
+        }
+
+        // 目前为止已经结束的阻塞
+        pub(crate) fn stalls(&self) -> Vec<Stall> {
+            self.shared.stalls.lock().unwrap().clone()
+        }
+
+        pub(crate) fn stop(mut self) -> Vec<Stall> {
+            self.shutdown();
+            self.stalls()
+        }
+
+        fn shutdown(&mut self) {
+            for heartbeat in &self.heartbeats {
+                heartbeat.abort();
+            }
+            self.stop.cancel();
+            if let Some(monitor) = self.monitor.take() {
+                monitor.join().unwrap();
+            }
+        }
+    }
+
+    impl Drop for Watchdog {
+        fn drop(&mut self) {
+            self.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::watchdog::*;
+    use crate::runtime_example::runtime_factory::{runtime, Preset, RuntimeFactory};
+    use std::thread;
+    use std::time::Duration;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+    const THRESHOLD: Duration = Duration::from_millis(100);
+
+    // 一个工作线程，心跳和被测的任务一定在同一个线程上
+    fn one_worker() -> tokio::runtime::Runtime {
+        RuntimeFactory::new(Preset::IoHeavy)
+            .name("wd")
+            .worker_threads(1)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn detects_a_blocking_sleep() {
+        let rt = one_worker();
+        let watchdog = Watchdog::start(rt.handle(), INTERVAL, THRESHOLD);
+        // 先让心跳跑起来
+        thread::sleep(Duration::from_millis(50));
+        rt.block_on(async {
+            tokio::spawn(async {
+                // 错误示范：异步任务里用线程的 sleep，整个工作线程停住 400 毫秒
+                thread::sleep(Duration::from_millis(400));
+            })
+            .await
+            .unwrap();
+        });
+        // 等心跳恢复，把这次阻塞报告出来
+        thread::sleep(Duration::from_millis(50));
+        let stalls = watchdog.stop();
+        assert_eq!(stalls.len(), 1, "{:?}", stalls);
+        assert_eq!(stalls[0].worker, "wd-0");
+        assert!(stalls[0].late >= Duration::from_millis(300), "{:?}", stalls);
+    }
+
+    #[test]
+    fn async_sleep_and_spawn_blocking_do_not_stall() {
+        let rt = one_worker();
+        let watchdog = Watchdog::start(rt.handle(), INTERVAL, THRESHOLD);
+        thread::sleep(Duration::from_millis(50));
+        rt.block_on(async {
+            let sleeping = tokio::spawn(tokio::time::sleep(Duration::from_millis(300)));
+            // 同步的阻塞调用放进 spawn_blocking，在阻塞线程上执行，工作线程照常调度
+            let blocking =
+                tokio::task::spawn_blocking(|| thread::sleep(Duration::from_millis(300)));
+            sleeping.await.unwrap();
+            blocking.await.unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(watchdog.stalls().is_empty(), "{:?}", watchdog.stalls());
+    }
+
+    #[test]
+    fn single_thread_runtime_inside_block_on() {
+        let rt = runtime(Preset::SingleThreadTest);
+        let watchdog = Watchdog::start(rt.handle(), INTERVAL, THRESHOLD);
+        let worker = thread::current().name().unwrap().to_string();
+        rt.block_on(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // 单线程运行时里 block_on 所在的线程就是唯一的工作线程
+            thread::sleep(Duration::from_millis(300));
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        });
+        let stalls = watchdog.stop();
+        assert_eq!(stalls.len(), 1, "{:?}", stalls);
+        assert_eq!(stalls[0].worker, worker);
+        assert!(stalls[0].late >= Duration::from_millis(200));
+    }
+}
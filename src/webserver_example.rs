@@ -1,205 +1,2967 @@
 // web服务器
+
+// Unix 域套接字文件的准备工作，同步服务器和 event_loop_example 里的异步服务器共用
+#[cfg(all(test, unix))]
+pub(crate) mod unix_socket {
+
+    use std::fs::{self, Permissions};
+    use std::io;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+    use std::path::Path;
+
+    // bind 之前清理上一次运行遗留的套接字文件。进程异常退出时套接字文件不会被删除，
+    // 再次 bind 同一个路径会失败（AddrInUse）。但不能无条件删除：如果还有服务器在监听就报错，如果不是套接字文件就不碰它
+    pub(crate) fn remove_stale(path: &Path) -> io::Result<()> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        // 能连上说明另一个服务器还在使用这个套接字
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another server", path.display()),
+            ));
+        }
+        fs::remove_file(path)
+    }
+
+    // 套接字文件的权限决定了谁能连接：连接需要对文件有写权限，0o660 表示只有所有者和同组用户可以连接
+    pub(crate) fn restrict(path: &Path, mode: u32) -> io::Result<()> {
+        fs::set_permissions(path, Permissions::from_mode(mode))
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    use crate::byte_size_example::byte_size::{self, ByteSize};
+    use crate::http_client_example::client;
+    use crate::i18n_example::i18n;
+    use crate::index_example::index::Index;
+    use crate::path_util_example::path_util;
+    use crate::ring_buffer_example::ring_buffer::RingBuffer;
+    use crate::runtime_example::runtime_factory::{runtime, Preset};
+    use crate::streaming_example::streaming::{self, Summary};
+    use crate::template_example::template::{Context, Template, Value};
+    use crate::thread_pool_example::thread_pool::{Priority, ThreadPool, WORKER_ID};
+    use crate::tsdb_example::tsdb::{Bucket, Store};
+    use crate::windows_example::windows::SlidingWindow;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    #[cfg(unix)]
+    use std::os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    };
     use std::{
-        fs,
-        io::{Read, Write},
-        net::{TcpListener, TcpStream},
-        sync::{mpsc, Arc, Mutex},
+        collections::HashMap,
+        env,
+        error::Error,
+        fmt,
+        fs::{self, File},
+        io::{self, IoSlice, Read, Seek, SeekFrom, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        path::{Component, Path, PathBuf},
+        process,
+        sync::{
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            mpsc, Arc, Mutex, OnceLock,
+        },
         thread,
+        time::{Duration, Instant},
     };
+    use tokio::sync::watch;
 
-    struct ThreadPool {
-        workers: Vec<Worker>,
-        sender: mpsc::Sender<Message>,
+    // 一个客户端连接，可能来自 TCP 端口，也可能来自 Unix 域套接字。两者都是可靠的字节流，HTTP 的处理完全相同
+    enum Stream {
+        Tcp(TcpStream),
+        #[cfg(unix)]
+        Unix(UnixStream),
     }
 
-    // Job 是一个有着 execute 接收到的闭包类型的 trait 对象的类型别名
-    type Job = Box<dyn FnOnce() + Send + 'static>;
+    impl Stream {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            match self {
+                Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            }
+        }
 
-    enum Message {
-        NewJob(Job),
-        Terminate,
-    }
+        fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            match self {
+                Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.set_write_timeout(timeout),
+            }
+        }
+
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            match self {
+                Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            }
+        }
 
-    impl ThreadPool {
-        // 选择 usize 作为 size 参数的类型，因为我们知道为负的线程数没有意义
-        fn new(size: usize) -> ThreadPool {
-            assert!(size > 0);
+        // 在提交给线程池之前窥探（peek）请求行，健康检查和指标请求排在慢的文件传输前面
+        // peek 不会把数据从内核缓冲区取走，之后 handle_connection 仍然能读到完整的请求
+        // 这里不能等待：请求还没到达，或者是不支持 peek 的 Unix 域套接字，都按普通优先级处理
+        fn priority(&self) -> Priority {
+            let Stream::Tcp(stream) = self else {
+                return Priority::Normal;
+            };
+            let mut head = [0; 32];
+            let peeked = stream
+                .set_nonblocking(true)
+                .and_then(|_| stream.peek(&mut head));
+            let _ = stream.set_nonblocking(false);
+            let head = &head[..peeked.unwrap_or(0)];
+            if head.starts_with(b"GET /api/health") || head.starts_with(b"GET /metrics") {
+                Priority::High
+            } else {
+                Priority::Normal
+            }
+        }
+
+        // 写进 X-Forwarded-For 的客户端标识，Unix 域套接字的对端没有 IP 地址
+        fn peer(&self) -> io::Result<String> {
+            match self {
+                Stream::Tcp(stream) => Ok(stream.peer_addr()?.ip().to_string()),
+                #[cfg(unix)]
+                Stream::Unix(_) => Ok(String::from("unix")),
+            }
+        }
+    }
 
-            // 这里通道将充当任务队列的作用，execute 将通过 ThreadPool 向其中线程正在寻找工作的 Worker 实例发送任务
-            // Rust 所提供的通道实现是多 生产者，单 消费者 的。这意味着不能简单的克隆通道的消费端来解决问题
-            // 我们希望通过在所有的 worker 中共享单一 receiver，在线程间分发任务
-            let (sender, receiver) = mpsc::channel();
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self {
+                Stream::Tcp(stream) => stream.read(buf),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.read(buf),
+            }
+        }
+    }
 
-            // 为了在多个线程间共享所有权并允许线程修改其值，需要使用 Arc<Mutex<T>>
-            // Arc 使得多个 worker 拥有接收端，而 Mutex 则确保一次只有一个 worker 能从接收端得到任务
-            let receiver = Arc::new(Mutex::new(receiver));
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self {
+                Stream::Tcp(stream) => stream.write(buf),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.write(buf),
+            }
+        }
 
-            // with_capacity 为 vector 预先分配空间。因为已经知道了 vector 中需要 size 个元素
-            // 预先进行分配比仅仅 Vec::new 要稍微有效率一些，因为 Vec::new 随着插入元素而重新改变大小
-            // 从通道队列中取出任务涉及到修改 receiver，所以这些线程需要一个能安全的共享和修改 receiver 的方式，否则可能导致竞争状态
-            let mut workers = Vec::with_capacity(size);
+        // 转发给内部的 stream，否则默认实现每次只写第一个缓冲区，write_all_vectored 就失去了意义
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            match self {
+                Stream::Tcp(stream) => stream.write_vectored(bufs),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.write_vectored(bufs),
+            }
+        }
 
-            for id in 0..size {
-                // 对于每一个新 worker，克隆 Arc 来增加引用计数，如此这些 worker 就可以共享接收端的所有权了
-                workers.push(Worker::new(id, Arc::clone(&receiver)));
+        fn flush(&mut self) -> io::Result<()> {
+            match self {
+                Stream::Tcp(stream) => stream.flush(),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.flush(),
             }
+        }
+    }
 
-            ThreadPool { workers, sender }
+    // sendfile 只需要目标的文件描述符，对 TCP 和 Unix 域套接字都适用
+    #[cfg(unix)]
+    impl AsRawFd for Stream {
+        fn as_raw_fd(&self) -> RawFd {
+            match self {
+                Stream::Tcp(stream) => stream.as_raw_fd(),
+                Stream::Unix(stream) => stream.as_raw_fd(),
+            }
         }
+    }
 
-        // spawn 使用 FnOnce 作为 F 的 trait bound，最终会将传递给 execute 的参数传给 spawn，处理请求的线程只会执行闭包一次，这也进一步确认了 FnOnce 是我们需要的 trait，这里符合 FnOnce 中 Once 的意思
-        // 需要 Send 来将闭包从一个线程转移到另一个线程，而 'static 是因为并不知道线程会执行多久
-        // FnOnce trait 仍然需要之后的 ()，因为这里的 FnOnce 代表一个没有参数也没有返回值的闭包。正如函数的定义，返回值类型可以从签名中省略，不过即便没有参数也需要括号
-        fn execute<F>(&self, f: F)
-        where
-            F: FnOnce() + Send + 'static,
-        {
-            // 把传递过来的闭包包装成 Box 发送到通道中
-            let job = Box::new(f);
-            // 调用 send 上的 unwrap，因为发送可能会失败，这可能发生于例如停止了所有线程执行的情况，这意味着接收端停止接收新消息了
-            self.sender.send(Message::NewJob(job)).unwrap();
+    impl From<TcpStream> for Stream {
+        fn from(stream: TcpStream) -> Stream {
+            Stream::Tcp(stream)
         }
     }
 
-    // 为 ThreadPool 实现 Drop Trait，当线程池被丢弃时，应该 join 所有线程以确保他们完成其操作
-    impl Drop for ThreadPool {
+    // 监听 TCP 端口或者 Unix 域套接字，由配置中的 unix_socket 决定
+    enum Listener {
+        Tcp(TcpListener),
+        #[cfg(unix)]
+        Unix(UnixSocketListener),
+    }
+
+    // 退出时删除套接字文件，免得留下一个没人监听的文件
+    #[cfg(unix)]
+    struct UnixSocketListener {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    #[cfg(unix)]
+    impl Drop for UnixSocketListener {
         fn drop(&mut self) {
-            println!("Sending terminate message to all workers.");
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    impl Listener {
+        fn bind(config: &ServerConfig) -> io::Result<Listener> {
+            match &config.unix_socket {
+                #[cfg(unix)]
+                Some(path) => {
+                    super::unix_socket::remove_stale(path)?;
+                    let listener = UnixListener::bind(path)?;
+                    let listener = UnixSocketListener {
+                        listener,
+                        path: path.clone(),
+                    };
+                    super::unix_socket::restrict(path, config.unix_socket_mode)?;
+                    Ok(Listener::Unix(listener))
+                }
+                #[cfg(not(unix))]
+                Some(_) => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unix domain sockets are not supported on this platform",
+                )),
+                None => TcpListener::bind(&config.bind).map(Listener::Tcp),
+            }
+        }
 
-            // 向每个 worker 发送一个 Terminate 消息
-            // 为什么发送终止消息要和join操作要分开循环？
-            // 1. 如果尝试在同一循环中发送消息并立即 join 线程，则无法保证当前迭代的 worker 是从通道收到终止消息的 worker
-            // 2. 想象一下只有两个 worker 的场景。如果在一个单独的循环中遍历每个 worker，在第一次迭代中向通道发出终止消息并对第一个 worker 线程调用 join
-            // 3. 如果此时第一个 worker 正忙于处理请求，那么第二个 worker 会收到终止消息并停止。我们会一直等待第一个 worker 结束，不过它永远也不会结束因为第二个线程接收了终止消息
-            for _ in &mut self.workers {
-                self.sender.send(Message::Terminate).unwrap();
+        fn accept(&self) -> io::Result<Stream> {
+            match self {
+                Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+                #[cfg(unix)]
+                Listener::Unix(socket) => socket
+                    .listener
+                    .accept()
+                    .map(|(stream, _)| Stream::Unix(stream)),
             }
+        }
 
-            println!("Shutting down all workers.");
+        // 和 TcpListener::incoming 一样，返回一个永不结束的连接迭代器
+        fn incoming(&self) -> impl Iterator<Item = io::Result<Stream>> + '_ {
+            std::iter::repeat_with(move || self.accept())
+        }
+    }
 
-            // 这里使用了 &mut 因为 self 本身是一个可变引用而且也需要能够修改 worker
-            for worker in &mut self.workers {
-                println!("Shutting down worker {}", worker.id);
+    // HTTP 请求：请求行（方法、路径、协议版本）、头部和请求体
+    struct Request {
+        method: String,
+        // 请求行里的原样的目标，包括查询字符串；转发给上游、写访问日志、取查询参数都用它
+        path: String,
+        // 去掉查询字符串并做过百分号解码的路径，路由表、查找静态文件都用它
+        decoded_path: String,
+        // 头部名称不区分大小写，这里统一转成小写后存储
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
 
-                // join 需要获取参数的所有权，worker 中的 thread 需要存放 Option<thread::JoinHandle<()> 而不是直接存放 thread::JoinHandle
-                // 如果 Worker 存放的是 Option<thread::JoinHandle<()>，就可以在 Option 上调用 take 方法将值从 Some 成员中移动出来而对 None 成员不做处理
-                // 正在运行的 Worker 的 thread 将是 Some 成员值，而当需要清理 worker 时，将 Some 替换为 None，这样 worker 就没有可以运行的线程了
-                // Option 上的 take 方法会取出 Some 而留下 None。使用 if let 解构 Some 并得到线程，接着在线程上调用 join。如果 worker 的线程已然是 None，就知道此时这个 worker 已经清理了其线程所以无需做任何操作
-                if let Some(thread) = worker.thread.take() {
-                    thread.join().unwrap();
-                }
+    impl Request {
+        // 解析请求行和头部，head 不包含请求体
+        fn parse(head: &[u8]) -> Option<Request> {
+            // 函数名的 “lossy” 部分来源于当其遇到无效的 UTF-8 序列时的行为：它使用 �，U+FFFD REPLACEMENT CHARACTER，来代替无效序列
+            let head = String::from_utf8_lossy(head);
+            let mut lines = head.split("\r\n");
+
+            // 请求行形如 GET / HTTP/1.1
+            let mut request_line = lines.next()?.split_whitespace();
+            let method = request_line.next()?.to_string();
+            let path = request_line.next()?.to_string();
+            let version = request_line.next()?;
+            // 请求行必须正好三部分；方法由大写字母组成，路径以 / 开头（OPTIONS * 除外），版本形如 HTTP/1.1
+            if request_line.next().is_some()
+                || !method.bytes().all(|b| b.is_ascii_uppercase())
+                || !(path.starts_with('/') || path == "*")
+                || !version.starts_with("HTTP/")
+            {
+                return None;
             }
+
+            let headers = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+                .collect();
+
+            let decoded_path = percent_decode_path(path.split('?').next().unwrap_or(&path));
+            Some(Request {
+                method,
+                path,
+                decoded_path,
+                headers,
+                body: Vec::new(),
+            })
+        }
+
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+        }
+
+        // 借助 serde 把 JSON 请求体反序列化成任意实现了 Deserialize 的结构体
+        fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+            serde_json::from_slice(&self.body)
         }
     }
 
-    // 实现的行为是创建线程并稍后发送代码，这会在 ThreadPool 和线程间引入一个新数据类型来管理这种新行为。这个数据结构称为 Worker
-    struct Worker {
-        id: usize,
-        thread: Option<thread::JoinHandle<()>>,
+    // HTTP 响应：状态码、原因短语、头部和响应体，Content-Length 在写出时根据 body 自动计算
+    struct Response {
+        status: u16,
+        reason: &'static str,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+        // 静态文件不读进内存，而是在写响应时直接从文件发送到连接，跟在 body 之后
+        file: Option<FileBody>,
+        // HEAD 请求的响应：头部（包括 Content-Length）和 GET 完全一样，但不发送响应体
+        head_only: bool,
     }
 
-    impl Worker {
-        // spawn 返回 JoinHandle<T>，其中 T 是闭包返回的类型
-        // 我们的情况中，传递给线程池的闭包会处理连接并不返回任何值，所以 T 将会是单元类型 ()
-        fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-            let thread = thread::spawn(move || {
-                // 需要闭包一直循环，向通道的接收端请求任务，并在得到任务时执行他们
-                loop {
-                    // 首先在 receiver 上调用了 lock 来获取互斥器，接着 unwrap 在出现任何错误时 panic
-                    // 如果互斥器处于一种叫做 被污染（poisoned）的状态时获取锁可能会失败，这可能发生于其他线程在持有锁时 panic 了且没有释放锁
-                    // 如果锁定了互斥器，接着调用 recv 从通道中接收 Job。最后的 unwrap 也绕过了一些错误，这可能发生于持有通道发送端的线程停止的情况，类似于如果接收端关闭时 send 方法如何返回 Err 一样
-                    // 调用 recv 会阻塞当前线程，所以如果还没有任务，其会等待直到有可用的任务。Mutex<T> 确保一次只有一个 Worker 线程尝试请求任务
-                    let message = receiver.lock().unwrap().recv().unwrap();
+    // 文件中从 offset 开始的 len 个字节
+    struct FileBody {
+        file: File,
+        offset: u64,
+        len: usize,
+    }
 
-                    // loop循环的写法可以并发执行job：
-                    // 1. 使用 loop 并在循环块之内而不是之外获取锁和任务，lock 方法返回的 MutexGuard 在 let job 语句结束之后立刻就被丢弃了
-                    // 2. 这确保了 recv 调用过程中持有锁，而在 job() 调用前锁就被释放了，这就允许并发处理多个请求了。
-                    match message {
-                        Message::NewJob(job) => {
-                            println!("Worker {} got a job; executing.", id);
-                            job();
-                        }
-                        Message::Terminate => {
-                            println!("Worker {} was told to terminate.", id);
-                            break;
-                        }
+    impl FileBody {
+        // 通用的写法：经过用户态缓冲区，从文件读一块再写一块
+        fn copy_to(&self, stream: &mut impl Write) -> io::Result<()> {
+            // &File 同样实现了 Read 和 Seek，不需要可变借用
+            let mut file = &self.file;
+            file.seek(SeekFrom::Start(self.offset))?;
+            let copied = io::copy(&mut file.take(self.len as u64), stream)?;
+            if copied < self.len as u64 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(())
+        }
+
+        // Linux 上的零拷贝：sendfile 让内核直接把文件页缓存中的数据发送到 socket，数据不经过用户态，也省去了 read/write 之间的拷贝
+        #[cfg(target_os = "linux")]
+        fn send_to(&self, stream: &mut Stream) -> io::Result<()> {
+            let mut offset = self.offset as libc::off_t;
+            let mut remaining = self.len;
+            while remaining > 0 {
+                // SAFETY: 两个文件描述符由 self.file 和 stream 持有，在调用期间一直有效；
+                // offset 是栈上的有效变量，sendfile 会把它更新为下一次要发送的位置
+                let sent = unsafe {
+                    libc::sendfile(
+                        stream.as_raw_fd(),
+                        self.file.as_raw_fd(),
+                        &mut offset,
+                        remaining,
+                    )
+                };
+                if sent < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
                     }
+                    return Err(err);
+                }
+                // 返回 0 说明文件在发送过程中被截短了
+                if sent == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
                 }
+                remaining -= sent as usize;
+            }
+            Ok(())
+        }
 
-                // 下面这种写法无法让job的执行并发起来：
-                // 1. Mutex 结构体没有公有 unlock 方法，因为锁的所有权依赖 lock 方法返回的 LockResult<MutexGuard<T>> 中 MutexGuard<T> 的生命周期
-                // 2. 这允许借用检查器在编译时确保绝不会在没有持有锁的情况下访问由 Mutex 守护的资源，不过如果没有认真的思考 MutexGuard<T> 的生命周期的话，也可能会导致比预期更久的持有锁
-                // 3. 因为 while 表达式中的值 job 在整个块一直处于作用域中，job() 调用的过程中其仍然持有锁，这意味着其他 worker 不能接收任务
-                // while let Ok(job) = receiver.lock().unwrap().recv() {
-                //     println!("Worker {} got a job; executing.", id);
-                //     job();
-                // }
-            });
+        // 其他平台没有 sendfile，退回到经过缓冲区的拷贝
+        #[cfg(not(target_os = "linux"))]
+        fn send_to(&self, stream: &mut Stream) -> io::Result<()> {
+            self.copy_to(stream)
+        }
+    }
+
+    impl Response {
+        fn new(status: u16, reason: &'static str) -> Response {
+            Response {
+                status,
+                reason,
+                headers: Vec::new(),
+                body: Vec::new(),
+                file: None,
+                head_only: false,
+            }
+        }
+
+        fn without_body(mut self) -> Response {
+            self.head_only = true;
+            self
+        }
+
+        // 实际发送的响应体字节数，HEAD 请求是 0
+        fn bytes_sent(&self) -> usize {
+            if self.head_only {
+                0
+            } else {
+                self.content_length()
+            }
+        }
+
+        // 把任意实现了 Serialize 的结构体序列化为 JSON 响应，并带上正确的 Content-Type
+        fn json<T: Serialize>(status: u16, reason: &'static str, value: &T) -> Response {
+            Response::new(status, reason)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_vec(value).unwrap())
+        }
+
+        // 以 self 为参数并返回 Self 的方法可以链式调用，类似于构建者模式
+        fn header(mut self, name: &str, value: &str) -> Response {
+            self.headers.push((name.to_string(), value.to_string()));
+            self
+        }
+
+        fn body(mut self, body: Vec<u8>) -> Response {
+            self.body = body;
+            self
+        }
+
+        fn file(mut self, file: File, offset: u64, len: usize) -> Response {
+            self.file = Some(FileBody { file, offset, len });
+            self
+        }
+
+        fn content_length(&self) -> usize {
+            self.body.len() + self.file.as_ref().map_or(0, |file| file.len)
+        }
+
+        // 状态行和每个头部各占一行，最后一行带上 Content-Length 和头部结束的空行
+        // 204 和 304 不带 Content-Length：它们没有响应体，而 304 的 Content-Length 按 RFC 9110 只能等于 200 响应的长度，
+        // 写成 0 会让缓存把存着的长度也改成 0
+        fn head_lines(&self) -> Vec<String> {
+            let mut lines = vec![format!("HTTP/1.1 {} {}\r\n", self.status, self.reason)];
+            for (name, value) in &self.headers {
+                lines.push(format!("{}: {}\r\n", name, value));
+            }
+            if matches!(self.status, 204 | 304) {
+                lines.push(String::from("\r\n"));
+            } else {
+                lines.push(format!("Content-Length: {}\r\n\r\n", self.content_length()));
+            }
+            lines
+        }
+
+        // 向量化写（vectored I/O）：状态行、各个头部和响应体分别是独立的缓冲区，通过一次 write_vectored（底层是 writev 系统调用）交给操作系统
+        // 这样既不需要把响应体拷贝进一个拼接好的大缓冲区，也不需要为每一段单独发起一次系统调用
+        fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+            self.write_head_and_body(stream)?;
+            if let Some(file) = self.file.as_ref().filter(|_| !self.head_only) {
+                file.copy_to(stream)?;
+            }
+            // flush 会等待并阻塞程序执行直到所有字节都被写入连接中；TcpStream 包含一个内部缓冲区来最小化对底层操作系统的调用
+            stream.flush()
+        }
 
-            Worker {
-                id,
-                thread: Some(thread),
+        // 写到连接时文件部分可以走 sendfile
+        fn send(&self, stream: &mut Stream) -> io::Result<()> {
+            self.write_head_and_body(stream)?;
+            if let Some(file) = self.file.as_ref().filter(|_| !self.head_only) {
+                file.send_to(stream)?;
             }
+            stream.flush()
+        }
+
+        fn write_head_and_body(&self, stream: &mut impl Write) -> io::Result<()> {
+            let lines = self.head_lines();
+            let mut bufs: Vec<IoSlice> = lines
+                .iter()
+                .map(|line| IoSlice::new(line.as_bytes()))
+                .collect();
+            if !self.body.is_empty() && !self.head_only {
+                bufs.push(IoSlice::new(&self.body));
+            }
+
+            write_all_vectored(stream, &mut bufs)
+        }
+
+        // 先用 format! 把整个响应拼接成一个缓冲区再一次写出，需要把响应体完整拷贝一遍，作为 write_to 的对照
+        fn write_to_copying(&self, stream: &mut impl Write) -> io::Result<()> {
+            let mut response = self.head_lines().concat().into_bytes();
+            response.extend_from_slice(&self.body);
+            if let Some(file) = &self.file {
+                file.copy_to(&mut response)?;
+            }
+
+            // 在 response 上调用 as_bytes，因为 stream 的 write 方法获取一个 &[u8] 并直接将这些字节发送给连接
+            // write_all 会一直写直到所有字节都被写入，而 write 可能只写入了一部分
+            stream.write_all(&response)?;
+            stream.flush()
         }
     }
 
-    // 处理连接
-    fn handle_connection(mut stream: TcpStream) {
+    // 标准库的 write_all_vectored 还不稳定，这里手写一个：write_vectored 和 write 一样可能只写入一部分，
+    // 需要用 IoSlice::advance_slices 跳过已经写完的部分，然后继续写剩下的
+    fn write_all_vectored(stream: &mut impl Write, mut bufs: &mut [IoSlice]) -> io::Result<()> {
+        while !bufs.is_empty() {
+            match stream.write_vectored(bufs) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // 在 haystack 中查找 needle 第一次出现的位置
+    fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    // 从连接中读取一个完整的请求：先读到头部结束标记 \r\n\r\n，再根据 Content-Length 读取请求体
+    // 连接在发送完整请求前就关闭时返回 None，请求行格式错误时返回 InvalidData 错误，
+    // Content-Length 超过 max_body 时不读请求体，返回 FileTooLarge 错误
+    fn read_request(stream: &mut impl Read, max_body: u64) -> io::Result<Option<Request>> {
+        let mut data = Vec::new();
         // 在栈上声明一个 buffer 来存放读取到的数据。这里创建了一个 1024 字节的缓冲区
         let mut buffer = [0; 1024];
-        // 接着将缓冲区传递给 stream.read ，它会从 TcpStream 中读取字节并放入缓冲区中
-        stream.read(&mut buffer).unwrap();
-        // 函数名的 “lossy” 部分来源于当其遇到无效的 UTF-8 序列时的行为：它使用 �，U+FFFD REPLACEMENT CHARACTER，来代替无效序列
-        println!("Request: {}", String::from_utf8_lossy(&buffer[..]));
 
-        let get = b"GET / HTTP/1.1\r\n";
+        let head_end = loop {
+            if let Some(pos) = find_subsequence(&data, b"\r\n\r\n") {
+                break pos + 4;
+            }
+            // 接着将缓冲区传递给 stream.read ，它会从 TcpStream 中读取字节并放入缓冲区中
+            let n = stream.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            data.extend_from_slice(&buffer[..n]);
+        };
+
+        let mut request = match Request::parse(&data[..head_end]) {
+            Some(request) => request,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "malformed request line",
+                ))
+            }
+        };
+
+        let content_length = request
+            .header("Content-Length")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        if content_length as u64 > max_body {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!(
+                    "request body of {} exceeds the limit of {}",
+                    byte_size::format_size(content_length as u64),
+                    byte_size::format_size(max_body)
+                ),
+            ));
+        }
+        let mut body = data[head_end..].to_vec();
+        while body.len() < content_length {
+            let n = stream.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buffer[..n]);
+        }
+        body.truncate(content_length);
+        request.body = body;
+
+        Ok(Some(request))
+    }
+
+    // 把上游的响应原样流式写回客户端，返回状态码和转发的总字节数（包括响应头）
+    fn relay(mut upstream: TcpStream, client: &mut Stream) -> io::Result<(u16, usize)> {
+        let mut buffer = [0; 8192];
+        // 状态行可能被拆在多次 read 里，先攒到第一个 \r\n 再解析
+        let mut status_line = Vec::new();
+        let mut status = None;
+        let mut total = 0;
+        loop {
+            let n = upstream.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            if status.is_none() {
+                status_line.extend_from_slice(&buffer[..n]);
+                if let Some(end) = find_subsequence(&status_line, b"\r\n") {
+                    let line = String::from_utf8_lossy(&status_line[..end]);
+                    status = Some(
+                        line.split_whitespace()
+                            .nth(1)
+                            .and_then(|code| code.parse().ok())
+                            .unwrap_or(502),
+                    );
+                }
+            }
+            client.write_all(&buffer[..n])?;
+            total += n;
+        }
+        client.flush()?;
+        Ok((status.unwrap_or(502), total))
+    }
+
+    // /api 下的请求体和响应体，derive 宏为结构体生成 serde 的序列化/反序列化实现
+    #[derive(Deserialize)]
+    struct GreetRequest {
+        name: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct GreetResponse {
+        message: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct ApiError {
+        error: String,
+    }
+
+    #[derive(Serialize)]
+    struct Health {
+        status: &'static str,
+    }
+
+    // /api 路由组：请求体和响应体都是 JSON
+    fn handle_api(request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/api/health") => Response::json(200, "OK", &Health { status: "ok" }),
+            ("POST", "/api/greet") => {
+                // 只接受 JSON 请求体，Content-Type 可能带有 ; charset=utf-8 之类的参数
+                let is_json = request
+                    .header("Content-Type")
+                    .is_some_and(|value| value.starts_with("application/json"));
+                if !is_json {
+                    let error = ApiError {
+                        error: String::from("expected Content-Type: application/json"),
+                    };
+                    return Response::json(415, "UNSUPPORTED MEDIA TYPE", &error);
+                }
+
+                // 反序列化失败（JSON 格式错误、缺少字段等）时返回 400，并把 serde 的错误信息带回给客户端
+                // 问候语按 Accept-Language 选语言，Content-Language 告诉客户端实际用的语言；
+                // 同一个地址的响应随这个头部变化，Vary 让缓存按它区分
+                match request.json::<GreetRequest>() {
+                    Ok(greet) => {
+                        let catalogs = i18n::builtin();
+                        let locale = i18n::negotiate(
+                            request.header("Accept-Language").unwrap_or(""),
+                            &catalogs.locales(),
+                            "en",
+                        );
+                        let response = GreetResponse {
+                            message: catalogs.text(locale, "greeting", &[("name", &greet.name)]),
+                        };
+                        Response::json(200, "OK", &response)
+                            .header("Content-Language", locale)
+                            .header("Vary", "Accept-Language")
+                    }
+                    Err(e) => Response::json(
+                        400,
+                        "BAD REQUEST",
+                        &ApiError {
+                            error: e.to_string(),
+                        },
+                    ),
+                }
+            }
+            (method, path) => {
+                let error = ApiError {
+                    error: format!("no route for {} {}", method, path),
+                };
+                Response::json(404, "NOT FOUND", &error)
+            }
+        }
+    }
+
+    // Range 请求头的解析结果
+    #[derive(Debug, PartialEq)]
+    enum ByteRange {
+        // 没有 Range 头或者格式不认识，按规范忽略它，返回完整文件
+        Full,
+        // 闭区间 [start, end]
+        Partial(usize, usize),
+        // 范围超出文件长度，返回 416
+        Unsatisfiable,
+    }
+
+    // 支持 bytes=start-end、bytes=start-（到文件末尾）和 bytes=-suffix（最后 suffix 个字节）三种写法，多段范围按不认识处理
+    fn parse_range(value: &str, len: usize) -> ByteRange {
+        let spec = match value.trim().strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec,
+            _ => return ByteRange::Full,
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return ByteRange::Full,
+        };
+
+        if start.is_empty() {
+            return match end.parse::<usize>() {
+                Ok(0) => ByteRange::Unsatisfiable,
+                Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+                Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+                Err(_) => ByteRange::Full,
+            };
+        }
 
-        let (status_line, filename) = if buffer.starts_with(get) {
-            ("HTTP/1.1 200 OK", "hello.html")
+        let start = match start.parse::<usize>() {
+            Ok(start) => start,
+            Err(_) => return ByteRange::Full,
+        };
+        let end = if end.is_empty() {
+            usize::MAX
         } else {
-            ("HTTP/1.1 404 NOT FOUND", "404.html")
+            match end.parse::<usize>() {
+                Ok(end) => end,
+                Err(_) => return ByteRange::Full,
+            }
         };
 
-        let contents = fs::read_to_string(filename).unwrap();
+        if start >= len || start > end {
+            ByteRange::Unsatisfiable
+        } else {
+            // 结束位置超出文件长度时截断到最后一个字节
+            ByteRange::Partial(start, end.min(len - 1))
+        }
+    }
 
-        let response = format!(
-            "{}\r\nContent-Length: {}\r\n\r\n{}",
-            status_line,
-            contents.len(),
-            contents
-        );
+    // HTTP 日期格式，例如 Sun, 06 Nov 1994 08:49:37 GMT
+    const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
 
-        // 在 response 上调用 as_bytes，因为 stream 的 write 方法获取一个 &[u8] 并直接将这些字节发送给连接
-        stream.write(response.as_bytes()).unwrap();
-        // flush 会等待并阻塞程序执行直到所有字节都被写入连接中；TcpStream 包含一个内部缓冲区来最小化对底层操作系统的调用
-        stream.flush().unwrap();
+    // 缓存校验器：客户端把上次拿到的 ETag 放在 If-None-Match 里、把 Last-Modified 放在 If-Modified-Since 里发回来，
+    // 服务端据此判断客户端手里的副本是否还是最新的
+    struct Validators {
+        etag: String,
+        // HTTP 日期只精确到秒，比较时也按秒比较
+        last_modified: DateTime<Utc>,
     }
 
-    // Web 服务器中涉及到的两个主要协议是 超文本传输协议（Hypertext Transfer Protocol，HTTP）和 传输控制协议（Transmission Control Protocol，TCP）
-    // 这两者都是 请求-响应（request-response）协议，也就是说，有 客户端（client）来初始化请求，并有 服务端（server）监听请求并向客户端提供响应
-    #[test]
-    fn webserver_example() {
-        // 监听 TCP 连接，这段代码会在地址 127.0.0.1:7878 上监听传入的 TCP 流
-        // 这个函数叫做 bind 是因为，在网络领域，连接到监听端口被称为 “绑定到一个端口”（“binding to a port”）
-        // bind 函数返回 Result<T, E>，这表明绑定可能会失败，例如，连接 80 端口需要管理员权限（非管理员用户只能监听大于 1024 的端口），所以如果不是管理员尝试连接 80 端口，则会绑定失败。另一个例子是如果运行两个此程序的实例这样会有两个程序监听相同的端口，绑定会失败
-        let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-        // 初始化一个容量为4的线程池
-        let pool = ThreadPool::new(4);
+    impl Validators {
+        // 和 nginx 一样用文件长度和修改时间生成 ETag，不需要读取文件内容计算哈希
+        fn new(len: usize, modified: DateTime<Utc>) -> Validators {
+            let last_modified = DateTime::from_timestamp(modified.timestamp(), 0).unwrap();
+            Validators {
+                etag: format!("\"{:x}-{:x}\"", modified.timestamp(), len),
+                last_modified,
+            }
+        }
 
-        // incoming 方法返回一个迭代器，它提供了一系列的流（更准确的说是 TcpStream 类型的流）
-        // 流（stream）代表一个客户端和服务端之间打开的连接
-        // 连接（connection）代表客户端连接服务端、服务端生成响应以及服务端关闭连接的全部请求 / 响应过程
-        // for 循环会依次处理每个连接并产生一系列的流供我们处理
-        for stream in listener.incoming() {
-            // 当客户端连接到服务端时 incoming 方法返回错误是可能的，因为我们实际上没有遍历连接，而是遍历 连接尝试（connection attempts）。连接可能会因为很多原因不能成功，大部分是操作系统相关的。例如，很多系统限制同时打开的连接数；新连接尝试产生错误，直到一些打开的连接关闭为止
-            let stream = stream.unwrap();
-            // 提交任务到池中
-            pool.execute(|| handle_connection(stream));
+        fn not_modified(&self, request: &Request) -> bool {
+            // 两个头部同时存在时以 If-None-Match 为准
+            if let Some(value) = request.header("If-None-Match") {
+                return value
+                    .split(',')
+                    .map(|tag| tag.trim())
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == self.etag);
+            }
+            match request.header("If-Modified-Since") {
+                Some(value) => DateTime::parse_from_rfc2822(value)
+                    .is_ok_and(|since| self.last_modified <= since),
+                None => false,
+            }
         }
-        println!("Shutting down.");
-        // 当 ThreadPool 在 webserver_example 的结尾离开作用域时，其 Drop 实现开始工作，线程池通知所有线程终止
+
+        fn apply(&self, response: Response) -> Response {
+            response.header("ETag", &self.etag).header(
+                "Last-Modified",
+                &self.last_modified.format(HTTP_DATE).to_string(),
+            )
+        }
+    }
+
+    fn content_type(path: &Path) -> &'static str {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html") => "text/html; charset=utf-8",
+            Some("txt") => "text/plain; charset=utf-8",
+            Some("css") => "text/css",
+            Some("js") => "application/javascript",
+            Some("json") => "application/json",
+            Some("png") => "image/png",
+            _ => "application/octet-stream",
+        }
+    }
+
+    // 一条访问日志：谁处理的、处理了什么、结果如何、花了多久
+    struct AccessLogEntry {
+        worker: Option<usize>,
+        method: String,
+        path: String,
+        status: u16,
+        bytes: usize,
+        elapsed: Duration,
+    }
+
+    // 输出为 key=value 形式的结构化日志，方便用 grep/awk 之类的工具处理
+    impl fmt::Display for AccessLogEntry {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.worker {
+                Some(id) => write!(f, "worker={}", id)?,
+                None => write!(f, "worker=-")?,
+            }
+            write!(
+                f,
+                " method={} path={} status={} bytes={} elapsed_ms={:.3}",
+                self.method,
+                self.path,
+                self.status,
+                self.bytes,
+                self.elapsed.as_secs_f64() * 1000.0
+            )
+        }
+    }
+
+    // 可插拔的日志输出：日志会在多个 worker 线程中同时写入，所以要求 Send + Sync
+    trait AccessLogger: Send + Sync {
+        fn log(&self, entry: &AccessLogEntry);
+    }
+
+    struct StdoutLogger;
+
+    impl AccessLogger for StdoutLogger {
+        fn log(&self, entry: &AccessLogEntry) {
+            println!("{}", entry);
+        }
+    }
+
+    // 把日志收集在内存里，测试中可以直接检查内容
+    #[derive(Default)]
+    struct MemoryLogger {
+        lines: Mutex<Vec<String>>,
+    }
+
+    impl AccessLogger for MemoryLogger {
+        fn log(&self, entry: &AccessLogEntry) {
+            self.lines.lock().unwrap().push(entry.to_string());
+        }
+    }
+
+    // 查询字符串里第一个名为 name 的参数，不做百分号解码
+    fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+        let (_, query) = path.split_once('?')?;
+        query.split('&').find_map(|pair| {
+            pair.split_once('=')
+                .filter(|(key, _)| *key == name)
+                .map(|(_, value)| value)
+        })
+    }
+
+    // 查询字符串的解码：+ 表示空格，%XX 是一个字节的十六进制编码，解码结果不是合法 UTF-8 时用替换字符代替
+    fn percent_decode(value: &str) -> String {
+        decode_bytes(value, true)
+    }
+
+    // 路径的解码：只有 %XX，路径里的 + 就是加号
+    fn percent_decode_path(path: &str) -> String {
+        decode_bytes(path, false)
+    }
+
+    fn decode_bytes(value: &str, plus_is_space: bool) -> String {
+        let bytes = value.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match (bytes[i], hex) {
+                (b'+', _) if plus_is_space => out.push(b' '),
+                (b'%', Some(byte)) => {
+                    out.push(byte);
+                    i += 2;
+                }
+                (byte, _) => out.push(byte),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    // 递归读取目录下所有的 .rs 文件建立索引，文档名是相对于该目录的路径；读不了的文件跳过
+    fn index_sources(dir: &Path) -> Index {
+        fn walk(root: &Path, dir: &Path, index: &mut Index) {
+            let Ok(entries) = fs::read_dir(dir) else {
+                return;
+            };
+            let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+            paths.sort();
+            for path in paths {
+                if path.is_dir() {
+                    walk(root, &path, index);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    if let Ok(text) = fs::read_to_string(&path) {
+                        let name = path.strip_prefix(root).unwrap_or(&path);
+                        index.add(&name.to_string_lossy(), &text);
+                    }
+                }
+            }
+        }
+
+        let mut index = Index::new();
+        walk(dir, dir, &mut index);
+        index
+    }
+
+    // HTTP/1.1 定义的方法，其他方法一律返回 501
+    const KNOWN_METHODS: [&str; 9] = [
+        "GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH", "CONNECT", "TRACE",
+    ];
+
+    // 服务器的启动配置，从 server.toml 读取，再用 WEBSERVER_* 环境变量覆盖其中的某几项
+    // 配置文件里没写的字段使用默认值，写错的字段名直接报错，免得拼写错误被悄悄忽略
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(default, deny_unknown_fields)]
+    struct ServerConfig {
+        bind: String,
+        workers: usize,
+        document_root: PathBuf,
+        // 服务端渲染用的模板，放在 document_root 外面，不会被当作静态文件原样发出去
+        template_dir: PathBuf,
+        // 超时以毫秒为单位，0 表示不设置超时
+        read_timeout_ms: u64,
+        write_timeout_ms: u64,
+        // 设置后监听这个路径上的 Unix 域套接字而不是 bind 指定的 TCP 地址
+        unix_socket: Option<PathBuf>,
+        // 套接字文件的权限
+        unix_socket_mode: u32,
+        // 请求体的大小上限，可以写成 "1MiB" 或者字节数
+        max_body_size: ByteSize,
+    }
+
+    impl Default for ServerConfig {
+        fn default() -> ServerConfig {
+            ServerConfig {
+                bind: String::from("127.0.0.1:7878"),
+                workers: 4,
+                document_root: PathBuf::from("public"),
+                template_dir: PathBuf::from("templates"),
+                read_timeout_ms: 5000,
+                write_timeout_ms: 5000,
+                unix_socket: None,
+                unix_socket_mode: 0o660,
+                max_body_size: ByteSize(1 << 20),
+            }
+        }
+    }
+
+    impl ServerConfig {
+        // 配置文件不存在时使用默认配置；env 用来查询环境变量，测试中可以传入一个假的实现
+        fn load(
+            path: &Path,
+            env: impl Fn(&str) -> Option<String>,
+        ) -> Result<ServerConfig, Box<dyn Error>> {
+            let mut config = match fs::read_to_string(path) {
+                Ok(text) => toml::from_str(&text)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => ServerConfig::default(),
+                Err(e) => return Err(e.into()),
+            };
+            config.apply_env(env)?;
+            Ok(config)
+        }
+
+        fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+            // 数字类型的环境变量解析失败时报告是哪个变量
+            let number = |name: &str, value: String| {
+                value
+                    .parse()
+                    .map_err(|_| format!("{} must be a number, got {:?}", name, value))
+            };
+
+            if let Some(bind) = env("WEBSERVER_BIND") {
+                self.bind = bind;
+            }
+            if let Some(workers) = env("WEBSERVER_WORKERS") {
+                self.workers = number("WEBSERVER_WORKERS", workers)? as usize;
+            }
+            if let Some(root) = env("WEBSERVER_DOCUMENT_ROOT") {
+                self.document_root = PathBuf::from(root);
+            }
+            if let Some(dir) = env("WEBSERVER_TEMPLATE_DIR") {
+                self.template_dir = PathBuf::from(dir);
+            }
+            if let Some(path) = env("WEBSERVER_UNIX_SOCKET") {
+                self.unix_socket = Some(PathBuf::from(path));
+            }
+            if let Some(ms) = env("WEBSERVER_READ_TIMEOUT_MS") {
+                self.read_timeout_ms = number("WEBSERVER_READ_TIMEOUT_MS", ms)?;
+            }
+            if let Some(ms) = env("WEBSERVER_WRITE_TIMEOUT_MS") {
+                self.write_timeout_ms = number("WEBSERVER_WRITE_TIMEOUT_MS", ms)?;
+            }
+            if let Some(size) = env("WEBSERVER_MAX_BODY_SIZE") {
+                let size = byte_size::parse_size(&size)
+                    .map_err(|e| format!("WEBSERVER_MAX_BODY_SIZE: {}", e))?;
+                self.max_body_size = ByteSize(size);
+            }
+
+            // ThreadPool::new 在容量为 0 时会 panic，在这里提前报告
+            if self.workers == 0 {
+                return Err(String::from("workers must be greater than 0"));
+            }
+            Ok(())
+        }
+
+        fn timeout(ms: u64) -> Option<Duration> {
+            (ms > 0).then(|| Duration::from_millis(ms))
+        }
+    }
+
+    // 服务器的运行时设置，所有 worker 共享同一份，所以在线程间以 Arc<Server> 传递
+    struct Server {
+        logger: Arc<dyn AccessLogger>,
+        // None 表示不设置超时，read/write 可能永远阻塞
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+        // 所有 worker 都在忙时最多允许多少个连接在队列里等待，超出的连接直接返回 503
+        max_pending: usize,
+        // 静态文件的根目录
+        document_root: PathBuf,
+        // 模板所在的目录
+        template_dir: PathBuf,
+        // 请求体超过这个字节数时返回 413
+        max_body_size: u64,
+        // 已经交给线程池、还没处理完的连接数（包括正在处理的和排队中的）
+        active: AtomicUsize,
+        // 连接在队列里等待超过这个时间才轮到 worker 时，客户端多半已经放弃了，直接返回 503 而不再处理
+        max_queue_wait: Option<Duration>,
+        admission: Admission,
+        // 反向代理：路径前缀和上游地址，匹配的请求原样转发给上游
+        proxies: Vec<(String, SocketAddr)>,
+        // 每个请求的耗时和响应大小的历史记录，/metrics/history 从这里按时间段降采样
+        history: Mutex<Store>,
+        // 请求耗时送进流式统计线程，统计结果（均值、标准差、分位数）定期发布到 watch 通道上，/metrics 读最新的一份
+        // 服务器被丢弃时发送端随之丢弃，统计线程自行退出
+        latency_events: mpsc::Sender<f64>,
+        latency_summary: watch::Receiver<Summary>,
+        // 最近一分钟内完成的请求，/metrics 的 requests_per_minute
+        request_rate: Mutex<SlidingWindow>,
+        // /search 检索的源代码目录，第一次搜索时才建立索引
+        source_dir: PathBuf,
+        search_index: OnceLock<Index>,
+        // 最近的访问日志，满了以后覆盖最旧的一条，/logs/recent 从这里读，不用翻 stdout
+        recent_log: Mutex<RingBuffer<String>>,
+    }
+
+    // 准入控制的统计数据，通过 /metrics 暴露出来
+    #[derive(Default)]
+    struct Admission {
+        // 已经交给线程池、还没被 worker 取走的连接数
+        queued: AtomicUsize,
+        // 被 worker 正常处理的连接数
+        admitted: AtomicUsize,
+        // 因为连接数超过上限被立即拒绝的连接数
+        shed_overload: AtomicUsize,
+        // 因为排队太久被拒绝的连接数
+        shed_queue_timeout: AtomicUsize,
+        // 排队时间的累计值和最大值（微秒）
+        queue_wait_total_us: AtomicU64,
+        queue_wait_max_us: AtomicU64,
+    }
+
+    impl Admission {
+        fn record_wait(&self, wait: Duration) {
+            let micros = wait.as_micros() as u64;
+            self.queue_wait_total_us.fetch_add(micros, Ordering::SeqCst);
+            self.queue_wait_max_us.fetch_max(micros, Ordering::SeqCst);
+        }
+    }
+
+    // 请求耗时的样本大小和统计结果的发布间隔
+    const LATENCY_RESERVOIR: usize = 1000;
+    const LATENCY_PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+    // /logs/recent 最多保留多少条访问日志
+    const RECENT_LOG_CAPACITY: usize = 100;
+    // /metrics/history 每个指标保留的样本数，每个请求每个指标一个样本；超过后丢掉最旧的，内存不随运行时间增长
+    const HISTORY_MAX_SAMPLES: usize = 100_000;
+
+    // 连接处理完（包括处理过程中 panic）时把 active 计数减一
+    struct ActiveGuard(Arc<Server>);
+
+    impl Drop for ActiveGuard {
+        fn drop(&mut self) {
+            self.0.active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Server {
+        fn new(logger: Arc<dyn AccessLogger>) -> Server {
+            let (latency_events, events) = mpsc::channel();
+            let (latency_summary, _) =
+                streaming::spawn_stage(events, LATENCY_RESERVOIR, LATENCY_PUBLISH_INTERVAL);
+            Server {
+                logger,
+                read_timeout: Some(Duration::from_secs(5)),
+                write_timeout: Some(Duration::from_secs(5)),
+                max_pending: 64,
+                document_root: PathBuf::from("public"),
+                template_dir: PathBuf::from("templates"),
+                max_body_size: 1 << 20,
+                active: AtomicUsize::new(0),
+                max_queue_wait: Some(Duration::from_secs(1)),
+                admission: Admission::default(),
+                proxies: Vec::new(),
+                history: Mutex::new(Store::with_max_samples(HISTORY_MAX_SAMPLES)),
+                latency_events,
+                latency_summary,
+                request_rate: Mutex::new(SlidingWindow::new(TimeDelta::minutes(1))),
+                source_dir: Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+                search_index: OnceLock::new(),
+                recent_log: Mutex::new(RingBuffer::with_capacity(RECENT_LOG_CAPACITY)),
+            }
+        }
+
+        fn from_config(config: &ServerConfig, logger: Arc<dyn AccessLogger>) -> Server {
+            Server {
+                read_timeout: ServerConfig::timeout(config.read_timeout_ms),
+                write_timeout: ServerConfig::timeout(config.write_timeout_ms),
+                document_root: config.document_root.clone(),
+                template_dir: config.template_dir.clone(),
+                max_body_size: config.max_body_size.0,
+                ..Server::new(logger)
+            }
+        }
+
+        // 路径等于前缀或者在前缀之下时由对应的上游处理，和 /api 的匹配规则一样
+        fn upstream_for(&self, path: &str) -> Option<SocketAddr> {
+            self.proxies
+                .iter()
+                .find(|(prefix, _)| {
+                    path == prefix
+                        || path
+                            .strip_prefix(prefix.as_str())
+                            .is_some_and(|rest| rest.starts_with('/'))
+                })
+                .map(|(_, upstream)| *upstream)
+        }
+
+        // 连接上游并转发请求：重写逐跳（hop-by-hop）头部，在 X-Forwarded-For 末尾追加客户端地址，
+        // 让上游知道请求最初来自谁。请求体已经完整读入，按实际长度重新设置 Content-Length
+        fn forward(
+            &self,
+            request: &Request,
+            client: &Stream,
+            upstream: SocketAddr,
+        ) -> io::Result<TcpStream> {
+            let mut stream = TcpStream::connect_timeout(&upstream, Duration::from_secs(5))?;
+            stream.set_read_timeout(self.read_timeout)?;
+            stream.set_write_timeout(self.write_timeout)?;
+
+            let client_ip = client.peer()?;
+            let forwarded_for = match request.header("X-Forwarded-For") {
+                Some(previous) => format!("{}, {}", previous, client_ip),
+                None => client_ip,
+            };
+
+            let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.path);
+            for (name, value) in &request.headers {
+                if !matches!(
+                    name.as_str(),
+                    "connection" | "keep-alive" | "content-length" | "x-forwarded-for"
+                ) {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+            }
+            head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for));
+            // 每个连接只处理一个请求，上游写完响应后关闭连接，这样读到 EOF 就知道响应结束了
+            head.push_str("Connection: close\r\n");
+            head.push_str(&format!("Content-Length: {}\r\n\r\n", request.body.len()));
+
+            write_all_vectored(
+                &mut stream,
+                &mut [IoSlice::new(head.as_bytes()), IoSlice::new(&request.body)],
+            )?;
+            Ok(stream)
+        }
+
+        // 根据请求的方法和路径分发到对应的处理函数
+        fn route(&self, request: &Request) -> Response {
+            // 服务器根本不认识的方法返回 501，和“这个路径不支持该方法”的 405 区分开
+            if !KNOWN_METHODS.contains(&request.method.as_str()) {
+                return Response::new(501, "NOT IMPLEMENTED");
+            }
+            // HEAD 按 GET 处理，只是不发送响应体
+            if request.method == "HEAD" {
+                let get = Request {
+                    method: String::from("GET"),
+                    path: request.path.clone(),
+                    decoded_path: request.decoded_path.clone(),
+                    headers: request.headers.clone(),
+                    body: Vec::new(),
+                };
+                return self.route(&get).without_body();
+            }
+
+            let path = request.decoded_path.as_str();
+            let is_api = path == "/api" || path.starts_with("/api/");
+            let allowed = match self.allowed_methods(path) {
+                Some(allowed) => allowed,
+                None if is_api => return handle_api(request),
+                None => return self.not_found(),
+            };
+            if request.method == "OPTIONS" {
+                return Response::new(200, "OK").header("Allow", &allowed.join(", "));
+            }
+            if !allowed.contains(&request.method.as_str()) {
+                return Response::new(405, "METHOD NOT ALLOWED")
+                    .header("Allow", &allowed.join(", "));
+            }
+
+            match path {
+                "/metrics" => self.metrics(),
+                "/metrics/history" => self.metrics_history(request),
+                "/" | "/hello" => self.render_hello(request),
+                "/search" => self.search(request),
+                "/logs/recent" => self.recent_logs(request),
+                _ if is_api => handle_api(request),
+                _ => self.serve_file(request),
+            }
+        }
+
+        // 路由表：每个路径支持哪些方法，OPTIONS 的应答和 405 的 Allow 头部都来自这里，None 表示没有这个路径
+        // path 是 Request::decoded_path，已经去掉了查询字符串
+        fn allowed_methods(&self, path: &str) -> Option<&'static [&'static str]> {
+            const READ_ONLY: &[&str] = &["GET", "HEAD", "OPTIONS"];
+            match path {
+                // OPTIONS * 询问的是整个服务器支持的方法
+                "*" => Some(&["GET", "HEAD", "POST", "OPTIONS"]),
+                "/" | "/metrics" | "/metrics/history" | "/hello" | "/search" | "/logs/recent"
+                | "/api/health" => Some(READ_ONLY),
+                "/api/greet" => Some(&["POST", "OPTIONS"]),
+                path if path == "/api" || path.starts_with("/api/") => None,
+                path => self
+                    .resolve(path)
+                    .filter(|file| file.is_file())
+                    .map(|_| READ_ONLY),
+            }
+        }
+
+        // 把请求路径映射到 document_root 下的文件
+        fn resolve(&self, path: &str) -> Option<PathBuf> {
+            let relative = path.trim_start_matches('/');
+            // .git、.env 这类以点开头的文件和目录往往不该公开，一律当作不存在
+            let hidden = Path::new(relative).components().any(|component| {
+                matches!(component, Component::Normal(name) if name.as_encoded_bytes().starts_with(b"."))
+            });
+            if hidden {
+                return None;
+            }
+            // 规范化之后逃出 document_root 的路径（包括指向外面的符号链接）都当作不存在
+            path_util::safe_join_resolved(&self.document_root, Path::new(relative)).ok()
+        }
+
+        // 服务端渲染：/ 和 /hello 都把 template_dir 下的 hello.html 当作模板，填入这次请求的信息后返回
+        fn render_hello(&self, request: &Request) -> Response {
+            let source = match fs::read_to_string(self.template_dir.join("hello.html")) {
+                Ok(source) => source,
+                Err(_) => return self.not_found(),
+            };
+            let template = match Template::parse(&source) {
+                Ok(template) => template,
+                Err(e) => {
+                    return Response::new(500, "INTERNAL SERVER ERROR")
+                        .header("Content-Type", "text/plain; charset=utf-8")
+                        .body(e.to_string().into_bytes())
+                }
+            };
+
+            // ?name=xxx 指定问候的对象
+            let name = query_param(&request.path, "name")
+                .filter(|name| !name.is_empty())
+                .unwrap_or("world");
+            let mut headers: Vec<_> = request.headers.iter().collect();
+            headers.sort();
+            let headers = headers
+                .into_iter()
+                .map(|(name, value)| {
+                    let mut header = Context::new();
+                    header.insert(String::from("name"), Value::from(name.as_str()));
+                    header.insert(String::from("value"), Value::from(value.as_str()));
+                    Value::Map(header)
+                })
+                .collect();
+
+            let mut context = Context::new();
+            context.insert(String::from("name"), Value::from(name));
+            context.insert(String::from("method"), Value::from(request.method.as_str()));
+            context.insert(String::from("path"), Value::from(request.path.as_str()));
+            context.insert(String::from("time"), Value::from(Utc::now().to_rfc2822()));
+            context.insert(String::from("headers"), Value::List(headers));
+
+            Response::new(200, "OK")
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(template.render(&context).into_bytes())
+        }
+
+        // 全文检索本 crate 的源代码：/search?q=thread+pool，查询语法见 index::Query::parse
+        fn search(&self, request: &Request) -> Response {
+            #[derive(Serialize)]
+            struct SearchHit<'a> {
+                path: &'a str,
+                score: usize,
+            }
+
+            #[derive(Serialize)]
+            struct SearchResults<'a> {
+                query: &'a str,
+                total: usize,
+                hits: Vec<SearchHit<'a>>,
+            }
+
+            let Some(query) = query_param(&request.path, "q").map(percent_decode) else {
+                let error = ApiError {
+                    error: String::from("missing query parameter q"),
+                };
+                return Response::json(400, "BAD REQUEST", &error);
+            };
+            let index = self
+                .search_index
+                .get_or_init(|| index_sources(&self.source_dir));
+            let hits = index.search(&index.query(&query));
+            let results = SearchResults {
+                query: &query,
+                total: hits.len(),
+                hits: hits
+                    .iter()
+                    .take(20)
+                    .map(|hit| SearchHit {
+                        path: hit.name,
+                        score: hit.score,
+                    })
+                    .collect(),
+            };
+            Response::json(200, "OK", &results)
+        }
+
+        fn not_found(&self) -> Response {
+            let body = fs::read(self.document_root.join("404.html"))
+                .unwrap_or_else(|_| b"404 Not Found".to_vec());
+            Response::new(404, "NOT FOUND")
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(body)
+        }
+
+        // 静态文件：支持 Range 请求，客户端可以只下载文件的一部分，从而实现断点续传
+        fn serve_file(&self, request: &Request) -> Response {
+            let path = match self.resolve(&request.decoded_path) {
+                Some(path) if path.is_file() => path,
+                _ => return self.not_found(),
+            };
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(_) => return self.not_found(),
+            };
+            let (len, modified) = match file.metadata().and_then(|m| Ok((m.len(), m.modified()?))) {
+                Ok((len, modified)) => (len as usize, DateTime::<Utc>::from(modified)),
+                Err(_) => return self.not_found(),
+            };
+            let validators = Validators::new(len, modified);
+
+            // 客户端缓存的版本仍然有效时直接返回 304，不需要再发送文件内容
+            if validators.not_modified(request) {
+                return validators.apply(Response::new(304, "NOT MODIFIED"));
+            }
+
+            let range = request
+                .header("Range")
+                .map_or(ByteRange::Full, |value| parse_range(value, len));
+            match range {
+                ByteRange::Full => validators
+                    .apply(Response::new(200, "OK"))
+                    .header("Content-Type", content_type(&path))
+                    .header("Accept-Ranges", "bytes")
+                    .file(file, 0, len),
+                ByteRange::Partial(start, end) => validators
+                    .apply(Response::new(206, "PARTIAL CONTENT"))
+                    .header("Content-Type", content_type(&path))
+                    .header("Content-Range", &format!("bytes {}-{}/{}", start, end, len))
+                    .file(file, start as u64, end - start + 1),
+                // 416 响应通过 Content-Range: bytes */len 告诉客户端文件的实际长度
+                ByteRange::Unsatisfiable => Response::new(416, "RANGE NOT SATISFIABLE")
+                    .header("Content-Range", &format!("bytes */{}", len)),
+            }
+        }
+
+        // 以纯文本的形式输出准入控制的当前状态，每行一个指标
+        fn metrics(&self) -> Response {
+            let admission = &self.admission;
+            let waited = admission.admitted.load(Ordering::SeqCst)
+                + admission.shed_queue_timeout.load(Ordering::SeqCst);
+            let total_us = admission.queue_wait_total_us.load(Ordering::SeqCst);
+            let average_us = if waited == 0 {
+                0
+            } else {
+                total_us / waited as u64
+            };
+            let mut body = format!(
+                "in_flight {}\nqueued {}\nadmitted_total {}\nshed_total{{reason=\"overload\"}} {}\nshed_total{{reason=\"queue_timeout\"}} {}\nqueue_wait_avg_us {}\nqueue_wait_max_us {}\n",
+                self.active.load(Ordering::SeqCst),
+                admission.queued.load(Ordering::SeqCst),
+                admission.admitted.load(Ordering::SeqCst),
+                admission.shed_overload.load(Ordering::SeqCst),
+                admission.shed_queue_timeout.load(Ordering::SeqCst),
+                average_us,
+                admission.queue_wait_max_us.load(Ordering::SeqCst),
+            );
+            let per_minute = self.request_rate.lock().unwrap().count(Utc::now());
+            body.push_str(&format!("requests_per_minute {}\n", per_minute));
+            // 统计线程最多一个发布间隔之前的结果，分位数是从样本估计的
+            let latency = self.latency_summary.borrow();
+            body.push_str(&format!(
+                "request_duration_us_count {}\nrequest_duration_us_mean {:.1}\nrequest_duration_us_stddev {:.1}\n",
+                latency.count, latency.mean, latency.stddev
+            ));
+            for q in [0.5, 0.9, 0.99] {
+                if let Some(value) = latency.quantile(q) {
+                    body.push_str(&format!(
+                        "request_duration_us{{quantile=\"{}\"}} {}\n",
+                        q, value
+                    ));
+                }
+            }
+            Response::new(200, "OK")
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(body.into_bytes())
+        }
+
+        // 写访问日志，同时把耗时和响应大小记入历史
+        fn record(&self, entry: AccessLogEntry) {
+            self.logger.log(&entry);
+            self.recent_log
+                .lock()
+                .unwrap()
+                .push_overwrite(entry.to_string());
+            let _ = self.latency_events.send(entry.elapsed.as_micros() as f64);
+            self.request_rate.lock().unwrap().add(Utc::now(), 1.0);
+            let mut history = self.history.lock().unwrap();
+            // 在锁里取时间，保证同一个指标的样本按时间递增；系统时钟被往回调时 append 会拒绝，丢掉这个样本即可
+            let now = Utc::now().timestamp_millis() as u64;
+            let _ = history.append("request_duration_us", now, entry.elapsed.as_micros() as f64);
+            let _ = history.append("response_bytes", now, entry.bytes as f64);
+        }
+
+        // 最近的访问日志，一行一条，从旧到新；/logs/recent?limit=10 只看最新的 10 条
+        fn recent_logs(&self, request: &Request) -> Response {
+            let recent_log = self.recent_log.lock().unwrap();
+            let limit = match query_param(&request.path, "limit").map(str::parse::<usize>) {
+                None => recent_log.len(),
+                Some(Ok(limit)) => limit.min(recent_log.len()),
+                Some(Err(_)) => {
+                    let error = ApiError {
+                        error: String::from("invalid limit"),
+                    };
+                    return Response::json(400, "BAD REQUEST", &error);
+                }
+            };
+            let mut body = String::new();
+            for line in recent_log.iter().skip(recent_log.len() - limit) {
+                body.push_str(line);
+                body.push('\n');
+            }
+            Response::new(200, "OK")
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(body.into_bytes())
+        }
+
+        // 指标的历史走势：/metrics/history?metric=request_duration_us&from=..&to=..&step=..
+        // 时间都是毫秒级的 Unix 时间戳，默认查询最近一分钟、每秒一个桶；不带 metric 时列出所有指标
+        fn metrics_history(&self, request: &Request) -> Response {
+            #[derive(Serialize)]
+            struct History<'a> {
+                metric: &'a str,
+                from: u64,
+                to: u64,
+                step: u64,
+                buckets: Vec<Bucket>,
+            }
+
+            let history = self.history.lock().unwrap();
+            let Some(metric) = query_param(&request.path, "metric") else {
+                return Response::json(200, "OK", &history.metrics());
+            };
+            if history.len(metric) == 0 {
+                let error = ApiError {
+                    error: format!("unknown metric {}", metric),
+                };
+                return Response::json(404, "NOT FOUND", &error);
+            }
+
+            let param = |name: &str, default: u64| match query_param(&request.path, name) {
+                Some(value) => value
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid {}", name)),
+                None => Ok(default),
+            };
+            let now = Utc::now().timestamp_millis() as u64;
+            let range = param("to", now + 1).and_then(|to| {
+                let from = param("from", to.saturating_sub(60_000))?;
+                Ok((from, to, param("step", 1000)?))
+            });
+            let buckets = range.and_then(|(from, to, step)| {
+                let buckets = history
+                    .downsample(metric, from, to, step)
+                    .map_err(|e| e.to_string())?;
+                Ok((from, to, step, buckets))
+            });
+            match buckets {
+                Ok((from, to, step, buckets)) => Response::json(
+                    200,
+                    "OK",
+                    &History {
+                        metric,
+                        from,
+                        to,
+                        step,
+                        buckets,
+                    },
+                ),
+                Err(error) => Response::json(400, "BAD REQUEST", &ApiError { error }),
+            }
+        }
+
+        // 拒绝一个连接：回复 503 并通过 Retry-After 告诉客户端过一会再试
+        fn shed(&self, stream: &mut Stream, worker: Option<usize>) {
+            // 设置一个很短的写超时，避免被一个不读数据的客户端卡住
+            let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+            let response = Response::new(503, "SERVICE UNAVAILABLE")
+                .header("Retry-After", "1")
+                .header("Connection", "close");
+            if response.write_to(stream).is_ok() {
+                // 客户端可能已经发来了请求，关闭时接收缓冲区里还有未读数据的话内核会发送 RST，
+                // 客户端就可能读不到这个 503，所以先把已经到达的数据读掉再关闭
+                let _ = stream.set_nonblocking(true);
+                let mut buffer = [0; 1024];
+                while matches!(stream.read(&mut buffer), Ok(n) if n > 0) {}
+                self.record(AccessLogEntry {
+                    worker,
+                    method: String::from("-"),
+                    path: String::from("-"),
+                    status: response.status,
+                    bytes: 0,
+                    elapsed: Duration::ZERO,
+                });
+            }
+        }
+
+        // 把连接交给线程池处理。线程池的队列满了以后，按 RejectionPolicy 要么阻塞接受连接的线程，要么拒绝任务，
+        // 客户端都得不到任何回应，所以在接受连接的线程里先检查负载，超出上限就立即回复 503，不再占用队列
+        fn dispatch(self: &Arc<Self>, pool: &ThreadPool, stream: impl Into<Stream>) {
+            let mut stream = stream.into();
+            let limit = pool.size() + self.max_pending;
+            if self.active.load(Ordering::SeqCst) >= limit {
+                self.admission.shed_overload.fetch_add(1, Ordering::SeqCst);
+                self.shed(&mut stream, None);
+                return;
+            }
+
+            self.active.fetch_add(1, Ordering::SeqCst);
+            self.admission.queued.fetch_add(1, Ordering::SeqCst);
+            let guard = ActiveGuard(Arc::clone(self));
+            let enqueued = Instant::now();
+            let priority = stream.priority();
+            let submitted = pool.execute_with_priority(
+                move || {
+                    let server = &guard.0;
+                    let admission = &server.admission;
+                    let wait = enqueued.elapsed();
+                    admission.queued.fetch_sub(1, Ordering::SeqCst);
+                    admission.record_wait(wait);
+
+                    // 排队时间也是背压的信号：即使队列没满，等待太久说明处理能力已经跟不上
+                    if server.max_queue_wait.is_some_and(|max| wait > max) {
+                        admission.shed_queue_timeout.fetch_add(1, Ordering::SeqCst);
+                        server.shed(&mut stream, WORKER_ID.with(|worker_id| worker_id.get()));
+                    } else {
+                        admission.admitted.fetch_add(1, Ordering::SeqCst);
+                        server.handle_connection(stream);
+                    }
+                    drop(guard);
+                },
+                priority,
+            );
+            // 被拒绝的任务连同其中的连接一起被丢弃，连接直接关闭；active 由 guard 的 drop 减掉
+            if submitted.is_err() {
+                self.admission.queued.fetch_sub(1, Ordering::SeqCst);
+                self.admission.shed_overload.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        // 处理连接
+        fn handle_connection(&self, mut stream: Stream) {
+            let start = Instant::now();
+
+            // 一个连上来却迟迟不发数据的客户端会让 stream.read 永远阻塞，占住一个 worker
+            // 设置超时后，read/write 在超时时返回错误（Unix 上是 WouldBlock，Windows 上是 TimedOut）
+            if stream.set_read_timeout(self.read_timeout).is_err()
+                || stream.set_write_timeout(self.write_timeout).is_err()
+            {
+                return;
+            }
+
+            let (method, path, response) = match read_request(&mut stream, self.max_body_size) {
+                Ok(Some(request)) => match self.upstream_for(&request.path) {
+                    Some(upstream) => match self.forward(&request, &stream, upstream) {
+                        // 上游的响应不经过 Response，边读边写回客户端
+                        Ok(upstream) => {
+                            if let Ok((status, bytes)) = relay(upstream, &mut stream) {
+                                self.record(AccessLogEntry {
+                                    worker: WORKER_ID.with(|worker_id| worker_id.get()),
+                                    method: request.method,
+                                    path: request.path,
+                                    status,
+                                    bytes,
+                                    elapsed: start.elapsed(),
+                                });
+                            }
+                            return;
+                        }
+                        // 上游连不上或者不接收请求
+                        Err(_) => {
+                            let response = Response::new(502, "BAD GATEWAY");
+                            (request.method, request.path, response)
+                        }
+                    },
+                    None => {
+                        let response = self.route(&request);
+                        (request.method, request.path, response)
+                    }
+                },
+                // 没等到完整的请求就超时了，告诉客户端 408 后关闭连接
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    let response =
+                        Response::new(408, "REQUEST TIMEOUT").header("Connection", "close");
+                    (String::from("-"), String::from("-"), response)
+                }
+                // 请求体太大，不读它，直接关闭连接
+                Err(e) if e.kind() == io::ErrorKind::FileTooLarge => {
+                    let response =
+                        Response::new(413, "PAYLOAD TOO LARGE").header("Connection", "close");
+                    (String::from("-"), String::from("-"), response)
+                }
+                // 请求行格式错误
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    let response = Response::new(400, "BAD REQUEST").header("Connection", "close");
+                    (String::from("-"), String::from("-"), response)
+                }
+                _ => return,
+            };
+
+            // 写超时同样会返回错误，此时对端已经不可用，直接放弃这个连接
+            if response.send(&mut stream).is_err() {
+                return;
+            }
+
+            self.record(AccessLogEntry {
+                worker: WORKER_ID.with(|worker_id| worker_id.get()),
+                method,
+                path,
+                status: response.status,
+                bytes: response.bytes_sent(),
+                elapsed: start.elapsed(),
+            });
+        }
+    }
+
+    // Web 服务器中涉及到的两个主要协议是 超文本传输协议（Hypertext Transfer Protocol，HTTP）和 传输控制协议（Transmission Control Protocol，TCP）
+    // 这两者都是 请求-响应（request-response）协议，也就是说，有 客户端（client）来初始化请求，并有 服务端（server）监听请求并向客户端提供响应
+    #[test]
+    fn webserver_example() {
+        // 监听地址、线程数等设置来自 server.toml（默认 127.0.0.1:7878 和 4 个线程），可以用 WEBSERVER_* 环境变量覆盖
+        let config =
+            ServerConfig::load(Path::new("server.toml"), |name| env::var(name).ok()).unwrap();
+        // 监听 TCP 连接，这段代码会在配置的地址上监听传入的 TCP 流
+        // 这个函数叫做 bind 是因为，在网络领域，连接到监听端口被称为 “绑定到一个端口”（“binding to a port”）
+        // bind 函数返回 Result<T, E>，这表明绑定可能会失败，例如，连接 80 端口需要管理员权限（非管理员用户只能监听大于 1024 的端口），所以如果不是管理员尝试连接 80 端口，则会绑定失败。另一个例子是如果运行两个此程序的实例这样会有两个程序监听相同的端口，绑定会失败
+        // 配置了 unix_socket 时监听的是 Unix 域套接字，例如 curl --unix-socket /tmp/webserver.sock http://localhost/
+        let listener = Listener::bind(&config).unwrap();
+        // 初始化一个容量为 config.workers 的线程池
+        let pool = ThreadPool::new(config.workers);
+        // 多个 worker 共享同一份服务器设置和日志输出，所以用 Arc 包装
+        let server = Arc::new(Server::from_config(&config, Arc::new(StdoutLogger)));
+
+        // incoming 方法返回一个迭代器，它提供了一系列的流（更准确的说是 TcpStream 类型的流）
+        // 流（stream）代表一个客户端和服务端之间打开的连接
+        // 连接（connection）代表客户端连接服务端、服务端生成响应以及服务端关闭连接的全部请求 / 响应过程
+        // for 循环会依次处理每个连接并产生一系列的流供我们处理
+        for stream in listener.incoming() {
+            // 当客户端连接到服务端时 incoming 方法返回错误是可能的，因为我们实际上没有遍历连接，而是遍历 连接尝试（connection attempts）。连接可能会因为很多原因不能成功，大部分是操作系统相关的。例如，很多系统限制同时打开的连接数；新连接尝试产生错误，直到一些打开的连接关闭为止
+            let stream = stream.unwrap();
+            // 提交任务到池中
+            server.dispatch(&pool, stream);
+        }
+        println!("Shutting down.");
+        // 关闭之前打印线程池的统计：处理了多少任务、worker 有多忙
+        print!("{}", pool.stats());
+        // 当 ThreadPool 在 webserver_example 的结尾离开作用域时，其 Drop 实现开始工作，线程池通知所有线程终止
+    }
+
+    // 在随机端口上启动服务器，处理完 connections 个连接后退出，返回监听地址
+    fn spawn_server(connections: usize, server: Server) -> SocketAddr {
+        spawn_server_with_workers(2, connections, server)
+    }
+
+    fn spawn_server_with_workers(workers: usize, connections: usize, server: Server) -> SocketAddr {
+        let server = Arc::new(server);
+        // 端口号为 0 时由操作系统分配一个空闲端口，这样多个测试可以并行运行而不会冲突
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let pool = ThreadPool::new(workers);
+            for stream in listener.incoming().take(connections) {
+                server.dispatch(&pool, stream.unwrap());
+            }
+        });
+        addr
+    }
+
+    // 发送原始请求并读取完整响应，服务端写完响应后会关闭连接，所以 read_to_string 能读到结尾
+    fn send_raw(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn server_config_from_toml_and_env() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            bind = "0.0.0.0:8080"
+            workers = 8
+            read_timeout_ms = 0
+            max_body_size = "64KiB"
+            "#,
+        )
+        .unwrap();
+        // 没写的字段使用默认值
+        assert_eq!(
+            config,
+            ServerConfig {
+                bind: String::from("0.0.0.0:8080"),
+                workers: 8,
+                read_timeout_ms: 0,
+                max_body_size: ByteSize(64 * 1024),
+                ..ServerConfig::default()
+            }
+        );
+        let server = Server::from_config(&config, Arc::new(StdoutLogger));
+        assert_eq!(server.read_timeout, None);
+        assert_eq!(server.write_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(server.max_body_size, 64 * 1024);
+
+        assert!(toml::from_str::<ServerConfig>("worker = 8").is_err());
+        // 大小也可以直接写字节数；单位写错时报错
+        let config: ServerConfig = toml::from_str("max_body_size = 4096").unwrap();
+        assert_eq!(config.max_body_size, ByteSize(4096));
+        assert!(toml::from_str::<ServerConfig>("max_body_size = \"4 KiBs\"").is_err());
+
+        // 环境变量优先于配置文件
+        let dir = temp_dir("config");
+        let path = dir.join("server.toml");
+        fs::write(&path, "bind = \"0.0.0.0:8080\"\nworkers = 8\n").unwrap();
+        let vars: HashMap<&str, &str> = [
+            ("WEBSERVER_WORKERS", "2"),
+            ("WEBSERVER_DOCUMENT_ROOT", "/srv/www"),
+            ("WEBSERVER_MAX_BODY_SIZE", "2MB"),
+        ]
+        .into();
+        let env = |name: &str| vars.get(name).map(|value| value.to_string());
+        let config = ServerConfig::load(&path, env).unwrap();
+        assert_eq!(config.bind, "0.0.0.0:8080");
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.document_root, PathBuf::from("/srv/www"));
+        assert_eq!(config.max_body_size, ByteSize(2_000_000));
+
+        let config = ServerConfig::load(&dir.join("missing.toml"), |_| None).unwrap();
+        assert_eq!(config, ServerConfig::default());
+        // 仓库里的 server.toml 写的就是默认值
+        let config = ServerConfig::load(Path::new("server.toml"), |_| None).unwrap();
+        assert_eq!(config, ServerConfig::default());
+        // 默认只公开 public 目录，仓库里的源代码和配置文件不会被当作静态文件
+        let server = Server::from_config(&config, Arc::new(StdoutLogger));
+        for path in [
+            "/server.toml",
+            "/Cargo.toml",
+            "/src/main.rs",
+            "/.git/config",
+        ] {
+            let response = server.route(&parse_request(&format!("GET {} HTTP/1.1\r\n\r\n", path)));
+            assert_eq!(response.status, 404, "{}", path);
+        }
+
+        let error = ServerConfig::load(&path, |name| {
+            (name == "WEBSERVER_WORKERS").then(|| String::from("many"))
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("WEBSERVER_WORKERS"));
+        assert!(ServerConfig::load(&path, |name| {
+            (name == "WEBSERVER_WORKERS").then(|| String::from("0"))
+        })
+        .is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn test_server() -> Server {
+        Server::new(Arc::new(StdoutLogger))
+    }
+
+    // 在系统临时目录下创建一个测试专用的目录，目录名带上进程号避免并行测试互相干扰
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("webserver-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 把响应完整写进内存，取出头部之后的部分
+    fn body_of(response: &Response) -> Vec<u8> {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let head_end = find_subsequence(&out, b"\r\n\r\n").unwrap() + 4;
+        out.split_off(head_end)
+    }
+
+    // &[u8] 实现了 Read，可以不经过网络直接构造请求
+    fn parse_request(raw: &str) -> Request {
+        read_request(&mut raw.as_bytes(), u64::MAX)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn api_greet_round_trip() {
+        let addr = spawn_server(1, Server::new(Arc::new(StdoutLogger)));
+        let response = client::post(
+            addr,
+            "/api/greet",
+            "application/json",
+            br#"{"name":"Ferris"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        let greet: GreetResponse = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(
+            greet,
+            GreetResponse {
+                message: String::from("Hello, Ferris!")
+            }
+        );
+        assert_eq!(response.header("Content-Language"), Some("en"));
+
+        // 按 Accept-Language 换成客户端想要的语言
+        let body = r#"{"name":"Ferris"}"#;
+        let request = parse_request(&format!(
+            "POST /api/greet HTTP/1.1\r\nContent-Type: application/json\r\nAccept-Language: de, fr;q=0.9, en;q=0.5\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        let response = test_server().route(&request);
+        assert_eq!(
+            body_of(&response),
+            r#"{"message":"Bonjour, Ferris !"}"#.as_bytes()
+        );
+        assert_eq!(header_value(&response, "Content-Language"), Some("fr"));
+        assert_eq!(header_value(&response, "Vary"), Some("Accept-Language"));
+    }
+
+    #[test]
+    fn api_rejects_invalid_json() {
+        let request = parse_request(
+            "POST /api/greet HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 9\r\n\r\n{\"nam\":1}",
+        );
+        let response = test_server().route(&request);
+        assert_eq!(response.status, 400);
+        let error: ApiError = serde_json::from_slice(&response.body).unwrap();
+        assert!(error.error.contains("missing field `name`"));
+    }
+
+    #[test]
+    fn api_requires_json_content_type() {
+        let request = parse_request(
+            "POST /api/greet HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi",
+        );
+        assert_eq!(test_server().route(&request).status, 415);
+    }
+
+    #[test]
+    fn api_health_and_unknown_route() {
+        let server = test_server();
+        let response = server.route(&parse_request("GET /api/health HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, br#"{"status":"ok"}"#);
+
+        let response = server.route(&parse_request("GET /api/nope HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 404);
+        assert!(response.headers.contains(&(
+            String::from("Content-Type"),
+            String::from("application/json")
+        )));
+    }
+
+    #[test]
+    fn access_log_records_each_request() {
+        let logger = Arc::new(MemoryLogger::default());
+        let addr = spawn_server(2, Server::new(logger.clone()));
+
+        send_raw(addr, "GET /api/health HTTP/1.1\r\n\r\n");
+        send_raw(addr, "DELETE /api/missing HTTP/1.1\r\n\r\n");
+
+        // 客户端读到 EOF 时服务端已经写完日志，因为日志在连接关闭之前记录
+        let lines = logger.lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        // 请求由线程池中的 worker 处理，所以日志中带有 worker id
+        assert!(lines[0].starts_with("worker=") && !lines[0].starts_with("worker=-"));
+        assert!(lines[0].contains(" method=GET path=/api/health status=200 bytes=15 elapsed_ms="));
+        assert!(lines[1].contains(" method=DELETE path=/api/missing status=404 "));
+    }
+
+    #[test]
+    fn head_and_options() {
+        let root = temp_dir("methods");
+        fs::write(root.join("page.html"), "<p>page</p>").unwrap();
+        let server = Server {
+            document_root: root.clone(),
+            ..test_server()
+        };
+
+        // HEAD 的头部和 GET 一样，Content-Length 是完整响应体的长度，但不写出响应体
+        let head = server.route(&parse_request("HEAD /page.html HTTP/1.1\r\n\r\n"));
+        assert_eq!(head.status, 200);
+        assert!(head.head_only);
+        assert_eq!(head.bytes_sent(), 0);
+        let mut out = Vec::new();
+        head.write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Content-Length: 11\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+
+        let head = server.route(&parse_request("HEAD /api/health HTTP/1.1\r\n\r\n"));
+        assert_eq!(body_of(&head), b"");
+        assert_eq!(head.content_length(), 15);
+
+        let options = |path: &str| {
+            let response = server.route(&parse_request(&format!(
+                "OPTIONS {} HTTP/1.1\r\n\r\n",
+                path
+            )));
+            assert_eq!(response.status, 200);
+            header_value(&response, "Allow").unwrap().to_string()
+        };
+        assert_eq!(options("/api/greet"), "POST, OPTIONS");
+        assert_eq!(options("/page.html"), "GET, HEAD, OPTIONS");
+        assert_eq!(options("*"), "GET, HEAD, POST, OPTIONS");
+        let missing = server.route(&parse_request("OPTIONS /missing HTTP/1.1\r\n\r\n"));
+        assert_eq!(missing.status, 404);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn error_statuses() {
+        let server = test_server();
+        // 路径存在但不支持这个方法
+        let response = server.route(&parse_request("GET /api/greet HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 405);
+        assert_eq!(header_value(&response, "Allow"), Some("POST, OPTIONS"));
+        let response = server.route(&parse_request("DELETE /api/health HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 405);
+        // 服务器不认识的方法
+        let response = server.route(&parse_request("BREW /api/health HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 501);
+
+        // 请求行格式错误
+        for raw in [
+            "GARBAGE\r\n\r\n",
+            "GET /\r\n\r\n",
+            "get / HTTP/1.1\r\n\r\n",
+            "GET index.html HTTP/1.1\r\n\r\n",
+            "GET / FTP/1.0\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+        ] {
+            let err = read_request(&mut raw.as_bytes(), u64::MAX).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn error_statuses_over_the_network() {
+        let logger = Arc::new(MemoryLogger::default());
+        let addr = spawn_server(4, Server::new(logger.clone()));
+
+        let response = send_raw(addr, "GARBAGE\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+        let response = send_raw(addr, "BREW /coffee HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501 NOT IMPLEMENTED\r\n"));
+
+        let head = client::request(addr, "HEAD", "/api/health", &[], &[]).unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.header("Content-Length"), Some("15"));
+        assert!(head.body.is_empty());
+
+        // 默认的请求体上限是 1 MiB，超过的请求体不会被读取
+        let response = send_raw(
+            addr,
+            "POST /api/greet HTTP/1.1\r\nContent-Length: 1048577\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n"));
+
+        // 客户端按 Content-Length 读完响应就返回了，不等连接关闭，给服务端一点时间写日志
+        thread::sleep(Duration::from_millis(50));
+        // 几个请求落在不同的 worker 上，日志的先后顺序不固定
+        let lines = logger.lines.lock().unwrap();
+        assert!(lines
+            .iter()
+            .any(|line| line.contains(" method=- path=- status=400 ")));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains(" method=HEAD path=/api/health status=200 bytes=0 ")));
+    }
+
+    #[test]
+    fn access_log_entry_format() {
+        let entry = AccessLogEntry {
+            worker: None,
+            method: String::from("GET"),
+            path: String::from("/"),
+            status: 200,
+            bytes: 42,
+            elapsed: Duration::from_micros(1500),
+        };
+        assert_eq!(
+            entry.to_string(),
+            "worker=- method=GET path=/ status=200 bytes=42 elapsed_ms=1.500"
+        );
+    }
+
+    #[test]
+    fn slow_client_gets_408() {
+        let server = Server {
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Server::new(Arc::new(StdoutLogger))
+        };
+        let addr = spawn_server(2, server);
+
+        // 一个故意很慢的客户端：只发送半个请求行就停下来
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HT").unwrap();
+        let started = Instant::now();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 REQUEST TIMEOUT"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // 连上之后什么都不发的客户端同样会超时，不会永远占住 worker
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 REQUEST TIMEOUT"));
+    }
+
+    #[test]
+    fn overloaded_server_answers_503() {
+        let logger = Arc::new(MemoryLogger::default());
+        let server = Server {
+            read_timeout: Some(Duration::from_millis(500)),
+            max_pending: 1,
+            ..Server::new(logger.clone())
+        };
+        // 1 个 worker + 1 个排队名额，第三个连接会被拒绝
+        let addr = spawn_server_with_workers(1, 3, server);
+
+        // 两个不发数据的客户端：一个占住 worker，一个占住排队名额
+        let busy = TcpStream::connect(addr).unwrap();
+        let queued = TcpStream::connect(addr).unwrap();
+
+        let started = Instant::now();
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 SERVICE UNAVAILABLE"));
+        assert!(response.contains("Retry-After: 1"));
+        // 不需要等前面的连接超时，503 是立即返回的
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(logger.lines.lock().unwrap()[0].contains("status=503"));
+
+        drop(busy);
+        drop(queued);
+    }
+
+    #[test]
+    fn queue_wait_sheds_load_and_reports_metrics() {
+        let server = Server {
+            read_timeout: Some(Duration::from_millis(300)),
+            max_queue_wait: Some(Duration::from_millis(100)),
+            ..test_server()
+        };
+        let addr = spawn_server_with_workers(1, 3, server);
+
+        // 一个不发数据的客户端占住唯一的 worker 300ms，后面的请求在队列里等待超过 100ms
+        let busy = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let response = send_raw(addr, "GET /api/health HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 SERVICE UNAVAILABLE"));
+        assert!(response.contains("Retry-After: 1"));
+        drop(busy);
+
+        let metrics = send_raw(addr, "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+        // 被占住的连接和 /metrics 本身被处理，排队太久的那个被拒绝
+        assert!(metrics.contains("in_flight 1\n"));
+        assert!(metrics.contains("queued 0\n"));
+        assert!(metrics.contains("admitted_total 2\n"));
+        assert!(metrics.contains("shed_total{reason=\"overload\"} 0\n"));
+        assert!(metrics.contains("shed_total{reason=\"queue_timeout\"} 1\n"));
+        let max_wait: u64 = metrics
+            .lines()
+            .find_map(|line| line.strip_prefix("queue_wait_max_us "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(max_wait >= 100_000);
+    }
+
+    // 每次 write_vectored 最多只接受 3 个字节，模拟内核缓冲区满时的部分写入
+    struct TrickleWriter(Vec<u8>);
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            match bufs.iter().find(|buf| !buf.is_empty()) {
+                Some(buf) => self.write(buf),
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn vectored_write_matches_copying_write() {
+        let response = Response::new(200, "OK")
+            .header("Content-Type", "text/plain")
+            .body(b"hello vectored world".to_vec());
+
+        let mut copied = Vec::new();
+        response.write_to_copying(&mut copied).unwrap();
+
+        // 即使每次只写入几个字节，最终写出的内容也和一次性拼接写出的完全一致
+        let mut trickle = TrickleWriter(Vec::new());
+        response.write_to(&mut trickle).unwrap();
+        assert_eq!(trickle.0, copied);
+
+        let mut vectored = Vec::new();
+        Response::new(204, "NO CONTENT")
+            .write_to(&mut vectored)
+            .unwrap();
+        assert_eq!(vectored, b"HTTP/1.1 204 NO CONTENT\r\n\r\n");
+    }
+
+    // 对比两种写法的耗时：cargo test bench_vectored_write -- --ignored --show-output
+    #[test]
+    #[ignore]
+    fn bench_vectored_write() {
+        const ROUNDS: usize = 20_000;
+        let response = Response::new(200, "OK")
+            .header("Content-Type", "application/octet-stream")
+            .header("Cache-Control", "no-cache")
+            .body(vec![b'x'; 16 * 1024]);
+
+        // 通过本地回环写入真实的 TcpStream，另一个线程负责把数据读走
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            io::copy(&mut stream, &mut io::sink()).unwrap()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let started = Instant::now();
+        for _ in 0..ROUNDS {
+            response.write_to_copying(&mut stream).unwrap();
+        }
+        let copying = started.elapsed();
+
+        let started = Instant::now();
+        for _ in 0..ROUNDS {
+            response.write_to(&mut stream).unwrap();
+        }
+        let vectored = started.elapsed();
+
+        drop(stream);
+        let total = reader.join().unwrap();
+        println!(
+            "{} responses x2, {} bytes: format! + write_all = {:?}, write_vectored = {:?}",
+            ROUNDS, total, copying, vectored
+        );
+    }
+
+    #[test]
+    fn parse_range_forms() {
+        assert_eq!(parse_range("bytes=0-4", 10), ByteRange::Partial(0, 4));
+        assert_eq!(parse_range("bytes=5-", 10), ByteRange::Partial(5, 9));
+        assert_eq!(parse_range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(parse_range("bytes=-30", 10), ByteRange::Partial(0, 9));
+        assert_eq!(parse_range("bytes=8-100", 10), ByteRange::Partial(8, 9));
+        assert_eq!(parse_range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-2", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), ByteRange::Unsatisfiable);
+        // 不认识的格式被忽略
+        assert_eq!(parse_range("items=0-4", 10), ByteRange::Full);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), ByteRange::Full);
+        assert_eq!(parse_range("bytes=a-b", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn static_file_range_requests() {
+        let root = temp_dir("range");
+        fs::write(root.join("data.txt"), "0123456789").unwrap();
+        let server = Server {
+            document_root: root.clone(),
+            ..test_server()
+        };
+
+        let full = server.route(&parse_request("GET /data.txt HTTP/1.1\r\n\r\n"));
+        assert_eq!(full.status, 200);
+        assert_eq!(body_of(&full), b"0123456789");
+
+        let partial = server.route(&parse_request(
+            "GET /data.txt HTTP/1.1\r\nRange: bytes=2-5\r\n\r\n",
+        ));
+        assert_eq!(partial.status, 206);
+        assert_eq!(body_of(&partial), b"2345");
+        assert!(partial
+            .headers
+            .contains(&(String::from("Content-Range"), String::from("bytes 2-5/10"))));
+
+        let invalid = server.route(&parse_request(
+            "GET /data.txt HTTP/1.1\r\nRange: bytes=20-\r\n\r\n",
+        ));
+        assert_eq!(invalid.status, 416);
+        assert!(invalid
+            .headers
+            .contains(&(String::from("Content-Range"), String::from("bytes */10"))));
+
+        // 逃出 document_root 的路径一律 404，没有逃出去的 .. 没关系
+        let escape = server.route(&parse_request("GET /../data.txt HTTP/1.1\r\n\r\n"));
+        assert_eq!(escape.status, 404);
+        let inside = server.route(&parse_request("GET /sub/../data.txt HTTP/1.1\r\n\r\n"));
+        assert_eq!(body_of(&inside), b"0123456789");
+        // 符号链接指向 document_root 外面也不行
+        let outside = temp_dir("range-outside");
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("link.txt")).unwrap();
+        let link = server.route(&parse_request("GET /link.txt HTTP/1.1\r\n\r\n"));
+        assert_eq!(link.status, 404);
+        fs::remove_dir_all(outside).unwrap();
+        // 以点开头的文件和目录不公开
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".git/config"), "[core]").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        for path in [
+            "/.git/config",
+            "/.env",
+            "/sub/../.env",
+            "/.git/../data.txt",
+            "/%2Eenv",
+            "/.env?x=1",
+        ] {
+            let hidden = server.route(&parse_request(&format!("GET {} HTTP/1.1\r\n\r\n", path)));
+            assert_eq!(hidden.status, 404, "{}", path);
+        }
+
+        // 查询字符串不是文件名的一部分，路径里的 %XX 解码以后再找文件，+ 就是加号
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        fs::write(root.join("a b+c.txt"), "abc").unwrap();
+        for (path, body) in [
+            ("/style.css?v=1", &b"body {}"[..]),
+            ("/data.txt?download=1&x=%2F", b"0123456789"),
+            ("/a%20b+c.txt", b"abc"),
+        ] {
+            let response = server.route(&parse_request(&format!("GET {} HTTP/1.1\r\n\r\n", path)));
+            assert_eq!(response.status, 200, "{}", path);
+            assert_eq!(body_of(&response), body);
+        }
+        let options = server.route(&parse_request("OPTIONS /style.css?v=1 HTTP/1.1\r\n\r\n"));
+        assert_eq!(options.status, 200);
+        let post = server.route(&parse_request("POST /style.css?v=1 HTTP/1.1\r\n\r\n"));
+        assert_eq!(post.status, 405);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn resumable_download() {
+        let root = temp_dir("resume");
+        let contents: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        fs::write(root.join("big.bin"), &contents).unwrap();
+        let addr = spawn_server(
+            2,
+            Server {
+                document_root: root.clone(),
+                ..test_server()
+            },
+        );
+
+        // 模拟下载到一半断开，然后用 Range 请求从断点处继续
+        let fetch = |range: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET /big.bin HTTP/1.1\r\nRange: {}\r\n\r\n", range).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            let head_end = find_subsequence(&response, b"\r\n\r\n").unwrap() + 4;
+            response[head_end..].to_vec()
+        };
+        let mut downloaded = fetch("bytes=0-2999");
+        assert_eq!(downloaded.len(), 3000);
+        downloaded.extend(fetch(&format!("bytes={}-", downloaded.len())));
+        assert_eq!(downloaded, contents);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn file_body_send_and_copy_agree() {
+        let root = temp_dir("sendfile");
+        let contents: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        let path = root.join("blob.bin");
+        fs::write(&path, &contents).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+
+        // 通过 send（Linux 上是 sendfile）发送文件的中间一段
+        let response = Response::new(200, "OK").file(File::open(&path).unwrap(), 1000, 50_000);
+        let sender = thread::spawn(move || response.send(&mut Stream::Tcp(server_side)).unwrap());
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        sender.join().unwrap();
+
+        let head_end = find_subsequence(&received, b"\r\n\r\n").unwrap() + 4;
+        assert!(received.starts_with(b"HTTP/1.1 200 OK\r\nContent-Length: 50000\r\n"));
+        assert_eq!(&received[head_end..], &contents[1000..51_000]);
+
+        // 经过缓冲区拷贝的写法得到同样的内容
+        let response = Response::new(200, "OK").file(File::open(&path).unwrap(), 1000, 50_000);
+        assert_eq!(body_of(&response), &contents[1000..51_000]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // 对比 sendfile 和缓冲区拷贝发送大文件的吞吐量：cargo test bench_sendfile -- --ignored --show-output
+    #[test]
+    #[ignore]
+    fn bench_sendfile() {
+        const SIZE: usize = 256 * 1024 * 1024;
+        let root = temp_dir("bench-sendfile");
+        let path = root.join("large.bin");
+        fs::write(&path, vec![7u8; SIZE]).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            io::copy(&mut stream, &mut io::sink()).unwrap()
+        });
+        let mut stream = Stream::Tcp(TcpStream::connect(addr).unwrap());
+
+        let body = FileBody {
+            file: File::open(&path).unwrap(),
+            offset: 0,
+            len: SIZE,
+        };
+        let throughput = |elapsed: Duration| SIZE as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64();
+
+        let started = Instant::now();
+        body.copy_to(&mut stream).unwrap();
+        let copying = started.elapsed();
+
+        let started = Instant::now();
+        body.send_to(&mut stream).unwrap();
+        let sendfile = started.elapsed();
+
+        drop(stream);
+        reader.join().unwrap();
+        println!(
+            "{} MiB: buffered copy = {:.0} MiB/s, send_to = {:.0} MiB/s",
+            SIZE / 1024 / 1024,
+            throughput(copying),
+            throughput(sendfile)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    fn header_value<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn conditional_get_returns_304() {
+        let root = temp_dir("etag");
+        fs::write(root.join("page.html"), "<h1>cached</h1>").unwrap();
+        let server = Server {
+            document_root: root.clone(),
+            ..test_server()
+        };
+
+        let first = server.route(&parse_request("GET /page.html HTTP/1.1\r\n\r\n"));
+        assert_eq!(first.status, 200);
+        let etag = header_value(&first, "ETag").unwrap().to_string();
+        let last_modified = header_value(&first, "Last-Modified").unwrap().to_string();
+
+        // 带上 ETag 的第二次请求被短路：304 且不携带文件内容
+        let second = server.route(&parse_request(&format!(
+            "GET /page.html HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n",
+            etag
+        )));
+        assert_eq!(second.status, 304);
+        assert!(second.file.is_none());
+        assert_eq!(second.content_length(), 0);
+        assert_eq!(header_value(&second, "ETag"), Some(etag.as_str()));
+        // 304 不写 Content-Length，免得缓存把存着的长度改成 0
+        let mut raw = Vec::new();
+        second.write_to(&mut raw).unwrap();
+        let raw = String::from_utf8(raw).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"), "{}", raw);
+        assert!(!raw.contains("Content-Length"), "{}", raw);
+        assert!(raw.ends_with("\r\n\r\n"));
+
+        let by_date = server.route(&parse_request(&format!(
+            "GET /page.html HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
+            last_modified
+        )));
+        assert_eq!(by_date.status, 304);
+
+        // ETag 不匹配或者日期早于修改时间时正常返回文件
+        let stale = server.route(&parse_request(
+            "GET /page.html HTTP/1.1\r\nIf-None-Match: \"0-0\"\r\n\r\n",
+        ));
+        assert_eq!(stale.status, 200);
+        let old = server.route(&parse_request(
+            "GET /page.html HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n",
+        ));
+        assert_eq!(old.status, 200);
+        assert_eq!(body_of(&old), b"<h1>cached</h1>");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // 用 http_client_example 的客户端从网络上访问服务器，覆盖静态文件、Range 和条件请求的完整往返
+    #[test]
+    fn http_client_round_trips() {
+        let root = temp_dir("client");
+        fs::write(root.join("page.html"), "<h1>hi</h1>").unwrap();
+        let server = Server {
+            document_root: root.clone(),
+            ..test_server()
+        };
+        let addr = spawn_server(5, server);
+
+        let index = client::get(addr, "/page.html").unwrap();
+        assert_eq!((index.status, index.reason.as_str()), (200, "OK"));
+        assert_eq!(
+            index.header("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(index.text(), "<h1>hi</h1>");
+
+        let etag = index.header("ETag").unwrap();
+        let cached =
+            client::request(addr, "GET", "/page.html", &[("If-None-Match", etag)], &[]).unwrap();
+        assert_eq!(cached.status, 304);
+        assert!(cached.body.is_empty());
+
+        let partial =
+            client::request(addr, "GET", "/page.html", &[("Range", "bytes=4-")], &[]).unwrap();
+        assert_eq!(partial.status, 206);
+        assert_eq!(partial.text(), "hi</h1>");
+
+        let missing = client::get(addr, "/missing.html").unwrap();
+        assert_eq!(missing.status, 404);
+
+        let health = client::get(addr, "/api/health").unwrap();
+        assert_eq!(health.text(), r#"{"status":"ok"}"#);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn renders_hello_template() {
+        let root = temp_dir("template");
+        fs::write(
+            root.join("hello.html"),
+            "<h1>Hello, {{name}}!</h1><p>{{method}} {{path}}</p><ul>{{#each headers}}<li>{{name}}={{value}}</li>{{/each}}</ul>",
+        )
+        .unwrap();
+        let server = Server {
+            template_dir: root.clone(),
+            ..test_server()
+        };
+
+        let response = server.route(&parse_request(
+            "GET /hello?name=<Ferris> HTTP/1.1\r\nAccept: text/html\r\nHost: localhost\r\n\r\n",
+        ));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            "<h1>Hello, &lt;Ferris&gt;!</h1><p>GET /hello?name=&lt;Ferris&gt;</p><ul><li>accept=text/html</li><li>host=localhost</li></ul>"
+        );
+
+        let response = server.route(&parse_request("GET /hello HTTP/1.1\r\n\r\n"));
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .starts_with("<h1>Hello, world!</h1>"));
+        // / 也是渲染后的页面；模板不在 document_root 里，不会被原样发出去
+        let response = server.route(&parse_request("GET / HTTP/1.1\r\n\r\n"));
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .starts_with("<h1>Hello, world!</h1>"));
+        let raw = server.route(&parse_request("GET /hello.html HTTP/1.1\r\n\r\n"));
+        assert_eq!(raw.status, 404);
+
+        // 仓库里带的模板
+        let shipped = test_server().route(&parse_request("GET /?name=Rust HTTP/1.1\r\n\r\n"));
+        assert_eq!(shipped.status, 200);
+        let page = String::from_utf8(shipped.body).unwrap();
+        assert!(page.contains("<h1>Hello, Rust!</h1>"), "{}", page);
+        assert!(!page.contains("{{"), "{}", page);
+
+        fs::write(root.join("hello.html"), "{{#each items}}").unwrap();
+        let response = server.route(&parse_request("GET /hello HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 500);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_over_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("unix");
+        let path = dir.join("server.sock");
+        let config = ServerConfig {
+            unix_socket: Some(path.clone()),
+            unix_socket_mode: 0o600,
+            ..ServerConfig::default()
+        };
+
+        // 上一次运行遗留的套接字文件：std 的 UnixListener 在 drop 时不会删除它
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = Listener::bind(&config).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // 已经有服务器在监听时拒绝删除它的套接字文件
+        let err = Listener::bind(&config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let server = Arc::new(Server::from_config(&config, Arc::new(StdoutLogger)));
+        let handle = thread::spawn(move || {
+            let pool = ThreadPool::new(2);
+            // 除了两个请求，还有上面检查套接字是否在用时建立的那个连接
+            for stream in listener.incoming().take(3) {
+                server.dispatch(&pool, stream.unwrap());
+            }
+            // listener 在这里被 drop，套接字文件随之删除
+        });
+
+        let health = client::request_unix(&path, "GET", "/api/health", &[], &[]).unwrap();
+        assert_eq!(health.status, 200);
+        assert_eq!(health.text(), r#"{"status":"ok"}"#);
+        let greet = client::request_unix(
+            &path,
+            "POST",
+            "/api/greet",
+            &[("Content-Type", "application/json")],
+            br#"{"name":"unix"}"#,
+        )
+        .unwrap();
+        assert_eq!(greet.text(), r#"{"message":"Hello, unix!"}"#);
+
+        handle.join().unwrap();
+        assert!(!path.exists());
+
+        // 不是套接字的文件不会被删除
+        fs::write(&path, "not a socket").unwrap();
+        let err = Listener::bind(&config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // 一个简单的上游：把收到的请求行、头部和请求体写进响应体里，方便检查代理转发了什么
+    fn spawn_upstream(connections: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream, u64::MAX).unwrap().unwrap();
+                let mut body = format!("{} {}\n", request.method, request.path);
+                let mut headers: Vec<_> = request.headers.iter().collect();
+                headers.sort();
+                for (name, value) in headers {
+                    body.push_str(&format!("{}: {}\n", name, value));
+                }
+                body.push_str(&String::from_utf8_lossy(&request.body));
+                let response = Response::new(201, "CREATED")
+                    .header("X-Upstream", "yes")
+                    .body(body.into_bytes());
+                response.write_to(&mut stream).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn reverse_proxy_forwards_requests() {
+        let upstream = spawn_upstream(2);
+        let logger = Arc::new(MemoryLogger::default());
+        let mut server = Server::new(logger.clone());
+        server.proxies.push((String::from("/backend"), upstream));
+        let addr = spawn_server(2, server);
+
+        let response = send_raw(
+            addr,
+            "POST /backend/items HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 10.0.0.1\r\nConnection: keep-alive\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        assert!(response.starts_with("HTTP/1.1 201 CREATED\r\n"));
+        assert!(response.contains("X-Upstream: yes\r\n"));
+        assert!(response.contains("POST /backend/items\n"));
+        assert!(response.contains("host: example.com\n"));
+        // 客户端地址追加在已有的 X-Forwarded-For 之后，逐跳头部被替换
+        assert!(response.contains("x-forwarded-for: 10.0.0.1, 127.0.0.1\n"));
+        assert!(response.contains("connection: close\n"));
+        assert!(response.contains("content-length: 5\n"));
+        assert!(response.ends_with("\nhello"));
+
+        let response = send_raw(addr, "GET /backend HTTP/1.1\r\n\r\n");
+        assert!(response.contains("GET /backend\n"));
+        assert!(response.contains("x-forwarded-for: 127.0.0.1\n"));
+
+        thread::sleep(Duration::from_millis(50));
+        let lines = logger.lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("method=POST path=/backend/items status=201"));
+    }
+
+    #[test]
+    fn reverse_proxy_upstream_down() {
+        // 绑定后立即释放，得到一个没有人监听的端口
+        let upstream = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut server = test_server();
+        server.proxies.push((String::from("/backend"), upstream));
+        let addr = spawn_server(2, server);
+
+        let response = send_raw(addr, "GET /backend/x HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 502 BAD GATEWAY\r\n"));
+        // 只是前缀相同的路径不会被代理
+        let response = send_raw(addr, "GET /backendless HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+    }
+
+    #[test]
+    fn metrics_report_streaming_latency() {
+        let server = test_server();
+        let metrics = || {
+            let response = server.route(&parse_request("GET /metrics HTTP/1.1\r\n\r\n"));
+            String::from_utf8(body_of(&response)).unwrap()
+        };
+        assert!(metrics().contains("request_duration_us_count 0\n"));
+        assert!(metrics().contains("requests_per_minute 0\n"));
+        assert!(!metrics().contains("quantile"));
+
+        for ms in 1..=10 {
+            server.record(AccessLogEntry {
+                worker: None,
+                method: String::from("GET"),
+                path: String::from("/"),
+                status: 200,
+                bytes: 0,
+                elapsed: Duration::from_millis(ms),
+            });
+        }
+        // 等统计线程发布包含这 10 个请求的结果
+        let mut summaries = server.latency_summary.clone();
+        let rt = runtime(Preset::SingleThreadTest);
+        rt.block_on(async {
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                summaries.wait_for(|summary| summary.count == 10),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        });
+
+        let metrics = metrics();
+        assert!(
+            metrics.contains("request_duration_us_count 10\n"),
+            "{}",
+            metrics
+        );
+        assert!(metrics.contains("requests_per_minute 10\n"));
+        assert!(metrics.contains("request_duration_us_mean 5500.0\n"));
+        assert!(metrics.contains("request_duration_us_stddev 3027.7\n"));
+        assert!(metrics.contains("request_duration_us{quantile=\"0.5\"} 5000\n"));
+        assert!(metrics.contains("request_duration_us{quantile=\"0.99\"} 10000\n"));
+    }
+
+    #[test]
+    fn metrics_history_downsamples_requests() {
+        let server = test_server();
+        let get =
+            |path: &str| server.route(&parse_request(&format!("GET {} HTTP/1.1\r\n\r\n", path)));
+        let json = |response: Response| -> serde_json::Value {
+            serde_json::from_slice(&body_of(&response)).unwrap()
+        };
+
+        // 还没有任何请求时没有指标
+        assert_eq!(json(get("/metrics/history")), serde_json::json!([]));
+
+        for (ms, bytes) in [(2, 100), (4, 300), (9, 50)] {
+            server.record(AccessLogEntry {
+                worker: Some(0),
+                method: String::from("GET"),
+                path: String::from("/"),
+                status: 200,
+                bytes,
+                elapsed: Duration::from_millis(ms),
+            });
+        }
+        assert_eq!(
+            json(get("/metrics/history")),
+            serde_json::json!(["request_duration_us", "response_bytes"])
+        );
+
+        // 一个足够大的桶装下刚才的所有请求
+        let response =
+            get("/metrics/history?metric=request_duration_us&from=0&step=100000000000000");
+        assert_eq!(response.status, 200);
+        let history = json(response);
+        assert_eq!(history["metric"], "request_duration_us");
+        let buckets = history["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0]["count"], 3);
+        assert_eq!(buckets[0]["min"], 2000.0);
+        assert_eq!(buckets[0]["max"], 9000.0);
+        assert_eq!(buckets[0]["avg"], 5000.0);
+
+        // 默认的最近一分钟也包含这些请求
+        let history = json(get("/metrics/history?metric=response_bytes"));
+        let buckets = history["buckets"].as_array().unwrap();
+        let count: u64 = buckets.iter().map(|b| b["count"].as_u64().unwrap()).sum();
+        assert_eq!(count, 3);
+
+        let response = get("/metrics/history?metric=nope");
+        assert_eq!(response.status, 404);
+        let response = get("/metrics/history?metric=response_bytes&step=abc");
+        assert_eq!(response.status, 400);
+        assert_eq!(json(response)["error"], "invalid step");
+        let response = get("/metrics/history?metric=response_bytes&step=0");
+        assert_eq!(
+            json(response)["error"],
+            "downsampling step must be positive"
+        );
+
+        // 历史记录有上限，请求再多也只保留最新的一部分样本
+        let server = Server {
+            history: Mutex::new(Store::with_max_samples(256)),
+            ..test_server()
+        };
+        for bytes in 0..5_000 {
+            server.record(AccessLogEntry {
+                worker: Some(0),
+                method: String::from("GET"),
+                path: String::from("/"),
+                status: 200,
+                bytes,
+                elapsed: Duration::from_micros(10),
+            });
+        }
+        let history = server.history.lock().unwrap();
+        for metric in ["request_duration_us", "response_bytes"] {
+            let len = history.len(metric);
+            assert!((129..=256).contains(&len), "{}: {}", metric, len);
+        }
+        let newest = history.range("response_bytes", 0, u64::MAX);
+        assert_eq!(newest.last().unwrap().1, 4_999.0);
+    }
+
+    #[test]
+    fn recent_logs_keep_the_newest_entries() {
+        let server = Server {
+            recent_log: Mutex::new(RingBuffer::with_capacity(3)),
+            ..test_server()
+        };
+        let get = |path: &str| {
+            let response = server.route(&parse_request(&format!("GET {} HTTP/1.1\r\n\r\n", path)));
+            (
+                response.status,
+                String::from_utf8(body_of(&response)).unwrap(),
+            )
+        };
+        assert_eq!(get("/logs/recent"), (200, String::new()));
+
+        for i in 1..=5 {
+            server.record(AccessLogEntry {
+                worker: Some(0),
+                method: String::from("GET"),
+                path: format!("/page/{}", i),
+                status: 200,
+                bytes: 0,
+                elapsed: Duration::ZERO,
+            });
+        }
+        // 容量是 3，最早的两条被覆盖掉了
+        let paths = |body: String| -> Vec<String> {
+            body.lines()
+                .map(|line| line.split(' ').nth(2).unwrap().to_string())
+                .collect()
+        };
+        let (status, body) = get("/logs/recent");
+        assert_eq!(status, 200);
+        assert_eq!(
+            paths(body),
+            ["path=/page/3", "path=/page/4", "path=/page/5"]
+        );
+        assert_eq!(paths(get("/logs/recent?limit=1").1), ["path=/page/5"]);
+        assert_eq!(paths(get("/logs/recent?limit=10").1).len(), 3);
+        assert_eq!(get("/logs/recent?limit=0").1, "");
+        let (status, body) = get("/logs/recent?limit=-1");
+        assert_eq!(status, 400);
+        assert!(body.contains("invalid limit"));
+    }
+
+    #[test]
+    fn health_checks_get_high_priority() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut peeked = Vec::new();
+        for request in [
+            "GET /api/health HTTP/1.1\r\n\r\n",
+            "GET /metrics/history?metric=x HTTP/1.1\r\n\r\n",
+            "GET /big-file HTTP/1.1\r\n\r\n",
+            "",
+        ] {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            // 等请求到达服务器端的缓冲区，peek 不会等待
+            thread::sleep(Duration::from_millis(50));
+            let stream = Stream::Tcp(stream);
+            peeked.push(stream.priority());
+
+            // peek 之后请求仍然完整可读，连接也回到了阻塞模式
+            let mut stream = stream;
+            let mut buf = vec![0; request.len()];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, request.as_bytes());
+        }
+        assert_eq!(
+            peeked,
+            [
+                Priority::High,
+                Priority::High,
+                Priority::Normal,
+                Priority::Normal
+            ]
+        );
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("thread+pool"), "thread pool");
+        assert_eq!(percent_decode("%22a%20b%22"), "\"a b\"");
+        assert_eq!(percent_decode("%E4%BD%A0"), "你");
+        // 不完整或不合法的转义原样保留
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn searches_source_files() {
+        let dir = temp_dir("search");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(
+            dir.join("pool.rs"),
+            "struct ThreadPool; // thread pool, a pool of threads, thread pool",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("nested/server.rs"),
+            "fn serve() { ThreadPool::new(4); }",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "ThreadPool").unwrap();
+        let server = Server {
+            source_dir: dir.clone(),
+            ..test_server()
+        };
+        let search = |query: &str| -> serde_json::Value {
+            let request = parse_request(&format!("GET /search?q={} HTTP/1.1\r\n\r\n", query));
+            let response = server.route(&request);
+            assert_eq!(response.status, 200);
+            serde_json::from_slice(&body_of(&response)).unwrap()
+        };
+
+        let results = search("threadpool");
+        assert_eq!(results["total"], 2);
+        assert_eq!(results["hits"][0]["path"], "nested/server.rs");
+        assert_eq!(results["hits"][1]["path"], "pool.rs");
+
+        let results = search("%22thread+pool%22+OR+serve");
+        assert_eq!(results["query"], "\"thread pool\" OR serve");
+        assert_eq!(results["hits"][0]["path"], "pool.rs");
+        assert_eq!(results["total"], 2);
+
+        let response = server.route(&parse_request("GET /search HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, 400);
+        fs::remove_dir_all(dir).unwrap();
+
+        // 默认检索的是本 crate 自己的源代码
+        let server = test_server();
+        let request = parse_request("GET /search?q=%22fn+percent_decode%22 HTTP/1.1\r\n\r\n");
+        let results: serde_json::Value =
+            serde_json::from_slice(&body_of(&server.route(&request))).unwrap();
+        assert_eq!(results["hits"][0]["path"], "webserver_example.rs");
     }
 }
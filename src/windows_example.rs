@@ -0,0 +1,331 @@
+// 窗口聚合：把带时间戳的事件按时间分段统计，时间指事件发生的时间（事件时间），而不是处理它的时间
+// 滚动窗口（tumbling）首尾相接互不重叠；跳跃窗口（hopping）每隔 hop 开一个长为 size 的窗口，彼此重叠，一个事件属于多个窗口；
+// 滑动窗口（sliding）随查询时刻移动，总是统计最近 size 这段时间
+// 事件可能乱序到达，水位线（watermark）表示“早于它的事件不会再来了”，窗口的结束时间过了水位线才输出结果，之后再来的事件算迟到
+#[cfg(test)]
+pub(crate) mod windows {
+
+    use chrono::{DateTime, TimeDelta, Utc};
+    use std::collections::{BTreeMap, VecDeque};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) struct Aggregate {
+        pub(crate) count: u64,
+        pub(crate) sum: f64,
+        pub(crate) min: f64,
+        pub(crate) max: f64,
+    }
+
+    impl Aggregate {
+        fn new(value: f64) -> Aggregate {
+            Aggregate {
+                count: 1,
+                sum: value,
+                min: value,
+                max: value,
+            }
+        }
+
+        fn add(&mut self, value: f64) {
+            self.count += 1;
+            self.sum += value;
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+
+        pub(crate) fn mean(&self) -> f64 {
+            self.sum / self.count as f64
+        }
+    }
+
+    // 一个已经关闭的窗口，覆盖 [start, end)
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct Window {
+        pub(crate) start: DateTime<Utc>,
+        pub(crate) end: DateTime<Utc>,
+        pub(crate) aggregate: Aggregate,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) enum WindowKind {
+        Tumbling(TimeDelta),
+        Hopping { size: TimeDelta, hop: TimeDelta },
+    }
+
+    impl WindowKind {
+        fn size(&self) -> TimeDelta {
+            match *self {
+                WindowKind::Tumbling(size) => size,
+                WindowKind::Hopping { size, .. } => size,
+            }
+        }
+
+        // 滚动窗口就是 hop 等于 size 的跳跃窗口
+        fn hop(&self) -> TimeDelta {
+            match *self {
+                WindowKind::Tumbling(size) => size,
+                WindowKind::Hopping { hop, .. } => hop,
+            }
+        }
+
+        // 包含 ts 的所有窗口的开始时间，从早到晚。窗口的开始时间对齐到 hop 的整数倍（从 Unix 纪元算起），
+        // 这样不管第一个事件什么时候来，窗口的边界都一样
+        fn starts(&self, ts: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+            let hop = self.hop().num_milliseconds();
+            let size = self.size().num_milliseconds();
+            let millis = ts.timestamp_millis();
+            // div_euclid 对负数（1970 年以前）也向下取整
+            let last = millis.div_euclid(hop) * hop;
+            let mut starts: Vec<DateTime<Utc>> = (0..)
+                .map(|k| last - k * hop)
+                .take_while(|start| start + size > millis)
+                .map(|start| DateTime::from_timestamp_millis(start).unwrap())
+                .collect();
+            starts.reverse();
+            starts
+        }
+    }
+
+    pub(crate) struct WindowAggregator {
+        kind: WindowKind,
+        // 允许事件比目前见过的最晚的事件早多久到达，水位线 = 最晚的事件时间 - allowed_lateness
+        allowed_lateness: TimeDelta,
+        max_seen: Option<DateTime<Utc>>,
+        // 还没关闭的窗口，按开始时间排序
+        open: BTreeMap<DateTime<Utc>, Aggregate>,
+        late: u64,
+    }
+
+    impl WindowAggregator {
+        pub(crate) fn new(kind: WindowKind, allowed_lateness: TimeDelta) -> WindowAggregator {
+            assert!(kind.size() > TimeDelta::zero() && kind.hop() > TimeDelta::zero());
+            assert!(kind.hop() <= kind.size());
+            WindowAggregator {
+                kind,
+                allowed_lateness,
+                max_seen: None,
+                open: BTreeMap::new(),
+                late: 0,
+            }
+        }
+
+        pub(crate) fn watermark(&self) -> Option<DateTime<Utc>> {
+            self.max_seen.map(|max| max - self.allowed_lateness)
+        }
+
+        // 加入一个事件，返回因为水位线前进而关闭的窗口
+        // 事件所属的窗口都已经关闭时丢弃它并计入迟到；只有一部分关闭时（跳跃窗口）只加到还开着的窗口里
+        pub(crate) fn add(&mut self, ts: DateTime<Utc>, value: f64) -> Vec<Window> {
+            let size = self.kind.size();
+            let watermark = self.watermark();
+            let mut accepted = false;
+            for start in self.kind.starts(ts) {
+                if watermark.is_some_and(|watermark| start + size <= watermark) {
+                    continue;
+                }
+                accepted = true;
+                self.open
+                    .entry(start)
+                    .and_modify(|aggregate| aggregate.add(value))
+                    .or_insert_with(|| Aggregate::new(value));
+            }
+            if !accepted {
+                self.late += 1;
+                return Vec::new();
+            }
+            if self.max_seen.is_none_or(|max| ts > max) {
+                self.max_seen = Some(ts);
+            }
+            let watermark = self.watermark().unwrap();
+            self.close(|end| end <= watermark)
+        }
+
+        // 数据结束（例如批处理读完了文件）时关闭所有窗口
+        pub(crate) fn flush(&mut self) -> Vec<Window> {
+            self.close(|_| true)
+        }
+
+        pub(crate) fn late_events(&self) -> u64 {
+            self.late
+        }
+
+        // 窗口按开始时间排序，结束时间的顺序也一样，所以从前往后关闭到第一个不满足条件的为止
+        fn close(&mut self, closed: impl Fn(DateTime<Utc>) -> bool) -> Vec<Window> {
+            let size = self.kind.size();
+            let mut windows = Vec::new();
+            while let Some(entry) = self.open.first_entry() {
+                let end = *entry.key() + size;
+                if !closed(end) {
+                    break;
+                }
+                let (start, aggregate) = entry.remove_entry();
+                windows.push(Window {
+                    start,
+                    end,
+                    aggregate,
+                });
+            }
+            windows
+        }
+    }
+
+    // 滑动窗口：保存最近 size 时间内的事件，查询时先淘汰过期的；事件基本按时间顺序到达，偶尔乱序的插到正确的位置
+    pub(crate) struct SlidingWindow {
+        size: TimeDelta,
+        events: VecDeque<(DateTime<Utc>, f64)>,
+    }
+
+    impl SlidingWindow {
+        pub(crate) fn new(size: TimeDelta) -> SlidingWindow {
+            SlidingWindow {
+                size,
+                events: VecDeque::new(),
+            }
+        }
+
+        pub(crate) fn add(&mut self, ts: DateTime<Utc>, value: f64) {
+            let at = self.events.partition_point(|(t, _)| *t <= ts);
+            self.events.insert(at, (ts, value));
+            // 比最新的事件早 size 以上的事件不会再被任何查询用到
+            let newest = self.events.back().unwrap().0;
+            self.evict(newest - self.size);
+        }
+
+        // (now - size, now] 内的事件的聚合，没有事件时返回 None
+        pub(crate) fn aggregate(&mut self, now: DateTime<Utc>) -> Option<Aggregate> {
+            self.evict(now - self.size);
+            let mut events = self.events.iter().take_while(|(ts, _)| *ts <= now);
+            let mut aggregate = Aggregate::new(events.next()?.1);
+            for (_, value) in events {
+                aggregate.add(*value);
+            }
+            Some(aggregate)
+        }
+
+        pub(crate) fn count(&mut self, now: DateTime<Utc>) -> u64 {
+            self.aggregate(now).map_or(0, |aggregate| aggregate.count)
+        }
+
+        fn evict(&mut self, before: DateTime<Utc>) {
+            while self.events.front().is_some_and(|(ts, _)| *ts <= before) {
+                self.events.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::windows::*;
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    // 2024-01-01 00:00:00 UTC 之后的第 seconds 秒
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_704_067_200 + seconds, 0).unwrap()
+    }
+
+    fn spans(windows: &[Window]) -> Vec<(i64, i64, u64)> {
+        windows
+            .iter()
+            .map(|w| {
+                let base = at(0).timestamp();
+                (
+                    w.start.timestamp() - base,
+                    w.end.timestamp() - base,
+                    w.aggregate.count,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tumbling_windows() {
+        let mut windows = WindowAggregator::new(
+            WindowKind::Tumbling(TimeDelta::seconds(60)),
+            TimeDelta::zero(),
+        );
+        assert!(windows.add(at(5), 1.0).is_empty());
+        assert!(windows.add(at(59), 3.0).is_empty());
+        // 第一分钟结束的时刻正好在水位线上，窗口关闭
+        let closed = windows.add(at(60), 10.0);
+        assert_eq!(spans(&closed), [(0, 60, 2)]);
+        assert_eq!(closed[0].aggregate.mean(), 2.0);
+        assert_eq!(
+            (closed[0].aggregate.min, closed[0].aggregate.max),
+            (1.0, 3.0)
+        );
+
+        // 跳过没有事件的分钟，空窗口不输出
+        assert_eq!(spans(&windows.add(at(200), 1.0)), [(60, 120, 1)]);
+        assert_eq!(spans(&windows.flush()), [(180, 240, 1)]);
+        assert!(windows.flush().is_empty());
+    }
+
+    #[test]
+    fn hopping_windows_overlap() {
+        // 每 30 秒开一个一分钟的窗口，每个事件属于两个窗口
+        let mut windows = WindowAggregator::new(
+            WindowKind::Hopping {
+                size: TimeDelta::seconds(60),
+                hop: TimeDelta::seconds(30),
+            },
+            TimeDelta::zero(),
+        );
+        let mut closed = Vec::new();
+        for second in [10, 40, 70, 100] {
+            closed.extend(windows.add(at(second), 1.0));
+        }
+        closed.extend(windows.flush());
+        assert_eq!(
+            spans(&closed),
+            [
+                (-30, 30, 1),
+                (0, 60, 2),
+                (30, 90, 2),
+                (60, 120, 2),
+                (90, 150, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn watermark_and_late_events() {
+        // 允许 10 秒的乱序
+        let mut windows = WindowAggregator::new(
+            WindowKind::Tumbling(TimeDelta::seconds(60)),
+            TimeDelta::seconds(10),
+        );
+        windows.add(at(50), 1.0);
+        // 第二分钟的事件先到，但水位线才到 55 秒，第一分钟还开着
+        assert!(windows.add(at(65), 1.0).is_empty());
+        assert_eq!(windows.watermark(), Some(at(55)));
+        // 乱序到达、但在允许范围内的事件仍然计入第一分钟
+        assert!(windows.add(at(58), 1.0).is_empty());
+        assert_eq!(spans(&windows.add(at(72), 1.0)), [(0, 60, 2)]);
+
+        // 第一分钟已经输出，再来的事件只能丢弃
+        assert!(windows.add(at(30), 1.0).is_empty());
+        assert_eq!(windows.late_events(), 1);
+        // 迟到的事件不会推动水位线
+        assert_eq!(windows.watermark(), Some(at(62)));
+        assert_eq!(spans(&windows.flush()), [(60, 120, 2)]);
+    }
+
+    #[test]
+    fn sliding_window() {
+        let mut window = SlidingWindow::new(TimeDelta::seconds(60));
+        assert_eq!(window.count(at(0)), 0);
+        for second in [0, 10, 20, 65] {
+            window.add(at(second), second as f64);
+        }
+        // 乱序的事件插到正确的位置
+        window.add(at(15), 15.0);
+        // (5, 65] 内有 10、15、20、65
+        let aggregate = window.aggregate(at(65)).unwrap();
+        assert_eq!(aggregate.count, 4);
+        assert_eq!((aggregate.min, aggregate.max), (10.0, 65.0));
+        assert_eq!(window.count(at(79)), 2);
+        assert_eq!(window.count(at(200)), 0);
+    }
+}
@@ -0,0 +1,40 @@
+// 分别启动 ipc_reader 和 ipc_writer 两个程序，通过共享内存里的环形缓冲区传递消息
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process::{self, Command, Stdio};
+
+const MESSAGES: usize = 1000;
+
+#[test]
+fn two_processes_share_a_ring() {
+    let path = env::temp_dir().join(format!("ipc-ring-{}", process::id()));
+    // 缓冲区只有 8 个槽位，写者写得比读者快时会被迫等待
+    let mut reader = Command::new(env!("CARGO_BIN_EXE_ipc_reader"))
+        .arg(&path)
+        .arg(MESSAGES.to_string())
+        .arg("8")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(reader.stdout.take().unwrap()).lines();
+    // 读者创建好缓冲区之后才能启动写者
+    assert_eq!(lines.next().unwrap().unwrap(), "ready");
+
+    let mut writer = Command::new(env!("CARGO_BIN_EXE_ipc_writer"))
+        .arg(&path)
+        .arg(MESSAGES.to_string())
+        .spawn()
+        .unwrap();
+
+    for i in 0..MESSAGES {
+        assert_eq!(lines.next().unwrap().unwrap(), format!("message {}", i));
+    }
+    assert!(lines.next().is_none());
+    assert!(writer.wait().unwrap().success());
+    assert!(reader.wait().unwrap().success());
+
+    fs::remove_file(path).unwrap();
+}